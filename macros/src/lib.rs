@@ -25,10 +25,9 @@ fn impl_into_hashmap(ast: &syn::DeriveInput) -> TokenStream {
                     syn::Meta::List(list) => list,
                     _ => panic!("Wrong type for #[implementation(...)] parameters"),
                 };
-                let trait_ = syn::punctuated::Punctuated::<Ident, Token![,]>::parse_terminated
+                syn::punctuated::Punctuated::<Ident, Token![,]>::parse_terminated
                     .parse(meta_list.tokens.clone().into())
-                    .expect("Invalid #[implementation(Trait, Error)] attribute entered");
-                trait_
+                    .expect("Invalid #[implementation(Trait, Error)] attribute entered")
             }),
             false => None,
         })
@@ -43,13 +42,11 @@ fn impl_into_hashmap(ast: &syn::DeriveInput) -> TokenStream {
         .expect("#[implementation(Trait, Error)] could not parse Error")
         .clone();
 
-    let type_map: Vec<(Ident, Ident)> = named_fields
+    let type_map: Vec<(Ident, Ident, Vec<syn::Attribute>)> = named_fields
         .named
         .iter()
         .filter_map(|f| {
-            if f.ident.is_none() {
-                return None;
-            }
+            f.ident.as_ref()?;
 
             let attrs = f
                 .attrs
@@ -67,11 +64,58 @@ fn impl_into_hashmap(ast: &syn::DeriveInput) -> TokenStream {
                     "#[derive(FiniteStateMachine)] need the #[device(...)] attribute on each field",
                 );
 
-            Some((f.ident.clone().unwrap(), attrs))
+            // Keep the #[cfg(...)] of feature-gated fields on the generated code
+            let cfgs = f
+                .attrs
+                .iter()
+                .filter(|attr| attr.path().is_ident("cfg"))
+                .cloned()
+                .collect();
+
+            Some((f.ident.clone().unwrap(), attrs, cfgs))
         })
         .collect();
 
-    let (fields, typ): (Vec<Ident>, Vec<Ident>) = type_map.into_iter().unzip();
+    let options_: Option<Ident> = ast
+        .attrs
+        .iter()
+        .find_map(|attr| match attr.path().is_ident("options") {
+            true => Some(
+                attr.parse_args()
+                    .expect("Invalid #[options(...)] attribute entered"),
+            ),
+            false => None,
+        });
+
+    let mut fields: Vec<Ident> = Vec::new();
+    let mut typ: Vec<Ident> = Vec::new();
+    let mut cfgs: Vec<Vec<syn::Attribute>> = Vec::new();
+    for (field, device, cfg) in type_map {
+        fields.push(field);
+        typ.push(device);
+        cfgs.push(cfg);
+    }
+
+    // Collect the `options` field of each entry when #[options(Type)] is set
+    let options_impl = match options_ {
+        Some(options_) => quote! {
+            impl #name {
+                pub fn options(&self) -> HashMap<String, #options_> {
+                    let mut res: HashMap<String, #options_> = HashMap::new();
+
+                    #(
+                    #(#cfgs)*
+                    if let Some(field) = &self.#fields {
+                        for (name, dev_def) in field {
+                            res.insert(name.clone(), dev_def.options.clone());
+                        }
+                    };)*
+                    res
+                }
+            }
+        },
+        None => quote! {},
+    };
 
     let gen = quote! {
         impl TryInto<HashMap<String, Box<dyn #type_ + Send>>> for #name {
@@ -81,10 +125,15 @@ fn impl_into_hashmap(ast: &syn::DeriveInput) -> TokenStream {
                 let mut res: HashMap<String, Box<dyn #type_ + Send>> = HashMap::new();
 
                 #(
+                #(#cfgs)*
                 match self.#fields {
                     Some(field) => {
                         for (name, dev_def) in field {
-                            let dev: Box<#typ> = Box::new(dev_def.try_into()?);
+                            // Attach the name of the entry to the error so the faulty config can be found
+                            let dev: #typ = dev_def
+                                .try_into()
+                                .map_err(|err| #error_::from(err).named(&name))?;
+                            let dev: Box<#typ> = Box::new(dev);
                             res.insert(name, dev);
                         }
                     },
@@ -94,11 +143,29 @@ fn impl_into_hashmap(ast: &syn::DeriveInput) -> TokenStream {
                 Ok(res)
             }
         }
+
+        impl #name {
+            /// The names of the entries of each section, sorted, keyed by section name
+            pub fn names(&self) -> Vec<(&'static str, Vec<String>)> {
+                let mut res: Vec<(&'static str, Vec<String>)> = Vec::new();
+
+                #(
+                #(#cfgs)*
+                if let Some(field) = &self.#fields {
+                    let mut names: Vec<String> = field.keys().cloned().collect();
+                    names.sort();
+                    res.push((stringify!(#fields), names));
+                };)*
+                res
+            }
+        }
+
+        #options_impl
     };
     gen.into()
 }
 
-#[proc_macro_derive(IntoHashMap, attributes(device, implementation))]
+#[proc_macro_derive(IntoHashMap, attributes(device, implementation, options))]
 pub fn instanciate_device_derive(input: TokenStream) -> TokenStream {
     let ast = syn::parse(input).unwrap();
 
@@ -1,27 +1,43 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 
-use influxdb::Client;
-use prometheus_push::prometheus_crate::PrometheusMetricsPusher;
+use log::info;
 use serde::Deserialize;
+use tokio::time::MissedTickBehavior;
 
+use crate::devices::bacnet::{self, BacnetDevice, BacnetIPDevice};
+use crate::devices::definitions::{check_types, register_names, DefinitionRetry};
+use crate::devices::fins::{self, FinsUdpDevice, OmronFinsDevice};
 use crate::devices::modbus_rtu::ModbusRTUDevice;
 use crate::devices::modbus_tcp::ModbusTCPDevice;
-use crate::remotes::influxdb::InfluxDBRemote;
-use crate::remotes::prometheus::PrometheusRemote;
+use crate::remotes::heartbeat::Heartbeat;
+use crate::remotes::influxdb::{InfluxDBClient, InfluxDBRemote};
+use crate::remotes::custom::{CustomRemote, CustomRemoteClient};
+use crate::remotes::history::{History, HistoryRemote};
+use crate::remotes::modbus_server::{ModbusServer, ModbusServerRemote};
+use crate::remotes::prometheus::{PrometheusPusher, PrometheusRemote};
+#[cfg(feature = "remote_write")]
+use crate::remotes::remote_write::{PrometheusRemoteWriteRemote, PrometheusRemoteWriter};
+use crate::remotes::webhook::{WebhookClient, WebhookRemote};
 
 use macros::IntoHashMap;
 
-use crate::remotes::remote::Remote;
+use crate::remotes::remote::{Remote, RemoteOptions};
 use industrial_device::IndustrialDevice;
 
 use crate::devices::errors::DeviceInitError;
+use crate::devices::DeviceOptions;
 use crate::remotes::errors::RemoteInitError;
+use crate::telemetry::OtelConfig;
 
-use modbus_device::ModbusDeviceAsync;
-use s7_device::S7Device;
+use crate::devices::chunking::{self, ModbusDevice};
+use crate::devices::s7::{self, S7PlcDevice};
+
+mod loading;
+pub use loading::{config_dir_files, load_config};
 
 #[derive(Deserialize, Debug, IntoHashMap)]
 #[implementation(IndustrialDevice, DeviceInitError)]
+#[options(DeviceOptions)]
 
 /// Defines all supported device configurations for the application.
 ///
@@ -29,18 +45,25 @@ use s7_device::S7Device;
 /// - `modbus_tcp`: Optional collection of Modbus TCP devices, keyed by name.
 /// - `modbus_rtu`: Optional collection of Modbus RTU devices, keyed by name.
 /// - `s7`: Optional collection of Siemens S7 PLC devices, keyed by name.
+/// - `bacnet`: Optional collection of BACnet/IP devices, keyed by name.
+/// - `fins`: Optional collection of Omron PLCs reached through FINS/UDP, keyed by name.
 ///
 pub struct Devices {
-    #[device(ModbusDeviceAsync)]
+    #[device(ModbusDevice)]
     pub modbus_tcp: Option<HashMap<String, ModbusTCPDevice>>,
-    #[device(ModbusDeviceAsync)]
+    #[device(ModbusDevice)]
     pub modbus_rtu: Option<HashMap<String, ModbusRTUDevice>>,
-    #[device(S7Device)]
+    #[device(S7PlcDevice)]
     pub s7: Option<HashMap<String, crate::devices::s7::S7Device>>,
+    #[device(BacnetDevice)]
+    pub bacnet: Option<HashMap<String, BacnetIPDevice>>,
+    #[device(OmronFinsDevice)]
+    pub fins: Option<HashMap<String, FinsUdpDevice>>,
 }
 
 #[derive(Deserialize, Debug, IntoHashMap)]
 #[implementation(Remote, RemoteInitError)]
+#[options(RemoteOptions)]
 
 /// Defines all remote backends where collected measurements can be sent.
 ///
@@ -51,11 +74,320 @@ pub struct Devices {
 /// # Fields
 /// - `influx_db`: Optional collection of InfluxDB remotes, keyed by name.
 /// - `prometheus`: Optional collection of Prometheus push remotes, keyed by name.
+/// - `prometheus_remote_write`: Optional collection of Prometheus remote-write remotes, keyed by name
+///   (`remote_write` feature).
+/// - `webhook`: Optional collection of webhooks receiving the measurements as JSON, keyed by name.
+/// - `modbus_server`: Optional collection of Modbus TCP servers exposing the last values in registers, keyed by name.
+/// - `history`: Optional collection of HTTP servers exposing the last batches as JSON, keyed by name.
+/// - `custom`: Optional collection of remotes of the types registered with `register_remote`, keyed by name.
 pub struct Remotes {
-    #[device(Client)]
+    #[device(InfluxDBClient)]
     pub influx_db: Option<HashMap<String, InfluxDBRemote>>,
-    #[device(PrometheusMetricsPusher)]
+    #[device(PrometheusPusher)]
     pub prometheus: Option<HashMap<String, PrometheusRemote>>,
+    #[cfg(feature = "remote_write")]
+    #[device(PrometheusRemoteWriter)]
+    pub prometheus_remote_write: Option<HashMap<String, PrometheusRemoteWriteRemote>>,
+    #[device(WebhookClient)]
+    pub webhook: Option<HashMap<String, WebhookRemote>>,
+    #[device(ModbusServer)]
+    pub modbus_server: Option<HashMap<String, ModbusServerRemote>>,
+    #[device(History)]
+    pub history: Option<HashMap<String, HistoryRemote>>,
+    #[device(CustomRemoteClient)]
+    pub custom: Option<HashMap<String, CustomRemote>>,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Type-specific part of a device entry in the list form of the `devices` section.
+///
+/// The `type` tag selects the protocol, the remaining fields are the ones of
+/// the corresponding device configuration.
+pub enum DeviceKind {
+    ModbusTcp(ModbusTCPDevice),
+    ModbusRtu(ModbusRTUDevice),
+    S7(crate::devices::s7::S7Device),
+    Bacnet(BacnetIPDevice),
+    Fins(FinsUdpDevice),
+}
+
+impl DeviceKind {
+    /// The `type` tag of the device
+    pub fn kind(&self) -> &'static str {
+        match self {
+            DeviceKind::ModbusTcp(_) => "modbus_tcp",
+            DeviceKind::ModbusRtu(_) => "modbus_rtu",
+            DeviceKind::S7(_) => "s7",
+            DeviceKind::Bacnet(_) => "bacnet",
+            DeviceKind::Fins(_) => "fins",
+        }
+    }
+
+    pub fn options(&self) -> DeviceOptions {
+        match self {
+            DeviceKind::ModbusTcp(dev_def) => dev_def.options.clone(),
+            DeviceKind::ModbusRtu(dev_def) => dev_def.device_options(),
+            DeviceKind::S7(dev_def) => dev_def.options.clone(),
+            DeviceKind::Bacnet(dev_def) => dev_def.options.clone(),
+            DeviceKind::Fins(dev_def) => dev_def.options.clone(),
+        }
+    }
+}
+
+impl DeviceKind {
+    /// Paths of the register definitions of the device, the Modbus classes of registers not read left out
+    fn definitions(&self) -> Vec<&str> {
+        let definitions = match self {
+            DeviceKind::ModbusTcp(dev_def) => vec![
+                (&dev_def.input_registers, dev_def.read_input),
+                (&dev_def.holding_registers, dev_def.read_holding),
+            ],
+            DeviceKind::ModbusRtu(dev_def) => vec![
+                (&dev_def.input_registers, dev_def.read_input),
+                (&dev_def.holding_registers, dev_def.read_holding),
+            ],
+            DeviceKind::S7(dev_def) => vec![(&dev_def.registers, true)],
+            DeviceKind::Bacnet(dev_def) => vec![(&dev_def.objects, true)],
+            DeviceKind::Fins(dev_def) => vec![(&dev_def.registers, true)],
+        };
+        definitions
+            .into_iter()
+            .filter(|(_, read)| *read)
+            .map(|(path, _)| path.as_str())
+            .collect()
+    }
+
+    /// The fields of a register in the definitions of the device
+    fn definition_fields(&self) -> &'static [&'static str] {
+        match self {
+            DeviceKind::ModbusTcp(_) | DeviceKind::ModbusRtu(_) => chunking::DEFINITION_FIELDS,
+            DeviceKind::S7(_) => s7::DEFINITION_FIELDS,
+            DeviceKind::Bacnet(_) => bacnet::DEFINITION_FIELDS,
+            DeviceKind::Fins(_) => fins::DEFINITION_FIELDS,
+        }
+    }
+
+    /// The names of the registers defined for the device, sorted (input then holding registers for Modbus)
+    pub fn register_names(&self) -> Result<Vec<String>, DeviceInitError> {
+        let pointer = self.options().definition_pointer;
+        let mut names = Vec::new();
+        for path in self.definitions() {
+            names.extend(register_names(
+                path,
+                pointer.as_deref(),
+                self.definition_fields(),
+            )?);
+        }
+        Ok(names)
+    }
+
+    /// Check the registers of the `types` option against the definitions of the device
+    pub fn check_types(&self) -> Result<(), DeviceInitError> {
+        let options = self.options();
+        if options.types.is_empty() {
+            return Ok(());
+        }
+        // BACnet objects are typed by their object type
+        let key = match self {
+            DeviceKind::Bacnet(_) => "object_type",
+            _ => "type",
+        };
+        check_types(
+            &self.definitions(),
+            options.definition_pointer.as_deref(),
+            self.definition_fields(),
+            key,
+            &options.types,
+        )
+    }
+}
+
+impl DeviceKind {
+    /// Tags describing the connection to the device
+    pub fn connection_info(&self) -> HashMap<String, String> {
+        match self {
+            DeviceKind::ModbusTcp(dev_def) => dev_def.connection_info(),
+            DeviceKind::ModbusRtu(dev_def) => dev_def.connection_info(),
+            DeviceKind::S7(dev_def) => dev_def.connection_info(),
+            DeviceKind::Bacnet(dev_def) => dev_def.connection_info(),
+            DeviceKind::Fins(dev_def) => dev_def.connection_info(),
+        }
+    }
+}
+
+impl TryFrom<DeviceKind> for Box<dyn IndustrialDevice + Send> {
+    type Error = DeviceInitError;
+
+    fn try_from(value: DeviceKind) -> Result<Self, Self::Error> {
+        let dev: Box<dyn IndustrialDevice + Send> = match value {
+            DeviceKind::ModbusTcp(dev_def) => Box::new(ModbusDevice::try_from(dev_def)?),
+            DeviceKind::ModbusRtu(dev_def) => Box::new(ModbusDevice::try_from(dev_def)?),
+            DeviceKind::S7(dev_def) => Box::new(S7PlcDevice::try_from(dev_def)?),
+            DeviceKind::Bacnet(dev_def) => Box::new(BacnetDevice::try_from(dev_def)?),
+            DeviceKind::Fins(dev_def) => Box::new(OmronFinsDevice::try_from(dev_def)?),
+        };
+        Ok(dev)
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// A single named device in the list form of the `devices` section.
+///
+/// # Fields
+/// - `name`: Name of the device, used as the measurement name on the remotes.
+/// - `device`: The device definition, selected by its `type` tag.
+pub struct DeviceConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub device: DeviceKind,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+/// The `devices` section accepts either a list of tagged devices or the
+/// historical map of device types.
+///
+/// ```yaml
+/// devices:
+///   - name: electrolyzer
+///     type: modbus_tcp
+///     remote: "127.0.0.1:4502"
+///     input_registers: "input_registers.json"
+///     holding_registers: "holding_registers.json"
+/// ```
+pub enum DevicesConfig {
+    List(Vec<DeviceConfig>),
+    Map(Box<Devices>),
+}
+
+impl DevicesConfig {
+    /// Options of each configured device, keyed by device name
+    pub fn options(&self) -> HashMap<String, DeviceOptions> {
+        match self {
+            DevicesConfig::Map(devices) => {
+                let mut options = devices.options();
+                for (name, dev_def) in devices.modbus_rtu.iter().flatten() {
+                    options.insert(name.clone(), dev_def.device_options());
+                }
+                options
+            }
+            DevicesConfig::List(devices) => devices
+                .iter()
+                .map(|dev| (dev.name.clone(), dev.device.options()))
+                .collect(),
+        }
+    }
+
+    /// Tags describing the connection to each configured device, keyed by device name
+    pub fn connection_info(&self) -> HashMap<String, HashMap<String, String>> {
+        match self {
+            DevicesConfig::Map(devices) => {
+                let mut res = HashMap::new();
+                for (name, dev_def) in devices.modbus_tcp.iter().flatten() {
+                    res.insert(name.clone(), dev_def.connection_info());
+                }
+                for (name, dev_def) in devices.modbus_rtu.iter().flatten() {
+                    res.insert(name.clone(), dev_def.connection_info());
+                }
+                for (name, dev_def) in devices.s7.iter().flatten() {
+                    res.insert(name.clone(), dev_def.connection_info());
+                }
+                for (name, dev_def) in devices.bacnet.iter().flatten() {
+                    res.insert(name.clone(), dev_def.connection_info());
+                }
+                for (name, dev_def) in devices.fins.iter().flatten() {
+                    res.insert(name.clone(), dev_def.connection_info());
+                }
+                res
+            }
+            DevicesConfig::List(devices) => devices
+                .iter()
+                .map(|dev| (dev.name.clone(), dev.device.connection_info()))
+                .collect(),
+        }
+    }
+
+    /// The definition of each configured device, keyed by device name (e.g. to build a device again)
+    pub fn kinds(&self) -> HashMap<String, DeviceKind> {
+        match self {
+            DevicesConfig::Map(devices) => {
+                let mut res = HashMap::new();
+                for (name, dev_def) in devices.modbus_tcp.iter().flatten() {
+                    res.insert(name.clone(), DeviceKind::ModbusTcp(dev_def.clone()));
+                }
+                for (name, dev_def) in devices.modbus_rtu.iter().flatten() {
+                    res.insert(name.clone(), DeviceKind::ModbusRtu(dev_def.clone()));
+                }
+                for (name, dev_def) in devices.s7.iter().flatten() {
+                    res.insert(name.clone(), DeviceKind::S7(dev_def.clone()));
+                }
+                for (name, dev_def) in devices.bacnet.iter().flatten() {
+                    res.insert(name.clone(), DeviceKind::Bacnet(dev_def.clone()));
+                }
+                for (name, dev_def) in devices.fins.iter().flatten() {
+                    res.insert(name.clone(), DeviceKind::Fins(dev_def.clone()));
+                }
+                res
+            }
+            DevicesConfig::List(devices) => devices
+                .iter()
+                .map(|dev| (dev.name.clone(), dev.device.clone()))
+                .collect(),
+        }
+    }
+
+    /// Check the `types` option of every device against its definitions, before the devices are created
+    pub fn check_types(&self) -> Result<(), DeviceInitError> {
+        for (name, kind) in self.kinds() {
+            kind.check_types().map_err(|err| err.named(&name))?;
+        }
+        Ok(())
+    }
+
+    /// The names of the devices of each type, sorted, keyed by type
+    pub fn names(&self) -> Vec<(&'static str, Vec<String>)> {
+        match self {
+            DevicesConfig::Map(devices) => devices.names(),
+            DevicesConfig::List(devices) => {
+                let mut res: BTreeMap<&'static str, Vec<String>> = BTreeMap::new();
+                for dev in devices {
+                    res.entry(dev.device.kind()).or_default().push(dev.name.clone());
+                }
+                res.into_iter()
+                    .map(|(kind, mut names)| {
+                        names.sort();
+                        (kind, names)
+                    })
+                    .collect()
+            }
+        }
+    }
+}
+
+impl TryInto<HashMap<String, Box<dyn IndustrialDevice + Send>>> for DevicesConfig {
+    type Error = DeviceInitError;
+
+    fn try_into(self) -> Result<HashMap<String, Box<dyn IndustrialDevice + Send>>, Self::Error> {
+        match self {
+            DevicesConfig::Map(devices) => (*devices).try_into(),
+            DevicesConfig::List(devices) => {
+                let mut res: HashMap<String, Box<dyn IndustrialDevice + Send>> = HashMap::new();
+                for DeviceConfig { name, device } in devices {
+                    if res.contains_key(&name) {
+                        return Err(DeviceInitError::DuplicateDevice { name });
+                    }
+                    let device = device.try_into().map_err(|err: DeviceInitError| err.named(&name))?;
+                    res.insert(name, device);
+                }
+                Ok(res)
+            }
+        }
+    }
+}
+
+fn default_connect_timeout() -> u64 {
+    5
 }
 
 #[derive(Deserialize, Debug)]
@@ -66,13 +398,237 @@ pub struct Remotes {
 /// a configuration file (e.g., TOML or YAML).
 ///
 /// # Fields
-/// - `devices`: All configured PLCs and field devices (`Devices`).
+/// - `devices`: All configured PLCs and field devices (`DevicesConfig`).
 /// - `remotes`: All configured remote data sinks (`Remotes`).
 /// - `period`: Collection period in milliseconds or seconds (depending on implementation).
 /// - `timeout`: Optional timeout (in milliseconds) for communication requests.
+/// - `connect_timeout`: Time (in seconds) given to a device to connect or reconnect (default 5).
+/// - `last_will`: Push `connected=0` for a device when it becomes unreachable, and
+///   `bridge_up=0` (measurement `bridge`) along with `connected=0` for every device on shutdown.
+/// - `otel`: Optional OpenTelemetry export of the bridge's own metrics and spans.
+/// - `tag_connection_info`: Tag every measurement with the connection of its device
+///   (`endpoint`, plus `slave` for Modbus RTU and `device_instance` for BACnet).
+/// - `runtime`: Flavor of the async runtime (`multi_thread` by default, `current_thread` to run on a single thread).
+/// - `worker_threads`: Number of worker threads of the `multi_thread` runtime (default: one per core).
+/// - `wal_path`: File of the write-ahead log, batches that were not pushed to every remote are replayed on startup.
+/// - `wal_max_batches`: Maximum number of batches kept in the write-ahead log, the oldest one is dropped first (default 1000).
+/// - `unix_socket`: Path of a Unix domain socket streaming every batch as a line of JSON to the local clients.
+/// - `missed_tick`: What the fetch timer does when a cycle took longer than the period (`skip` by default).
+/// - `watchdog_ms`: Hard bound of a device fetch, past which the device is built again from its configuration
+///   and reconnected (a last resort for the reads that ignore the `timeout`).
+/// - `max_dead_cycles`: Number of cycles in a row where every device failed after which the bridge exits
+///   with a non-zero code, so its supervisor starts it again (default: never exits, also when 0).
+/// - `definition_retry`: How the local definition files that can't be opened are tried again on startup.
+/// - `definition_cache_dir`: Directory the remote and converted definitions are cached in, created private
+///   (default: `$XDG_CACHE_HOME/industrial_bridge`).
+/// - `stagger`: Spread the polls of the devices evenly over the period instead of polling them all at once
+///   (the devices with an `initial_delay_ms` keep theirs).
+/// - `max_concurrent_pushes`: Maximum number of remotes pushed to at the same time, the others wait for
+///   a push to finish (default: all the remotes at once).
+/// - `heartbeat`: Liveness measurement pushed to every remote on its own interval (default: none).
+/// - `deadletter_path`: File the batches that no remote received are appended to, as JSON lines (default: dropped).
 pub struct AppConfig {
-    pub devices: Devices,
+    pub devices: DevicesConfig,
     pub remotes: Remotes,
     pub period: u64,
     pub timeout: Option<u64>,
+    #[serde(default = "default_connect_timeout")]
+    pub connect_timeout: u64,
+    #[serde(default)]
+    pub last_will: bool,
+    pub otel: Option<OtelConfig>,
+    #[serde(default)]
+    pub tag_connection_info: bool,
+    #[serde(default)]
+    pub runtime: RuntimeFlavor,
+    pub worker_threads: Option<usize>,
+    pub wal_path: Option<String>,
+    pub wal_max_batches: Option<usize>,
+    pub unix_socket: Option<String>,
+    #[serde(default)]
+    pub missed_tick: MissedTick,
+    pub watchdog_ms: Option<u64>,
+    pub max_dead_cycles: Option<u32>,
+    #[serde(default)]
+    pub definition_retry: DefinitionRetry,
+    pub definition_cache_dir: Option<String>,
+    #[serde(default)]
+    pub stagger: bool,
+    pub max_concurrent_pushes: Option<usize>,
+    pub heartbeat: Option<Heartbeat>,
+    pub deadletter_path: Option<String>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// What the fetch timer does with the cycles missed while a cycle took longer than the period
+pub enum MissedTick {
+    /// The missed cycles are run back to back to catch up, keeping the long-term rate
+    Burst,
+    /// The next cycle starts a full period after the late one, shifting the schedule
+    Delay,
+    /// The missed cycles are dropped, the next cycle starts on the next multiple of the period
+    #[default]
+    Skip,
+}
+
+impl From<MissedTick> for MissedTickBehavior {
+    fn from(value: MissedTick) -> Self {
+        match value {
+            MissedTick::Burst => MissedTickBehavior::Burst,
+            MissedTick::Delay => MissedTickBehavior::Delay,
+            MissedTick::Skip => MissedTickBehavior::Skip,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Flavor of the tokio runtime the bridge runs on
+pub enum RuntimeFlavor {
+    /// Everything runs on the main thread
+    CurrentThread,
+    /// Tasks are spread over a pool of worker threads
+    #[default]
+    MultiThread,
+}
+
+impl AppConfig {
+    /// Log the devices and remotes that were loaded and the main settings, to check the configuration at a glance
+    pub fn log_summary(&self) {
+        let devices = self.devices.names();
+        let remotes = self.remotes.names();
+        let count = |sections: &[(&str, Vec<String>)]| {
+            sections.iter().map(|(_, names)| names.len()).sum::<usize>()
+        };
+        info!("Loaded {} devices and {} remotes", count(&devices), count(&remotes));
+        for (kind, names) in &devices {
+            info!("  devices {kind} ({}) : {}", names.len(), names.join(", "));
+        }
+        for (kind, names) in &remotes {
+            info!("  remotes {kind} ({}) : {}", names.len(), names.join(", "));
+        }
+        let timeout = match self.timeout {
+            Some(timeout) => format!("{timeout}s"),
+            None => "none".to_string(),
+        };
+        info!(
+            "  period {}s ({:?} on missed ticks), timeout {timeout}, connect_timeout {}s",
+            self.period, self.missed_tick, self.connect_timeout
+        );
+        info!(
+            "  last_will {}, tag_connection_info {}, otel {}",
+            self.last_will,
+            self.tag_connection_info,
+            self.otel.is_some()
+        );
+    }
+
+    /// Build the runtime described by `runtime` and `worker_threads`
+    pub fn build_runtime(&self) -> std::io::Result<tokio::runtime::Runtime> {
+        let mut builder = match self.runtime {
+            RuntimeFlavor::CurrentThread => {
+                if self.worker_threads.is_some() {
+                    log::warn!("worker_threads is ignored by the current_thread runtime");
+                }
+                tokio::runtime::Builder::new_current_thread()
+            }
+            RuntimeFlavor::MultiThread => {
+                let mut builder = tokio::runtime::Builder::new_multi_thread();
+                if let Some(threads) = self.worker_threads {
+                    builder.worker_threads(threads);
+                }
+                builder
+            }
+        };
+        builder.enable_all().build()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::measurement::Measurement;
+    use crate::remotes::influxdb::{line_protocol, Precision};
+    use crate::types_conversion::RegisterValue;
+
+    #[test]
+    fn endpoint_tag_is_written_for_a_modbus_tcp_device() {
+        let devices: DevicesConfig = serde_json::from_value(serde_json::json!({
+            "modbus_tcp": {"press1": {"remote": "10.0.0.1:502"}},
+        }))
+        .unwrap();
+        let tags = devices.connection_info();
+
+        let mut measurement = Measurement::new(HashMap::from([(
+            "temperature".to_string(),
+            RegisterValue::Float(21.5),
+        )]));
+        measurement.tags.extend(tags["press1"].clone());
+        let line = line_protocol::line("press1", &measurement, &HashMap::new(), None, Precision::S)
+            .unwrap();
+        assert!(line.starts_with("press1,endpoint=10.0.0.1:502 temperature=21.5 "));
+    }
+
+    #[test]
+    fn rtu_device_is_tagged_with_its_port_and_slave() {
+        let devices: DevicesConfig = serde_json::from_value(serde_json::json!([{
+            "name": "meter1",
+            "type": "modbus_rtu",
+            "port": "/dev/ttyUSB0",
+            "slave": 7,
+            "speed": 9600,
+        }]))
+        .unwrap();
+        let tags = &devices.connection_info()["meter1"];
+        assert_eq!(tags["endpoint"], "/dev/ttyUSB0");
+        assert_eq!(tags["slave"], "7");
+    }
+
+    fn runtime_config(runtime: serde_json::Value) -> AppConfig {
+        let mut config = serde_json::json!({"devices": {}, "remotes": {}, "period": 1});
+        config
+            .as_object_mut()
+            .unwrap()
+            .extend(runtime.as_object().unwrap().clone());
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn runtime_has_the_configured_worker_threads() {
+        let config = runtime_config(serde_json::json!({"worker_threads": 2}));
+        assert_eq!(config.runtime, RuntimeFlavor::MultiThread);
+        let runtime = config.build_runtime().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 2);
+
+        let config = runtime_config(serde_json::json!({"runtime": "current_thread"}));
+        let runtime = config.build_runtime().unwrap();
+        assert_eq!(runtime.metrics().num_workers(), 1);
+    }
+
+    #[test]
+    fn device_with_a_conflicting_type_fails_the_startup() {
+        let dir = tempfile::tempdir().unwrap();
+        let registers = dir.path().join("plc1.json");
+        let definition = r#"{"temperature": {"area": "dm", "address": 100, "type": "u16"}}"#;
+        std::fs::write(&registers, definition).unwrap();
+        let devices = |expected: &str| -> DevicesConfig {
+            serde_json::from_value(serde_json::json!({"fins": {"plc1": {
+                "remote": "10.0.0.2:9600",
+                "node": 1,
+                "source_node": 10,
+                "registers": registers,
+                "types": {"temperature": expected},
+            }}}))
+            .unwrap()
+        };
+
+        devices("u16").check_types().unwrap();
+        let err = devices("f32").check_types().unwrap_err();
+        assert!(matches!(
+            &err,
+            DeviceInitError::Device { name, err }
+                if name == "plc1" && matches!(**err, DeviceInitError::TypeMismatch { .. })
+        ));
+    }
 }
@@ -0,0 +1,275 @@
+use serde_json::Value;
+use thiserror::Error;
+
+use super::AppConfig;
+
+#[derive(Debug, Error)]
+/// Errors while loading and merging the configuration files
+pub enum ConfigLoadError {
+    #[error("Could not read {file} ({err})")]
+    ReadFailed {
+        file: String,
+        #[source]
+        err: config::ConfigError,
+    },
+    #[error("Could not list the config directory {dir} ({err})")]
+    ReadDirFailed {
+        dir: String,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("{path} is defined in several files (again in {file})")]
+    DuplicateEntry { path: String, file: String },
+    #[error(
+        "The devices of {file} are not in the same form (list or map) as in the previous files"
+    )]
+    MixedDevicesForms { file: String },
+    #[error("Invalid configuration ({err})")]
+    InvalidConfig {
+        #[source]
+        err: serde_json::Error,
+    },
+}
+
+/// Named devices or remotes (`devices.<type>.<name>` or `remotes.<type>.<name>`, e.g. `remotes.influx_db.local`),
+/// which can't be redefined by a later file, while the maps of a type are unioned
+fn is_named_entry(path: &str) -> bool {
+    let parts: Vec<&str> = path.split('.').collect();
+    parts.len() == 3 && (parts[0] == "devices" || parts[0] == "remotes")
+}
+
+/// Merge the config `other` into `base`, scalars of `other` override the ones of `base`,
+/// maps are merged and device lists are concatenated
+///
+/// # Arguments
+///
+/// - `base` (`&mut Value`) - The config loaded so far
+/// - `other` (`Value`) - The config of the next file
+/// - `path` (`&str`) - The path of the values in the config (empty at the root)
+/// - `file` (`&str`) - The file `other` comes from (for the errors)
+fn merge(base: &mut Value, other: Value, path: &str, file: &str) -> Result<(), ConfigLoadError> {
+    match (base, other) {
+        (Value::Object(base), Value::Object(other)) => {
+            for (key, value) in other {
+                let child_path = match path.is_empty() {
+                    true => key.clone(),
+                    false => format!("{path}.{key}"),
+                };
+                match base.get_mut(&key) {
+                    Some(_) if is_named_entry(&child_path) => {
+                        return Err(ConfigLoadError::DuplicateEntry {
+                            path: child_path,
+                            file: file.to_string(),
+                        })
+                    }
+                    Some(existing) => merge(existing, value, &child_path, file)?,
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+        }
+        (Value::Array(base), Value::Array(other)) if path == "devices" => base.extend(other),
+        (Value::Array(_), Value::Object(_)) | (Value::Object(_), Value::Array(_))
+            if path == "devices" =>
+        {
+            return Err(ConfigLoadError::MixedDevicesForms {
+                file: file.to_string(),
+            })
+        }
+        (base, other) => *base = other,
+    }
+    Ok(())
+}
+
+/// The config files of a directory (`.yaml`, `.yml`, `.toml`), sorted by name
+pub fn config_dir_files(dir: &str) -> Result<Vec<String>, ConfigLoadError> {
+    let read_dir_failed = |err| ConfigLoadError::ReadDirFailed {
+        dir: dir.to_string(),
+        err,
+    };
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).map_err(read_dir_failed)? {
+        let path = entry.map_err(read_dir_failed)?.path();
+        let is_config = path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .is_some_and(|ext| ["yaml", "yml", "toml"].contains(&ext));
+        if is_config {
+            files.push(path.to_string_lossy().to_string());
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+/// Load and merge the config files, in order, into the application config.
+/// Later files override the scalar values of the previous ones, while the devices and
+/// remotes of each type are unioned (the same `<type>.<name>` defined in two files is an error).
+///
+/// # Arguments
+///
+/// - `files` (`&[String]`) - The config files
+///
+/// # Returns
+///
+/// - `Result<AppConfig, ConfigLoadError>` - The merged config
+pub fn load_config(files: &[String]) -> Result<AppConfig, ConfigLoadError> {
+    let mut merged = Value::Object(Default::default());
+    for file in files {
+        let read_failed = |err| ConfigLoadError::ReadFailed {
+            file: file.clone(),
+            err,
+        };
+        let value: Value = config::Config::builder()
+            .add_source(config::File::with_name(file))
+            .build()
+            .map_err(read_failed)?
+            .try_deserialize()
+            .map_err(read_failed)?;
+        merge(&mut merged, value, "", file)?;
+    }
+    serde_json::from_value(merged).map_err(|err| ConfigLoadError::InvalidConfig { err })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    /// Write the config files to `dir`, returning their paths in order
+    fn write_files(dir: &tempfile::TempDir, files: &[(&str, &str)]) -> Vec<String> {
+        files
+            .iter()
+            .map(|(name, content)| {
+                let path = dir.path().join(name);
+                std::fs::write(&path, content).unwrap();
+                path.to_string_lossy().to_string()
+            })
+            .collect()
+    }
+
+    const BASE: &str = r#"
+period: 10
+devices:
+  modbus_tcp:
+    press1:
+      remote: "10.0.0.1:502"
+remotes:
+  influx_db:
+    local:
+      remote: "http://localhost:8086"
+      bucket: "plant"
+      token: "TOKEN"
+"#;
+
+    /// The names of the devices of a type in the merged config
+    fn device_names(app: &AppConfig, kind: &str) -> Vec<String> {
+        let names = app
+            .devices
+            .names()
+            .into_iter()
+            .find(|(name, _)| *name == kind);
+        names.map(|(_, names)| names).unwrap_or_default()
+    }
+
+    #[test]
+    fn override_file_overrides_scalars_and_adds_devices() {
+        let dir = tempfile::tempdir().unwrap();
+        let site = r#"
+period: 5
+devices:
+  modbus_tcp:
+    press2:
+      remote: "10.0.0.2:502"
+remotes:
+  influx_db:
+    cloud:
+      remote: "https://cloud.example.com"
+      bucket: "plant"
+      token: "TOKEN"
+"#;
+        let files = write_files(&dir, &[("base.yaml", BASE), ("site.yaml", site)]);
+        let app = load_config(&files).unwrap();
+        assert_eq!(app.period, 5);
+        assert_eq!(device_names(&app, "modbus_tcp"), ["press1", "press2"]);
+        let mut remotes: Vec<&String> = app.remotes.influx_db.as_ref().unwrap().keys().collect();
+        remotes.sort();
+        assert_eq!(remotes, ["cloud", "local"]);
+    }
+
+    #[test]
+    fn redefined_device_is_an_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let site = r#"
+devices:
+  modbus_tcp:
+    press1:
+      remote: "10.0.0.2:502"
+"#;
+        let files = write_files(&dir, &[("base.yaml", BASE), ("site.yaml", site)]);
+        assert!(matches!(
+            load_config(&files),
+            Err(ConfigLoadError::DuplicateEntry { path, .. }) if path == "devices.modbus_tcp.press1"
+        ));
+    }
+
+    #[test]
+    fn device_lists_are_concatenated() {
+        let mut config = json!({"devices": [{"name": "press1"}]});
+        merge(
+            &mut config,
+            json!({"devices": [{"name": "press2"}]}),
+            "",
+            "site.yaml",
+        )
+        .unwrap();
+        assert_eq!(config["devices"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn mixed_devices_forms_are_an_error() {
+        let mut config = json!({"devices": [{"name": "press1"}]});
+        let site = json!({"devices": {"press2": {}}});
+        assert!(matches!(
+            merge(&mut config, site, "", "site.yaml"),
+            Err(ConfigLoadError::MixedDevicesForms { .. })
+        ));
+    }
+
+    #[test]
+    fn config_dir_files_are_merged_per_device_type() {
+        let dir = tempfile::tempdir().unwrap();
+        let line2 = r#"
+devices:
+  modbus_tcp:
+    press3:
+      remote: "10.0.0.3:502"
+remotes:
+  influx_db:
+    line2:
+      remote: "http://10.0.1.5:8086"
+      bucket: "line2"
+      token: "TOKEN"
+"#;
+        write_files(
+            &dir,
+            &[
+                ("20-line2.yaml", line2),
+                ("10-base.yaml", BASE),
+                ("notes.txt", ""),
+            ],
+        );
+        let files = config_dir_files(dir.path().to_str().unwrap()).unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files[0].ends_with("10-base.yaml"));
+
+        let app = load_config(&files).unwrap();
+        assert_eq!(app.period, 10);
+        assert_eq!(device_names(&app, "modbus_tcp"), ["press1", "press3"]);
+        let mut remotes: Vec<&String> = app.remotes.influx_db.as_ref().unwrap().keys().collect();
+        remotes.sort();
+        assert_eq!(remotes, ["line2", "local"]);
+    }
+}
@@ -2,15 +2,193 @@ use std::{cell::RefCell, collections::HashMap, rc::Rc, sync::Arc, time::Duration
 
 use industrial_device::errors::IndustrialDeviceError;
 use industrial_device::IndustrialDevice;
-use log::{error, info, warn};
-use tokio::{sync::Mutex, task::JoinSet, time::timeout};
+use log::{error, info, Level};
+use serde::Deserialize;
+use tokio::{
+    sync::Mutex,
+    task::JoinSet,
+    time::{sleep, sleep_until, timeout, Instant},
+};
 
-use crate::types_conversion::{convert_hashmap, RegisterValue};
+use crate::measurement::Measurement;
+use crate::telemetry;
+use crate::types_conversion::{
+    convert_hashmap, encode_bytes, integer_values, round_values, BytesEncoding, RegisterValue,
+};
+use clamping::{clamp_values, Clamp};
+use conditions::{filter_values, Condition, Trigger};
+use decoding::{apply_encodings, decode_values, Decode, SignedEncoding};
+use errors::{ErrorKind, FetchError};
+use polling::PollState;
+use proxy::Proxy;
+use sampling::{aggregate, Aggregation, Sampling};
 
+pub mod bacnet;
+pub mod chunking;
+pub mod clamping;
+pub mod conditions;
+pub mod decoding;
+pub mod definitions;
+pub mod endpoint;
 pub mod errors;
+pub mod fins;
+pub mod http_auth;
+pub mod log_throttle;
+pub mod modbus_exceptions;
 pub mod modbus_rtu;
 pub mod modbus_tcp;
+pub mod polling;
+pub mod probe;
+pub mod proxy;
 pub mod s7;
+pub mod sampling;
+pub mod suggestions;
+
+/// Number of timed out cycles in a row after which the `timeout` is reported as too low, by default
+const DEFAULT_TIMEOUT_WARNING_CYCLES: u32 = 3;
+
+#[derive(Deserialize, Debug, Clone, Default)]
+/// Options shared by every device, flattened into each device configuration.
+///
+/// # Fields
+/// - `timestamp_field`: Register holding the device time (Unix timestamp in seconds),
+///   used as the measurement timestamp instead of the local time and removed from the pushed values.
+/// - `samples`: Number of reads spread over the period, combined into the single pushed value.
+/// - `aggregation`: How the samples of numeric registers are combined (`mean`, `min` or `max`, default `mean`),
+///   other registers take the last sample.
+/// - `sample_stats`: Also push the minimum, maximum and mean of the samples of the numeric registers,
+///   as `{field}_min`, `{field}_max` and `{field}_mean` (default `false`).
+/// - `poll_every`: Registers only read every Nth cycle, keyed by register name (e.g. slowly-changing setpoints).
+/// - `age_fields`: Push the last known value of every register with a `{field}_age_seconds` companion field,
+///   so stale values (skipped registers, failed cycles) can be told apart from fresh ones (forever unless `value_ttl` is set).
+/// - `value_ttl`: Keep forwarding the last known value of the registers (skipped registers, failed cycles)
+///   until it is older than this number of seconds.
+/// - `decode`: Values spanning several registers (64 bits numbers, strings), keyed by the name of the decoded value.
+/// - `clamp`: Engineering bounds of the registers, keyed by register name, out of range values are clamped or dropped.
+/// - `bus`: Name of the bus the device is on, the devices sharing a bus are read one after the other
+///   (defaults to the serial port for Modbus RTU devices).
+/// - `reconnect_on`: Kinds of read errors after which the bridge reconnects to the device
+///   (default `not_accessible` and `not_connected`), the other errors only skip the cycle.
+/// - `chunk_size`: Read the registers of a Modbus device in chunks of at most this many contiguous registers,
+///   a failing chunk is skipped instead of failing the whole read (default: the device reads all its registers at once).
+/// - `conditions`: Expressions over the registers of the device, keyed by register name,
+///   the register is only pushed on the cycles where its expression holds.
+/// - `failure_threshold`: Number of failed cycles in a row after which an error is logged for the device
+///   (default: not logged, the count is exported as `bridge.device.consecutive_failures` with OpenTelemetry).
+/// - `bytes_encoding`: How the byte arrays read are turned into text (`hex`, `base64`, `ascii` or `utf8`, default `hex`).
+/// - `encoding`: How the signed integers are encoded in the registers (`twos_complement`, `sign_magnitude` or `offset_binary`),
+///   keyed by register name, applied after `decode` and before `clamp` (default: as read).
+/// - `priority`: Priority of the registers, keyed by register name (default 0). Once the registers are known (after
+///   the first read), they are read one by one, highest priority first, and a timeout only drops the ones not read yet.
+/// - `trigger`: Register read alone on every cycle, the other registers are only read on the cycles where
+///   its condition holds (default: all registers read on every cycle).
+/// - `proxy`: Proxy the TCP connection to the device is routed through (Modbus TCP and S7 only, default: direct connection).
+/// - `decimals`: Number of decimals the floating point values are rounded to, keyed by register name,
+///   applied last (after the aggregation of the samples) (default: as read).
+/// - `initial_delay_ms`: Delay of the polls of the device after the start of each cycle, to keep the devices
+///   sharing a bus or a gateway from all being polled at once (default: polled at the start of the cycle).
+/// - `tags`: Tags added to every measurement of the device (e.g. `critical: "true"`), remotes can select
+///   the devices they receive by these tags with `device_filter`.
+/// - `timeout_warning_cycles`: Number of cycles in a row ending in a timeout after which a warning suggests
+///   that the `timeout` is too low for the device (default 3, never warned when 0).
+/// - `reconnect_every`: Reconnect the device at the start of every Nth cycle before reading it (1 for every cycle),
+///   for the devices whose connection silently goes stale (default: the connection is kept until an error).
+/// - `failure_grace_cycles`: Number of failed cycles in a row before a read error triggers a reconnection
+///   (see `reconnect_on`), the errors of the previous cycles only skip them (default 1: reconnected on the first error).
+/// - `units`: Unit of the registers, keyed by register name (e.g. `°C`, `kW`, `rpm`), pushed as metadata
+///   by the remotes that can represent it (default: no unit).
+/// - `as_integer`: Registers pushed as integers, rounded after `decimals` (e.g. counts that a scaling turned into floats),
+///   as `IntGauge` to the Prometheus pushgateway and as integer fields to InfluxDB (default: as read).
+/// - `definition_pointer`: JSON Pointer to the registers within the definition files (e.g. `/registers` for
+///   `{"registers": {...}}`), for the files wrapping them in an object (default: the whole file).
+/// - `types`: Expected type of registers, keyed by register name, checked against the definition at startup
+///   (as named by the `type` field of the definition, `object_type` for BACnet) so a definition drifting fails early.
+pub struct DeviceOptions {
+    pub timestamp_field: Option<String>,
+    pub samples: Option<u32>,
+    #[serde(default)]
+    pub aggregation: Aggregation,
+    #[serde(default)]
+    pub sample_stats: bool,
+    #[serde(default)]
+    pub poll_every: HashMap<String, u32>,
+    #[serde(default)]
+    pub age_fields: bool,
+    pub value_ttl: Option<u64>,
+    #[serde(default)]
+    pub decode: HashMap<String, Decode>,
+    #[serde(default)]
+    pub clamp: HashMap<String, Clamp>,
+    pub bus: Option<String>,
+    pub reconnect_on: Option<Vec<ErrorKind>>,
+    pub chunk_size: Option<u16>,
+    pub failure_threshold: Option<u32>,
+    pub failure_grace_cycles: Option<u32>,
+    #[serde(default)]
+    pub conditions: HashMap<String, Condition>,
+    #[serde(default)]
+    pub bytes_encoding: BytesEncoding,
+    #[serde(default)]
+    pub encoding: HashMap<String, SignedEncoding>,
+    #[serde(default)]
+    pub priority: HashMap<String, i32>,
+    pub trigger: Option<Trigger>,
+    pub proxy: Option<Proxy>,
+    #[serde(default)]
+    pub decimals: HashMap<String, u8>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    pub initial_delay_ms: Option<u64>,
+    #[serde(default)]
+    pub units: HashMap<String, String>,
+    pub timeout_warning_cycles: Option<u32>,
+    pub reconnect_every: Option<u32>,
+    #[serde(default)]
+    pub as_integer: Vec<String>,
+    pub definition_pointer: Option<String>,
+    #[serde(default)]
+    pub types: HashMap<String, String>,
+}
+
+impl DeviceOptions {
+    /// The kinds of errors triggering a reconnection
+    pub fn reconnect_on(&self) -> &[ErrorKind] {
+        match &self.reconnect_on {
+            Some(kinds) => kinds,
+            None => ErrorKind::DEFAULT_RECONNECT,
+        }
+    }
+
+    /// The number of timed out cycles in a row after which the `timeout` is reported as too low
+    pub fn timeout_warning_cycles(&self) -> u32 {
+        self.timeout_warning_cycles.unwrap_or(DEFAULT_TIMEOUT_WARNING_CYCLES)
+    }
+}
+
+/// Connect a device, giving up once `connect_timeout` elapsed (e.g. an unreachable host)
+///
+/// # Arguments
+///
+/// - `device` (`&mut T`) - The device to connect
+/// - `connect_timeout` (`Duration`) - The time after which the device is considered not accessible
+///
+/// # Returns
+///
+/// - `Result<(), IndustrialDeviceError>` - `DeviceNotAccessibleError` if the timeout elapsed
+async fn connect_device<T: IndustrialDevice + ?Sized>(
+    device: &mut T,
+    connect_timeout: Duration,
+) -> Result<(), IndustrialDeviceError> {
+    match timeout(connect_timeout, device.connect()).await {
+        Ok(res) => res,
+        Err(_err) => Err(IndustrialDeviceError::DeviceNotAccessibleError {
+            err: Box::new(std::io::Error::new(
+                std::io::ErrorKind::TimedOut,
+                format!("connection timed out after {connect_timeout:?}"),
+            )),
+        }),
+    }
+}
 
 /// Connect all devices passed as arguments to their targets, panics on error (this should then only be used in the initialisation)
 /// The connection for all devices is realized in parallel
@@ -18,8 +196,10 @@ pub mod s7;
 /// # Arguments
 /// 
 /// - `devices` (`Rc<RefCell<HashMap<String, Arc<Mutex<Box<T>>>>>>`) - All the devices
+/// - `connect_timeout` (`Duration`) - The time given to each device to connect
 pub async fn connect_devices<T: IndustrialDevice + Send + 'static + ?Sized>(
     devices: Rc<RefCell<HashMap<String, Arc<Mutex<Box<T>>>>>>,
+    connect_timeout: Duration,
 ) {
     // Create a task for each target
     let mut set = JoinSet::new();
@@ -29,7 +209,7 @@ pub async fn connect_devices<T: IndustrialDevice + Send + 'static + ?Sized>(
         set.spawn(async move {
             let dc = d.clone();
             let mut dm = dc.lock().await;
-            let res = dm.connect().await;
+            let res = connect_device(dm.as_mut(), connect_timeout).await;
             (name, res)
         });
     }
@@ -49,108 +229,1109 @@ pub async fn connect_devices<T: IndustrialDevice + Send + 'static + ?Sized>(
     .await;
 }
 
-/// Manage errors occuring on a modbus data read, try to reconnect if a BrokenPipe is detected
+/// Manage errors occuring on a modbus data read, try to reconnect if the kind of the error is in `reconnect_on`
+/// Repeated errors of a device are collapsed in the logs
 /// # Arguments
 /// 
+/// - `device_name` (`&str`) - the name of the device (for logging)
 /// - `err` (`IndustrialDeviceError`) - The error we whant to treat
 /// - `device` (`Arc<Mutex<Box<impl IndustrialDevice + ?Sized>>>`) - the device where there is the error
+/// - `known` (`&[String]`) - the registers known on the device, used to suggest a name when one is not found
+/// - `reconnect_on` (`&[ErrorKind]`) - the kinds of errors triggering a reconnection
+/// - `connect_timeout` (`Duration`) - the time given to the device to reconnect
 /// 
 /// # Returns
 /// 
 /// - `Result<(), IndustrialDeviceError>` - Describe the return value.
 /// 
 async fn manage_errors(
+    device_name: &str,
     err: IndustrialDeviceError,
     device: Arc<Mutex<Box<impl IndustrialDevice + ?Sized>>>,
+    known: &[String],
+    reconnect_on: &[ErrorKind],
+    connect_timeout: Duration,
 ) -> Result<(), IndustrialDeviceError> {
+    if reconnect_on.contains(&ErrorKind::from(&err)) {
+        log_throttle::log(
+            device_name,
+            Level::Error,
+            format!(
+                "Error while reading registers, reconnecting to device ({err}){}",
+                explain(&err)
+            ),
+        );
+        let connection_res = connect_device(device.lock().await.as_mut(), connect_timeout).await;
+        return match connection_res {
+            Ok(_res) => {
+                log_throttle::reset(device_name);
+                info!("Reconnexion successful !");
+                Ok(())
+            }
+            Err(err) => {
+                log_throttle::log(device_name, Level::Error, format!("Reconnexion failed ({err:?})"));
+                Err(err.into())
+            }
+        };
+    }
     match err {
-        IndustrialDeviceError::DeviceNotAccessibleError { err }
-        | IndustrialDeviceError::DeviceNotConnectedError { err } => {
-            error!("Device not accessible while reading register reconnecting to device ({err})");
-            let connection_res = device.lock().await.connect().await;
-            return match connection_res {
-                Ok(_res) => {
-                    info!("Reconnexion successful !");
-                    Ok(())
-                }
-                Err(err) => {
-                    error!("Reconnexion failed ({err:?})");
-                    Err(err.into())
-                }
-            };
+        IndustrialDeviceError::DeviceNotAccessibleError { err: _ }
+        | IndustrialDeviceError::DeviceNotConnectedError { err: _ } => {
+            log_throttle::log(
+                device_name,
+                Level::Error,
+                format!("Device not accessible while reading registers, skipping this run ({err})"),
+            );
+            return Err(err);
         }
         IndustrialDeviceError::RequestError { err: _ }
         | IndustrialDeviceError::ConversionError { err: _ } => {
-            error!("Error reading registers, skipping this run ({err:?})");
+            log_throttle::log(
+                device_name,
+                Level::Error,
+                format!(
+                    "Error reading registers, skipping this run ({err:?}){}",
+                    explain(&err)
+                ),
+            );
             return Err(err);
         }
         IndustrialDeviceError::RegisterNotFoundError { ref name } => {
-            error!("Register not found : ({name})");
+            let suggestions = suggestions::closest(name, known, 3);
+            let message = match suggestions.is_empty() {
+                true => format!("Register not found : ({name}) ({} registers available)", known.len()),
+                false => format!(
+                    "Register not found : ({name}), did you mean {} ? ({} registers available)",
+                    suggestions.iter().map(|s| s.as_str()).collect::<Vec<_>>().join(", "),
+                    known.len()
+                ),
+            };
+            log_throttle::log(device_name, Level::Error, message);
             return Err(err);
         }
         IndustrialDeviceError::WrongValueType { ref val } => {
-            error!("Wrong value type : ({val})");
+            log_throttle::log(device_name, Level::Error, format!("Wrong value type : ({val})"));
             return Err(err);
         }
     };
 }
 
-/// For all the devices passed, dump all registers and returns it as a HashMap<device_name, HashMap<register_name, register_value>>
+/// What to read on a device during a cycle and how to check it
+///
+/// # Fields
+/// - `registers`: The registers to read, all of them if `None`.
+/// - `known`: The registers known on the device, for the error messages.
+/// - `bus`: The lock of the bus the device is on, held during each read.
+/// - `options`: The options of the device.
+/// - `connect_timeout`: The time given to the device to reconnect after an error.
+/// - `reconnect`: Whether a read error may trigger a reconnection (the device is past its `failure_grace_cycles`).
+struct ReadRequest<'a> {
+    registers: Option<&'a [String]>,
+    known: &'a [String],
+    bus: Option<&'a Mutex<()>>,
+    options: &'a DeviceOptions,
+    connect_timeout: Duration,
+    reconnect: bool,
+}
+
+/// Values read on a device
+///
+/// # Fields
+/// - `values`: The values, after decoding and clamping.
+/// - `registers`: The registers the values were read from.
+struct ReadResult {
+    values: HashMap<String, RegisterValue>,
+    registers: Vec<String>,
+}
+
+/// The registers in reading order, highest priority first (registers without priority count as 0)
+fn by_priority(registers: &[String], priority: &HashMap<String, i32>) -> Vec<String> {
+    let mut ordered = registers.to_vec();
+    ordered.sort_by_key(|register| std::cmp::Reverse(priority.get(register).copied().unwrap_or(0)));
+    ordered
+}
+
+/// Send the read requests of a device, within the timeout
+/// With `priority` set, the registers are read one by one in priority order once they are known,
+/// and the registers read before the timeout are kept
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - the name of the device (for logging)
+/// - `device` (`&Mutex<Box<T>>`) - the device to read
+/// - `request` (`&ReadRequest`) - the registers to read, all of them if `None`, and their priority
+/// - `timeout_duration` (`Duration`) - the time where we concider that we can't access to the data
+///
+/// # Returns
+///
+/// - `Result<Result<HashMap<String, Value>, IndustrialDeviceError>, FetchError>` the answer of the device,
+///   or `FetchError::Timeout` if it did not answer in time
+async fn read_registers<T: IndustrialDevice + Send + ?Sized>(
+    name: &str,
+    device: &Mutex<Box<T>>,
+    request: &ReadRequest<'_>,
+    timeout_duration: Duration,
+) -> Result<Result<HashMap<String, industrial_device::types::Value>, IndustrialDeviceError>, FetchError> {
+    let priority = &request.options.priority;
+    let registers = match (priority.is_empty(), request.registers) {
+        (true, registers) => registers.map(|registers| registers.to_vec()),
+        (false, Some(registers)) => Some(by_priority(registers, priority)),
+        (false, None) if !request.known.is_empty() => Some(by_priority(request.known, priority)),
+        (false, None) => None,
+    };
+    let mut values = HashMap::new();
+    let read = async {
+        let mut device = device.lock().await;
+        match &registers {
+            None => device.dump_registers().await,
+            Some(registers) => {
+                for register in registers {
+                    let value = device.read_register_by_name(register).await?;
+                    values.insert(register.clone(), value);
+                }
+                Ok(std::mem::take(&mut values))
+            }
+        }
+    };
+    match timeout(timeout_duration, read).await {
+        Ok(res) => Ok(res),
+        Err(_err) if !priority.is_empty() && !values.is_empty() => {
+            log_throttle::log(
+                name,
+                Level::Warn,
+                format!(
+                    "Timeout reached while fetching, keeping the {} registers of highest priority read",
+                    values.len()
+                ),
+            );
+            Ok(Ok(values))
+        }
+        Err(_err) => {
+            log_throttle::log(
+                name,
+                Level::Warn,
+                "Timeout reached while fetching, skipping this run".to_string(),
+            );
+            Err(FetchError::Timeout {})
+        }
+    }
+}
+
+/// The meaning of the Modbus exception behind a read error, to be appended to its log (empty for the other errors)
+fn explain(err: &IndustrialDeviceError) -> String {
+    match modbus_exceptions::describe(err) {
+        Some(exception) => format!(" - {exception}"),
+        None => String::new(),
+    }
+}
+
+/// Why a device could not be read, from the error of its last read
+fn fetch_error(err: &IndustrialDeviceError) -> FetchError {
+    match err {
+        IndustrialDeviceError::DeviceNotAccessibleError { err: _ }
+        | IndustrialDeviceError::DeviceNotConnectedError { err: _ } => FetchError::Disconnected {},
+        _ => FetchError::ReadFailed {},
+    }
+}
+
+/// Connect a device again before reading it, replacing a connection that may have silently gone stale
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - the name of the device (for logging)
+/// - `device` (`&Mutex<Box<T>>`) - the device to reconnect
+/// - `request` (`&ReadRequest`) - the bus the device is on and the time given to connect
+///
+/// # Returns
+///
+/// - `Result<(), FetchError>` - `Ok` once reconnected, or why the device could not be reconnected
+async fn refresh_connection<T: IndustrialDevice + Send + ?Sized>(
+    name: &str,
+    device: &Mutex<Box<T>>,
+    request: &ReadRequest<'_>,
+) -> Result<(), FetchError> {
+    let _bus = match request.bus {
+        Some(bus) => Some(bus.lock().await),
+        None => None,
+    };
+    info!("Reconnecting to {name} before reading it");
+    let res = connect_device(device.lock().await.as_mut(), request.connect_timeout).await;
+    res.map_err(|err| {
+        log_throttle::log(
+            name,
+            Level::Error,
+            format!("Could not reconnect to the device before reading it ({err})"),
+        );
+        fetch_error(&err)
+    })
+}
+
+/// Read the registers of a device once, calls manage_error on error to try to reconnect
+/// and reads the device again once reconnected, so the cycle is not lost
+/// The values spanning several registers are decoded, then the engineering bounds of the registers are applied,
+/// then the registers whose condition does not hold are removed
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - the name of the device (for logging)
+/// - `device` (`Arc<Mutex<Box<T>>>`) - the device to read
+/// - `request` (`&ReadRequest`) - the registers to read and how
+/// - `timeout_duration` (`Duration`) - the time where we concider that we can't access to the data
+///
+/// # Returns
+///
+/// - `Result<ReadResult, FetchError>` the register values, or why they could not be read
+async fn read_device<T: IndustrialDevice + Send + ?Sized>(
+    name: &str,
+    device: Arc<Mutex<Box<T>>>,
+    request: &ReadRequest<'_>,
+    timeout_duration: Duration,
+) -> Result<ReadResult, FetchError> {
+    // Wait for the other devices on the bus before starting the timeout
+    let _bus = match request.bus {
+        Some(bus) => Some(bus.lock().await),
+        None => None,
+    };
+    info!("Fetching registers from {name}");
+    let mut data_input = read_registers(name, &device, request, timeout_duration).await?;
+
+    if let Err(err) = data_input {
+        // Within the failure grace, the errors only skip the cycle
+        let reconnect_on = match request.reconnect {
+            true => request.options.reconnect_on(),
+            false => &[],
+        };
+        match manage_errors(
+            name,
+            err,
+            device.clone(),
+            request.known,
+            reconnect_on,
+            request.connect_timeout,
+        )
+        .await
+        {
+            Ok(()) => {
+                info!("Fetching registers from {name} again after the reconnection");
+                data_input = read_registers(name, &device, request, timeout_duration).await?;
+            }
+            Err(err) => return Err(fetch_error(&err)),
+        }
+    }
+
+    match data_input {
+        Ok(val) => {
+            log_throttle::reset(name);
+            let mut values = convert_hashmap(val);
+            let registers = values.keys().cloned().collect();
+            decode_values(name, &mut values, &request.options.decode);
+            apply_encodings(name, &mut values, &request.options.encoding);
+            clamp_values(name, &mut values, &request.options.clamp);
+            encode_bytes(&mut values, request.options.bytes_encoding);
+            filter_values(name, &mut values, &request.options.conditions);
+            Ok(ReadResult { values, registers })
+        }
+        // Only one reconnection per cycle
+        Err(err) => {
+            log_throttle::log(
+                name,
+                Level::Error,
+                format!(
+                    "Error reading registers after the reconnection, skipping this run ({err}){}",
+                    explain(&err)
+                ),
+            );
+            Err(fetch_error(&err))
+        }
+    }
+}
+
+/// Read a device `sampling.samples` times, `sampling.interval` apart, and aggregate the successful reads
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - the name of the device (for logging)
+/// - `device` (`Arc<Mutex<Box<T>>>`) - the device to read
+/// - `request` (`&ReadRequest`) - the registers to read and how
+/// - `timeout_duration` (`Duration`) - the timeout of each read
+/// - `sampling` (`Sampling`) - the number of reads, their interval and how they are combined
+///
+/// # Returns
+///
+/// - `Result<ReadResult, FetchError>` the aggregated values, or the last error if no read succeeded
+async fn sample_device<T: IndustrialDevice + Send + ?Sized>(
+    name: &str,
+    device: Arc<Mutex<Box<T>>>,
+    request: &ReadRequest<'_>,
+    timeout_duration: Duration,
+    sampling: Sampling,
+) -> Result<ReadResult, FetchError> {
+    let start = Instant::now();
+    let mut reads = Vec::new();
+    let mut registers = Vec::new();
+    let mut last_err = FetchError::ReadFailed {};
+    for i in 0..sampling.samples {
+        sleep_until(start + sampling.interval * i).await;
+        match read_device(name, device.clone(), request, timeout_duration).await {
+            Ok(read) => {
+                reads.push(read.values);
+                registers = read.registers;
+            }
+            Err(err) => last_err = err,
+        }
+    }
+    match reads.is_empty() {
+        true => Err(last_err),
+        false => Ok(ReadResult {
+            values: aggregate(reads, sampling.aggregation, sampling.stats),
+            registers,
+        }),
+    }
+}
+
+/// Read the trigger register of a device alone and check whether it triggers the full read
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - the name of the device (for logging)
+/// - `device` (`Arc<Mutex<Box<T>>>`) - the device to read
+/// - `request` (`&ReadRequest`) - how to read the device
+/// - `timeout_duration` (`Duration`) - the time where we concider that we can't access to the data
+/// - `trigger` (`&Trigger`) - the trigger register and its condition
+///
+/// # Returns
+///
+/// - `Result<Option<ReadResult>, FetchError>` the value of the trigger register if the condition does not hold,
+///   `None` if the full read is triggered
+async fn check_trigger<T: IndustrialDevice + Send + ?Sized>(
+    name: &str,
+    device: Arc<Mutex<Box<T>>>,
+    request: &ReadRequest<'_>,
+    timeout_duration: Duration,
+    trigger: &Trigger,
+) -> Result<Option<ReadResult>, FetchError> {
+    let trigger_request = ReadRequest {
+        registers: Some(std::slice::from_ref(&trigger.register)),
+        ..*request
+    };
+    let read = read_device(name, device, &trigger_request, timeout_duration).await?;
+    match trigger.fires(name, &read.values) {
+        true => Ok(None),
+        false => {
+            info!("Trigger of {name} not set, only its trigger register was read");
+            Ok(Some(read))
+        }
+    }
+}
+
+/// For all the devices passed, dump all registers and returns it as a HashMap<device_name, Measurement>
 /// Calls manage_error on error to try to reconnect
-/// The data fetch if realized in parallel for each target
+/// The data fetch if realized in parallel for each target, except for the devices sharing a bus which are read one after the other
+/// Devices with `samples` set are read several times over the period and their values aggregated
+/// Registers with `poll_every` set are skipped on the cycles where they are not due
+/// With a `watchdog`, the devices whose task did not end within it are reported as stuck
+/// Devices with a `trigger` set are only fully read on the cycles where the trigger register holds its condition,
+/// the trigger register alone is pushed on the other cycles
+/// Devices with an `initial_delay_ms` set are read this long after the start of the cycle
 /// 
 /// # Arguments
 /// 
 /// - `devices` (`Rc<RefCell<HashMap<String, Arc<Mutex<Box<T>>>>>>`) - the list of device
+/// - `options` (`&HashMap<String, DeviceOptions>`) - the options of each device
+/// - `polls` (`&mut HashMap<String, PollState>`) - the polling state of each device, updated for the next cycle
+/// - `period` (`Duration`) - the fetch period, over which the samples are spread
 /// - `timeout_duration` (`Duration`) - the time where we concider that we can't access to the data
+/// - `connect_timeout` (`Duration`) - the time given to a device to reconnect after an error
+/// - `watchdog` (`Option<Duration>`) - the hard bound of the fetch, the tasks still running past it are aborted
 /// 
 /// # Returns
 /// 
-/// - `HashMap<String, HashMap<String, RegisterValue>>` the liste of register and value
+/// - `HashMap<String, Result<Measurement, FetchError>>` the liste of register and value for each device,
+///   or why it could not be read this run (`FetchError::Stuck` for the aborted tasks)
 pub async fn fetch_device<T: IndustrialDevice + Send + 'static + ?Sized>(
     devices: Rc<RefCell<HashMap<String, Arc<Mutex<Box<T>>>>>>,
+    options: &HashMap<String, DeviceOptions>,
+    polls: &mut HashMap<String, PollState>,
+    period: Duration,
     timeout_duration: Duration,
-) -> HashMap<String, HashMap<String, RegisterValue>> {
+    connect_timeout: Duration,
+    watchdog: Option<Duration>,
+) -> HashMap<String, Result<Measurement, FetchError>> {
+    // One lock per shared bus (e.g. a RS-485 serial port)
+    let mut buses: HashMap<String, Arc<Mutex<()>>> = HashMap::new();
+
     // Create a task for each device
     let mut set = JoinSet::new();
+    // The polling state of the running tasks as they started, carried over if the watchdog aborts them
+    let mut running: HashMap<String, PollState> = HashMap::new();
     for (name, device) in devices.borrow().iter() {
         let d = device.clone();
         let name = name.clone();
-        set.spawn(async move {
-            info!("Fetching registers from {name}");
-            let data_input: Result<HashMap<String, industrial_device::types::Value>, _> =
-                match timeout(timeout_duration, d.lock().await.dump_registers()).await {
-                    Ok(res) => res,
-                    Err(_err) => {
-                        warn!("Timeout reached while fetching {name} skipping this run");
-                        return HashMap::new();
+        let options = options.get(&name).cloned().unwrap_or_default();
+        let mut poll = polls.remove(&name).unwrap_or_default();
+        running.insert(name.clone(), poll.clone());
+        let bus = options
+            .bus
+            .as_ref()
+            .map(|bus| buses.entry(bus.clone()).or_default().clone());
+        let span_attributes = [("device", name.clone())];
+        set.spawn(telemetry::propagate(async move {
+            if let Some(delay) = options.initial_delay_ms {
+                sleep(Duration::from_millis(delay)).await;
+            }
+            let registers = poll.due(&options.poll_every);
+            // The first cycle follows the initial connection
+            let reconnect = options.reconnect_every.is_some_and(|every| {
+                every > 0 && poll.cycle > 0 && poll.cycle.is_multiple_of(every as u64)
+            });
+            let request = ReadRequest {
+                registers: registers.as_deref(),
+                known: &poll.registers,
+                bus: bus.as_deref(),
+                options: &options,
+                connect_timeout,
+                reconnect: poll.escalates(options.failure_grace_cycles),
+            };
+            let read = async {
+                if reconnect {
+                    refresh_connection(&name, &d, &request).await?;
+                }
+                if let Some(trigger) = &options.trigger {
+                    let trigger_read =
+                        check_trigger(&name, d.clone(), &request, timeout_duration, trigger).await?;
+                    if let Some(read) = trigger_read {
+                        return Ok((read, false));
                     }
-                };
-
-            let res: HashMap<String, RegisterValue> = match data_input {
-                Ok(val) => HashMap::from(convert_hashmap(val)),
-                Err(err) => {
-                    let _ = manage_errors(err, d.clone()).await;
-                    return HashMap::new();
                 }
+                let read = match options.samples {
+                    Some(samples) if samples > 1 => {
+                        let sampling = Sampling {
+                            samples,
+                            interval: period / samples,
+                            aggregation: options.aggregation,
+                            stats: options.sample_stats,
+                        };
+                        sample_device(&name, d, &request, timeout_duration, sampling).await
+                    }
+                    _ => read_device(&name, d, &request, timeout_duration).await,
+                };
+                read.map(|read| (read, true))
             };
+            let start = Instant::now();
+            let res = telemetry::in_span("fetch_device", &span_attributes, read).await;
+            telemetry::record_fetch(&name, res.is_ok());
+            poll.cycle += 1;
+            poll.record_result(&name, res.is_ok(), options.failure_threshold);
+            poll.record_timeout(
+                &name,
+                matches!(res, Err(FetchError::Timeout {})),
+                start.elapsed(),
+                timeout_duration,
+                options.timeout_warning_cycles(),
+            );
+            telemetry::record_failures(&name, poll.failures);
+            let (res, triggered) = match res {
+                Ok(res) => res,
+                Err(err) => return (name, poll, Err(err)),
+            };
+            // Remember the registers of a full read to only request the due ones later,
+            // a read in priority order may have been cut by the timeout
+            if triggered
+                && registers.is_none()
+                && (options.priority.is_empty() || poll.registers.is_empty())
+            {
+                poll.registers = res.registers;
+            }
 
-            HashMap::from([(name, res)])
-        });
-    }
+            let mut values = res.values;
+            round_values(&mut values, &options.decimals);
+            integer_values(&mut values, &options.as_integer);
+            let mut measurement = Measurement::new(values);
+            if let Some(field) = &options.timestamp_field {
+                measurement.take_timestamp(field);
+            }
 
+            (name, poll, Ok(measurement))
+        }));
+    }
     // join the tasks and merge the results
-    let mut res: HashMap<String, HashMap<String, RegisterValue>> = HashMap::new();
-    async {
+    let mut res: HashMap<String, Result<Measurement, FetchError>> = HashMap::new();
+    let join = async {
         while let Some(result) = set.join_next().await {
             match result {
-                Ok(val) => {
-                        res.extend(val);
+                Ok((name, poll, val)) => {
+                        running.remove(&name);
+                        polls.insert(name.clone(), poll);
+                        res.insert(name, val);
                 }
                 Err(err) => error!("There was an error joining the tasks responsible for fetching device data ({err})"),
             }
         }
+    };
+    let finished = match watchdog {
+        Some(watchdog) => timeout(watchdog, join).await.is_ok(),
+        None => {
+            join.await;
+            true
+        }
+    };
+    if !finished {
+        // A task blocking its thread can't be stopped, aborting it at least detaches it from the fetch loop
+        set.abort_all();
+        for (name, mut poll) in running {
+            error!("[{name}] fetch still running after the watchdog bound, aborting it");
+            // Counted as a failed cycle, the device keeps its registers and its failure count
+            let threshold = options
+                .get(&name)
+                .and_then(|options| options.failure_threshold);
+            poll.cycle += 1;
+            poll.record_result(&name, false, threshold);
+            polls.insert(name.clone(), poll);
+            res.insert(name, Err(FetchError::Stuck {}));
+        }
     }
-    .await;
     res
 }
+
+/// Spread the polls of the devices evenly over the period, in name order, by setting their `initial_delay_ms`
+/// (the devices with an `initial_delay_ms` of their own keep it)
+///
+/// # Arguments
+///
+/// - `options` (`&mut HashMap<String, DeviceOptions>`) - the options of each device
+/// - `names` (`Vec<String>`) - the devices polled
+/// - `period` (`Duration`) - the fetch period
+pub fn stagger(
+    options: &mut HashMap<String, DeviceOptions>,
+    mut names: Vec<String>,
+    period: Duration,
+) {
+    names.sort();
+    let count = names.len() as u128;
+    for (i, name) in names.into_iter().enumerate() {
+        let delay = (period.as_millis() * i as u128 / count) as u64;
+        let options = options.entry(name).or_default();
+        options.initial_delay_ms.get_or_insert(delay);
+    }
+}
+
+/// Connect a new instance of a device whose fetch got stuck, built again from its configuration,
+/// to replace the stuck one in the devices map (which is dropped once its task ends).
+/// A failed connection is retried on the next cycles.
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - The name of the device (for logging)
+/// - `device` (`Box<T>`) - The new instance of the device
+/// - `connect_timeout` (`Duration`) - The time given to the device to connect
+///
+/// # Returns
+///
+/// - `Arc<Mutex<Box<T>>>` - The device, to insert in the devices map
+pub async fn restart_device<T: IndustrialDevice + Send + ?Sized>(
+    name: &str,
+    mut device: Box<T>,
+    connect_timeout: Duration,
+) -> Arc<Mutex<Box<T>>> {
+    info!("[{name}] restarting the device");
+    if let Err(err) = connect_device(device.as_mut(), connect_timeout).await {
+        log_throttle::log(name, Level::Error, format!("Could not connect the restarted device ({err})"));
+    }
+    Arc::new(Mutex::new(device))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use super::*;
+    use async_trait::async_trait;
+    use industrial_device::types::Value;
+
+    type Devices = Rc<RefCell<HashMap<String, Arc<Mutex<Box<dyn IndustrialDevice + Send>>>>>>;
+
+    /// A device holding fixed values, recording the requests it receives (`connect`, `dump`
+    /// or the name of the register read), its reads failing while `failing` is set
+    /// (until it is reconnected if `recovers` is set), the registers of `delays` answering after their delay
+    #[derive(Clone, Default)]
+    struct MockDevice {
+        values: HashMap<String, Value>,
+        calls: Arc<std::sync::Mutex<Vec<String>>>,
+        failing: Arc<AtomicBool>,
+        recovers: bool,
+        delays: HashMap<String, Duration>,
+    }
+
+    impl MockDevice {
+        fn new(values: &[(&str, Value)]) -> Self {
+            MockDevice {
+                values: values
+                    .iter()
+                    .map(|(name, value)| (name.to_string(), value.clone()))
+                    .collect(),
+                ..Default::default()
+            }
+        }
+
+        /// The requests received since the last call
+        fn calls(&self) -> Vec<String> {
+            std::mem::take(&mut self.calls.lock().unwrap())
+        }
+
+        fn check(&self, call: &str) -> Result<(), IndustrialDeviceError> {
+            self.calls.lock().unwrap().push(call.to_string());
+            match self.failing.load(Ordering::SeqCst) {
+                true => Err(IndustrialDeviceError::DeviceNotAccessibleError {
+                    err: Box::new(std::io::Error::other("unplugged")),
+                }),
+                false => Ok(()),
+            }
+        }
+    }
+
+    #[async_trait]
+    impl IndustrialDevice for MockDevice {
+        async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+            self.calls.lock().unwrap().push("connect".to_string());
+            if self.recovers {
+                self.failing.store(false, Ordering::SeqCst);
+            }
+            Ok(())
+        }
+
+        async fn read_register_by_name(
+            &mut self,
+            name: &str,
+        ) -> Result<Value, IndustrialDeviceError> {
+            self.check(name)?;
+            if let Some(delay) = self.delays.get(name) {
+                sleep(*delay).await;
+            }
+            self.values
+                .get(name)
+                .cloned()
+                .ok_or(IndustrialDeviceError::RegisterNotFoundError {
+                    name: name.to_string(),
+                })
+        }
+
+        async fn write_register_by_name(
+            &mut self,
+            _: &str,
+            _: &Value,
+        ) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn dump_registers(
+            &mut self,
+        ) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+            self.check("dump")?;
+            Ok(self.values.clone())
+        }
+    }
+
+    fn devices(devices: &[(&str, &MockDevice)]) -> Devices {
+        let devices = devices.iter().map(|(name, device)| {
+            let device: Box<dyn IndustrialDevice + Send> = Box::new((*device).clone());
+            (name.to_string(), Arc::new(Mutex::new(device)))
+        });
+        Rc::new(RefCell::new(devices.collect()))
+    }
+
+    /// Run a fetch cycle, without watchdog
+    async fn fetch(
+        devices: &Devices,
+        options: &HashMap<String, DeviceOptions>,
+        polls: &mut HashMap<String, PollState>,
+    ) -> HashMap<String, Result<Measurement, FetchError>> {
+        let second = Duration::from_secs(1);
+        fetch_device(
+            devices.clone(),
+            options,
+            polls,
+            second,
+            second,
+            second,
+            None,
+        )
+        .await
+    }
+
+    /// A device whose dump never ends, as a read stuck on a dead connection
+    struct StuckDevice;
+
+    #[async_trait]
+    impl IndustrialDevice for StuckDevice {
+        async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn read_register_by_name(&mut self, _: &str) -> Result<Value, IndustrialDeviceError> {
+            std::future::pending().await
+        }
+
+        async fn write_register_by_name(
+            &mut self,
+            _: &str,
+            _: &Value,
+        ) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn dump_registers(
+            &mut self,
+        ) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+            std::future::pending().await
+        }
+    }
+
+    #[tokio::test]
+    async fn stuck_device_keeps_its_polling_state() {
+        let device: Box<dyn IndustrialDevice + Send> = Box::new(StuckDevice);
+        let devices = Rc::new(RefCell::new(HashMap::from([(
+            "press1".to_string(),
+            Arc::new(Mutex::new(device)),
+        )])));
+        let mut polls = HashMap::from([(
+            "press1".to_string(),
+            PollState {
+                cycle: 4,
+                registers: vec!["temperature".to_string()],
+                failures: 2,
+                ..Default::default()
+            },
+        )]);
+
+        let res = fetch_device(
+            devices,
+            &HashMap::new(),
+            &mut polls,
+            Duration::from_secs(1),
+            Duration::from_secs(60),
+            Duration::from_secs(1),
+            Some(Duration::from_millis(20)),
+        )
+        .await;
+
+        assert!(matches!(res["press1"], Err(FetchError::Stuck {})));
+        let poll = &polls["press1"];
+        assert_eq!(poll.cycle, 5);
+        assert_eq!(poll.failures, 3);
+        assert_eq!(poll.registers, vec!["temperature"]);
+    }
+
+    #[tokio::test]
+    async fn register_polled_every_third_cycle() {
+        let device = MockDevice::new(&[
+            ("temperature", Value::U16(215)),
+            ("setpoint", Value::U16(220)),
+        ]);
+        let devices = devices(&[("press1", &device)]);
+        let options = HashMap::from([(
+            "press1".to_string(),
+            DeviceOptions {
+                poll_every: HashMap::from([("setpoint".to_string(), 3)]),
+                ..Default::default()
+            },
+        )]);
+        let mut polls = HashMap::new();
+
+        let mut cycles = Vec::new();
+        for cycle in 0..7 {
+            let res = fetch(&devices, &options, &mut polls).await;
+            let measurement = res["press1"].as_ref().unwrap();
+            assert!(measurement.values.contains_key("temperature"));
+            if measurement.values.contains_key("setpoint") {
+                cycles.push(cycle);
+            }
+            if cycle == 1 {
+                // Only the due register is requested
+                assert_eq!(device.calls(), ["temperature"]);
+            }
+            device.calls();
+        }
+        assert_eq!(cycles, [0, 3, 6]);
+    }
+
+    #[tokio::test]
+    async fn consecutive_failures_are_counted_until_the_device_recovers() {
+        let device = MockDevice::new(&[("temperature", Value::U16(215))]);
+        let devices = devices(&[("press1", &device)]);
+        let options = HashMap::from([(
+            "press1".to_string(),
+            DeviceOptions {
+                failure_threshold: Some(2),
+                ..Default::default()
+            },
+        )]);
+        let mut polls = HashMap::new();
+
+        device.failing.store(true, Ordering::SeqCst);
+        for failures in 1..=3 {
+            let res = fetch(&devices, &options, &mut polls).await;
+            assert!(res["press1"].is_err());
+            assert_eq!(polls["press1"].failures, failures);
+        }
+        device.failing.store(false, Ordering::SeqCst);
+        let res = fetch(&devices, &options, &mut polls).await;
+        assert!(res["press1"].is_ok());
+        assert_eq!(polls["press1"].failures, 0);
+    }
+
+    #[tokio::test]
+    async fn device_is_read_again_once_reconnected() {
+        let device = MockDevice {
+            recovers: true,
+            ..MockDevice::new(&[("temperature", Value::U16(215))])
+        };
+        device.failing.store(true, Ordering::SeqCst);
+        let devices = devices(&[("press1", &device)]);
+        let mut polls = HashMap::new();
+
+        let res = fetch(&devices, &HashMap::new(), &mut polls).await;
+        let measurement = res["press1"].as_ref().unwrap();
+        assert!(measurement.values.contains_key("temperature"));
+        assert_eq!(device.calls(), ["dump", "connect", "dump"]);
+        assert_eq!(polls["press1"].failures, 0);
+    }
+
+    #[tokio::test]
+    async fn timeout_keeps_the_registers_of_highest_priority() {
+        let device = MockDevice {
+            delays: HashMap::from([("history".to_string(), Duration::from_secs(5))]),
+            ..MockDevice::new(&[
+                ("history", Value::U16(1)),
+                ("pressure", Value::U16(12)),
+                ("temperature", Value::U16(215)),
+            ])
+        };
+        let devices = devices(&[("press1", &device)]);
+        let options = HashMap::from([(
+            "press1".to_string(),
+            DeviceOptions {
+                priority: HashMap::from([
+                    ("pressure".to_string(), 10),
+                    ("temperature".to_string(), 5),
+                ]),
+                ..Default::default()
+            },
+        )]);
+        let mut polls = HashMap::new();
+        let second = Duration::from_secs(1);
+        let timeout = Duration::from_millis(100);
+
+        // The first read dumps the registers to know them
+        let res = fetch_device(
+            devices.clone(),
+            &options,
+            &mut polls,
+            second,
+            timeout,
+            second,
+            None,
+        )
+        .await;
+        assert_eq!(res["press1"].as_ref().unwrap().values.len(), 3);
+        device.calls();
+
+        let res = fetch_device(devices, &options, &mut polls, second, timeout, second, None).await;
+        let values = &res["press1"].as_ref().unwrap().values;
+        assert!(values.contains_key("pressure"));
+        assert!(values.contains_key("temperature"));
+        assert!(!values.contains_key("history"));
+        assert_eq!(device.calls(), ["pressure", "temperature", "history"]);
+        // The registers cut by the timeout are still requested on the next cycles
+        assert_eq!(polls["press1"].registers.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn float_register_is_rounded_before_the_push() {
+        let device = MockDevice::new(&[("setpoint", Value::Float32(0.1))]);
+        let devices = devices(&[("press1", &device)]);
+        let options = HashMap::from([(
+            "press1".to_string(),
+            DeviceOptions {
+                decimals: HashMap::from([("setpoint".to_string(), 2)]),
+                ..Default::default()
+            },
+        )]);
+        let res = fetch(&devices, &options, &mut HashMap::new()).await;
+        let values = &res["press1"].as_ref().unwrap().values;
+        assert_eq!(values["setpoint"], RegisterValue::Float(0.1));
+    }
+
+    /// A device taking 20ms to dump its registers, counting the reads running at the same time
+    struct SlowDevice {
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+    }
+
+    #[async_trait]
+    impl IndustrialDevice for SlowDevice {
+        async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn read_register_by_name(&mut self, _: &str) -> Result<Value, IndustrialDeviceError> {
+            Ok(Value::U16(0))
+        }
+
+        async fn write_register_by_name(
+            &mut self,
+            _: &str,
+            _: &Value,
+        ) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn dump_registers(
+            &mut self,
+        ) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            sleep(Duration::from_millis(20)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            Ok(HashMap::new())
+        }
+    }
+
+    /// The maximum number of devices read at the same time, out of three devices on `bus`
+    async fn max_concurrent_reads(bus: Option<&str>) -> usize {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let mut devices: HashMap<String, Arc<Mutex<Box<dyn IndustrialDevice + Send>>>> =
+            HashMap::new();
+        let mut options = HashMap::new();
+        for name in ["slave1", "slave2", "slave3"] {
+            let device = SlowDevice {
+                active: active.clone(),
+                max_active: max_active.clone(),
+            };
+            devices.insert(name.to_string(), Arc::new(Mutex::new(Box::new(device))));
+            let device_options = DeviceOptions {
+                bus: bus.map(String::from),
+                ..Default::default()
+            };
+            options.insert(name.to_string(), device_options);
+        }
+        let res = fetch(
+            &Rc::new(RefCell::new(devices)),
+            &options,
+            &mut HashMap::new(),
+        )
+        .await;
+        assert!(res.values().all(|res| res.is_ok()));
+        max_active.load(Ordering::SeqCst)
+    }
+
+    #[tokio::test]
+    async fn devices_sharing_a_bus_are_read_one_after_the_other() {
+        assert_eq!(max_concurrent_reads(Some("/dev/ttyUSB0")).await, 1);
+        assert_eq!(max_concurrent_reads(None).await, 3);
+    }
+
+    #[test]
+    fn stagger_spreads_the_devices_over_the_period() {
+        let mut options = HashMap::from([(
+            "press4".to_string(),
+            DeviceOptions {
+                initial_delay_ms: Some(50),
+                ..Default::default()
+            },
+        )]);
+        let names = ["press3", "press1", "press4", "press2"].map(String::from);
+        stagger(&mut options, names.to_vec(), Duration::from_secs(1));
+        let delays: Vec<Option<u64>> = ["press1", "press2", "press3", "press4"]
+            .iter()
+            .map(|name| options[*name].initial_delay_ms)
+            .collect();
+        // The device with a delay of its own keeps it
+        assert_eq!(delays, [Some(0), Some(250), Some(500), Some(50)]);
+    }
+
+    #[tokio::test]
+    async fn staggered_devices_are_read_at_their_delay() {
+        let device = MockDevice::new(&[("temperature", Value::U16(215))]);
+        let names = ["press1", "press2", "press3"];
+        let devices = devices(&names.map(|name| (name, &device)));
+        let mut options = HashMap::new();
+        stagger(
+            &mut options,
+            names.map(String::from).to_vec(),
+            Duration::from_millis(600),
+        );
+        let start = chrono::Utc::now();
+
+        let res = fetch(&devices, &options, &mut HashMap::new()).await;
+        let read_at = |name: &str| {
+            let measurement = res[name].as_ref().unwrap();
+            (measurement.timestamp - start).num_milliseconds()
+        };
+        assert!(read_at("press1") < 150);
+        assert!((200..350).contains(&read_at("press2")));
+        assert!((400..550).contains(&read_at("press3")));
+    }
+
+    fn grace_options(cycles: u32) -> HashMap<String, DeviceOptions> {
+        HashMap::from([(
+            "press1".to_string(),
+            DeviceOptions {
+                failure_grace_cycles: Some(cycles),
+                ..Default::default()
+            },
+        )])
+    }
+
+    #[tokio::test]
+    async fn single_missed_cycle_does_not_reconnect() {
+        let device = MockDevice::new(&[("temperature", Value::U16(215))]);
+        let devices = devices(&[("press1", &device)]);
+        let options = grace_options(3);
+        let mut polls = HashMap::new();
+
+        device.failing.store(true, Ordering::SeqCst);
+        assert!(fetch(&devices, &options, &mut polls).await["press1"].is_err());
+        device.failing.store(false, Ordering::SeqCst);
+        assert!(fetch(&devices, &options, &mut polls).await["press1"].is_ok());
+        assert_eq!(device.calls(), ["dump", "dump"]);
+    }
+
+    #[tokio::test]
+    async fn device_is_reconnected_after_the_grace_cycles() {
+        let device = MockDevice {
+            recovers: true,
+            ..MockDevice::new(&[("temperature", Value::U16(215))])
+        };
+        device.failing.store(true, Ordering::SeqCst);
+        let devices = devices(&[("press1", &device)]);
+        let options = grace_options(3);
+        let mut polls = HashMap::new();
+
+        for _ in 0..2 {
+            assert!(fetch(&devices, &options, &mut polls).await["press1"].is_err());
+            assert_eq!(device.calls(), ["dump"]);
+        }
+        // The third failed cycle in a row reconnects the device
+        assert!(fetch(&devices, &options, &mut polls).await["press1"].is_ok());
+        assert_eq!(device.calls(), ["dump", "connect", "dump"]);
+    }
+
+    #[tokio::test]
+    async fn device_is_reconnected_every_n_cycles() {
+        let device = MockDevice::new(&[("temperature", Value::U16(215))]);
+        let devices = devices(&[("press1", &device)]);
+        let options: DeviceOptions =
+            serde_json::from_value(serde_json::json!({"reconnect_every": 2})).unwrap();
+        let options = HashMap::from([("press1".to_string(), options)]);
+        let mut polls = HashMap::new();
+
+        for cycle in 0..5 {
+            assert!(fetch(&devices, &options, &mut polls).await["press1"].is_ok());
+            match cycle {
+                2 | 4 => assert_eq!(device.calls(), ["connect", "dump"], "cycle {cycle}"),
+                _ => assert_eq!(device.calls(), ["dump"], "cycle {cycle}"),
+            }
+        }
+    }
+}
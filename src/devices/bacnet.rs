@@ -0,0 +1,719 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use async_trait::async_trait;
+use industrial_device::{errors::IndustrialDeviceError, types::Value, IndustrialDevice};
+use log::warn;
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::{net::UdpSocket, time::timeout};
+
+use super::definitions::open_definition;
+use super::errors::DeviceInitError;
+use super::DeviceOptions;
+
+/// BACnet object type of the device object
+const OBJECT_DEVICE: u32 = 8;
+/// `object-identifier` property
+const PROPERTY_OBJECT_IDENTIFIER: u32 = 75;
+/// `present-value` property, read when none is given in the objects file
+const PROPERTY_PRESENT_VALUE: u32 = 85;
+/// ReadPropertyMultiple confirmed service choice
+const SERVICE_READ_PROPERTY_MULTIPLE: u8 = 14;
+/// Number of objects asked in a single ReadPropertyMultiple, keeps the answer in one unsegmented APDU
+const OBJECTS_PER_REQUEST: usize = 20;
+/// Time to wait for the answer to a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+
+#[derive(Debug, Error)]
+/// List of error related to the BACnet/IP exchanges
+pub enum BacnetError {
+    #[error("No answer from the device")]
+    Timeout {},
+    #[error("Malformed answer ({reason})")]
+    Malformed { reason: String },
+    #[error("The device rejected the request ({reason})")]
+    Rejected { reason: String },
+    #[error("Socket error ({err})")]
+    Io {
+        #[from]
+        err: std::io::Error,
+    },
+}
+
+impl From<BacnetError> for IndustrialDeviceError {
+    fn from(value: BacnetError) -> Self {
+        match value {
+            BacnetError::Timeout {} | BacnetError::Io { err: _ } => {
+                IndustrialDeviceError::DeviceNotAccessibleError {
+                    err: Box::new(value),
+                }
+            }
+            _ => IndustrialDeviceError::RequestError {
+                err: Box::new(value),
+            },
+        }
+    }
+}
+
+/// The fields of a [`BacnetObjectDef`], the columns a CSV definition may have besides `name`
+pub const DEFINITION_FIELDS: &[&str] = &["object_type", "instance", "property"];
+
+#[derive(Deserialize, Debug, Clone)]
+/// Definition of a register in the objects file
+///
+/// # Fields
+/// - `object_type`: BACnet object type, by name (`analog-input`, `binary-value`, ...) or number
+/// - `instance`: Instance number of the object
+/// - `property`: Property identifier to read (default `present-value`)
+pub struct BacnetObjectDef {
+    pub object_type: String,
+    pub instance: u32,
+    pub property: Option<u32>,
+}
+
+/// A BACnet object property to read
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct BacnetObject {
+    pub object_type: u32,
+    pub instance: u32,
+    pub property: u32,
+}
+
+impl BacnetObject {
+    /// Encoded object identifier (10 bits type, 22 bits instance)
+    fn identifier(&self) -> u32 {
+        (self.object_type << 22) | (self.instance & 0x3F_FFFF)
+    }
+}
+
+impl TryFrom<BacnetObjectDef> for BacnetObject {
+    type Error = DeviceInitError;
+
+    fn try_from(value: BacnetObjectDef) -> Result<Self, Self::Error> {
+        let object_type = match value.object_type.as_str() {
+            "analog-input" => 0,
+            "analog-output" => 1,
+            "analog-value" => 2,
+            "binary-input" => 3,
+            "binary-output" => 4,
+            "binary-value" => 5,
+            "device" => OBJECT_DEVICE,
+            "multi-state-input" => 13,
+            "multi-state-output" => 14,
+            "multi-state-value" => 19,
+            "accumulator" => 23,
+            other => other.parse().map_err(|_| DeviceInitError::ParsingFailed {
+                reason: format!("Unknown BACnet object type {other}"),
+            })?,
+        };
+        Ok(BacnetObject {
+            object_type,
+            instance: value.instance,
+            property: value.property.unwrap_or(PROPERTY_PRESENT_VALUE),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// Config of a BACnet/IP device
+///
+/// # Fields
+/// - `remote`: Address of the device, to be parsed as a SocketAddr (usually port 47808)
+/// - `device_instance`: Instance number of the device object, checked on connection
+/// - `objects`: Path to the JSON file mapping register names to object/property pairs
+pub struct BacnetIPDevice {
+    pub remote: String,
+    pub device_instance: u32,
+    pub objects: String,
+    #[serde(flatten)]
+    pub options: DeviceOptions,
+}
+
+/// Client of a BACnet/IP device reading its objects with ReadPropertyMultiple
+pub struct BacnetDevice {
+    addr: SocketAddr,
+    device_instance: u32,
+    objects: HashMap<String, BacnetObject>,
+    socket: Option<UdpSocket>,
+    invoke_id: u8,
+}
+
+impl BacnetIPDevice {
+    /// Tags describing the connection to the device
+    pub fn connection_info(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("endpoint".to_string(), self.remote.clone()),
+            (
+                "device_instance".to_string(),
+                self.device_instance.to_string(),
+            ),
+        ])
+    }
+}
+
+impl TryFrom<BacnetIPDevice> for BacnetDevice {
+    type Error = DeviceInitError;
+
+    fn try_from(value: BacnetIPDevice) -> Result<Self, Self::Error> {
+        let pointer = value.options.definition_pointer.as_deref();
+        let objects_json = open_definition(&value.objects, pointer, DEFINITION_FIELDS)?;
+        let defs: HashMap<String, BacnetObjectDef> = serde_json::from_reader(objects_json)?;
+        let objects = defs
+            .into_iter()
+            .map(|(name, def)| Ok((name, def.try_into()?)))
+            .collect::<Result<HashMap<String, BacnetObject>, DeviceInitError>>()?;
+
+        let addr: SocketAddr = value.remote.parse()?;
+
+        Ok(BacnetDevice {
+            addr,
+            device_instance: value.device_instance,
+            objects,
+            socket: None,
+            invoke_id: 0,
+        })
+    }
+}
+
+impl BacnetDevice {
+    /// Send a ReadPropertyMultiple request and wait for the matching answer
+    ///
+    /// # Arguments
+    ///
+    /// - `objects` (`&[BacnetObject]`) - The object properties to read
+    ///
+    /// # Returns
+    ///
+    /// - `Result<HashMap<BacnetObject, Value>, IndustrialDeviceError>` - The values read, properties
+    ///   the device answered with an error for are left out
+    async fn read_property_multiple(
+        &mut self,
+        objects: &[BacnetObject],
+    ) -> Result<HashMap<BacnetObject, Value>, IndustrialDeviceError> {
+        self.invoke_id = self.invoke_id.wrapping_add(1);
+        let invoke_id = self.invoke_id;
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => {
+                return Err(IndustrialDeviceError::DeviceNotConnectedError {
+                    err: Box::new(BacnetError::Rejected {
+                        reason: "socket not opened".to_string(),
+                    }),
+                })
+            }
+        };
+
+        socket
+            .send(&encode_read_property_multiple(invoke_id, objects))
+            .await
+            .map_err(BacnetError::from)?;
+
+        let mut buf = [0u8; 1500];
+        loop {
+            let len = match timeout(REQUEST_TIMEOUT, socket.recv(&mut buf)).await {
+                Ok(len) => len.map_err(BacnetError::from)?,
+                Err(_) => return Err(BacnetError::Timeout {}.into()),
+            };
+            // Answers to previous (timed out) requests are discarded
+            if let Some(values) = decode_answer(&buf[..len], invoke_id)? {
+                return Ok(values);
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl IndustrialDevice for BacnetDevice {
+    async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+        let local: SocketAddr = match self.addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await.map_err(BacnetError::from)?;
+        socket.connect(self.addr).await.map_err(BacnetError::from)?;
+        self.socket = Some(socket);
+
+        // Make sure the expected device answers
+        let device = BacnetObject {
+            object_type: OBJECT_DEVICE,
+            instance: self.device_instance,
+            property: PROPERTY_OBJECT_IDENTIFIER,
+        };
+        let res = self.read_property_multiple(&[device]).await?;
+        match res.contains_key(&device) {
+            true => Ok(()),
+            false => Err(BacnetError::Rejected {
+                reason: format!("device {} not found", self.device_instance),
+            }
+            .into()),
+        }
+    }
+
+    async fn read_register_by_name(&mut self, name: &str) -> Result<Value, IndustrialDeviceError> {
+        let object = match self.objects.get(name) {
+            Some(object) => *object,
+            None => {
+                return Err(IndustrialDeviceError::RegisterNotFoundError {
+                    name: name.to_string(),
+                })
+            }
+        };
+        self.read_property_multiple(&[object])
+            .await?
+            .remove(&object)
+            .ok_or_else(|| {
+                BacnetError::Rejected {
+                    reason: format!("could not read {name}"),
+                }
+                .into()
+            })
+    }
+
+    async fn write_register_by_name(
+        &mut self,
+        _name: &str,
+        _value: &Value,
+    ) -> Result<(), IndustrialDeviceError> {
+        Err(BacnetError::Rejected {
+            reason: "writing is not supported".to_string(),
+        }
+        .into())
+    }
+
+    async fn dump_registers(&mut self) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+        let objects: Vec<(String, BacnetObject)> = self
+            .objects
+            .iter()
+            .map(|(name, object)| (name.clone(), *object))
+            .collect();
+
+        let mut res = HashMap::new();
+        for chunk in objects.chunks(OBJECTS_PER_REQUEST) {
+            let to_read: Vec<BacnetObject> = chunk.iter().map(|(_, object)| *object).collect();
+            let mut values = self.read_property_multiple(&to_read).await?;
+            for (name, object) in chunk {
+                match values.remove(object) {
+                    Some(value) => {
+                        res.insert(name.clone(), value);
+                    }
+                    None => warn!("Could not read BACnet object {name} ({object:?})"),
+                }
+            }
+        }
+        Ok(res)
+    }
+}
+
+/// Append a context tagged unsigned integer
+fn encode_context_unsigned(buf: &mut Vec<u8>, tag: u8, value: u32) {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(3).take_while(|b| **b == 0).count();
+    buf.push((tag << 4) | 0x08 | (4 - skip) as u8);
+    buf.extend_from_slice(&bytes[skip..]);
+}
+
+/// Build the BVLC/NPDU/APDU frame of a ReadPropertyMultiple request
+fn encode_read_property_multiple(invoke_id: u8, objects: &[BacnetObject]) -> Vec<u8> {
+    // Confirmed request, unsegmented, up to 1476 bytes accepted
+    let mut apdu = vec![0x00, 0x05, invoke_id, SERVICE_READ_PROPERTY_MULTIPLE];
+    for object in objects {
+        apdu.push(0x0C);
+        apdu.extend_from_slice(&object.identifier().to_be_bytes());
+        apdu.push(0x1E);
+        encode_context_unsigned(&mut apdu, 0, object.property);
+        apdu.push(0x1F);
+    }
+
+    // NPDU version 1, expecting a reply
+    let length = (4 + 2 + apdu.len()) as u16;
+    let mut frame = vec![0x81, 0x0A];
+    frame.extend_from_slice(&length.to_be_bytes());
+    frame.extend_from_slice(&[0x01, 0x04]);
+    frame.extend(apdu);
+    frame
+}
+
+#[derive(Debug, PartialEq)]
+enum TagKind {
+    Opening,
+    Closing,
+    /// Length of the content, or the value itself for application booleans
+    Length(usize),
+}
+
+#[derive(Debug)]
+struct Tag {
+    number: u8,
+    context: bool,
+    kind: TagKind,
+}
+
+/// Cursor over an APDU
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn malformed(reason: &str) -> BacnetError {
+        BacnetError::Malformed {
+            reason: reason.to_string(),
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        self.pos >= self.data.len()
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], BacnetError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos + len)
+            .ok_or(Self::malformed("truncated"))?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn unsigned(&mut self, len: usize) -> Result<u64, BacnetError> {
+        if len > 8 {
+            return Err(Self::malformed("integer too long"));
+        }
+        Ok(self
+            .bytes(len)?
+            .iter()
+            .fold(0u64, |acc, b| (acc << 8) | *b as u64))
+    }
+
+    fn tag(&mut self) -> Result<Tag, BacnetError> {
+        let first = self.bytes(1)?[0];
+        let mut number = first >> 4;
+        if number == 0x0F {
+            number = self.bytes(1)?[0];
+        }
+        let context = first & 0x08 != 0;
+        let kind = match (context, first & 0x07) {
+            (true, 6) => TagKind::Opening,
+            (true, 7) => TagKind::Closing,
+            (_, 5) => match self.bytes(1)?[0] {
+                254 => TagKind::Length(self.unsigned(2)? as usize),
+                255 => TagKind::Length(self.unsigned(4)? as usize),
+                len => TagKind::Length(len as usize),
+            },
+            (_, len) => TagKind::Length(len as usize),
+        };
+        Ok(Tag {
+            number,
+            context,
+            kind,
+        })
+    }
+
+    fn peek_tag(&mut self) -> Result<Tag, BacnetError> {
+        let pos = self.pos;
+        let tag = self.tag();
+        self.pos = pos;
+        tag
+    }
+
+    fn expect_context(&mut self, number: u8) -> Result<Tag, BacnetError> {
+        let tag = self.tag()?;
+        match tag.context && tag.number == number {
+            true => Ok(tag),
+            false => Err(Self::malformed("unexpected tag")),
+        }
+    }
+
+    fn expect_delimiter(&mut self, number: u8, kind: TagKind) -> Result<(), BacnetError> {
+        match self.expect_context(number)?.kind == kind {
+            true => Ok(()),
+            false => Err(Self::malformed("unexpected tag")),
+        }
+    }
+
+    fn context_unsigned(&mut self, number: u8) -> Result<u64, BacnetError> {
+        match self.expect_context(number)?.kind {
+            TagKind::Length(len) => self.unsigned(len),
+            _ => Err(Self::malformed("unexpected tag")),
+        }
+    }
+
+    /// Decode an application tagged value, `None` for types without a `Value` equivalent (null)
+    fn application_value(&mut self) -> Result<Option<Value>, BacnetError> {
+        let tag = self.tag()?;
+        let len = match (tag.context, tag.kind) {
+            (false, TagKind::Length(len)) => len,
+            _ => return Err(Self::malformed("expected an application tag")),
+        };
+        let value = match tag.number {
+            0 => None,
+            1 => Some(Value::Boolean(len != 0)),
+            2 => {
+                let val = self.unsigned(len)?;
+                Some(match u32::try_from(val) {
+                    Ok(val) => Value::U32(val),
+                    Err(_) => Value::U64(val),
+                })
+            }
+            3 => {
+                let raw = self.unsigned(len)?;
+                let shift = 64 - 8 * len.max(1) as u32;
+                let val = ((raw << shift) as i64) >> shift;
+                Some(Value::S32(
+                    i32::try_from(val).map_err(|_| Self::malformed("signed value too large"))?,
+                ))
+            }
+            4 => Some(Value::Float32(f32::from_bits(self.unsigned(4)? as u32))),
+            5 => Some(Value::Float32(f64::from_bits(self.unsigned(8)?) as f32)),
+            9 => {
+                let val = self.unsigned(len)?;
+                Some(match u16::try_from(val) {
+                    Ok(val) => Value::Enum16(val),
+                    Err(_) => Value::U32(val as u32),
+                })
+            }
+            // Character strings start with their charset
+            7 => Some(Value::Sized(
+                self.bytes(len)?.iter().skip(1).copied().collect(),
+            )),
+            _ => Some(Value::Sized(self.bytes(len)?.to_vec())),
+        };
+        Ok(value)
+    }
+}
+
+/// Decode an answer frame
+///
+/// # Returns
+///
+/// - `Ok(None)` if the frame does not answer the request `invoke_id`
+/// - `Ok(Some(values))` with the values read, keyed by object property
+fn decode_answer(
+    frame: &[u8],
+    invoke_id: u8,
+) -> Result<Option<HashMap<BacnetObject, Value>>, BacnetError> {
+    let mut reader = Reader {
+        data: frame,
+        pos: 0,
+    };
+
+    // BVLC
+    let bvlc = reader.bytes(4)?;
+    if bvlc[0] != 0x81 {
+        return Ok(None);
+    }
+    // NPDU
+    let npdu = reader.bytes(2)?;
+    let control = npdu[1];
+    if control & 0x80 != 0 {
+        return Ok(None);
+    }
+    if control & 0x20 != 0 {
+        reader.bytes(2)?;
+        let len = reader.bytes(1)?[0] as usize;
+        reader.bytes(len)?;
+    }
+    if control & 0x08 != 0 {
+        reader.bytes(2)?;
+        let len = reader.bytes(1)?[0] as usize;
+        reader.bytes(len)?;
+    }
+    if control & 0x20 != 0 {
+        reader.bytes(1)?;
+    }
+
+    // APDU
+    let header = reader.bytes(2)?;
+    let pdu_type = header[0] >> 4;
+    let answer_id = match pdu_type {
+        // Abort carries the invoke id at the same place
+        3 | 5 | 6 | 7 => header[1],
+        _ => return Ok(None),
+    };
+    if answer_id != invoke_id {
+        return Ok(None);
+    }
+    match pdu_type {
+        3 if header[0] & 0x08 != 0 => {
+            return Err(BacnetError::Rejected {
+                reason: "segmented answers are not supported".to_string(),
+            })
+        }
+        3 => {}
+        5 => {
+            return Err(BacnetError::Rejected {
+                reason: "error PDU".to_string(),
+            })
+        }
+        6 => {
+            return Err(BacnetError::Rejected {
+                reason: format!("reject reason {}", reader.bytes(1)?[0]),
+            })
+        }
+        _ => {
+            return Err(BacnetError::Rejected {
+                reason: format!("abort reason {}", reader.bytes(1)?[0]),
+            })
+        }
+    }
+    if reader.bytes(1)?[0] != SERVICE_READ_PROPERTY_MULTIPLE {
+        return Err(Reader::malformed("unexpected service"));
+    }
+
+    let mut values = HashMap::new();
+    while !reader.is_empty() {
+        let identifier = reader.context_unsigned(0)? as u32;
+        reader.expect_delimiter(1, TagKind::Opening)?;
+        loop {
+            let tag = reader.peek_tag()?;
+            if tag.context && tag.number == 1 && tag.kind == TagKind::Closing {
+                reader.tag()?;
+                break;
+            }
+            let property = reader.context_unsigned(2)? as u32;
+            if reader.peek_tag()?.number == 3 {
+                reader.context_unsigned(3)?;
+            }
+            let result = reader.tag()?;
+            match (result.number, result.kind) {
+                (4, TagKind::Opening) => {
+                    let value = reader.application_value()?;
+                    // Only the first value of a list is kept
+                    while reader.peek_tag()?.kind != TagKind::Closing {
+                        reader.application_value()?;
+                    }
+                    reader.expect_delimiter(4, TagKind::Closing)?;
+                    if let Some(value) = value {
+                        let object = BacnetObject {
+                            object_type: identifier >> 22,
+                            instance: identifier & 0x3F_FFFF,
+                            property,
+                        };
+                        values.insert(object, value);
+                    }
+                }
+                (5, TagKind::Opening) => {
+                    // error class and code
+                    reader.application_value()?;
+                    reader.application_value()?;
+                    reader.expect_delimiter(5, TagKind::Closing)?;
+                }
+                _ => return Err(Reader::malformed("unexpected property result")),
+            }
+        }
+    }
+    Ok(Some(values))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEMPERATURE: BacnetObject = BacnetObject {
+        object_type: 0,
+        instance: 1,
+        property: PROPERTY_PRESENT_VALUE,
+    };
+
+    /// ComplexACK answering the request `invoke_id` with `results`, the encoded object results
+    fn answer(invoke_id: u8, results: &[u8]) -> Vec<u8> {
+        let mut apdu = vec![0x30, invoke_id, SERVICE_READ_PROPERTY_MULTIPLE];
+        apdu.extend_from_slice(results);
+        let mut frame = vec![0x81, 0x0A];
+        frame.extend_from_slice(&((6 + apdu.len()) as u16).to_be_bytes());
+        frame.extend_from_slice(&[0x01, 0x00]);
+        frame.extend(apdu);
+        frame
+    }
+
+    /// The result of an object property holding a REAL
+    fn real_result(object: &BacnetObject, value: f32) -> Vec<u8> {
+        let mut result = vec![0x0C];
+        result.extend_from_slice(&object.identifier().to_be_bytes());
+        result.extend_from_slice(&[0x1E, 0x29, object.property as u8, 0x4E, 0x44]);
+        result.extend_from_slice(&value.to_be_bytes());
+        result.extend_from_slice(&[0x4F, 0x1F]);
+        result
+    }
+
+    #[test]
+    fn request_lists_the_object_properties() {
+        assert_eq!(
+            encode_read_property_multiple(7, &[TEMPERATURE]),
+            [
+                0x81, 0x0A, 0x00, 0x13, 0x01, 0x04, 0x00, 0x05, 0x07, 0x0E, 0x0C, 0x00, 0x00, 0x00,
+                0x01, 0x1E, 0x09, 0x55, 0x1F
+            ]
+        );
+    }
+
+    #[test]
+    fn answer_values_are_decoded() {
+        let mut results = real_result(&TEMPERATURE, 21.5);
+        // An unknown object answered with error class object, code unknown-object
+        results.extend_from_slice(&[0x0C, 0x00, 0x00, 0x00, 0x02, 0x1E, 0x29, 0x55]);
+        results.extend_from_slice(&[0x5E, 0x91, 0x01, 0x91, 0x1F, 0x5F, 0x1F]);
+        let values = decode_answer(&answer(7, &results), 7).unwrap().unwrap();
+        assert_eq!(values.len(), 1);
+        assert!(matches!(values[&TEMPERATURE], Value::Float32(val) if val == 21.5));
+    }
+
+    #[test]
+    fn answer_to_another_request_is_skipped() {
+        let frame = answer(6, &real_result(&TEMPERATURE, 21.5));
+        assert!(decode_answer(&frame, 7).unwrap().is_none());
+    }
+
+    #[test]
+    fn object_types_are_read_by_name_or_number() {
+        let def = |object_type: &str| BacnetObjectDef {
+            object_type: object_type.to_string(),
+            instance: 3,
+            property: None,
+        };
+        let object = BacnetObject::try_from(def("binary-value")).unwrap();
+        assert_eq!(
+            (object.object_type, object.property),
+            (5, PROPERTY_PRESENT_VALUE)
+        );
+        assert_eq!(BacnetObject::try_from(def("19")).unwrap().object_type, 19);
+        assert!(BacnetObject::try_from(def("thermostat")).is_err());
+    }
+
+    #[tokio::test]
+    async fn registers_are_read_from_the_device() {
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = server.local_addr().unwrap();
+        // Answers every object property asked with 21.5
+        tokio::spawn(async move {
+            let mut buf = [0u8; 1500];
+            loop {
+                let (len, client) = server.recv_from(&mut buf).await.unwrap();
+                let mut results = Vec::new();
+                for object in buf[10..len].chunks(9) {
+                    let identifier = u32::from_be_bytes(object[1..5].try_into().unwrap());
+                    let object = BacnetObject {
+                        object_type: identifier >> 22,
+                        instance: identifier & 0x3F_FFFF,
+                        property: object[7] as u32,
+                    };
+                    results.extend(real_result(&object, 21.5));
+                }
+                server
+                    .send_to(&answer(buf[8], &results), client)
+                    .await
+                    .unwrap();
+            }
+        });
+
+        let mut device = BacnetDevice {
+            addr,
+            device_instance: 1234,
+            objects: HashMap::from([("temperature".to_string(), TEMPERATURE)]),
+            socket: None,
+            invoke_id: 0,
+        };
+        device.connect().await.unwrap();
+        let values = device.dump_registers().await.unwrap();
+        assert!(matches!(values["temperature"], Value::Float32(val) if val == 21.5));
+    }
+}
@@ -0,0 +1,359 @@
+use std::{collections::HashMap, ops::Range};
+
+use async_trait::async_trait;
+use industrial_device::{errors::IndustrialDeviceError, types::Value, IndustrialDevice};
+use log::Level;
+use modbus_device::{
+    modbus_connexion_async::ModbusConnexionAsync, registers::Register, utils::get_defs_from_json,
+    ModbusDeviceAsync,
+};
+
+use super::definitions::open_definition;
+use super::endpoint::Forwarded;
+use super::errors::DeviceInitError;
+use super::log_throttle;
+
+/// Modbus device whose dump can be split in chunks of registers,
+/// connected through a local forwarding port when reached by host name or through a proxy (TCP only)
+pub type ModbusDevice = Forwarded<ChunkedDevice<ModbusDeviceAsync>>;
+
+/// The fields of a register in the Modbus definitions, the columns a CSV definition may have besides `name`
+pub const DEFINITION_FIELDS: &[&str] = &["address", "type", "scale", "offset", "unit"];
+
+/// Both classes of registers (input and holding) are read by default
+pub fn default_read() -> bool {
+    true
+}
+
+/// Load the registers of a definition (input or holding registers), none if this class of registers is not read
+///
+/// # Arguments
+///
+/// - `path` (`&str`) - Path or URL of the definition, not opened if `read` is false
+/// - `read` (`bool`) - Whether the registers of this class are read from the device
+/// - `pointer` (`Option<&str>`) - JSON Pointer to the definition within the file
+///
+/// # Returns
+///
+/// - `Result<HashMap<String, Register>, DeviceInitError>` - The registers, by name
+pub fn register_definition(
+    path: &str,
+    read: bool,
+    pointer: Option<&str>,
+) -> Result<HashMap<String, Register>, DeviceInitError> {
+    if !read {
+        return Ok(HashMap::new());
+    }
+    Ok(get_defs_from_json(open_definition(
+        path,
+        pointer,
+        DEFINITION_FIELDS,
+    )?)?)
+}
+
+/// The class of the registers of a chunk, each class is read with its own Modbus function
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RegisterClass {
+    Input,
+    Holding,
+}
+
+/// Registers of contiguous addresses of a class, read with a single request
+///
+/// # Fields
+/// - `class`: Whether these are input or holding registers.
+/// - `names`: The names of the registers, in address order.
+/// - `registers`: The registers, in the same order.
+pub struct Chunk {
+    pub class: RegisterClass,
+    pub names: Vec<String>,
+    pub registers: Vec<Register>,
+}
+
+/// Split registers sorted by address in runs of contiguous addresses spanning at most `size` 16 bits registers.
+/// A register starting past the end of the previous one (a gap in the addresses) starts a new run,
+/// since a ranged read would include the addresses in between, which the device may not map.
+///
+/// # Arguments
+///
+/// - `spans` (`&[(u16, u16)]`) - The address and the length of each register, sorted by address
+/// - `size` (`u16`) - The maximum number of 16 bits registers of a run
+///
+/// # Returns
+///
+/// - `Vec<Range<usize>>` - The indexes in `spans` of each run
+fn runs(spans: &[(u16, u16)], size: u16) -> Vec<Range<usize>> {
+    let mut res: Vec<Range<usize>> = Vec::new();
+    let (mut start, mut end): (u32, u32) = (0, 0);
+    for (i, (addr, len)) in spans.iter().enumerate() {
+        let (addr, len) = (*addr as u32, *len as u32);
+        match res.last_mut() {
+            Some(run) if addr <= end && (addr + len).max(end) - start <= size as u32 => {
+                run.end = i + 1;
+                end = end.max(addr + len);
+            }
+            _ => {
+                (start, end) = (addr, addr + len);
+                res.push(i..i + 1);
+            }
+        }
+    }
+    res
+}
+
+/// Group the registers of a definition in chunks of contiguous addresses,
+/// each chunk spanning at most `size` 16 bits registers
+///
+/// # Arguments
+///
+/// - `registers` (`&HashMap<String, Register>`) - The registers of a definition (input or holding registers)
+/// - `class` (`RegisterClass`) - The class of these registers
+/// - `size` (`u16`) - The maximum number of 16 bits registers of a chunk (125 for a Modbus read)
+///
+/// # Returns
+///
+/// - `Vec<Chunk>` - The chunks, in address order
+pub fn chunks(
+    registers: &HashMap<String, Register>,
+    class: RegisterClass,
+    size: u16,
+) -> Vec<Chunk> {
+    let mut sorted: Vec<(&String, &Register)> = registers.iter().collect();
+    sorted.sort_by_key(|(_, register)| register.addr);
+    let spans: Vec<(u16, u16)> = sorted
+        .iter()
+        .map(|(_, register)| (register.addr, register.len))
+        .collect();
+
+    runs(&spans, size)
+        .into_iter()
+        .map(|run| Chunk {
+            class,
+            names: sorted[run.clone()]
+                .iter()
+                .map(|(name, _)| name.to_string())
+                .collect(),
+            registers: sorted[run]
+                .iter()
+                .map(|(_, register)| (*register).clone())
+                .collect(),
+        })
+        .collect()
+}
+
+/// A device able to read a chunk of registers in a single request
+#[async_trait]
+pub trait ChunkRead {
+    async fn read_chunk(
+        &mut self,
+        chunk: &Chunk,
+    ) -> Result<HashMap<String, Value>, IndustrialDeviceError>;
+}
+
+#[async_trait]
+impl ChunkRead for ModbusDeviceAsync {
+    /// Read the span of the chunk with one read of its class (function 4 or 3), decoded register by register
+    async fn read_chunk(
+        &mut self,
+        chunk: &Chunk,
+    ) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+        let registers = chunk.registers.clone();
+        match chunk.class {
+            RegisterClass::Input => self.read_input_registers(registers).await,
+            RegisterClass::Holding => self.read_holding_registers(registers).await,
+        }
+        .map_err(|err| IndustrialDeviceError::RequestError { err: Box::new(err) })
+    }
+}
+
+/// A device whose dump is read in chunks of registers, a failing chunk is skipped instead of failing the whole dump
+///
+/// # Fields
+/// - `device`: The device.
+/// - `label`: How the device is named in the logs (its endpoint).
+/// - `chunks`: The chunks of registers, the device dumps its registers itself if `None`.
+pub struct ChunkedDevice<T> {
+    pub device: T,
+    pub label: String,
+    pub chunks: Option<Vec<Chunk>>,
+}
+
+#[async_trait]
+impl<T: IndustrialDevice + ChunkRead + Send> IndustrialDevice for ChunkedDevice<T> {
+    async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+        self.device.connect().await
+    }
+
+    async fn read_register_by_name(&mut self, name: &str) -> Result<Value, IndustrialDeviceError> {
+        self.device.read_register_by_name(name).await
+    }
+
+    async fn write_register_by_name(
+        &mut self,
+        name: &str,
+        value: &Value,
+    ) -> Result<(), IndustrialDeviceError> {
+        self.device.write_register_by_name(name, value).await
+    }
+
+    /// Read the registers with one request per chunk, the chunks that fail are logged and left out.
+    /// Fails if the connection is lost or if no chunk could be read.
+    async fn dump_registers(&mut self) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+        let chunks = match &self.chunks {
+            Some(chunks) => chunks,
+            None => return self.device.dump_registers().await,
+        };
+
+        let mut values = HashMap::new();
+        let mut last_err = None;
+        for chunk in chunks {
+            match self.device.read_chunk(chunk).await {
+                Ok(read) => values.extend(read),
+                Err(
+                    err @ (IndustrialDeviceError::DeviceNotAccessibleError { .. }
+                    | IndustrialDeviceError::DeviceNotConnectedError { .. }),
+                ) => return Err(err),
+                Err(err) => {
+                    let (first, last) = (&chunk.names[0], &chunk.names[chunk.names.len() - 1]);
+                    log_throttle::log(
+                        &format!("{}/{first}", self.label),
+                        Level::Warn,
+                        format!(
+                            "Could not read the chunk of registers {first}..{last}, skipping it ({err})"
+                        ),
+                    );
+                    last_err = Some(err);
+                }
+            }
+        }
+        match (values.is_empty(), last_err) {
+            (true, Some(err)) => Err(err),
+            _ => Ok(values),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn contiguous_registers_are_grouped_up_to_the_size() {
+        // 5 registers of 2 words from address 0
+        let spans: Vec<(u16, u16)> = (0..5).map(|i| (i * 2, 2)).collect();
+        assert_eq!(runs(&spans, 4), vec![0..2, 2..4, 4..5]);
+        assert_eq!(runs(&spans, 125), vec![0..5]);
+    }
+
+    #[test]
+    fn gaps_in_the_addresses_split_the_chunks() {
+        let spans = [(0, 1), (1, 2), (10, 1), (11, 1), (100, 4)];
+        assert_eq!(runs(&spans, 125), vec![0..2, 2..4, 4..5]);
+    }
+
+    #[test]
+    fn overlapping_registers_stay_in_the_chunk() {
+        // A 32 bits register and its low word, both named in the definition
+        let spans = [(0, 2), (1, 1), (2, 1)];
+        assert_eq!(runs(&spans, 3), vec![0..3]);
+        assert_eq!(runs(&spans, 2), vec![0..2, 2..3]);
+    }
+
+    #[test]
+    fn register_longer_than_the_size_is_a_chunk_of_its_own() {
+        let spans = [(0, 1), (1, 8), (9, 1)];
+        assert_eq!(runs(&spans, 4), vec![0..1, 1..2, 2..3]);
+    }
+
+    /// Reads the chunks it is given, failing those starting with `failing`
+    struct Mock {
+        reads: Vec<Vec<String>>,
+        failing: &'static str,
+    }
+
+    #[async_trait]
+    impl IndustrialDevice for Mock {
+        async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn read_register_by_name(&mut self, _: &str) -> Result<Value, IndustrialDeviceError> {
+            panic!("the registers are read by chunk")
+        }
+
+        async fn write_register_by_name(
+            &mut self,
+            _: &str,
+            _: &Value,
+        ) -> Result<(), IndustrialDeviceError> {
+            Ok(())
+        }
+
+        async fn dump_registers(
+            &mut self,
+        ) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+            panic!("the registers are read by chunk")
+        }
+    }
+
+    #[async_trait]
+    impl ChunkRead for Mock {
+        async fn read_chunk(
+            &mut self,
+            chunk: &Chunk,
+        ) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+            self.reads.push(chunk.names.clone());
+            if chunk.names[0] == self.failing {
+                return Err(IndustrialDeviceError::RequestError {
+                    err: "illegal data address".into(),
+                });
+            }
+            Ok(chunk
+                .names
+                .iter()
+                .map(|name| (name.clone(), Value::U16(1)))
+                .collect())
+        }
+    }
+
+    fn device(failing: &'static str, chunks: &[&[&str]]) -> ChunkedDevice<Mock> {
+        ChunkedDevice {
+            device: Mock {
+                reads: Vec::new(),
+                failing,
+            },
+            label: "127.0.0.1:502".to_string(),
+            chunks: Some(
+                chunks
+                    .iter()
+                    .map(|names| Chunk {
+                        class: RegisterClass::Holding,
+                        names: names.iter().map(|name| name.to_string()).collect(),
+                        registers: Vec::new(),
+                    })
+                    .collect(),
+            ),
+        }
+    }
+
+    #[tokio::test]
+    async fn each_chunk_is_read_in_one_request() {
+        let mut device = device("", &[&["a", "b", "c"], &["d"]]);
+        let values = device.dump_registers().await.unwrap();
+        assert_eq!(values.len(), 4);
+        assert_eq!(device.device.reads, vec![vec!["a", "b", "c"], vec!["d"]]);
+    }
+
+    #[tokio::test]
+    async fn failing_chunk_is_skipped() {
+        let mut device = device("a", &[&["a", "b"], &["c"]]);
+        let values = device.dump_registers().await.unwrap();
+        assert_eq!(values.keys().collect::<Vec<_>>(), vec!["c"]);
+    }
+
+    #[tokio::test]
+    async fn dump_fails_when_no_chunk_is_read() {
+        let mut device = device("a", &[&["a", "b"]]);
+        assert!(device.dump_registers().await.is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use std::collections::HashMap;
+
+use industrial_device::types::Value;
+use log::{debug, Level};
+use serde::Deserialize;
+
+use super::log_throttle;
+use crate::types_conversion::RegisterValue;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// What to do with a value outside of its bounds
+pub enum ClampPolicy {
+    /// Replace the value by the bound it crossed
+    #[default]
+    Clamp,
+    /// Do not forward the value
+    Drop,
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+/// Engineering bounds of a register
+///
+/// # Fields
+/// - `min`: Lowest valid value (default: no lower bound).
+/// - `max`: Highest valid value (default: no upper bound).
+/// - `policy`: What to do with a value outside of the bounds (`clamp` or `drop`, default `clamp`).
+pub struct Clamp {
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    #[serde(default)]
+    pub policy: ClampPolicy,
+}
+
+impl Clamp {
+    /// The bound crossed by `value`, if any
+    fn crossed(&self, value: f64) -> Option<f64> {
+        match (self.min, self.max) {
+            (Some(min), _) if value < min => Some(min),
+            (_, Some(max)) if value > max => Some(max),
+            _ => None,
+        }
+    }
+}
+
+/// The bound crossed by a value, in the type of the value, so that a register keeps its type on the
+/// clamped cycles (e.g. an InfluxDB field is not written once as an integer and once as a float).
+/// The integers take the nearest integer within the bounds, saturated to their type.
+///
+/// # Arguments
+///
+/// - `value` (`&RegisterValue`) - The value out of range
+/// - `bound` (`f64`) - The bound it crossed
+///
+/// # Returns
+///
+/// - `RegisterValue` - The bound, of the same variant as `value`
+fn clamped(value: &RegisterValue, bound: f64) -> RegisterValue {
+    let integer = match bound > value.as_f64() {
+        // The lower bound, rounded up to stay above it
+        true => bound.ceil(),
+        false => bound.floor(),
+    };
+    match value {
+        RegisterValue::Raw(Value::U16(_)) => Value::U16(integer as u16).into(),
+        RegisterValue::Raw(Value::U32(_)) => Value::U32(integer as u32).into(),
+        RegisterValue::Raw(Value::U64(_)) => Value::U64(integer as u64).into(),
+        RegisterValue::Raw(Value::U128(_)) => Value::U128(integer as u128).into(),
+        RegisterValue::Raw(Value::S16(_)) => Value::S16(integer as i16).into(),
+        RegisterValue::Raw(Value::S32(_)) => Value::S32(integer as i32).into(),
+        RegisterValue::Raw(Value::Float32(_)) => Value::Float32(bound as f32).into(),
+        RegisterValue::Int(_) => RegisterValue::Int(integer as i64),
+        _ => RegisterValue::Float(bound),
+    }
+}
+
+/// Apply the bounds of the registers to the values read on a device, non numeric registers are left untouched
+///
+/// # Arguments
+///
+/// - `device` (`&str`) - The name of the device (for logging)
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read
+/// - `bounds` (`&HashMap<String, Clamp>`) - The bounds, keyed by register name
+pub fn clamp_values(
+    device: &str,
+    values: &mut HashMap<String, RegisterValue>,
+    bounds: &HashMap<String, Clamp>,
+) {
+    for (register, clamp) in bounds {
+        let value = match values.get(register) {
+            // NaN is left as read, it is neither below nor above the bounds
+            Some(value) if value.is_numeric() => value.as_f64(),
+            _ => continue,
+        };
+        let bound = match clamp.crossed(value) {
+            Some(bound) => bound,
+            None => continue,
+        };
+        debug!("{register} of {device} out of range ({value})");
+        // Throttled per register, so that the successful reads of the device do not reset it
+        let source = format!("{device}/{register}");
+        match clamp.policy {
+            ClampPolicy::Clamp => {
+                log_throttle::log(
+                    &source,
+                    Level::Warn,
+                    format!("Out of range, clamped to {bound}"),
+                );
+                let value = clamped(&values[register], bound);
+                values.insert(register.clone(), value);
+            }
+            ClampPolicy::Drop => {
+                log_throttle::log(&source, Level::Warn, "Out of range, dropped".to_string());
+                values.remove(register);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn clamp(min: f64, max: f64, policy: ClampPolicy) -> HashMap<String, Clamp> {
+        HashMap::from([(
+            "level".to_string(),
+            Clamp {
+                min: Some(min),
+                max: Some(max),
+                policy,
+            },
+        )])
+    }
+
+    fn apply(value: RegisterValue, bounds: &HashMap<String, Clamp>) -> Option<RegisterValue> {
+        let mut values = HashMap::from([("level".to_string(), value)]);
+        clamp_values("tank", &mut values, bounds);
+        values.remove("level")
+    }
+
+    #[test]
+    fn int_register_stays_int() {
+        let bounds = clamp(0.5, 99.5, ClampPolicy::Clamp);
+        assert!(matches!(
+            apply(RegisterValue::Int(-3), &bounds),
+            Some(RegisterValue::Int(1))
+        ));
+        assert!(matches!(
+            apply(RegisterValue::Int(120), &bounds),
+            Some(RegisterValue::Int(99))
+        ));
+        assert!(matches!(
+            apply(RegisterValue::Int(50), &bounds),
+            Some(RegisterValue::Int(50))
+        ));
+    }
+
+    #[test]
+    fn raw_integer_register_keeps_its_type() {
+        let bounds = clamp(-10.0, 100.0, ClampPolicy::Clamp);
+        assert!(matches!(
+            apply(Value::S16(-40).into(), &bounds),
+            Some(RegisterValue::Raw(Value::S16(-10)))
+        ));
+        assert!(matches!(
+            apply(Value::U16(500).into(), &bounds),
+            Some(RegisterValue::Raw(Value::U16(100)))
+        ));
+    }
+
+    #[test]
+    fn float_register_is_clamped_to_the_bounds() {
+        let bounds = clamp(0.5, 99.5, ClampPolicy::Clamp);
+        assert!(matches!(
+            apply(RegisterValue::Float(-3.2), &bounds),
+            Some(RegisterValue::Float(bound)) if bound == 0.5
+        ));
+        assert!(matches!(
+            apply(RegisterValue::Float(120.0), &bounds),
+            Some(RegisterValue::Float(bound)) if bound == 99.5
+        ));
+        assert!(matches!(
+            apply(Value::Float32(120.0).into(), &bounds),
+            Some(RegisterValue::Raw(Value::Float32(bound))) if bound == 99.5
+        ));
+    }
+
+    #[test]
+    fn out_of_range_value_is_dropped() {
+        let bounds = clamp(0.0, 100.0, ClampPolicy::Drop);
+        assert!(apply(RegisterValue::Int(-1), &bounds).is_none());
+        assert!(apply(RegisterValue::Float(100.5), &bounds).is_none());
+        assert!(apply(RegisterValue::Float(42.0), &bounds).is_some());
+    }
+
+    #[test]
+    fn nan_is_left_as_read() {
+        let bounds = clamp(0.0, 100.0, ClampPolicy::Drop);
+        assert!(matches!(
+            apply(RegisterValue::Float(f64::NAN), &bounds),
+            Some(RegisterValue::Float(val)) if val.is_nan()
+        ));
+    }
+}
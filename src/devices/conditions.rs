@@ -0,0 +1,145 @@
+use std::collections::HashMap;
+
+use evalexpr::{build_operator_tree, ContextWithMutableVariables, EvalexprError, HashMapContext, Node};
+use industrial_device::types::Value;
+use log::Level;
+use serde::Deserialize;
+
+use super::log_throttle;
+use crate::types_conversion::RegisterValue;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(try_from = "String")]
+/// Condition a register is pushed on, an expression over the registers of the same device
+/// (e.g. `state == 1 && temp > 20`), parsed when the configuration is loaded
+pub struct Condition {
+    node: Node,
+}
+
+impl TryFrom<String> for Condition {
+    type Error = EvalexprError;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        Ok(Condition {
+            node: build_operator_tree(&value)?,
+        })
+    }
+}
+
+/// The value of a register as seen by the conditions, `None` for the byte arrays
+fn variable(value: &RegisterValue) -> Option<evalexpr::Value> {
+    let value = match value {
+        RegisterValue::Raw(value) => value,
+        RegisterValue::Float(val) => return Some(evalexpr::Value::Float(*val)),
+        RegisterValue::Int(val) => return Some(evalexpr::Value::Int(*val)),
+        RegisterValue::Text(val) => return Some(evalexpr::Value::String(val.clone())),
+    };
+    Some(match value {
+        Value::U16(val) => evalexpr::Value::Int((*val).into()),
+        Value::U32(val) => evalexpr::Value::Int((*val).into()),
+        Value::S16(val) => evalexpr::Value::Int((*val).into()),
+        Value::S32(val) => evalexpr::Value::Int((*val).into()),
+        Value::Enum16(val) => evalexpr::Value::Int((*val).into()),
+        Value::U64(val) => match i64::try_from(*val) {
+            Ok(val) => evalexpr::Value::Int(val),
+            Err(_) => evalexpr::Value::Float(*val as f64),
+        },
+        Value::U128(val) => match i64::try_from(*val) {
+            Ok(val) => evalexpr::Value::Int(val),
+            Err(_) => evalexpr::Value::Float(*val as f64),
+        },
+        Value::Float32(val) => evalexpr::Value::Float((*val).into()),
+        Value::Boolean(val) => evalexpr::Value::Boolean(*val),
+        Value::Sized(_) => return None,
+    })
+}
+
+/// The variables of the conditions, one per register value
+fn context(values: &HashMap<String, RegisterValue>) -> HashMapContext {
+    let mut context = HashMapContext::new();
+    for (name, value) in values.iter() {
+        if let Some(value) = variable(value) {
+            let _ = context.set_value(name.clone(), value);
+        }
+    }
+    context
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// Register read alone on every cycle, the other registers of the device are only read
+/// on the cycles where the condition holds (e.g. a "data ready" flag)
+///
+/// # Fields
+/// - `register`: The name of the trigger register.
+/// - `condition`: Expression over the trigger register (e.g. `data_ready == 1`).
+pub struct Trigger {
+    pub register: String,
+    pub condition: Condition,
+}
+
+impl Trigger {
+    /// Whether the full read is triggered, a condition that can't be evaluated
+    /// (e.g. the trigger register was not read) does not trigger it
+    ///
+    /// # Arguments
+    ///
+    /// - `device` (`&str`) - The name of the device (for logging)
+    /// - `values` (`&HashMap<String, RegisterValue>`) - The values read, holding the trigger register
+    ///
+    /// # Returns
+    ///
+    /// - `bool` - Whether the condition holds
+    pub fn fires(&self, device: &str, values: &HashMap<String, RegisterValue>) -> bool {
+        match self.condition.node.eval_boolean_with_context(&context(values)) {
+            Ok(fires) => fires,
+            Err(err) => {
+                log_throttle::log(
+                    &format!("{device}/{}", self.register),
+                    Level::Warn,
+                    format!("Could not evaluate the trigger, only the trigger register is read ({err})"),
+                );
+                false
+            }
+        }
+    }
+}
+
+/// Remove the registers whose condition does not hold, a condition that can't be evaluated
+/// (e.g. a register that was not read this cycle) also removes its register
+///
+/// # Arguments
+///
+/// - `device` (`&str`) - The name of the device (for logging)
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read
+/// - `conditions` (`&HashMap<String, Condition>`) - The conditions, keyed by register name
+pub fn filter_values(
+    device: &str,
+    values: &mut HashMap<String, RegisterValue>,
+    conditions: &HashMap<String, Condition>,
+) {
+    if conditions.is_empty() {
+        return;
+    }
+    // Every condition sees all the values read, before any is removed
+    let context = context(values);
+
+    for (register, condition) in conditions {
+        if !values.contains_key(register) {
+            continue;
+        }
+        match condition.node.eval_boolean_with_context(&context) {
+            Ok(true) => {}
+            Ok(false) => {
+                values.remove(register);
+            }
+            Err(err) => {
+                log_throttle::log(
+                    &format!("{device}/{register}"),
+                    Level::Warn,
+                    format!("Could not evaluate the condition, skipping the register ({err})"),
+                );
+                values.remove(register);
+            }
+        }
+    }
+}
@@ -0,0 +1,403 @@
+use std::collections::HashMap;
+
+use industrial_device::types::Value;
+use log::Level;
+use serde::Deserialize;
+
+use super::log_throttle;
+use crate::types_conversion::RegisterValue;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Type a value spanning several registers is decoded to
+pub enum DecodeType {
+    U64,
+    I64,
+    F64,
+    String,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Order of the words in a value, or of the bytes in a word
+pub enum Order {
+    /// Most significant first (Modbus default)
+    #[default]
+    Big,
+    /// Least significant first
+    Little,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How a signed integer is encoded in the bits of a register
+pub enum SignedEncoding {
+    /// The usual encoding, the value minus 2^n when the most significant bit is set
+    TwosComplement,
+    /// The most significant bit is the sign, the others the magnitude
+    SignMagnitude,
+    /// The value plus 2^(n-1) (e.g. 0x8000 is 0 in 16 bits)
+    OffsetBinary,
+}
+
+impl SignedEncoding {
+    /// Read the `bits` lowest bits of `raw` as a signed integer
+    fn decode(&self, raw: u64, bits: u32) -> i64 {
+        let sign = 1u64 << (bits - 1);
+        let mask = match bits {
+            64 => u64::MAX,
+            _ => (1u64 << bits) - 1,
+        };
+        let twos_complement = |raw: u64| match raw & sign {
+            0 => raw as i64,
+            _ => (raw | !mask) as i64,
+        };
+        let raw = raw & mask;
+        match self {
+            SignedEncoding::TwosComplement => twos_complement(raw),
+            SignedEncoding::SignMagnitude => {
+                let magnitude = (raw & !sign) as i64;
+                match raw & sign {
+                    0 => magnitude,
+                    _ => -magnitude,
+                }
+            }
+            SignedEncoding::OffsetBinary => twos_complement(raw ^ sign),
+        }
+    }
+
+    /// Reinterpret the bits of a value read as an integer
+    ///
+    /// # Returns
+    ///
+    /// - `Option<RegisterValue>` - The signed value, of the width of the register, `None` if the value is not an integer
+    fn apply(&self, value: &RegisterValue) -> Option<RegisterValue> {
+        let (raw, bits) = match value {
+            RegisterValue::Raw(Value::U16(val)) => (*val as u64, 16),
+            RegisterValue::Raw(Value::S16(val)) => (*val as u16 as u64, 16),
+            RegisterValue::Raw(Value::Enum16(val)) => (*val as u64, 16),
+            RegisterValue::Raw(Value::U32(val)) => (*val as u64, 32),
+            RegisterValue::Raw(Value::S32(val)) => (*val as u32 as u64, 32),
+            RegisterValue::Raw(Value::U64(val)) => (*val, 64),
+            RegisterValue::Int(val) => (*val as u64, 64),
+            _ => return None,
+        };
+        let value = self.decode(raw, bits);
+        Some(match bits {
+            16 => Value::S16(value as i16).into(),
+            32 => Value::S32(value as i32).into(),
+            _ => RegisterValue::Int(value),
+        })
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// How to decode a value spanning several registers
+///
+/// # Fields
+/// - `kind`: The type of the value (`u64`, `i64`, `f64` or `string`), given as `type`.
+/// - `registers`: Number of registers the value spans, checked against the data read (default: not checked).
+/// - `from`: Registers holding the words of the value, in address order. The decoded value replaces them
+///   (default: the register of the same name, read as a byte array or a wide integer).
+/// - `word_order`: Order of the registers in the value (default `big`).
+/// - `byte_order`: Order of the bytes in each register (default `big`).
+pub struct Decode {
+    #[serde(rename = "type")]
+    pub kind: DecodeType,
+    pub registers: Option<usize>,
+    #[serde(default)]
+    pub from: Vec<String>,
+    #[serde(default)]
+    pub word_order: Order,
+    #[serde(default)]
+    pub byte_order: Order,
+}
+
+/// The 16 bits words of a value as read from the device (most significant first)
+fn words(value: &RegisterValue) -> Option<Vec<u16>> {
+    let value = match value {
+        RegisterValue::Raw(value) => value,
+        _ => return None,
+    };
+    let bytes = match value {
+        Value::U16(val) => val.to_be_bytes().to_vec(),
+        Value::S16(val) => val.to_be_bytes().to_vec(),
+        Value::Enum16(val) => val.to_be_bytes().to_vec(),
+        Value::U32(val) => val.to_be_bytes().to_vec(),
+        Value::S32(val) => val.to_be_bytes().to_vec(),
+        Value::U64(val) => val.to_be_bytes().to_vec(),
+        Value::U128(val) => val.to_be_bytes().to_vec(),
+        Value::Sized(val) if val.len() % 2 == 0 => val.clone(),
+        _ => return None,
+    };
+    Some(
+        bytes
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect(),
+    )
+}
+
+impl Decode {
+    /// Assemble the words into the bytes of the value, most significant first
+    fn bytes(&self, mut words: Vec<u16>) -> Vec<u8> {
+        if self.word_order == Order::Little {
+            words.reverse();
+        }
+        words
+            .into_iter()
+            .flat_map(|word| match self.byte_order {
+                Order::Big => word.to_be_bytes(),
+                Order::Little => word.to_le_bytes(),
+            })
+            .collect()
+    }
+
+    /// Decode the words of a value
+    ///
+    /// # Returns
+    ///
+    /// - `Result<RegisterValue, String>` - The value, or why it could not be decoded
+    fn decode(&self, words: Vec<u16>) -> Result<RegisterValue, String> {
+        if let Some(registers) = self.registers {
+            if words.len() != registers {
+                return Err(format!("expected {registers} registers, got {}", words.len()));
+            }
+        }
+        let bytes = self.bytes(words);
+        if self.kind == DecodeType::String {
+            let text = String::from_utf8_lossy(&bytes);
+            return Ok(RegisterValue::Text(
+                text.trim_end_matches(['\0', ' ']).to_string(),
+            ));
+        }
+        let bytes: [u8; 8] = match bytes.try_into() {
+            Ok(bytes) => bytes,
+            Err(bytes) => return Err(format!("expected 4 registers, got {}", bytes.len() / 2)),
+        };
+        Ok(match self.kind {
+            DecodeType::U64 => RegisterValue::Raw(Value::U64(u64::from_be_bytes(bytes))),
+            DecodeType::I64 => RegisterValue::Int(i64::from_be_bytes(bytes)),
+            DecodeType::F64 => RegisterValue::Float(f64::from_be_bytes(bytes)),
+            DecodeType::String => unreachable!(),
+        })
+    }
+}
+
+/// Decode the values spanning several registers, the values that can't be decoded are left as read
+///
+/// # Arguments
+///
+/// - `device` (`&str`) - The name of the device (for logging)
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read
+/// - `decodes` (`&HashMap<String, Decode>`) - How to decode the values, keyed by the name of the decoded value
+pub fn decode_values(
+    device: &str,
+    values: &mut HashMap<String, RegisterValue>,
+    decodes: &HashMap<String, Decode>,
+) {
+    for (name, decode) in decodes {
+        let sources = match decode.from.is_empty() {
+            true => std::slice::from_ref(name),
+            false => decode.from.as_slice(),
+        };
+        // Skipped this cycle (e.g. `poll_every`)
+        if !sources.iter().all(|source| values.contains_key(source)) {
+            continue;
+        }
+        let words: Option<Vec<u16>> = sources
+            .iter()
+            .map(|source| words(&values[source]))
+            .collect::<Option<Vec<Vec<u16>>>>()
+            .map(|words| words.concat());
+        let res = match words {
+            Some(words) => decode.decode(words),
+            None => Err("unsupported register type".to_string()),
+        };
+        match res {
+            Ok(value) => {
+                for source in sources {
+                    values.remove(source);
+                }
+                values.insert(name.clone(), value);
+            }
+            Err(err) => log_throttle::log(
+                &format!("{device}/{name}"),
+                Level::Warn,
+                format!("Could not decode the value ({err})"),
+            ),
+        }
+    }
+}
+
+/// Read the integer registers whose signed encoding is given with this encoding,
+/// the registers that are not integers are left as read
+///
+/// # Arguments
+///
+/// - `device` (`&str`) - The name of the device (for logging)
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read, after decoding
+/// - `encodings` (`&HashMap<String, SignedEncoding>`) - The encoding of the registers, keyed by register name
+pub fn apply_encodings(
+    device: &str,
+    values: &mut HashMap<String, RegisterValue>,
+    encodings: &HashMap<String, SignedEncoding>,
+) {
+    for (name, encoding) in encodings {
+        let value = match values.get_mut(name) {
+            Some(value) => value,
+            None => continue,
+        };
+        match encoding.apply(value) {
+            Some(signed) => *value = signed,
+            None => log_throttle::log(
+                &format!("{device}/{name}"),
+                Level::Warn,
+                "The register is not an integer, its encoding is ignored".to_string(),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(kind: DecodeType, registers: usize, from: &[&str]) -> Decode {
+        Decode {
+            kind,
+            registers: Some(registers),
+            from: from.iter().map(|source| source.to_string()).collect(),
+            word_order: Order::Big,
+            byte_order: Order::Big,
+        }
+    }
+
+    /// The registers `{prefix}0`, `{prefix}1`... holding the words
+    fn registers(prefix: &str, words: &[u16]) -> HashMap<String, RegisterValue> {
+        words
+            .iter()
+            .enumerate()
+            .map(|(i, word)| {
+                (
+                    format!("{prefix}{i}"),
+                    RegisterValue::Raw(Value::U16(*word)),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn four_registers_decode_to_a_f64() {
+        let words: Vec<u16> = 1234.5f64
+            .to_be_bytes()
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect();
+        let mut values = registers("energy", &words);
+        let decodes = HashMap::from([(
+            "energy".to_string(),
+            decode(
+                DecodeType::F64,
+                4,
+                &["energy0", "energy1", "energy2", "energy3"],
+            ),
+        )]);
+        decode_values("meter", &mut values, &decodes);
+        assert_eq!(values.len(), 1);
+        assert_eq!(values["energy"], RegisterValue::Float(1234.5));
+    }
+
+    #[test]
+    fn eight_registers_decode_to_a_string() {
+        let mut words: Vec<u16> = b"SN-20481"
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect();
+        words.extend([0x2020, 0x2020, 0x0000, 0x0000]);
+        let from: Vec<String> = (0..8).map(|i| format!("serial{i}")).collect();
+        let from: Vec<&str> = from.iter().map(String::as_str).collect();
+        let mut values = registers("serial", &words);
+        let decodes = HashMap::from([("serial".to_string(), decode(DecodeType::String, 8, &from))]);
+        decode_values("meter", &mut values, &decodes);
+        assert_eq!(
+            values["serial"],
+            RegisterValue::Text("SN-20481".to_string())
+        );
+    }
+
+    #[test]
+    fn word_and_byte_order_are_honored() {
+        let mut decode = decode(DecodeType::U64, 4, &[]);
+        decode.word_order = Order::Little;
+        let value = decode.decode(vec![0x0004, 0x0003, 0x0002, 0x0001]).unwrap();
+        assert_eq!(value, RegisterValue::Raw(Value::U64(0x0001_0002_0003_0004)));
+        decode.byte_order = Order::Little;
+        let value = decode.decode(vec![0x0400, 0x0300, 0x0200, 0x0100]).unwrap();
+        assert_eq!(value, RegisterValue::Raw(Value::U64(0x0001_0002_0003_0004)));
+    }
+
+    #[test]
+    fn wrong_register_count_is_left_as_read() {
+        let mut values = registers("energy", &[1, 2, 3]);
+        let decodes = HashMap::from([(
+            "energy".to_string(),
+            decode(DecodeType::I64, 4, &["energy0", "energy1", "energy2"]),
+        )]);
+        decode_values("meter", &mut values, &decodes);
+        assert_eq!(values.len(), 3);
+        assert!(!values.contains_key("energy"));
+    }
+
+    #[test]
+    fn byte_array_register_is_decoded_in_place() {
+        let mut values = HashMap::from([(
+            "counter".to_string(),
+            RegisterValue::Raw(Value::Sized((-42i64).to_be_bytes().to_vec())),
+        )]);
+        let decodes = HashMap::from([("counter".to_string(), decode(DecodeType::I64, 4, &[]))]);
+        decode_values("meter", &mut values, &decodes);
+        assert_eq!(values["counter"], RegisterValue::Int(-42));
+    }
+
+    #[test]
+    fn negative_value_is_decoded_under_each_encoding() {
+        let cases = [
+            (SignedEncoding::TwosComplement, 0xfffb, 0xffff_fffb),
+            (SignedEncoding::SignMagnitude, 0x8005, 0x8000_0005),
+            (SignedEncoding::OffsetBinary, 0x7ffb, 0x7fff_fffb),
+        ];
+        for (encoding, word, double_word) in cases {
+            assert_eq!(
+                encoding.apply(&RegisterValue::Raw(Value::U16(word))),
+                Some(RegisterValue::Raw(Value::S16(-5)))
+            );
+            assert_eq!(
+                encoding.apply(&RegisterValue::Raw(Value::U32(double_word))),
+                Some(RegisterValue::Raw(Value::S32(-5)))
+            );
+        }
+    }
+
+    #[test]
+    fn encodings_are_applied_by_register_name() {
+        let encodings: HashMap<String, SignedEncoding> =
+            serde_json::from_value(serde_json::json!({
+                "power": "sign_magnitude",
+                "offset": "offset_binary",
+                "label": "sign_magnitude",
+            }))
+            .unwrap();
+        let mut values = HashMap::from([
+            ("power".to_string(), RegisterValue::Raw(Value::U16(0x8005))),
+            ("offset".to_string(), RegisterValue::Raw(Value::U16(0x8005))),
+            ("label".to_string(), RegisterValue::Text("A".to_string())),
+            ("speed".to_string(), RegisterValue::Raw(Value::U16(0x8005))),
+        ]);
+        apply_encodings("drive1", &mut values, &encodings);
+        assert_eq!(values["power"], RegisterValue::Raw(Value::S16(-5)));
+        assert_eq!(values["offset"], RegisterValue::Raw(Value::S16(5)));
+        assert_eq!(values["label"], RegisterValue::Text("A".to_string()));
+        assert_eq!(values["speed"], RegisterValue::Raw(Value::U16(0x8005)));
+    }
+}
@@ -0,0 +1,483 @@
+use std::{
+    collections::HashMap,
+    fs::{self, File},
+    io::{self, Read},
+    path::{Path, PathBuf},
+    sync::Mutex,
+    thread,
+    time::Duration,
+};
+
+use log::{info, warn};
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::errors::DeviceInitError;
+
+pub mod csv;
+
+/// URLs already downloaded by this process, the cached copy is reused for them
+static FETCHED: Mutex<Vec<String>> = Mutex::new(Vec::new());
+/// How the local definitions are opened, set once from the configuration
+static RETRY: Mutex<DefinitionRetry> = Mutex::new(DefinitionRetry::DEFAULT);
+/// Where the downloaded and converted definitions are written, set once from the configuration
+static CACHE_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+#[serde(default)]
+/// Retry of the local definition files that can't be opened (e.g. not written yet by a config-management tool)
+///
+/// # Fields
+/// - `attempts`: Number of times the file is opened before giving up (default 3, at least 1).
+/// - `delay_ms`: Time waited between two attempts (default 500).
+pub struct DefinitionRetry {
+    pub attempts: u32,
+    pub delay_ms: u64,
+}
+
+impl DefinitionRetry {
+    const DEFAULT: DefinitionRetry = DefinitionRetry {
+        attempts: 3,
+        delay_ms: 500,
+    };
+}
+
+impl Default for DefinitionRetry {
+    fn default() -> Self {
+        DefinitionRetry::DEFAULT
+    }
+}
+
+/// Set how the local definitions are opened, before the devices are created
+pub fn set_retry(retry: DefinitionRetry) {
+    *RETRY.lock().unwrap() = retry;
+}
+
+/// Set the directory the downloaded and converted definitions are written to, before the devices are created
+/// (default: `$XDG_CACHE_HOME/industrial_bridge`, see [`cache_dir`])
+pub fn set_cache_dir(dir: Option<PathBuf>) {
+    *CACHE_DIR.lock().unwrap() = dir;
+}
+
+/// Open a definition file, `path` being either a local path or an http(s) URL.
+/// Remote definitions are downloaded once and cached on disk, the cached copy is used
+/// if the download fails later (e.g. the config service is down at startup).
+/// A `.csv` definition is converted to its JSON form (see [`csv::csv_to_json`]),
+/// so every device library reading JSON definitions accepts it.
+/// With a `pointer`, only the sub-document it points to is opened (see [`extract_definition`]).
+///
+/// # Arguments
+///
+/// - `path` (`&str`) - Path or URL of the definition
+/// - `pointer` (`Option<&str>`) - JSON Pointer to the definition within the file (e.g. `/registers`)
+/// - `fields` (`&[&str]`) - The fields of a register of the device type, the columns a CSV definition may have
+///
+/// # Returns
+///
+/// - `Result<File, DeviceInitError>` - The opened (cached) definition file
+pub fn open_definition(
+    path: &str,
+    pointer: Option<&str>,
+    fields: &[&str],
+) -> Result<File, DeviceInitError> {
+    let file = open_source(path)?;
+    let file = match is_csv(path) {
+        true => csv_definition(path, file, fields)?,
+        false => file,
+    };
+    match pointer {
+        Some(pointer) => extract_definition(path, pointer, file),
+        None => Ok(file),
+    }
+}
+
+/// The registers of a definition, every definition being a JSON object keyed by register name
+fn definition_entries(
+    path: &str,
+    pointer: Option<&str>,
+    fields: &[&str],
+) -> Result<HashMap<String, serde_json::Value>, DeviceInitError> {
+    Ok(serde_json::from_reader(open_definition(
+        path, pointer, fields,
+    )?)?)
+}
+
+/// The names of the registers of a definition
+///
+/// # Arguments
+///
+/// - `path` (`&str`) - Path or URL of the definition
+/// - `pointer` (`Option<&str>`) - JSON Pointer to the definition within the file
+/// - `fields` (`&[&str]`) - The fields of a register of the device type
+///
+/// # Returns
+///
+/// - `Result<Vec<String>, DeviceInitError>` - The names of the registers, sorted
+pub fn register_names(
+    path: &str,
+    pointer: Option<&str>,
+    fields: &[&str],
+) -> Result<Vec<String>, DeviceInitError> {
+    let mut names: Vec<String> = definition_entries(path, pointer, fields)?
+        .into_keys()
+        .collect();
+    names.sort();
+    Ok(names)
+}
+
+/// Check that registers have the expected type in the definitions of a device,
+/// to catch a definition managed by another tool drifting from what the configuration expects
+///
+/// # Arguments
+///
+/// - `paths` (`&[&str]`) - Paths or URLs of the definitions of the device
+/// - `pointer` (`Option<&str>`) - JSON Pointer to the definitions within the files
+/// - `fields` (`&[&str]`) - The fields of a register of the device type
+/// - `key` (`&str`) - The field holding the type of a register in the definitions
+/// - `types` (`&HashMap<String, String>`) - The expected type of the registers, keyed by register name
+///
+/// # Returns
+///
+/// - `Result<(), DeviceInitError>` - `TypeMismatch` for the first register whose type differs (case is ignored),
+///   `TypedRegisterNotFound` if a register is in none of the definitions
+pub fn check_types(
+    paths: &[&str],
+    pointer: Option<&str>,
+    fields: &[&str],
+    key: &str,
+    types: &HashMap<String, String>,
+) -> Result<(), DeviceInitError> {
+    let mut entries = HashMap::new();
+    for path in paths {
+        entries.extend(definition_entries(path, pointer, fields)?);
+    }
+    let mut types: Vec<(&String, &String)> = types.iter().collect();
+    types.sort();
+    for (register, expected) in types {
+        let Some(entry) = entries.get(register) else {
+            return Err(DeviceInitError::TypedRegisterNotFound {
+                register: register.clone(),
+            });
+        };
+        let found = entry
+            .get(key)
+            .and_then(|found| found.as_str())
+            .unwrap_or("none");
+        if !found.eq_ignore_ascii_case(expected) {
+            return Err(DeviceInitError::TypeMismatch {
+                register: register.clone(),
+                expected: expected.clone(),
+                found: found.to_string(),
+            });
+        }
+    }
+    Ok(())
+}
+
+/// Whether the definition at `path` is a CSV file (judging by its extension, query string excluded)
+fn is_csv(path: &str) -> bool {
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    Path::new(path)
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("csv"))
+}
+
+/// Convert the CSV definition `file`, whose columns are `name` and some of `fields`, and open its JSON form
+fn csv_definition(path: &str, mut file: File, fields: &[&str]) -> Result<File, DeviceInitError> {
+    let mut text = String::new();
+    file.read_to_string(&mut text)?;
+    let definition = csv::csv_to_json(&text, fields)?;
+    open_converted(path, &definition)
+}
+
+/// Open the sub-document of the definition `file` at `pointer`, a JSON Pointer (RFC 6901, e.g. `/registers`
+/// for `{"registers": {...}}`), for the definition files wrapping the registers in an object
+fn extract_definition(path: &str, pointer: &str, file: File) -> Result<File, DeviceInitError> {
+    let definition: serde_json::Value = serde_json::from_reader(file)?;
+    let Some(extracted) = definition.pointer(pointer) else {
+        return Err(DeviceInitError::DefinitionPointerNotFound {
+            path: path.to_string(),
+            pointer: pointer.to_string(),
+        });
+    };
+    open_converted(&format!("{path}#{pointer}"), extracted)
+}
+
+/// Write a definition converted from `key` (its path, and the pointer extracted) next to the cached downloads, and open it
+fn open_converted(key: &str, definition: &serde_json::Value) -> Result<File, DeviceInitError> {
+    let converted = cache_path(key)?.with_extension("json");
+    fs::write(&converted, definition.to_string())?;
+    Ok(File::open(converted)?)
+}
+
+/// Open the definition as it is, downloading (and caching) it if `path` is a URL
+fn open_source(path: &str) -> Result<File, DeviceInitError> {
+    if !(path.starts_with("http://") || path.starts_with("https://")) {
+        return open_local(path);
+    }
+
+    let cache = cache_path(path)?;
+    let mut fetched = FETCHED.lock().unwrap();
+    if !fetched.iter().any(|url| url == path) {
+        match download(path) {
+            Ok(body) => {
+                info!("Downloaded definition {path}");
+                fs::write(&cache, body)?;
+                fetched.push(path.to_string());
+            }
+            Err(err) if cache.exists() => {
+                warn!("Could not download definition {path} ({err}), using the cached copy")
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(File::open(cache)?)
+}
+
+/// Open a local definition, trying again after a delay while it can't be opened
+fn open_local(path: &str) -> Result<File, DeviceInitError> {
+    let retry = *RETRY.lock().unwrap();
+    let attempts = retry.attempts.max(1);
+    let mut attempt = 1;
+    loop {
+        match File::open(path) {
+            Ok(file) => return Ok(file),
+            Err(err) if attempt < attempts => {
+                warn!("Could not open definition {path} ({err}), retrying ({attempt}/{attempts})");
+                thread::sleep(Duration::from_millis(retry.delay_ms));
+                attempt += 1;
+            }
+            Err(err) => {
+                return Err(DeviceInitError::DefinitionNotFound {
+                    path: path.to_string(),
+                    attempts,
+                    err,
+                })
+            }
+        }
+    }
+}
+
+/// The directory the downloaded and converted definitions are written to: `definition_cache_dir` if set,
+/// `$XDG_CACHE_HOME/industrial_bridge` (`~/.cache/industrial_bridge`) otherwise. It is created private
+/// (see [`private_dir`]), a definition planted in a shared directory would otherwise be used as the fallback.
+fn cache_dir() -> io::Result<PathBuf> {
+    let configured = CACHE_DIR.lock().unwrap().clone();
+    let dir = match configured {
+        Some(dir) => dir,
+        None => std::env::var_os("XDG_CACHE_HOME")
+            .filter(|dir| !dir.is_empty())
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))
+            .ok_or_else(|| {
+                io::Error::other("no cache directory (HOME is not set), set definition_cache_dir")
+            })?
+            .join("industrial_bridge"),
+    };
+    private_dir(&dir)?;
+    Ok(dir)
+}
+
+/// Create the directory `dir` if missing, only readable and writable by the bridge (0700 on Unix).
+/// An existing directory is made private, which fails if it belongs to another user, and a symbolic link is refused.
+fn private_dir(dir: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::{DirBuilderExt, PermissionsExt};
+
+        fs::DirBuilder::new()
+            .recursive(true)
+            .mode(0o700)
+            .create(dir)?;
+        if fs::symlink_metadata(dir)?.file_type().is_symlink() {
+            return Err(io::Error::other(format!(
+                "the definition cache {} is a symbolic link",
+                dir.display()
+            )));
+        }
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+    #[cfg(not(unix))]
+    fs::create_dir_all(dir)?;
+    Ok(())
+}
+
+/// The name of the file caching the definition downloaded from (or converted from) `url`:
+/// the SHA-256 of `url`, stable across builds, followed by the last segment of `url` for readability
+fn cache_name(url: &str) -> String {
+    let name: String = url
+        .rsplit('/')
+        .next()
+        .unwrap_or_default()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '.' {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    format!("{:x}-{name}", Sha256::digest(url.as_bytes()))
+}
+
+/// Where the definition downloaded from `url` (or converted from `url`) is cached
+fn cache_path(url: &str) -> io::Result<PathBuf> {
+    Ok(cache_dir()?.join(cache_name(url)))
+}
+
+/// Download `url`, the blocking client runs on its own thread to stay out of the async runtime
+fn download(url: &str) -> Result<Vec<u8>, DeviceInitError> {
+    let url = url.to_string();
+    let res = thread::spawn(move || -> Result<Vec<u8>, reqwest::Error> {
+        let res = reqwest::blocking::get(url)?.error_for_status()?;
+        Ok(res.bytes()?.to_vec())
+    })
+    .join()
+    .map_err(|_| std::io::Error::other("The download thread panicked"))?;
+    Ok(res?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cache_name_is_the_sha256_of_the_url() {
+        assert_eq!(
+            cache_name("https://example.com/defs/press 1.json"),
+            "1ccf1d9e97c19ed19d3730bbae4efa10ff8dd9fdb28fad7ad6741aa50da62069-press_1.json"
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn cache_dir_is_created_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        let dir = root.path().join("cache").join("industrial_bridge");
+        private_dir(&dir).unwrap();
+        let mode = fs::metadata(&dir).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shared_cache_dir_is_made_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let root = tempfile::tempdir().unwrap();
+        fs::set_permissions(root.path(), fs::Permissions::from_mode(0o777)).unwrap();
+        private_dir(root.path()).unwrap();
+        let mode = fs::metadata(root.path()).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o700);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinked_cache_dir_is_refused() {
+        let root = tempfile::tempdir().unwrap();
+        let target = root.path().join("target");
+        fs::create_dir(&target).unwrap();
+        let link = root.path().join("link");
+        std::os::unix::fs::symlink(&target, &link).unwrap();
+        assert!(private_dir(&link).is_err());
+    }
+
+    /// Write a definition in `dir`, returning its path
+    fn definition(dir: &tempfile::TempDir, name: &str, content: serde_json::Value) -> String {
+        let path = dir.path().join(name);
+        fs::write(&path, content.to_string()).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn expected_types_match_the_definitions() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = definition(
+            &dir,
+            "input.json",
+            serde_json::json!({"temperature": {"addr": 0, "type": "f32"}}),
+        );
+        let holding = definition(
+            &dir,
+            "holding.json",
+            serde_json::json!({"setpoint": {"addr": 10, "type": "u16"}}),
+        );
+        let types = HashMap::from([
+            ("temperature".to_string(), "F32".to_string()),
+            ("setpoint".to_string(), "u16".to_string()),
+        ]);
+        check_types(&[&input, &holding], None, &[], "type", &types).unwrap();
+    }
+
+    #[test]
+    fn conflicting_type_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = definition(
+            &dir,
+            "registers.json",
+            serde_json::json!({"temperature": {"addr": 0, "type": "u16"}}),
+        );
+        let types = HashMap::from([("temperature".to_string(), "f32".to_string())]);
+        let err = check_types(&[&path], None, &[], "type", &types).unwrap_err();
+        assert!(matches!(
+            err,
+            DeviceInitError::TypeMismatch { register, expected, found }
+                if register == "temperature" && expected == "f32" && found == "u16"
+        ));
+    }
+
+    #[test]
+    fn typed_register_missing_from_the_definitions_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = definition(
+            &dir,
+            "registers.json",
+            serde_json::json!({"temperature": {"addr": 0, "type": "u16"}}),
+        );
+        let types = HashMap::from([("pressure".to_string(), "u16".to_string())]);
+        assert!(matches!(
+            check_types(&[&path], None, &[], "type", &types),
+            Err(DeviceInitError::TypedRegisterNotFound { register }) if register == "pressure"
+        ));
+    }
+
+    #[test]
+    fn registers_are_read_at_the_pointer() {
+        let dir = tempfile::tempdir().unwrap();
+        set_cache_dir(Some(dir.path().join("cache")));
+        let path = definition(
+            &dir,
+            "wrapped.json",
+            serde_json::json!({
+                "version": 2,
+                "device": {"registers": {
+                    "temperature": {"addr": 0, "type": "u16"},
+                    "pressure": {"addr": 1, "type": "u16"},
+                }},
+            }),
+        );
+        let names = register_names(&path, Some("/device/registers"), &[]).unwrap();
+        assert_eq!(names, ["pressure", "temperature"]);
+        let types = HashMap::from([("pressure".to_string(), "u16".to_string())]);
+        check_types(&[&path], Some("/device/registers"), &[], "type", &types).unwrap();
+        set_cache_dir(None);
+    }
+
+    #[test]
+    fn pointer_leading_nowhere_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = definition(
+            &dir,
+            "wrapped.json",
+            serde_json::json!({"registers": {"temperature": {"addr": 0, "type": "u16"}}}),
+        );
+        // Missing key, and a pointer not starting with `/`
+        for pointer in ["/definitions", "registers"] {
+            let err = open_definition(&path, Some(pointer), &[]).unwrap_err();
+            assert!(matches!(
+                err,
+                DeviceInitError::DefinitionPointerNotFound { pointer: found, .. } if found == pointer
+            ));
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use serde_json::{Map, Number, Value};
+
+use crate::devices::errors::DeviceInitError;
+
+/// Columns whose cells are kept as text, even when they look like numbers
+const TEXT_COLUMNS: [&str; 3] = ["name", "type", "unit"];
+/// Columns whose cells must be unsigned integers
+const INTEGER_COLUMNS: [&str; 1] = ["address"];
+/// Columns whose cells must be numbers
+const NUMBER_COLUMNS: [&str; 2] = ["scale", "offset"];
+
+/// An error of the CSV definition, on line `line` of the file
+fn csv_error(line: usize, reason: String) -> DeviceInitError {
+    DeviceInitError::ParsingFailed {
+        reason: format!("CSV definition, line {line}: {reason}"),
+    }
+}
+
+/// Split a CSV text in records (RFC 4180: comma separated, fields optionally quoted with `"`,
+/// `""` standing for a quote in a quoted field), the empty lines are left out
+///
+/// # Returns
+///
+/// - `Result<Vec<(usize, Vec<String>)>, DeviceInitError>` - The fields of each record, with the line it starts on
+fn records(text: &str) -> Result<Vec<(usize, Vec<String>)>, DeviceInitError> {
+    let mut res = Vec::new();
+    let mut record: Vec<String> = Vec::new();
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut line = 1;
+    let mut start = 1;
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (quoted, c) {
+            (true, '"') if chars.peek() == Some(&'"') => {
+                chars.next();
+                field.push('"');
+            }
+            (true, '"') => quoted = false,
+            (false, '"') if field.is_empty() => quoted = true,
+            (false, ',') => record.push(std::mem::take(&mut field)),
+            (false, '\r') => {}
+            (false, '\n') => {
+                record.push(std::mem::take(&mut field));
+                if record.iter().any(|field| !field.is_empty()) {
+                    res.push((start, std::mem::take(&mut record)));
+                }
+                record.clear();
+                line += 1;
+                start = line;
+            }
+            (_, c) => {
+                if c == '\n' {
+                    line += 1;
+                }
+                field.push(c);
+            }
+        }
+    }
+    if quoted {
+        return Err(csv_error(start, "unterminated quoted field".to_string()));
+    }
+    record.push(field);
+    if record.iter().any(|field| !field.is_empty()) {
+        res.push((start, record));
+    }
+    Ok(res)
+}
+
+/// The JSON value of a cell, `None` for an empty cell
+fn cell_value(column: &str, cell: &str, line: usize) -> Result<Option<Value>, DeviceInitError> {
+    let cell = cell.trim();
+    if cell.is_empty() {
+        return Ok(None);
+    }
+    if TEXT_COLUMNS.contains(&column) {
+        return Ok(Some(Value::String(cell.to_string())));
+    }
+    if let Ok(integer) = cell.parse::<u64>() {
+        return Ok(Some(Value::Number(integer.into())));
+    }
+    if INTEGER_COLUMNS.contains(&column) {
+        return Err(csv_error(
+            line,
+            format!("{column} `{cell}` is not an unsigned integer"),
+        ));
+    }
+    if let Ok(integer) = cell.parse::<i64>() {
+        return Ok(Some(Value::Number(integer.into())));
+    }
+    if let Some(number) = cell.parse::<f64>().ok().and_then(Number::from_f64) {
+        return Ok(Some(Value::Number(number)));
+    }
+    if NUMBER_COLUMNS.contains(&column) {
+        return Err(csv_error(
+            line,
+            format!("{column} `{cell}` is not a number"),
+        ));
+    }
+    Ok(Some(match cell {
+        "true" => Value::Bool(true),
+        "false" => Value::Bool(false),
+        _ => Value::String(cell.to_string()),
+    }))
+}
+
+/// Convert a CSV definition to the JSON form expected by the device libraries.
+/// The first line names the columns, `name` and some of the `fields` of the device type. Each following
+/// line becomes an entry keyed by its name, the other columns being its fields (empty cells left out),
+/// e.g. `name,address,type,scale,offset,unit` gives `{"<name>": {"address": 12, "type": "u16", ...}}`.
+///
+/// # Arguments
+///
+/// - `text` (`&str`) - The content of the CSV file
+/// - `fields` (`&[&str]`) - The fields of a register of the device type, any other column is rejected
+///
+/// # Returns
+///
+/// - `Result<Value, DeviceInitError>` - The definition, or the first invalid line
+pub fn csv_to_json(text: &str, fields: &[&str]) -> Result<Value, DeviceInitError> {
+    let mut records = records(text)?.into_iter();
+    let (_, header) = records
+        .next()
+        .ok_or_else(|| csv_error(1, "the header line is missing".to_string()))?;
+    let header: Vec<String> = header
+        .iter()
+        .map(|column| column.trim().to_lowercase())
+        .collect();
+    for (i, column) in header.iter().enumerate() {
+        if column.is_empty() {
+            return Err(csv_error(1, format!("column {} has no name", i + 1)));
+        }
+        if header[..i].contains(column) {
+            return Err(csv_error(1, format!("column {column} appears twice")));
+        }
+        if column != "name" && !fields.contains(&column.as_str()) {
+            return Err(csv_error(
+                1,
+                format!(
+                    "unknown column {column}, expected name and some of {}",
+                    fields.join(", ")
+                ),
+            ));
+        }
+    }
+    let name_column = header
+        .iter()
+        .position(|column| column == "name")
+        .ok_or_else(|| csv_error(1, "the name column is missing".to_string()))?;
+
+    let mut definitions = Map::new();
+    for (line, record) in records {
+        if record.len() != header.len() {
+            return Err(csv_error(
+                line,
+                format!("{} fields for {} columns", record.len(), header.len()),
+            ));
+        }
+        let name = record[name_column].trim();
+        if name.is_empty() {
+            return Err(csv_error(line, "the name is empty".to_string()));
+        }
+        if definitions.contains_key(name) {
+            return Err(csv_error(line, format!("{name} is defined twice")));
+        }
+        let mut fields = Map::new();
+        for (column, cell) in header.iter().zip(&record) {
+            if column == "name" {
+                continue;
+            }
+            if let Some(value) = cell_value(column, cell, line)? {
+                fields.insert(column.clone(), value);
+            }
+        }
+        definitions.insert(name.to_string(), Value::Object(fields));
+    }
+    Ok(Value::Object(definitions))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const FIELDS: &[&str] = &["address", "type", "scale", "unit"];
+
+    fn error(text: &str) -> String {
+        csv_to_json(text, FIELDS).unwrap_err().to_string()
+    }
+
+    #[test]
+    fn lines_become_entries_keyed_by_name() {
+        let text = "name,address,type,scale\ntemperature,12,u16,0.1\n\npressure,14,s16,\n";
+        assert_eq!(
+            csv_to_json(text, FIELDS).unwrap(),
+            json!({
+                "temperature": {"address": 12, "type": "u16", "scale": 0.1},
+                "pressure": {"address": 14, "type": "s16"},
+            })
+        );
+    }
+
+    #[test]
+    fn quoted_fields_keep_their_commas_and_quotes() {
+        let text = "name,address,unit\r\n\"flow, inlet\",3,\"m3/h \"\"raw\"\"\"\r\n";
+        assert_eq!(
+            csv_to_json(text, FIELDS).unwrap(),
+            json!({"flow, inlet": {"address": 3, "unit": "m3/h \"raw\""}})
+        );
+    }
+
+    #[test]
+    fn unknown_column_is_rejected() {
+        assert!(error("name,address,adress\nflow,3,4\n").contains("unknown column adress"));
+    }
+
+    #[test]
+    fn missing_column_is_rejected() {
+        assert!(error("address,type\n3,u16\n").contains("the name column is missing"));
+        assert!(error("name,address,type\nflow,3\n").contains("line 2: 2 fields for 3 columns"));
+    }
+
+    #[test]
+    fn invalid_address_is_rejected() {
+        assert!(error("name,address\nflow,3\nlevel,-4\n")
+            .contains("line 3: address `-4` is not an unsigned integer"));
+        assert!(error("name,scale\nflow,ten\n").contains("scale `ten` is not a number"));
+    }
+
+    #[test]
+    fn unterminated_quote_is_rejected() {
+        assert!(error("name,address\n\"flow,3\n").contains("unterminated quoted field"));
+    }
+}
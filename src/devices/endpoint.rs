@@ -0,0 +1,330 @@
+use std::{
+    collections::HashMap,
+    fmt,
+    net::{Ipv4Addr, SocketAddr, ToSocketAddrs},
+};
+
+use async_trait::async_trait;
+use industrial_device::{errors::IndustrialDeviceError, types::Value, IndustrialDevice};
+use log::{error, info, warn};
+use tokio::{
+    io::copy_bidirectional,
+    net::{TcpListener, TcpStream},
+    task::{AbortHandle, JoinSet},
+};
+
+use super::errors::DeviceInitError;
+use super::proxy::Proxy;
+
+#[derive(Debug, Clone, PartialEq)]
+/// Where the TCP connection to a device goes
+pub enum Target {
+    /// An address (`192.168.0.10:502` or `[fd00::10]:502`)
+    Addr(SocketAddr),
+    /// A host name and a port (`plc-1.plant.local:502`), resolved on each connection
+    Host(String, u16),
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Addr(addr) => write!(f, "{addr}"),
+            Target::Host(host, port) => write!(f, "{host}:{port}"),
+        }
+    }
+}
+
+impl Target {
+    /// Parse the `remote` of a device, an IPv6 address must be bracketed (`[fd00::10]:502`)
+    ///
+    /// # Arguments
+    ///
+    /// - `remote` (`&str`) - The endpoint of the device
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Target, DeviceInitError>` - The target, `BadRemoteUri` if the endpoint has no port
+    pub fn parse(remote: &str) -> Result<Target, DeviceInitError> {
+        if let Ok(addr) = remote.parse() {
+            return Ok(Target::Addr(addr));
+        }
+        let bad_remote = |reason: &str| DeviceInitError::BadRemoteUri {
+            reason: format!("{remote}: {reason}"),
+        };
+        let (host, port) = remote
+            .rsplit_once(':')
+            .ok_or_else(|| bad_remote("expected host:port"))?;
+        if host.is_empty() || host.contains(':') {
+            return Err(bad_remote(
+                "expected host:port, IPv6 addresses must be bracketed ([::1]:502)",
+            ));
+        }
+        let port = port.parse().map_err(|_| bad_remote("invalid port"))?;
+        Ok(Target::Host(host.to_string(), port))
+    }
+
+    /// Open a TCP connection to the target, a host name being resolved again (e.g. a DNS change)
+    pub async fn connect(&self) -> std::io::Result<TcpStream> {
+        match self {
+            Target::Addr(addr) => TcpStream::connect(addr).await,
+            Target::Host(host, port) => TcpStream::connect((host.as_str(), *port)).await,
+        }
+    }
+}
+
+/// The forwarding of a device (see [`forward`]), stopped with its connections when dropped
+pub struct Forwarder(AbortHandle);
+
+impl Drop for Forwarder {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+/// A device connecting through a local forwarding port, which lives as long as the device:
+/// building the device again (e.g. after its fetch got stuck) closes the port of the previous one
+///
+/// # Fields
+/// - `device`: The device.
+/// - `_forwarder`: The forwarding the device connects through, `None` if it connects directly.
+pub struct Forwarded<T> {
+    pub device: T,
+    _forwarder: Option<Forwarder>,
+}
+
+impl<T> Forwarded<T> {
+    pub fn new(device: T, forwarder: Option<Forwarder>) -> Self {
+        Forwarded {
+            device,
+            _forwarder: forwarder,
+        }
+    }
+}
+
+#[async_trait]
+impl<T: IndustrialDevice + Send> IndustrialDevice for Forwarded<T> {
+    async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+        self.device.connect().await
+    }
+
+    async fn read_register_by_name(&mut self, name: &str) -> Result<Value, IndustrialDeviceError> {
+        self.device.read_register_by_name(name).await
+    }
+
+    async fn write_register_by_name(
+        &mut self,
+        name: &str,
+        value: &Value,
+    ) -> Result<(), IndustrialDeviceError> {
+        self.device.write_register_by_name(name, value).await
+    }
+
+    async fn dump_registers(&mut self) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+        self.device.dump_registers().await
+    }
+}
+
+/// The address a TCP device connects to for its `remote`.
+/// An address without proxy is used as is. A host name, or any endpoint behind a proxy, is reached
+/// through a local port forwarding each connection (see [`forward`]), so the host name is resolved
+/// on every (re)connection.
+///
+/// # Arguments
+///
+/// - `remote` (`&str`) - The endpoint of the device
+/// - `proxy` (`Option<&Proxy>`) - The proxy the connection is routed through
+///
+/// # Returns
+///
+/// - `Result<(SocketAddr, Option<Forwarder>), DeviceInitError>` - The address to connect the device to,
+///   and the forwarding to keep alongside the device if any
+pub fn device_address(
+    remote: &str,
+    proxy: Option<&Proxy>,
+) -> Result<(SocketAddr, Option<Forwarder>), DeviceInitError> {
+    let target = Target::parse(remote)?;
+    match (&target, proxy) {
+        (Target::Addr(addr), None) => return Ok((*addr, None)),
+        (Target::Host(host, port), None) => {
+            if let Err(err) = (host.as_str(), *port).to_socket_addrs() {
+                warn!("{target} can't be resolved yet ({err}), it is resolved again on each connection");
+            }
+        }
+        // The proxy resolves the host names itself
+        (_, Some(_)) => {}
+    }
+    let (local, forwarder) = forward(target, proxy.cloned())?;
+    Ok((local, Some(forwarder)))
+}
+
+/// Whether the connection from `peer` (an address of the loopback) was opened by this process.
+/// The forwarding port is reachable by every local user, the connections of the other processes
+/// are refused so they can't reach the device through it. The socket of `peer` is looked up
+/// in `/proc/net/tcp`, then among the file descriptors of the process.
+#[cfg(target_os = "linux")]
+fn is_own_connection(peer: SocketAddr) -> bool {
+    let SocketAddr::V4(peer) = peer else {
+        return false;
+    };
+    let Ok(sockets) = std::fs::read_to_string("/proc/net/tcp") else {
+        return false;
+    };
+    // `local_address` is `<ip as a hexadecimal u32 in host order>:<port in hexadecimal>`, the inode is the 10th column
+    let inode = sockets.lines().skip(1).find_map(|line| {
+        let columns: Vec<&str> = line.split_whitespace().collect();
+        let (ip, port) = columns.get(1)?.split_once(':')?;
+        let matches = u32::from_str_radix(ip, 16).ok()? == u32::from_ne_bytes(peer.ip().octets())
+            && u16::from_str_radix(port, 16).ok()? == peer.port();
+        matches.then(|| columns.get(9).map(|inode| inode.to_string()))?
+    });
+    let Some(inode) = inode else {
+        return false;
+    };
+    let socket = format!("socket:[{inode}]");
+    std::fs::read_dir("/proc/self/fd").is_ok_and(|fds| {
+        fds.flatten().any(|fd| {
+            std::fs::read_link(fd.path()).is_ok_and(|link| link.as_os_str() == socket.as_str())
+        })
+    })
+}
+
+/// Without `/proc`, any local process can connect to the forwarding port
+#[cfg(not(target_os = "linux"))]
+fn is_own_connection(_peer: SocketAddr) -> bool {
+    true
+}
+
+/// Listen on a local port forwarding every connection to `target`, through the proxy if any,
+/// the device connects to the returned address instead of its own (as with `ssh -L`).
+/// Only the connections of the bridge itself are forwarded (see [`is_own_connection`]).
+/// Must be called from the async runtime, the forwarding runs in its own task until the returned
+/// [`Forwarder`] is dropped.
+///
+/// # Arguments
+///
+/// - `target` (`Target`) - The endpoint of the device
+/// - `proxy` (`Option<Proxy>`) - The proxy the connections are routed through
+///
+/// # Returns
+///
+/// - `Result<(SocketAddr, Forwarder), DeviceInitError>` - The local address to connect the device to,
+///   and the handle of the forwarding
+fn forward(
+    target: Target,
+    proxy: Option<Proxy>,
+) -> Result<(SocketAddr, Forwarder), DeviceInitError> {
+    let listener = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?;
+    listener.set_nonblocking(true)?;
+    let local = listener.local_addr()?;
+    let listener =
+        TcpListener::from_std(listener).map_err(|err| DeviceInitError::ProxyFailed { err })?;
+
+    let task = tokio::task::spawn(async move {
+        info!("Forwarding {local} to {target}");
+        // Aborted with the forwarding
+        let mut connections = JoinSet::new();
+        loop {
+            let mut client = match listener.accept().await {
+                Ok((client, peer)) if is_own_connection(peer) => client,
+                Ok((_, peer)) => {
+                    warn!("Refused the connection of {peer} to {target} on {local}, it doesn't come from the bridge");
+                    continue;
+                }
+                Err(err) => {
+                    error!("Could not accept the connection to {target} on {local} ({err})");
+                    continue;
+                }
+            };
+            // Reap the connections that ended
+            while connections.try_join_next().is_some() {}
+            let target = target.clone();
+            let proxy = proxy.clone();
+            connections.spawn(async move {
+                let upstream = match &proxy {
+                    Some(proxy) => proxy.connect(&target).await,
+                    None => target.connect().await,
+                };
+                // Dropping the client on error lets the device see a failed connection
+                let mut upstream = match upstream {
+                    Ok(upstream) => upstream,
+                    Err(err) => {
+                        error!("Could not connect to {target} ({err})");
+                        return;
+                    }
+                };
+                let _ = copy_bidirectional(&mut client, &mut upstream).await;
+            });
+        }
+    });
+    Ok((local, Forwarder(task.abort_handle())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    #[test]
+    fn endpoints_are_parsed() {
+        assert_eq!(
+            Target::parse("[fd00::10]:502").unwrap(),
+            Target::Addr("[fd00::10]:502".parse().unwrap())
+        );
+        assert_eq!(
+            Target::parse("plc-1.plant.local:102").unwrap(),
+            Target::Host("plc-1.plant.local".to_string(), 102)
+        );
+        assert!(Target::parse("fd00::10:502").is_err());
+        assert!(Target::parse("plc-1").is_err());
+    }
+
+    /// Answer every connection with what it sent
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::task::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        addr
+    }
+
+    #[tokio::test]
+    async fn connections_are_forwarded_until_the_forwarder_is_dropped() {
+        let target = echo_server().await;
+        let (local, forwarder) = forward(Target::Addr(target), None).unwrap();
+
+        let mut stream = TcpStream::connect(local).await.unwrap();
+        stream.write_all(b"ping").await.unwrap();
+        let mut buf = [0; 4];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(&buf, b"ping");
+
+        drop(forwarder);
+        tokio::task::yield_now().await;
+        // The open connection is closed, and the port no longer accepts any
+        let mut buf = [0; 4];
+        assert!(!matches!(stream.read(&mut buf).await, Ok(n) if n > 0));
+        assert!(TcpStream::connect(local).await.is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn only_the_connections_of_the_bridge_are_its_own() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let stream = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        assert!(is_own_connection(stream.local_addr().unwrap()));
+        drop(stream);
+        // A port no socket of this process is bound to
+        let free = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let addr = free.local_addr().unwrap();
+        drop(free);
+        assert!(!is_own_connection(addr));
+    }
+}
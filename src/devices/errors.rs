@@ -1,35 +1,162 @@
-use custom_error::custom_error;
-use serde_json;
-use std::{error::Error, net::AddrParseError};
-
-custom_error! {
-    /// List of error related to the config of the device
-    pub DeviceInitError
-    CouldNotOpenDefinition{ err: Box<dyn Error>} = "Could not find definition file ({err})",
-    ParsingFailed{ err: Box<dyn Error> } = "Could not parse file ({err})",
-    BadRemoteUri{ err: Box<dyn Error> } = "Could not get a correct URL from passed remote address ({err})",
+use industrial_device::errors::IndustrialDeviceError;
+use s7_device::utils::JsonReadError;
+use serde::Deserialize;
+use std::net::AddrParseError;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+/// List of error related to the config of the device
+pub enum DeviceInitError {
+    #[error("Could not find definition file ({err})")]
+    CouldNotOpenDefinition {
+        #[from]
+        err: std::io::Error,
+    },
+    #[error("Could not open definition {path} after {attempts} attempts ({err})")]
+    DefinitionNotFound {
+        path: String,
+        attempts: u32,
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Could not parse file ({reason})")]
+    ParsingFailed { reason: String },
+    #[error("Could not parse file ({err})")]
+    BadJson {
+        #[from]
+        err: serde_json::Error,
+    },
+    #[error("Could not parse file ({err})")]
+    BadS7Definition {
+        #[from]
+        err: JsonReadError,
+    },
+    #[error("Could not get a correct URL from passed remote address ({reason})")]
+    BadRemoteUri { reason: String },
+    #[error("Could not get a correct URL from passed remote address ({err})")]
+    BadAddress {
+        #[from]
+        err: AddrParseError,
+    },
+    #[error("Device {name} is defined more than once")]
+    DuplicateDevice { name: String },
+    #[error("Could not download definition ({err})")]
+    DownloadFailed {
+        #[from]
+        err: reqwest::Error,
+    },
+    #[error("The API key can't be sent as a header ({err})")]
+    BadApiKey {
+        #[source]
+        err: Box<dyn std::error::Error>,
+    },
+    #[error("Could not create the HTTP client ({err})")]
+    HttpClientFailed {
+        #[source]
+        err: reqwest::Error,
+    },
+    #[error("Could not set up the proxy ({err})")]
+    ProxyFailed {
+        #[source]
+        err: std::io::Error,
+    },
+    #[error("Nothing at {pointer} in definition {path} (`definition_pointer` must start with `/`)")]
+    DefinitionPointerNotFound { path: String, pointer: String },
+    #[error("Register {register} of `types` is not in the definition")]
+    TypedRegisterNotFound { register: String },
+    #[error("Register {register} is defined as {found} but expected as {expected} by `types`")]
+    TypeMismatch {
+        register: String,
+        expected: String,
+        found: String,
+    },
+    #[error("device `{name}`: {err}")]
+    Device {
+        name: String,
+        #[source]
+        err: Box<DeviceInitError>,
+    },
 }
 
-impl From<std::io::Error> for DeviceInitError {
-    fn from(value: std::io::Error) -> Self {
-        DeviceInitError::CouldNotOpenDefinition {
-            err: Box::new(value),
+impl DeviceInitError {
+    /// Attach the name of the device whose configuration caused the error
+    pub fn named(self, name: &str) -> Self {
+        DeviceInitError::Device {
+            name: name.to_string(),
+            err: Box::new(self),
         }
     }
 }
 
-impl From<serde_json::error::Error> for DeviceInitError {
-    fn from(value: serde_json::error::Error) -> Self {
-        DeviceInitError::ParsingFailed {
-            err: Box::new(value),
+#[derive(Debug, Error)]
+/// Why a device could not be read during a fetch run
+pub enum FetchError {
+    #[error("Timeout reached while fetching")]
+    Timeout {},
+    #[error("The device is not accessible")]
+    Disconnected {},
+    #[error("Could not read the registers")]
+    ReadFailed {},
+    #[error("The fetch did not end within the watchdog bound")]
+    Stuck {},
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Kind of an `IndustrialDeviceError`, used to configure which errors trigger a reconnection
+pub enum ErrorKind {
+    /// `RequestError`: the device answered the request with an error, or the request itself failed
+    /// (some devices report a dropped connection this way)
+    Request,
+    /// `DeviceNotConnectedError`: the connection to the device is not established (never connected or closed)
+    NotConnected,
+    /// `DeviceNotAccessibleError`: the device could not be reached (broken pipe, refused connection...)
+    NotAccessible,
+    /// `ConversionError`: the data read could not be converted to the type of the register
+    Conversion,
+    /// `RegisterNotFoundError`: the register is not in the definition of the device
+    RegisterNotFound,
+    /// `WrongValueType`: the value does not match the type of the register
+    WrongValueType,
+}
+
+impl ErrorKind {
+    /// The errors triggering a reconnection when `reconnect_on` is not set
+    pub const DEFAULT_RECONNECT: &[ErrorKind] = &[ErrorKind::NotAccessible, ErrorKind::NotConnected];
+}
+
+impl From<&IndustrialDeviceError> for ErrorKind {
+    fn from(value: &IndustrialDeviceError) -> Self {
+        match value {
+            IndustrialDeviceError::RequestError { .. } => ErrorKind::Request,
+            IndustrialDeviceError::DeviceNotConnectedError { .. } => ErrorKind::NotConnected,
+            IndustrialDeviceError::DeviceNotAccessibleError { .. } => ErrorKind::NotAccessible,
+            IndustrialDeviceError::ConversionError { .. } => ErrorKind::Conversion,
+            IndustrialDeviceError::RegisterNotFoundError { .. } => ErrorKind::RegisterNotFound,
+            IndustrialDeviceError::WrongValueType { .. } => ErrorKind::WrongValueType,
         }
     }
 }
 
-impl From<AddrParseError> for DeviceInitError {
-    fn from(value: AddrParseError) -> Self {
-        DeviceInitError::BadRemoteUri {
-            err: Box::new(value),
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_unchanged() {
+        let err = DeviceInitError::ParsingFailed {
+            reason: "CSV definition, line 3: unknown type".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Could not parse file (CSV definition, line 3: unknown type)"
+        );
+        let err = DeviceInitError::BadRemoteUri {
+            reason: "plc: expected host:port".to_string(),
+        };
+        assert_eq!(
+            err.to_string(),
+            "Could not get a correct URL from passed remote address (plc: expected host:port)"
+        );
     }
 }
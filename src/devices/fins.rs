@@ -0,0 +1,514 @@
+use std::{collections::HashMap, net::SocketAddr, time::Duration};
+
+use async_trait::async_trait;
+use industrial_device::{errors::IndustrialDeviceError, types::Value, IndustrialDevice};
+use serde::Deserialize;
+use thiserror::Error;
+use tokio::{net::UdpSocket, time::timeout};
+
+use super::definitions::open_definition;
+use super::errors::DeviceInitError;
+use super::DeviceOptions;
+
+/// Memory Area Read command (MRC, SRC)
+const MEMORY_AREA_READ: [u8; 2] = [0x01, 0x01];
+/// Maximum number of words read in a single request, keeps the answer in one UDP datagram
+const WORDS_PER_REQUEST: u16 = 500;
+/// Time to wait for the answer to a request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(3);
+/// Length of the FINS header
+const HEADER_LEN: usize = 10;
+
+#[derive(Debug, Error)]
+/// List of error related to the FINS/UDP exchanges
+pub enum FinsError {
+    #[error("No answer from the PLC")]
+    Timeout {},
+    #[error("Malformed answer ({reason})")]
+    Malformed { reason: String },
+    #[error("The PLC rejected the request (end code {code:#06x})")]
+    Rejected { code: u16 },
+    #[error("Socket error ({err})")]
+    Io {
+        #[from]
+        err: std::io::Error,
+    },
+}
+
+impl From<FinsError> for IndustrialDeviceError {
+    fn from(value: FinsError) -> Self {
+        match value {
+            FinsError::Timeout {} | FinsError::Io { err: _ } => {
+                IndustrialDeviceError::DeviceNotAccessibleError {
+                    err: Box::new(value),
+                }
+            }
+            _ => IndustrialDeviceError::RequestError {
+                err: Box::new(value),
+            },
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+/// Memory area of a CS/CJ/CP PLC, read as words
+pub enum FinsArea {
+    /// Core I/O
+    Cio,
+    /// Work area
+    Wr,
+    /// Holding area
+    Hr,
+    /// Auxiliary area
+    Ar,
+    /// Data memory
+    Dm,
+}
+
+impl FinsArea {
+    /// Memory area code of the word access
+    fn code(&self) -> u8 {
+        match self {
+            FinsArea::Cio => 0xB0,
+            FinsArea::Wr => 0xB1,
+            FinsArea::Hr => 0xB2,
+            FinsArea::Ar => 0xB3,
+            FinsArea::Dm => 0x82,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Type of a register, 32 bits values span two words, least significant word first
+pub enum FinsType {
+    U16,
+    S16,
+    U32,
+    S32,
+    Float32,
+    /// A bit of a word, given by `bit`
+    Bool,
+}
+
+impl FinsType {
+    /// Number of words of the value
+    fn words(&self) -> u16 {
+        match self {
+            FinsType::U32 | FinsType::S32 | FinsType::Float32 => 2,
+            _ => 1,
+        }
+    }
+}
+
+/// The fields of a [`FinsRegister`], the columns a CSV definition may have besides `name`
+pub const DEFINITION_FIELDS: &[&str] = &["area", "address", "type", "bit"];
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+/// Definition of a register in the registers file
+///
+/// # Fields
+/// - `area`: Memory area of the register (`cio`, `wr`, `hr`, `ar` or `dm`)
+/// - `address`: Address of the (first) word
+/// - `kind`: Type of the value (`u16`, `s16`, `u32`, `s32`, `float32` or `bool`), given as `type`
+/// - `bit`: Bit of the word holding a `bool` (0 to 15)
+pub struct FinsRegister {
+    pub area: FinsArea,
+    pub address: u16,
+    #[serde(rename = "type")]
+    pub kind: FinsType,
+    #[serde(default)]
+    pub bit: u8,
+}
+
+impl FinsRegister {
+    /// Build the value of the register from its words
+    fn value(&self, words: &[u16]) -> Value {
+        match self.kind {
+            FinsType::U16 => Value::U16(words[0]),
+            FinsType::S16 => Value::S16(words[0] as i16),
+            FinsType::U32 => Value::U32(double_word(words)),
+            FinsType::S32 => Value::S32(double_word(words) as i32),
+            FinsType::Float32 => Value::Float32(f32::from_bits(double_word(words))),
+            FinsType::Bool => Value::Boolean(words[0] >> (self.bit & 0x0F) & 1 == 1),
+        }
+    }
+}
+
+/// A 32 bits value stored least significant word first
+fn double_word(words: &[u16]) -> u32 {
+    (words[1] as u32) << 16 | words[0] as u32
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// Config of an Omron PLC reached through FINS/UDP
+///
+/// # Fields
+/// - `remote`: Address of the PLC, to be parsed as a SocketAddr (usually port 9600)
+/// - `network`: FINS network address of the PLC (default 0, the local network)
+/// - `node`: FINS node address of the PLC (usually the last byte of its IP address)
+/// - `unit`: FINS unit address of the CPU (default 0)
+/// - `source_node`: FINS node address of the bridge (usually the last byte of its IP address)
+/// - `registers`: Path to the JSON file mapping register names to memory area words
+pub struct FinsUdpDevice {
+    pub remote: String,
+    #[serde(default)]
+    pub network: u8,
+    pub node: u8,
+    #[serde(default)]
+    pub unit: u8,
+    pub source_node: u8,
+    pub registers: String,
+    #[serde(flatten)]
+    pub options: DeviceOptions,
+}
+
+impl FinsUdpDevice {
+    /// Tags describing the connection to the device
+    pub fn connection_info(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("endpoint".to_string(), self.remote.clone()),
+            (
+                "fins_address".to_string(),
+                format!("{}.{}.{}", self.network, self.node, self.unit),
+            ),
+        ])
+    }
+}
+
+/// Client of an Omron PLC reading its memory areas with FINS/UDP
+pub struct OmronFinsDevice {
+    addr: SocketAddr,
+    network: u8,
+    node: u8,
+    unit: u8,
+    source_node: u8,
+    registers: HashMap<String, FinsRegister>,
+    socket: Option<UdpSocket>,
+    sid: u8,
+}
+
+impl TryFrom<FinsUdpDevice> for OmronFinsDevice {
+    type Error = DeviceInitError;
+
+    fn try_from(value: FinsUdpDevice) -> Result<Self, Self::Error> {
+        let pointer = value.options.definition_pointer.as_deref();
+        let registers_json = open_definition(&value.registers, pointer, DEFINITION_FIELDS)?;
+        let registers: HashMap<String, FinsRegister> = serde_json::from_reader(registers_json)?;
+
+        Ok(OmronFinsDevice {
+            addr: value.remote.parse()?,
+            network: value.network,
+            node: value.node,
+            unit: value.unit,
+            source_node: value.source_node,
+            registers,
+            socket: None,
+            sid: 0,
+        })
+    }
+}
+
+impl OmronFinsDevice {
+    /// Read consecutive words of a memory area
+    ///
+    /// # Arguments
+    ///
+    /// - `area` (`FinsArea`) - The memory area
+    /// - `address` (`u16`) - The address of the first word
+    /// - `count` (`u16`) - The number of words
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Vec<u16>, IndustrialDeviceError>` - The words read
+    async fn read_words(
+        &mut self,
+        area: FinsArea,
+        address: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, IndustrialDeviceError> {
+        self.sid = self.sid.wrapping_add(1);
+        let sid = self.sid;
+        let frame = self.encode_read(sid, area, address, count);
+        let socket = match &self.socket {
+            Some(socket) => socket,
+            None => {
+                return Err(IndustrialDeviceError::DeviceNotConnectedError {
+                    err: Box::new(FinsError::Malformed {
+                        reason: "socket not opened".to_string(),
+                    }),
+                })
+            }
+        };
+
+        socket.send(&frame).await.map_err(FinsError::from)?;
+
+        let mut buf = [0u8; 2048];
+        loop {
+            let len = match timeout(REQUEST_TIMEOUT, socket.recv(&mut buf)).await {
+                Ok(len) => len.map_err(FinsError::from)?,
+                Err(_) => return Err(FinsError::Timeout {}.into()),
+            };
+            // Answers to previous (timed out) requests are discarded
+            if let Some(words) = decode_answer(&buf[..len], sid, count)? {
+                return Ok(words);
+            }
+        }
+    }
+
+    /// Build the frame of a Memory Area Read request
+    fn encode_read(&self, sid: u8, area: FinsArea, address: u16, count: u16) -> Vec<u8> {
+        // Command expecting a response, gateway count 2, the bridge on the local network
+        let mut frame = vec![
+            0x80,
+            0x00,
+            0x02,
+            self.network,
+            self.node,
+            self.unit,
+            0x00,
+            self.source_node,
+            0x00,
+            sid,
+        ];
+        frame.extend_from_slice(&MEMORY_AREA_READ);
+        frame.push(area.code());
+        frame.extend_from_slice(&address.to_be_bytes());
+        frame.push(0x00);
+        frame.extend_from_slice(&count.to_be_bytes());
+        frame
+    }
+}
+
+/// Decode the answer to a Memory Area Read request
+///
+/// # Returns
+///
+/// - `Result<Option<Vec<u16>>, FinsError>` - The words read, `None` if the answer is not the one to `sid`
+fn decode_answer(frame: &[u8], sid: u8, count: u16) -> Result<Option<Vec<u16>>, FinsError> {
+    if frame.len() < HEADER_LEN + 4 {
+        return Err(FinsError::Malformed {
+            reason: "truncated".to_string(),
+        });
+    }
+    if frame[9] != sid || frame[HEADER_LEN..HEADER_LEN + 2] != MEMORY_AREA_READ {
+        return Ok(None);
+    }
+    // The end code bits 15, 14 and 7 are flags (network relay error, fatal/non fatal CPU error)
+    let code = u16::from_be_bytes([frame[HEADER_LEN + 2], frame[HEADER_LEN + 3]]) & 0x3F7F;
+    if code != 0 {
+        return Err(FinsError::Rejected { code });
+    }
+    let data = &frame[HEADER_LEN + 4..];
+    if data.len() != count as usize * 2 {
+        return Err(FinsError::Malformed {
+            reason: format!("expected {count} words, got {} bytes", data.len()),
+        });
+    }
+    Ok(Some(
+        data.chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect(),
+    ))
+}
+
+#[async_trait]
+impl IndustrialDevice for OmronFinsDevice {
+    async fn connect(&mut self) -> Result<(), IndustrialDeviceError> {
+        let local: SocketAddr = match self.addr {
+            SocketAddr::V4(_) => ([0, 0, 0, 0], 0).into(),
+            SocketAddr::V6(_) => ([0u16; 8], 0).into(),
+        };
+        let socket = UdpSocket::bind(local).await.map_err(FinsError::from)?;
+        socket.connect(self.addr).await.map_err(FinsError::from)?;
+        self.socket = Some(socket);
+
+        // Make sure the PLC answers
+        self.read_words(FinsArea::Dm, 0, 1).await?;
+        Ok(())
+    }
+
+    async fn read_register_by_name(&mut self, name: &str) -> Result<Value, IndustrialDeviceError> {
+        let register = match self.registers.get(name) {
+            Some(register) => *register,
+            None => {
+                return Err(IndustrialDeviceError::RegisterNotFoundError {
+                    name: name.to_string(),
+                })
+            }
+        };
+        let words = self
+            .read_words(register.area, register.address, register.kind.words())
+            .await?;
+        Ok(register.value(&words))
+    }
+
+    async fn write_register_by_name(
+        &mut self,
+        _name: &str,
+        _value: &Value,
+    ) -> Result<(), IndustrialDeviceError> {
+        Err(FinsError::Malformed {
+            reason: "writing is not supported".to_string(),
+        }
+        .into())
+    }
+
+    /// Read the registers of each area in blocks of contiguous words
+    async fn dump_registers(&mut self) -> Result<HashMap<String, Value>, IndustrialDeviceError> {
+        let mut sorted: Vec<(String, FinsRegister)> = self
+            .registers
+            .iter()
+            .map(|(name, register)| (name.clone(), *register))
+            .collect();
+        sorted.sort_by_key(|(_, register)| (register.area, register.address));
+
+        // Registers read by each request, along with the area, first word and number of words
+        let mut blocks: Vec<(FinsArea, u16, u16, Vec<(String, FinsRegister)>)> = Vec::new();
+        for (name, register) in sorted {
+            let end = register.address as u32 + register.kind.words() as u32;
+            match blocks.last_mut() {
+                Some((area, start, count, registers))
+                    if *area == register.area
+                        && register.address as u32 <= *start as u32 + *count as u32
+                        && end - *start as u32 <= WORDS_PER_REQUEST as u32 =>
+                {
+                    *count = (*count).max((end - *start as u32) as u16);
+                    registers.push((name, register));
+                }
+                _ => blocks.push((
+                    register.area,
+                    register.address,
+                    register.kind.words(),
+                    vec![(name, register)],
+                )),
+            }
+        }
+
+        let mut res = HashMap::new();
+        for (area, start, count, registers) in blocks {
+            let words = self.read_words(area, start, count).await?;
+            for (name, register) in registers {
+                let offset = (register.address - start) as usize;
+                res.insert(name, register.value(&words[offset..]));
+            }
+        }
+        Ok(res)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The answer of the PLC to the request `sid`, with its end code and words
+    fn answer(sid: u8, code: u16, words: &[u16]) -> Vec<u8> {
+        let mut frame = vec![0xC0, 0x00, 0x02, 0x00, 0x00, 0x00, 0x00, 0x0A, 0x00, sid];
+        frame.extend_from_slice(&MEMORY_AREA_READ);
+        frame.extend_from_slice(&code.to_be_bytes());
+        for word in words {
+            frame.extend_from_slice(&word.to_be_bytes());
+        }
+        frame
+    }
+
+    /// A PLC answering the reads of its data memory (the other words read 0)
+    async fn mock_plc(dm: HashMap<u16, u16>) -> SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 2048];
+            while let Ok((len, client)) = socket.recv_from(&mut buf).await {
+                let request = &buf[..len];
+                let address = u16::from_be_bytes([request[13], request[14]]);
+                let count = u16::from_be_bytes([request[16], request[17]]);
+                let words: Vec<u16> = (address..address + count)
+                    .map(|address| match request[12] {
+                        0x82 => dm.get(&address).copied().unwrap_or(0),
+                        _ => 0,
+                    })
+                    .collect();
+                let frame = answer(request[9], 0, &words);
+                socket.send_to(&frame, client).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    fn device(addr: SocketAddr, registers: serde_json::Value) -> OmronFinsDevice {
+        OmronFinsDevice {
+            addr,
+            network: 0,
+            node: 1,
+            unit: 0,
+            source_node: 10,
+            registers: serde_json::from_value(registers).unwrap(),
+            socket: None,
+            sid: 0,
+        }
+    }
+
+    #[test]
+    fn read_request_is_encoded() {
+        let device = device(([127, 0, 0, 1], 9600).into(), serde_json::json!({}));
+        assert_eq!(
+            device.encode_read(7, FinsArea::Dm, 100, 2),
+            [
+                0x80, 0x00, 0x02, 0x00, 0x01, 0x00, 0x00, 0x0A, 0x00, 0x07, 0x01, 0x01, 0x82, 0x00,
+                0x64, 0x00, 0x00, 0x02
+            ]
+        );
+    }
+
+    #[test]
+    fn answer_is_decoded() {
+        let words = decode_answer(&answer(3, 0, &[0x1234, 0xABCD]), 3, 2).unwrap();
+        assert_eq!(words, Some(vec![0x1234, 0xABCD]));
+        // The flag bits of the end code are not errors
+        assert!(decode_answer(&answer(3, 0x8080, &[1]), 3, 1)
+            .unwrap()
+            .is_some());
+        assert!(decode_answer(&answer(2, 0, &[1]), 3, 1).unwrap().is_none());
+        assert!(matches!(
+            decode_answer(&answer(3, 0x1103, &[]), 3, 1),
+            Err(FinsError::Rejected { code: 0x1103 })
+        ));
+        assert!(matches!(
+            decode_answer(&answer(3, 0, &[1]), 3, 2),
+            Err(FinsError::Malformed { .. })
+        ));
+    }
+
+    #[tokio::test]
+    async fn data_memory_is_read_from_the_plc() {
+        let power = 1234.5f32.to_bits();
+        let addr = mock_plc(HashMap::from([
+            (100, 215),
+            (101, power as u16),
+            (102, (power >> 16) as u16),
+            (200, 0b100),
+        ]))
+        .await;
+        let mut device = device(
+            addr,
+            serde_json::json!({
+                "temperature": {"area": "dm", "address": 100, "type": "s16"},
+                "power": {"area": "dm", "address": 101, "type": "float32"},
+                "running": {"area": "dm", "address": 200, "type": "bool", "bit": 2},
+                "output": {"area": "cio", "address": 0, "type": "u16"},
+            }),
+        );
+        device.connect().await.unwrap();
+
+        let values = device.dump_registers().await.unwrap();
+        assert_eq!(values["temperature"], Value::S16(215));
+        assert_eq!(values["power"], Value::Float32(1234.5));
+        assert_eq!(values["running"], Value::Boolean(true));
+        assert_eq!(values["output"], Value::U16(0));
+        assert_eq!(
+            device.read_register_by_name("temperature").await.unwrap(),
+            Value::S16(215)
+        );
+    }
+}
@@ -0,0 +1,108 @@
+// Building block of the devices reached through an HTTP API (e.g. Modbus gateways wrapping
+// their registers in a REST API), no such device is part of the bridge yet
+#![allow(dead_code)]
+
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use serde::Deserialize;
+
+use super::errors::DeviceInitError;
+
+fn default_header() -> String {
+    "X-API-Key".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// API key authenticating the requests of a device reached through HTTP, flattened into its configuration
+///
+/// # Fields
+/// - `api_key`: The key sent with every request.
+/// - `api_key_header`: The header carrying the key (default `X-API-Key`).
+pub struct ApiKey {
+    pub api_key: String,
+    #[serde(default = "default_header")]
+    pub api_key_header: String,
+}
+
+impl ApiKey {
+    /// Build an HTTP client sending the key with every request
+    ///
+    /// # Returns
+    ///
+    /// - `Result<reqwest::Client, DeviceInitError>` - The client, or why the key could not be used as a header
+    pub fn http_client(&self) -> Result<reqwest::Client, DeviceInitError> {
+        let name = HeaderName::from_bytes(self.api_key_header.as_bytes()).map_err(|err| {
+            DeviceInitError::BadApiKey {
+                err: Box::new(err),
+            }
+        })?;
+        let mut value = HeaderValue::from_str(&self.api_key).map_err(|err| DeviceInitError::BadApiKey {
+            err: Box::new(err),
+        })?;
+        // Keep the key out of the logs
+        value.set_sensitive(true);
+        reqwest::Client::builder()
+            .default_headers(HeaderMap::from_iter([(name, value)]))
+            .build()
+            .map_err(|err| DeviceInitError::HttpClientFailed { err })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct HttpModbusDevice {
+        remote: String,
+        #[serde(flatten)]
+        auth: ApiKey,
+    }
+
+    #[test]
+    fn api_key_is_flattened_into_the_device_config() {
+        let device: HttpModbusDevice = serde_json::from_value(serde_json::json!({
+            "remote": "http://10.0.0.5",
+            "api_key": "secret",
+        }))
+        .unwrap();
+        assert_eq!(device.remote, "http://10.0.0.5");
+        assert_eq!(device.auth.api_key, "secret");
+        assert_eq!(device.auth.api_key_header, "X-API-Key");
+
+        let device: HttpModbusDevice = serde_json::from_value(serde_json::json!({
+            "remote": "http://10.0.0.5",
+            "api_key": "secret",
+            "api_key_header": "Authorization",
+        }))
+        .unwrap();
+        assert_eq!(device.auth.api_key_header, "Authorization");
+    }
+
+    #[test]
+    fn missing_api_key_is_rejected() {
+        let device = serde_json::from_value::<HttpModbusDevice>(serde_json::json!({
+            "remote": "http://10.0.0.5",
+        }));
+        assert!(device.is_err());
+    }
+
+    #[test]
+    fn bad_header_is_rejected() {
+        let auth = ApiKey {
+            api_key: "secret".to_string(),
+            api_key_header: "X API Key".to_string(),
+        };
+        assert!(matches!(
+            auth.http_client(),
+            Err(DeviceInitError::BadApiKey { .. })
+        ));
+        let auth = ApiKey {
+            api_key: "secret\n".to_string(),
+            api_key_header: "X-API-Key".to_string(),
+        };
+        assert!(matches!(
+            auth.http_client(),
+            Err(DeviceInitError::BadApiKey { .. })
+        ));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+    time::{Duration, Instant},
+};
+
+use log::{log, Level};
+
+/// Time during which the identical messages of a device are collapsed
+const WINDOW: Duration = Duration::from_secs(60);
+
+/// A message logged for a device and how many times it was repeated since
+struct Repeated {
+    message: String,
+    level: Level,
+    since: Instant,
+    count: u32,
+}
+
+/// The messages logged for each device within the window, keyed by device name
+#[derive(Default)]
+struct Throttle {
+    repeated: HashMap<String, Vec<Repeated>>,
+}
+
+static THROTTLE: LazyLock<Mutex<Throttle>> = LazyLock::new(|| Mutex::new(Throttle::default()));
+
+/// Log the number of times a message was repeated, if it was
+fn report(device: &str, last: &Repeated, now: Instant) {
+    if last.count > 0 {
+        log!(
+            last.level,
+            "[{device}] last error repeated {} times in the last {}s ({})",
+            last.count,
+            now.duration_since(last.since).as_secs(),
+            last.message
+        );
+    }
+}
+
+impl Throttle {
+    /// Forget the messages whose window elapsed, of every device, logging how many times they were repeated.
+    /// Only the messages of the last minute are kept, however many devices and messages come and go.
+    fn evict(&mut self, now: Instant) {
+        self.repeated.retain(|device, messages| {
+            messages.retain(|last| {
+                let expired = now.duration_since(last.since) >= WINDOW;
+                if expired {
+                    report(device, last, now);
+                }
+                !expired
+            });
+            !messages.is_empty()
+        });
+    }
+
+    /// Whether a message has to be logged, a message already logged within the window is only counted
+    fn should_log(&mut self, device: &str, level: Level, message: &str, now: Instant) -> bool {
+        self.evict(now);
+        let messages = self.repeated.entry(device.to_string()).or_default();
+        match messages.iter_mut().find(|last| last.message == message) {
+            Some(last) => {
+                last.count += 1;
+                false
+            }
+            None => {
+                messages.push(Repeated {
+                    message: message.to_string(),
+                    level,
+                    since: now,
+                    count: 0,
+                });
+                true
+            }
+        }
+    }
+}
+
+/// Log a message about a device, the same message repeated within a minute is only counted
+/// and the count is logged once the minute elapsed
+///
+/// # Arguments
+///
+/// - `device` (`&str`) - The device the message is about
+/// - `level` (`Level`) - The log level
+/// - `message` (`String`) - The message
+pub fn log(device: &str, level: Level, message: String) {
+    let mut throttle = THROTTLE.lock().unwrap();
+    if throttle.should_log(device, level, &message, Instant::now()) {
+        log!(level, "[{device}] {message}");
+    }
+}
+
+/// Forget the messages of a device (e.g. once it reads fine again), logging how many times they were repeated
+pub fn reset(device: &str) {
+    if let Some(messages) = THROTTLE.lock().unwrap().repeated.remove(device) {
+        let now = Instant::now();
+        for last in &messages {
+            report(device, last, now);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeated_message_is_counted_within_the_window() {
+        let mut throttle = Throttle::default();
+        let start = Instant::now();
+        assert!(throttle.should_log("press1", Level::Warn, "timeout", start));
+        assert!(!throttle.should_log("press1", Level::Warn, "timeout", start + WINDOW / 2));
+        assert!(throttle.should_log("press1", Level::Warn, "refused", start + WINDOW / 2));
+        assert!(throttle.should_log("press2", Level::Warn, "timeout", start + WINDOW / 2));
+        assert_eq!(throttle.repeated["press1"][0].count, 1);
+    }
+
+    #[test]
+    fn message_is_logged_again_once_the_window_elapsed() {
+        let mut throttle = Throttle::default();
+        let start = Instant::now();
+        assert!(throttle.should_log("press1", Level::Warn, "timeout", start));
+        assert!(throttle.should_log("press1", Level::Warn, "timeout", start + WINDOW));
+    }
+
+    #[test]
+    fn expired_messages_are_evicted() {
+        let mut throttle = Throttle::default();
+        let start = Instant::now();
+        for chunk in 0..100 {
+            throttle.should_log(
+                &format!("press1/reg{chunk}"),
+                Level::Warn,
+                "illegal address",
+                start,
+            );
+        }
+        throttle.should_log("press2", Level::Warn, "timeout", start + WINDOW);
+        assert_eq!(throttle.repeated.len(), 1);
+        assert!(throttle.repeated.contains_key("press2"));
+    }
+}
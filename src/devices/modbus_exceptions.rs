@@ -0,0 +1,129 @@
+use std::error::Error;
+
+use industrial_device::errors::IndustrialDeviceError;
+use tokio_modbus::ExceptionCode;
+
+/// Modbus exception codes (Modbus application protocol, section 7): the code, its name,
+/// and what it usually means for the bridge
+const EXCEPTIONS: [(u8, &str, &str); 9] = [
+    (
+        0x01,
+        "Illegal function",
+        "the device does not support this function code (e.g. it has no input registers, see `read_input`)",
+    ),
+    (
+        0x02,
+        "Illegal data address",
+        "a register of the definition is outside the register map of the device (check its address and length)",
+    ),
+    (
+        0x03,
+        "Illegal data value",
+        "the device rejects the request itself (e.g. too many registers at once, see `chunk_size`)",
+    ),
+    (
+        0x04,
+        "Server device failure",
+        "the device hit an unrecoverable error while processing the request",
+    ),
+    (
+        0x05,
+        "Acknowledge",
+        "the device accepted the request but needs a long time to process it",
+    ),
+    (
+        0x06,
+        "Server device busy",
+        "the device is processing a long command, the request can be sent again later",
+    ),
+    (
+        0x08,
+        "Memory parity error",
+        "the device detected a parity error in its memory",
+    ),
+    (
+        0x0A,
+        "Gateway path unavailable",
+        "the gateway is misconfigured or overloaded",
+    ),
+    (
+        0x0B,
+        "Gateway target device failed to respond",
+        "the device behind the gateway did not answer (check its slave id)",
+    ),
+];
+
+/// The code of a `tokio_modbus` exception
+fn exception_code(exception: &ExceptionCode) -> u8 {
+    match exception {
+        ExceptionCode::IllegalFunction => 0x01,
+        ExceptionCode::IllegalDataAddress => 0x02,
+        ExceptionCode::IllegalDataValue => 0x03,
+        ExceptionCode::ServerDeviceFailure => 0x04,
+        ExceptionCode::Acknowledge => 0x05,
+        ExceptionCode::ServerDeviceBusy => 0x06,
+        ExceptionCode::MemoryParityError => 0x08,
+        ExceptionCode::GatewayPathUnavailable => 0x0A,
+        ExceptionCode::GatewayTargetDevice => 0x0B,
+        ExceptionCode::Custom(code) => *code,
+    }
+}
+
+/// Whether the text of an error names the exception, either by its `tokio_modbus` variant
+/// (`IllegalDataAddress`) or by its name (`Illegal data address`)
+fn names_exception(text: &str, name: &str) -> bool {
+    let text = text.to_lowercase();
+    let name = name.to_lowercase();
+    text.contains(&name) || text.contains(&name.replace(' ', ""))
+}
+
+/// Find the Modbus exception an error of the device comes from, looking for a `tokio_modbus`
+/// exception along the sources of the error, then for its name in the text of an error mentioning an
+/// exception (the device libraries don't always keep the exception itself)
+///
+/// # Arguments
+///
+/// - `err` (`&dyn Error`) - The error of the request
+///
+/// # Returns
+///
+/// - `Option<u8>` - The exception code, if the error comes from a Modbus exception
+fn find_exception(err: &(dyn Error + 'static)) -> Option<u8> {
+    let mut source = Some(err);
+    while let Some(err) = source {
+        if let Some(exception) = err.downcast_ref::<ExceptionCode>() {
+            return Some(exception_code(exception));
+        }
+        source = err.source();
+    }
+    let text = format!("{err} {err:?}");
+    if !text.to_lowercase().contains("exception") {
+        return None;
+    }
+    EXCEPTIONS
+        .iter()
+        .find(|(_, name, _)| names_exception(&text, name))
+        .map(|(code, _, _)| *code)
+}
+
+/// Explain the Modbus exception a read error comes from, to be added to its log
+///
+/// # Arguments
+///
+/// - `err` (`&IndustrialDeviceError`) - The error of the read
+///
+/// # Returns
+///
+/// - `Option<String>` - The code of the exception and its meaning, `None` if the error is not a Modbus exception
+pub fn describe(err: &IndustrialDeviceError) -> Option<String> {
+    let IndustrialDeviceError::RequestError { err } = err else {
+        return None;
+    };
+    let code = find_exception(err.as_ref())?;
+    Some(
+        match EXCEPTIONS.iter().find(|(known, _, _)| *known == code) {
+            Some((_, name, meaning)) => format!("Modbus exception {code:#04x} {name}: {meaning}"),
+            None => format!("Modbus exception {code:#04x} (specific to the device)"),
+        },
+    )
+}
@@ -1,29 +1,66 @@
-use std::fs::File;
+use std::collections::HashMap;
 
-use modbus_device::{types::RTUContext, utils::get_defs_from_json, ModbusDeviceAsync};
+use modbus_device::{types::RTUContext, ModbusDeviceAsync};
 use serde::Deserialize;
 use tokio_modbus::Slave;
 
+use super::chunking::{
+    chunks, default_read, register_definition, ChunkedDevice, ModbusDevice, RegisterClass,
+};
+use super::endpoint::Forwarded;
 use super::errors::DeviceInitError;
+use super::DeviceOptions;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ModbusRTUDevice {
     pub port: String,
     pub slave: u32,
     pub speed: u32,
+    #[serde(default)]
     pub input_registers: String,
+    #[serde(default)]
     pub holding_registers: String,
+    #[serde(default = "default_read")]
+    pub read_input: bool,
+    #[serde(default = "default_read")]
+    pub read_holding: bool,
+    #[serde(flatten)]
+    pub options: DeviceOptions,
 }
 
-impl TryFrom<ModbusRTUDevice> for ModbusDeviceAsync {
+impl ModbusRTUDevice {
+    /// The options of the device, its bus defaulting to the serial port
+    pub fn device_options(&self) -> DeviceOptions {
+        let mut options = self.options.clone();
+        options.bus.get_or_insert_with(|| self.port.clone());
+        options
+    }
+
+    /// Tags describing the connection to the device
+    pub fn connection_info(&self) -> HashMap<String, String> {
+        HashMap::from([
+            ("endpoint".to_string(), self.port.clone()),
+            ("slave".to_string(), self.slave.to_string()),
+        ])
+    }
+}
+
+impl TryFrom<ModbusRTUDevice> for ModbusDevice {
     type Error = DeviceInitError;
 
     fn try_from(value: ModbusRTUDevice) -> Result<Self, Self::Error> {
-        let input_registers_json = File::open(value.input_registers)?;
-        let input_registers = get_defs_from_json(input_registers_json)?;
+        let pointer = value.options.definition_pointer.as_deref();
+        let input_registers =
+            register_definition(&value.input_registers, value.read_input, pointer)?;
+        let holding_registers =
+            register_definition(&value.holding_registers, value.read_holding, pointer)?;
 
-        let holding_registers_json = File::open(value.holding_registers)?;
-        let holding_registers = get_defs_from_json(holding_registers_json)?;
+        let register_chunks = value.options.chunk_size.map(|size| {
+            let mut res = chunks(&input_registers, RegisterClass::Input, size);
+            res.extend(chunks(&holding_registers, RegisterClass::Holding, size));
+            res
+        });
+        let label = format!("{}/{}", value.port, value.slave);
 
         let context = RTUContext {
             port: value.port,
@@ -31,10 +68,34 @@ impl TryFrom<ModbusRTUDevice> for ModbusDeviceAsync {
             speed: value.speed,
         };
 
-        Ok(ModbusDeviceAsync::new(
-            context.into(),
-            input_registers,
-            holding_registers,
-        ))
+        let device = ChunkedDevice {
+            device: ModbusDeviceAsync::new(context.into(), input_registers, holding_registers),
+            label,
+            chunks: register_chunks,
+        };
+        Ok(Forwarded::new(device, None))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn device(config: serde_json::Value) -> ModbusRTUDevice {
+        serde_json::from_value(config).unwrap()
+    }
+
+    #[test]
+    fn bus_defaults_to_the_serial_port() {
+        let rtu = device(serde_json::json!({"port": "/dev/ttyUSB0", "slave": 2, "speed": 9600}));
+        assert_eq!(rtu.device_options().bus.as_deref(), Some("/dev/ttyUSB0"));
+
+        let rtu = device(serde_json::json!({
+            "port": "/dev/ttyUSB0",
+            "slave": 2,
+            "speed": 9600,
+            "bus": "line1",
+        }));
+        assert_eq!(rtu.device_options().bus.as_deref(), Some("line1"));
     }
 }
@@ -1,34 +1,91 @@
-use std::{fs::File, net::SocketAddr};
+use std::collections::HashMap;
 
-use modbus_device::{types::TCPContext, utils::get_defs_from_json, ModbusDeviceAsync};
+use modbus_device::{types::TCPContext, ModbusDeviceAsync};
 use serde::Deserialize;
 
+use super::chunking::{
+    chunks, default_read, register_definition, ChunkedDevice, ModbusDevice, RegisterClass,
+};
+use super::endpoint::{device_address, Forwarded};
 use super::errors::DeviceInitError;
+use super::DeviceOptions;
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct ModbusTCPDevice {
     pub remote: String,
+    #[serde(default)]
     pub input_registers: String,
+    #[serde(default)]
     pub holding_registers: String,
+    #[serde(default = "default_read")]
+    pub read_input: bool,
+    #[serde(default = "default_read")]
+    pub read_holding: bool,
+    /// The slaves behind a gateway, rejected until the unit id of a TCP device can be set
+    #[serde(default)]
+    pub slaves: Vec<serde_json::Value>,
+    #[serde(flatten)]
+    pub options: DeviceOptions,
 }
 
-impl TryFrom<ModbusTCPDevice> for ModbusDeviceAsync {
+impl ModbusTCPDevice {
+    /// Tags describing the connection to the device
+    pub fn connection_info(&self) -> HashMap<String, String> {
+        HashMap::from([("endpoint".to_string(), self.remote.clone())])
+    }
+}
+
+impl TryFrom<ModbusTCPDevice> for ModbusDevice {
     type Error = DeviceInitError;
 
     fn try_from(value: ModbusTCPDevice) -> Result<Self, Self::Error> {
-        let input_registers_json = File::open(value.input_registers)?;
-        let input_registers = get_defs_from_json(input_registers_json)?;
+        if !value.slaves.is_empty() {
+            return Err(DeviceInitError::ParsingFailed {
+                reason: format!(
+                    "`slaves` of gateway {} are not supported, the requests can't be sent to another unit id",
+                    value.remote
+                ),
+            });
+        }
+        let pointer = value.options.definition_pointer.as_deref();
+        let input_registers =
+            register_definition(&value.input_registers, value.read_input, pointer)?;
+        let holding_registers =
+            register_definition(&value.holding_registers, value.read_holding, pointer)?;
 
-        let holding_registers_json = File::open(value.holding_registers)?;
-        let holding_registers = get_defs_from_json(holding_registers_json)?;
+        let register_chunks = value.options.chunk_size.map(|size| {
+            let mut res = chunks(&input_registers, RegisterClass::Input, size);
+            res.extend(chunks(&holding_registers, RegisterClass::Holding, size));
+            res
+        });
+        let label = value.remote.clone();
 
-        let addr: SocketAddr = value.remote.parse()?;
+        let (addr, forwarder) = device_address(&value.remote, value.options.proxy.as_ref())?;
         let context = TCPContext { addr };
 
-        Ok(ModbusDeviceAsync::new(
-            context.into(),
-            input_registers,
-            holding_registers,
-        ))
+        let device = ChunkedDevice {
+            device: ModbusDeviceAsync::new(context.into(), input_registers, holding_registers),
+            label,
+            chunks: register_chunks,
+        };
+        Ok(Forwarded::new(device, forwarder))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gateway_slaves_are_rejected() {
+        let gateway: ModbusTCPDevice = serde_json::from_value(serde_json::json!({
+            "remote": "10.0.0.1:502",
+            "slaves": [{"unit_id": 1, "name": "pump1"}, {"unit_id": 2, "name": "pump2"}],
+        }))
+        .unwrap();
+        assert!(matches!(
+            ModbusDevice::try_from(gateway),
+            Err(DeviceInitError::ParsingFailed { .. })
+        ));
     }
 }
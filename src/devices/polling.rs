@@ -0,0 +1,179 @@
+use std::{collections::HashMap, time::Duration};
+
+use log::{error, info, warn};
+
+/// Polling state of a device, kept between the fetch cycles
+///
+/// # Fields
+/// - `cycle`: Number of fetch cycles already run for the device.
+/// - `registers`: Registers returned by the last full read, used to request only the due ones.
+/// - `failures`: Number of fetch cycles in a row the device failed.
+/// - `timeouts`: Number of fetch cycles in a row that ended in a timeout.
+/// - `last_read_time`: Time taken by the last fetch that did not time out.
+#[derive(Debug, Clone, Default)]
+pub struct PollState {
+    pub cycle: u64,
+    pub registers: Vec<String>,
+    pub failures: u32,
+    pub timeouts: u32,
+    pub last_read_time: Option<Duration>,
+}
+
+impl PollState {
+    /// Registers to read on the current cycle, a register with `poll_every: N` is only due every Nth cycle
+    ///
+    /// # Arguments
+    ///
+    /// - `poll_every` (`&HashMap<String, u32>`) - The polling divider of the registers, others are read every cycle
+    ///
+    /// # Returns
+    ///
+    /// - `Option<Vec<String>>` - The registers to read, `None` when every register has to be read
+    ///   (nothing skipped this cycle, or registers not known yet)
+    pub fn due(&self, poll_every: &HashMap<String, u32>) -> Option<Vec<String>> {
+        if self.registers.is_empty() {
+            return None;
+        }
+        let is_due = |register: &String| match poll_every.get(register) {
+            Some(every) if *every > 1 => self.cycle.is_multiple_of(*every as u64),
+            _ => true,
+        };
+        if self.registers.iter().all(is_due) {
+            return None;
+        }
+        Some(self.registers.iter().filter(|r| is_due(r)).cloned().collect())
+    }
+
+    /// Whether a read error of the current cycle may trigger a reconnection, which only happens once the device
+    /// failed `grace` cycles in a row (this one included), so an isolated missed read doesn't cause a reconnection
+    ///
+    /// # Arguments
+    ///
+    /// - `grace` (`Option<u32>`) - The number of failed cycles in a row tolerated before reconnecting (default 1)
+    pub fn escalates(&self, grace: Option<u32>) -> bool {
+        self.failures + 1 >= grace.unwrap_or(1)
+    }
+
+    /// Count the consecutive failed cycles, an error is logged when they reach the threshold
+    /// and once the device recovers after having reached it
+    ///
+    /// # Arguments
+    ///
+    /// - `device` (`&str`) - The name of the device (for logging)
+    /// - `success` (`bool`) - Whether the device was read this cycle
+    /// - `threshold` (`Option<u32>`) - The number of failed cycles in a row considered an outage
+    ///
+    /// # Returns
+    ///
+    /// - `bool` - Whether the device reached the threshold this cycle (the error was logged)
+    pub fn record_result(&mut self, device: &str, success: bool, threshold: Option<u32>) -> bool {
+        let reached = threshold.is_some_and(|threshold| self.failures >= threshold);
+        match success {
+            true if reached => {
+                info!("[{device}] recovered after {} failed cycles in a row", self.failures);
+                self.failures = 0;
+                false
+            }
+            true => {
+                self.failures = 0;
+                false
+            }
+            false => {
+                self.failures += 1;
+                let escalated = threshold == Some(self.failures);
+                if escalated {
+                    error!("[{device}] failed {} cycles in a row", self.failures);
+                }
+                escalated
+            }
+        }
+    }
+
+    /// Count the consecutive cycles ending in a timeout, a warning suggesting that the `timeout` is too low
+    /// is logged when they reach the threshold, with the time the last complete fetch took
+    ///
+    /// # Arguments
+    ///
+    /// - `device` (`&str`) - The name of the device (for logging)
+    /// - `timed_out` (`bool`) - Whether the fetch of this cycle ended in a timeout
+    /// - `read_time` (`Duration`) - The time the fetch of this cycle took
+    /// - `timeout` (`Duration`) - The configured `timeout`
+    /// - `threshold` (`u32`) - The number of timed out cycles in a row to warn at, never warned when 0
+    pub fn record_timeout(
+        &mut self,
+        device: &str,
+        timed_out: bool,
+        read_time: Duration,
+        timeout: Duration,
+        threshold: u32,
+    ) {
+        if !timed_out {
+            self.timeouts = 0;
+            self.last_read_time = Some(read_time);
+            return;
+        }
+        self.timeouts += 1;
+        if threshold == 0 || self.timeouts != threshold {
+            return;
+        }
+        let last_read = match self.last_read_time {
+            Some(last) => format!("its last complete fetch took {last:?}"),
+            None => "it never completed a fetch".to_string(),
+        };
+        warn!(
+            "[{device}] timed out {} cycles in a row (this one after {read_time:?}), the timeout of {timeout:?} may be too low for this device, {last_read}",
+            self.timeouts
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn escalates_once_at_the_threshold() {
+        let mut poll = PollState::default();
+        let escalated: Vec<bool> = (0..5)
+            .map(|_| poll.record_result("press1", false, Some(3)))
+            .collect();
+        assert_eq!(escalated, [false, false, true, false, false]);
+        assert_eq!(poll.failures, 5);
+    }
+
+    #[test]
+    fn success_resets_the_count() {
+        let mut poll = PollState::default();
+        poll.record_result("press1", false, Some(3));
+        poll.record_result("press1", false, Some(3));
+        assert!(!poll.record_result("press1", true, Some(3)));
+        assert_eq!(poll.failures, 0);
+        // The count starts over, the threshold is reached again after as many failures
+        let escalated: Vec<bool> = (0..3)
+            .map(|_| poll.record_result("press1", false, Some(3)))
+            .collect();
+        assert_eq!(escalated, [false, false, true]);
+    }
+
+    #[test]
+    fn never_escalates_without_threshold() {
+        let mut poll = PollState::default();
+        assert!((0..10).all(|_| !poll.record_result("press1", false, None)));
+        assert_eq!(poll.failures, 10);
+    }
+
+    #[test]
+    fn escalates_after_the_grace_cycles() {
+        let mut poll = PollState::default();
+        let escalates: Vec<bool> = (0..4)
+            .map(|_| {
+                let escalates = poll.escalates(Some(3));
+                poll.record_result("press1", false, None);
+                escalates
+            })
+            .collect();
+        assert_eq!(escalates, [false, false, true, true]);
+        // Escalated on the first error by default
+        assert!(PollState::default().escalates(None));
+    }
+}
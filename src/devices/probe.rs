@@ -0,0 +1,83 @@
+use std::time::Duration;
+
+use industrial_device::IndustrialDevice;
+use log::info;
+use tokio::time::timeout;
+
+use super::connect_device;
+use crate::types_conversion::RegisterValue;
+
+/// Outcome of the read of a single register
+pub enum Probe {
+    /// The value read, as pushed to the remotes
+    Ok(String),
+    /// Why the register could not be read
+    Failed(String),
+}
+
+/// Read the registers of a device one at a time, to validate a register map against the device
+/// (a register failing doesn't stop the others from being read)
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - The name of the device (for logging)
+/// - `device` (`&mut T`) - The device, not connected yet
+/// - `registers` (`Vec<String>`) - The names of the registers to read
+/// - `connect_timeout` (`Duration`) - The time given to the device to connect
+/// - `read_timeout` (`Duration`) - The time given to each read
+///
+/// # Returns
+///
+/// - `Result<Vec<(String, Probe)>, String>` - The outcome of each register in order, an error if the device can't be connected
+pub async fn probe_registers<T: IndustrialDevice + ?Sized>(
+    name: &str,
+    device: &mut T,
+    registers: Vec<String>,
+    connect_timeout: Duration,
+    read_timeout: Duration,
+) -> Result<Vec<(String, Probe)>, String> {
+    connect_device(device, connect_timeout)
+        .await
+        .map_err(|err| format!("Could not connect to {name} ({err})"))?;
+    info!("Connected to {name}, reading {} registers", registers.len());
+
+    let mut res = Vec::new();
+    for register in registers {
+        let probe = match timeout(read_timeout, device.read_register_by_name(&register)).await {
+            Ok(Ok(value)) => {
+                Probe::Ok(serde_json::to_string(&RegisterValue::from(value)).unwrap_or_default())
+            }
+            Ok(Err(err)) => Probe::Failed(err.to_string()),
+            Err(_) => Probe::Failed(format!("timed out after {read_timeout:?}")),
+        };
+        res.push((register, probe));
+    }
+    Ok(res)
+}
+
+/// Format the outcome of the reads as a table, one register per line and a summary last
+pub fn probe_table(probes: &[(String, Probe)]) -> String {
+    let width = probes
+        .iter()
+        .map(|(register, _)| register.len())
+        .chain(["REGISTER".len()])
+        .max()
+        .unwrap_or_default();
+    let mut table = format!("{:width$}  STATUS  VALUE\n", "REGISTER");
+    let mut failed = 0;
+    for (register, probe) in probes {
+        let (status, detail) = match probe {
+            Probe::Ok(value) => ("OK", value),
+            Probe::Failed(err) => {
+                failed += 1;
+                ("FAIL", err)
+            }
+        };
+        table.push_str(&format!("{register:width$}  {status:6}  {detail}\n"));
+    }
+    table.push_str(&format!(
+        "{} registers readable, {failed} failed\n",
+        probes.len() - failed
+    ));
+    table
+}
@@ -0,0 +1,306 @@
+use std::{io, net::IpAddr};
+
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use super::endpoint::Target;
+
+/// SOCKS protocol version
+const SOCKS5: u8 = 0x05;
+/// Authentication methods
+const NO_AUTH: u8 = 0x00;
+const USER_PASS: u8 = 0x02;
+/// Version of the username/password subnegotiation
+const USER_PASS_VERSION: u8 = 0x01;
+/// Command to open a TCP connection
+const CONNECT: u8 = 0x01;
+/// Address types
+const ATYP_IPV4: u8 = 0x01;
+const ATYP_DOMAIN: u8 = 0x03;
+const ATYP_IPV6: u8 = 0x04;
+
+#[derive(Deserialize, Debug, Clone)]
+#[serde(tag = "type", rename_all = "snake_case")]
+/// Proxy the TCP connection to a device is routed through (e.g. a jump host)
+pub enum Proxy {
+    /// SOCKS5 proxy (e.g. `ssh -D` on the jump host)
+    ///
+    /// # Fields
+    /// - `host`: Host name or address of the proxy.
+    /// - `port`: Port of the proxy (usually 1080).
+    /// - `username`: Username, if the proxy requires authentication.
+    /// - `password`: Password of `username`.
+    Socks5 {
+        host: String,
+        port: u16,
+        username: Option<String>,
+        password: Option<String>,
+    },
+}
+
+impl Proxy {
+    /// Open a TCP connection to `target` through the proxy
+    ///
+    /// # Arguments
+    ///
+    /// - `target` (`&Target`) - The endpoint of the device, a host name being resolved by the proxy
+    ///
+    /// # Returns
+    ///
+    /// - `io::Result<TcpStream>` - The stream to the device, once the proxy opened the connection
+    pub async fn connect(&self, target: &Target) -> io::Result<TcpStream> {
+        match self {
+            Proxy::Socks5 {
+                host,
+                port,
+                username,
+                password,
+            } => {
+                let mut stream = TcpStream::connect((host.as_str(), *port)).await?;
+                let credentials = username
+                    .as_deref()
+                    .map(|username| (username, password.as_deref().unwrap_or("")));
+                socks5_handshake(&mut stream, target, credentials).await?;
+                Ok(stream)
+            }
+        }
+    }
+}
+
+/// Negotiate a SOCKS5 connection to `target` on a stream opened to the proxy (RFC 1928 and RFC 1929)
+///
+/// # Arguments
+///
+/// - `stream` (`&mut TcpStream`) - The stream to the proxy
+/// - `target` (`&Target`) - The endpoint the proxy connects to
+/// - `credentials` (`Option<(&str, &str)>`) - The username and password, if the proxy requires them
+async fn socks5_handshake(
+    stream: &mut TcpStream,
+    target: &Target,
+    credentials: Option<(&str, &str)>,
+) -> io::Result<()> {
+    let method = match credentials {
+        Some(_) => USER_PASS,
+        None => NO_AUTH,
+    };
+    stream.write_all(&[SOCKS5, 1, method]).await?;
+    let mut answer = [0u8; 2];
+    stream.read_exact(&mut answer).await?;
+    if answer[0] != SOCKS5 || answer[1] != method {
+        return Err(socks_error("the proxy refused the authentication method"));
+    }
+
+    if let Some((username, password)) = credentials {
+        let mut request = vec![USER_PASS_VERSION];
+        for field in [username, password] {
+            let len = u8::try_from(field.len())
+                .map_err(|_| socks_error("the username and password are limited to 255 bytes"))?;
+            request.push(len);
+            request.extend_from_slice(field.as_bytes());
+        }
+        stream.write_all(&request).await?;
+        stream.read_exact(&mut answer).await?;
+        if answer[1] != 0 {
+            return Err(socks_error("the proxy rejected the credentials"));
+        }
+    }
+
+    let mut request = vec![SOCKS5, CONNECT, 0x00];
+    let port = match target {
+        Target::Addr(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    request.push(ATYP_IPV4);
+                    request.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    request.push(ATYP_IPV6);
+                    request.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        Target::Host(host, port) => {
+            let len = u8::try_from(host.len())
+                .map_err(|_| socks_error("the host name is limited to 255 bytes"))?;
+            request.push(ATYP_DOMAIN);
+            request.push(len);
+            request.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+    request.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&request).await?;
+
+    // Version, reply, reserved, address type, then the bound address and port which are not used
+    let mut header = [0u8; 4];
+    stream.read_exact(&mut header).await?;
+    if header[1] != 0 {
+        return Err(socks_error(&format!(
+            "the proxy could not connect to the device (reply {})",
+            header[1]
+        )));
+    }
+    let address_len = match header[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => stream.read_u8().await? as usize,
+        _ => return Err(socks_error("unknown address type in the answer")),
+    };
+    let mut bound = vec![0u8; address_len + 2];
+    stream.read_exact(&mut bound).await?;
+    Ok(())
+}
+
+/// An error of the SOCKS5 negotiation
+fn socks_error(reason: &str) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::ConnectionRefused,
+        format!("SOCKS5: {reason}"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    use tokio::{io::copy_bidirectional, net::TcpListener, sync::mpsc};
+
+    use super::*;
+    use crate::devices::endpoint::device_address;
+
+    /// Answer every connection with what it sent
+    async fn echo_server() -> SocketAddr {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                tokio::spawn(async move {
+                    let (mut read, mut write) = stream.split();
+                    let _ = tokio::io::copy(&mut read, &mut write).await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Read the target of a CONNECT request, as `host:port`
+    async fn read_target(stream: &mut TcpStream) -> String {
+        let mut header = [0u8; 4];
+        stream.read_exact(&mut header).await.unwrap();
+        assert_eq!(header[..3], [SOCKS5, CONNECT, 0x00]);
+        let host = match header[3] {
+            ATYP_IPV4 => {
+                let mut ip = [0u8; 4];
+                stream.read_exact(&mut ip).await.unwrap();
+                Ipv4Addr::from(ip).to_string()
+            }
+            ATYP_DOMAIN => {
+                let mut host = vec![0u8; stream.read_u8().await.unwrap() as usize];
+                stream.read_exact(&mut host).await.unwrap();
+                String::from_utf8(host).unwrap()
+            }
+            atyp => panic!("unexpected address type {atyp}"),
+        };
+        format!("{host}:{}", stream.read_u16().await.unwrap())
+    }
+
+    /// A SOCKS5 proxy accepting `credentials`, relaying every connection to `upstream`
+    /// whatever the target requested, which it sends back
+    async fn mock_proxy(
+        credentials: (&'static str, &'static str),
+        upstream: SocketAddr,
+    ) -> (SocketAddr, mpsc::UnboundedReceiver<String>) {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (targets, requested) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let targets = targets.clone();
+                tokio::spawn(async move {
+                    let mut greeting = [0u8; 3];
+                    stream.read_exact(&mut greeting).await.unwrap();
+                    assert_eq!(greeting, [SOCKS5, 1, USER_PASS]);
+                    stream.write_all(&[SOCKS5, USER_PASS]).await.unwrap();
+
+                    let mut fields = Vec::new();
+                    assert_eq!(stream.read_u8().await.unwrap(), USER_PASS_VERSION);
+                    for _ in 0..2 {
+                        let mut field = vec![0u8; stream.read_u8().await.unwrap() as usize];
+                        stream.read_exact(&mut field).await.unwrap();
+                        fields.push(String::from_utf8(field).unwrap());
+                    }
+                    let accepted = fields == [credentials.0, credentials.1];
+                    stream
+                        .write_all(&[USER_PASS_VERSION, u8::from(!accepted)])
+                        .await
+                        .unwrap();
+                    if !accepted {
+                        return;
+                    }
+
+                    targets.send(read_target(&mut stream).await).unwrap();
+                    let mut upstream = TcpStream::connect(upstream).await.unwrap();
+                    let reply = [SOCKS5, 0x00, 0x00, ATYP_IPV4, 127, 0, 0, 1, 0x01, 0xF6];
+                    stream.write_all(&reply).await.unwrap();
+                    let _ = copy_bidirectional(&mut stream, &mut upstream).await;
+                });
+            }
+        });
+        (addr, requested)
+    }
+
+    fn proxy(addr: SocketAddr, password: &str) -> Proxy {
+        serde_json::from_value(serde_json::json!({
+            "type": "socks5",
+            "host": addr.ip().to_string(),
+            "port": addr.port(),
+            "username": "bridge",
+            "password": password,
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn modbus_connection_is_routed_through_the_proxy() {
+        let (proxy_addr, mut requested) =
+            mock_proxy(("bridge", "secret"), echo_server().await).await;
+        let proxy = proxy(proxy_addr, "secret");
+        let (local, _forwarder) = device_address("plc-1.plant.local:502", Some(&proxy)).unwrap();
+
+        // The device connects to the local port as to the PLC
+        let mut stream = TcpStream::connect(local).await.unwrap();
+        let read_holding = [
+            0x00, 0x01, 0x00, 0x00, 0x00, 0x06, 0x01, 0x03, 0x00, 0x00, 0x00, 0x02,
+        ];
+        stream.write_all(&read_holding).await.unwrap();
+        let mut buf = [0u8; 12];
+        stream.read_exact(&mut buf).await.unwrap();
+        assert_eq!(buf, read_holding);
+        // The host name is resolved by the proxy
+        assert_eq!(requested.recv().await.unwrap(), "plc-1.plant.local:502");
+    }
+
+    #[tokio::test]
+    async fn address_target_is_sent_as_ipv4() {
+        let (proxy_addr, mut requested) =
+            mock_proxy(("bridge", "secret"), echo_server().await).await;
+        let target = Target::Addr("10.0.0.1:502".parse().unwrap());
+        proxy(proxy_addr, "secret").connect(&target).await.unwrap();
+        assert_eq!(requested.recv().await.unwrap(), "10.0.0.1:502");
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_fail_the_connection() {
+        let (proxy_addr, _requested) = mock_proxy(("bridge", "secret"), echo_server().await).await;
+        let target = Target::Addr("10.0.0.1:502".parse().unwrap());
+        let err = proxy(proxy_addr, "wrong")
+            .connect(&target)
+            .await
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::ConnectionRefused);
+    }
+}
@@ -1,33 +1,47 @@
-use std::{fs::File, net::SocketAddr};
+use std::collections::HashMap;
 
-use s7_device::utils::{get_defs_from_json, JsonReadError};
+use s7_device::utils::get_defs_from_json;
 use serde::Deserialize;
 
+use super::definitions::open_definition;
+use super::endpoint::{device_address, Forwarded};
 use super::errors::DeviceInitError;
+use super::DeviceOptions;
+
+/// The fields of a register in the S7 definitions, the columns a CSV definition may have besides `name`
+pub const DEFINITION_FIELDS: &[&str] = &["address", "type", "scale", "offset", "unit"];
 
 #[derive(Deserialize, Debug, Clone)]
 pub struct S7Device {
     pub remote: String,
     pub registers: String,
+    #[serde(flatten)]
+    pub options: DeviceOptions,
+}
+
+impl S7Device {
+    /// Tags describing the connection to the device
+    pub fn connection_info(&self) -> HashMap<String, String> {
+        HashMap::from([("endpoint".to_string(), self.remote.clone())])
+    }
 }
 
-impl TryFrom<S7Device> for s7_device::S7Device {
+/// Siemens S7 PLC, connected through a local forwarding port when reached by host name or through a proxy
+pub type S7PlcDevice = Forwarded<s7_device::S7Device>;
+
+impl TryFrom<S7Device> for S7PlcDevice {
     type Error = DeviceInitError;
 
     fn try_from(value: S7Device) -> Result<Self, Self::Error> {
-        let registers_json = File::open(value.registers)?;
+        let pointer = value.options.definition_pointer.as_deref();
+        let registers_json = open_definition(&value.registers, pointer, DEFINITION_FIELDS)?;
         let registers = get_defs_from_json(registers_json)?;
 
-        let addr: SocketAddr = value.remote.parse()?;
-
-        Ok(s7_device::S7Device::new(addr, registers))
-    }
-}
+        let (addr, forwarder) = device_address(&value.remote, value.options.proxy.as_ref())?;
 
-impl From<JsonReadError> for DeviceInitError {
-    fn from(value: JsonReadError) -> Self {
-        DeviceInitError::ParsingFailed {
-            err: Box::new(value),
-        }
+        Ok(Forwarded::new(
+            s7_device::S7Device::new(addr, registers),
+            forwarder,
+        ))
     }
 }
@@ -0,0 +1,145 @@
+use std::{collections::HashMap, time::Duration};
+
+use serde::Deserialize;
+
+use crate::types_conversion::RegisterValue;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How the samples of a numeric register are combined
+pub enum Aggregation {
+    #[default]
+    Mean,
+    Min,
+    Max,
+}
+
+/// How a device is sampled over a fetch period
+///
+/// # Fields
+/// - `samples`: Number of reads.
+/// - `interval`: Time between the start of two reads.
+/// - `aggregation`: How the numeric values are combined.
+/// - `stats`: Whether the minimum, maximum and mean of the numeric values are added as fields of their own.
+#[derive(Debug, Clone, Copy)]
+pub struct Sampling {
+    pub samples: u32,
+    pub interval: Duration,
+    pub aggregation: Aggregation,
+    pub stats: bool,
+}
+
+/// Combine several samples of the registers of a device into a single value per register.
+/// Numeric registers are aggregated, the others (booleans, enums, byte arrays) take the last sample.
+/// NaN samples are ignored, a register with only NaN samples takes the last one.
+/// With `stats`, the numeric registers also get `{register}_min`, `{register}_max` and `{register}_mean` fields.
+///
+/// # Arguments
+///
+/// - `samples` (`Vec<HashMap<String, RegisterValue>>`) - The samples, in acquisition order
+/// - `aggregation` (`Aggregation`) - How the numeric values are combined
+/// - `stats` (`bool`) - Whether the statistics of the numeric registers are added
+///
+/// # Returns
+///
+/// - `HashMap<String, RegisterValue>` - One value for each register present in any sample, and its statistics
+pub fn aggregate(
+    samples: Vec<HashMap<String, RegisterValue>>,
+    aggregation: Aggregation,
+    stats: bool,
+) -> HashMap<String, RegisterValue> {
+    let mut fields: HashMap<String, Vec<RegisterValue>> = HashMap::new();
+    for sample in samples {
+        for (name, value) in sample {
+            fields.entry(name).or_default().push(value);
+        }
+    }
+
+    let mut res = HashMap::new();
+    for (name, values) in fields {
+        let Some(last) = values.last().cloned() else {
+            continue;
+        };
+        if !values.iter().all(RegisterValue::is_numeric) {
+            res.insert(name, last);
+            continue;
+        }
+        // Invalid readings (NaN) are left out rather than counted as -1
+        let numbers: Vec<f64> = values
+            .iter()
+            .map(RegisterValue::as_f64)
+            .filter(|val| !val.is_nan())
+            .collect();
+        if numbers.is_empty() {
+            res.insert(name, last);
+            continue;
+        }
+        let min = numbers.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = numbers.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        let mean = numbers.iter().sum::<f64>() / numbers.len() as f64;
+        if stats {
+            res.insert(format!("{name}_min"), RegisterValue::Float(min));
+            res.insert(format!("{name}_max"), RegisterValue::Float(max));
+            res.insert(format!("{name}_mean"), RegisterValue::Float(mean));
+        }
+        let value = match aggregation {
+            Aggregation::Mean => mean,
+            Aggregation::Min => min,
+            Aggregation::Max => max,
+        };
+        res.insert(name, RegisterValue::Float(value));
+    }
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use industrial_device::types::Value;
+
+    /// Samples of a temperature read four times, with an invalid reading, and of a text field
+    fn samples() -> Vec<HashMap<String, RegisterValue>> {
+        [21.0, 23.5, f64::NAN, 21.5]
+            .iter()
+            .enumerate()
+            .map(|(i, temperature)| {
+                HashMap::from([
+                    (
+                        "temperature".to_string(),
+                        RegisterValue::Float(*temperature),
+                    ),
+                    ("mode".to_string(), RegisterValue::Text(format!("step{i}"))),
+                ])
+            })
+            .collect()
+    }
+
+    #[test]
+    fn stats_match_the_samples() {
+        let res = aggregate(samples(), Aggregation::Max, true);
+        assert_eq!(res["temperature"], RegisterValue::Float(23.5));
+        assert_eq!(res["temperature_min"], RegisterValue::Float(21.0));
+        assert_eq!(res["temperature_max"], RegisterValue::Float(23.5));
+        assert_eq!(res["temperature_mean"], RegisterValue::Float(22.0));
+        // Only the numeric registers get statistics
+        assert_eq!(res["mode"], RegisterValue::Text("step3".to_string()));
+        assert_eq!(res.len(), 5);
+    }
+
+    #[test]
+    fn stats_are_only_pushed_when_enabled() {
+        let res = aggregate(samples(), Aggregation::Mean, false);
+        assert_eq!(res["temperature"], RegisterValue::Float(22.0));
+        assert_eq!(res.len(), 2);
+    }
+
+    #[test]
+    fn raw_values_are_aggregated_as_numbers() {
+        let samples = [10, 20]
+            .map(|value| HashMap::from([("speed".to_string(), Value::U16(value).into())]))
+            .to_vec();
+        let res = aggregate(samples, Aggregation::Min, true);
+        assert_eq!(res["speed"], RegisterValue::Float(10.0));
+        assert_eq!(res["speed_mean"], RegisterValue::Float(15.0));
+    }
+}
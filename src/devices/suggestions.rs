@@ -0,0 +1,71 @@
+/// Maximum edit distance for a register name to be suggested
+const MAX_DISTANCE: usize = 3;
+
+/// Levenshtein distance between two strings (number of single character edits)
+fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut curr = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        curr[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let cost = (ca != *cb) as usize;
+            curr[j + 1] = (prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1);
+        }
+        std::mem::swap(&mut prev, &mut curr);
+    }
+    prev[b.len()]
+}
+
+/// Find the known names closest to a name that could not be found (e.g. a typo in a register definition)
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - The name that was not found
+/// - `known` (`&[String]`) - The names that exist
+/// - `count` (`usize`) - The maximum number of suggestions
+///
+/// # Returns
+///
+/// - `Vec<&String>` - The closest names, best match first
+pub fn closest<'a>(name: &str, known: &'a [String], count: usize) -> Vec<&'a String> {
+    let mut candidates: Vec<(usize, &String)> = known
+        .iter()
+        .map(|candidate| (levenshtein(name, candidate), candidate))
+        .filter(|(distance, _)| *distance <= MAX_DISTANCE)
+        .collect();
+    candidates.sort();
+    candidates
+        .into_iter()
+        .take(count)
+        .map(|(_, candidate)| candidate)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn known() -> Vec<String> {
+        ["temperature", "temperature_setpoint", "pressure", "speed"]
+            .map(String::from)
+            .to_vec()
+    }
+
+    #[test]
+    fn near_miss_suggests_the_register() {
+        assert_eq!(closest("temprature", &known(), 3), ["temperature"]);
+        assert_eq!(closest("pressur", &known(), 3), ["pressure"]);
+    }
+
+    #[test]
+    fn closest_names_come_first() {
+        assert_eq!(closest("sped", &known(), 1), ["speed"]);
+        assert_eq!(levenshtein("kitten", "sitting"), 3);
+    }
+
+    #[test]
+    fn unrelated_name_suggests_nothing() {
+        assert!(closest("vibration", &known(), 3).is_empty());
+    }
+}
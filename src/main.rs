@@ -1,29 +1,55 @@
-use devices::{connect_devices, fetch_device};
+use devices::errors::FetchError;
+use devices::probe::{probe_registers, probe_table, Probe};
+use devices::{connect_devices, fetch_device, restart_device, stagger};
 use industrial_device::IndustrialDevice;
 use remotes::remote::Remote;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::path::PathBuf;
 use std::rc::Rc;
 use std::sync::Arc;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
-use log::{debug, error};
+use chrono::{TimeDelta, Utc};
 
-use clap::Parser;
+use log::{debug, error, info, warn};
 
-use tokio::sync::{watch, Mutex};
+use clap::Parser;
 
-use config;
+use tokio::select;
+use tokio::sync::{mpsc, watch, Mutex};
 
 mod app_config;
-use app_config::AppConfig;
+use app_config::{config_dir_files, load_config, AppConfig};
 
 mod types_conversion;
-use types_conversion::RegisterValue;
+
+mod measurement;
+use measurement::{Connections, LastValues, Measurement};
 
 mod devices;
 mod remotes;
-use remotes::send_data_to_remotes;
+mod telemetry;
+use remotes::deadletter::DeadLetter;
+use remotes::heartbeat::send_heartbeats;
+use remotes::wal::{Wal, DEFAULT_MAX_BATCHES};
+use remotes::{replay_wal, send_data_to_remotes, send_last_will, send_once, RemoteEntry};
+
+/// Exit code when every device failed for `max_dead_cycles` cycles in a row
+const DEAD_EXIT_CODE: i32 = 3;
+/// Exit code when a push of the single cycle of `--once` failed
+const ONCE_PUSH_EXIT_CODE: i32 = 4;
+/// Exit code when a register of `--check-registers` could not be read
+const CHECK_FAILED_EXIT_CODE: i32 = 5;
+
+/// What the single fetch cycle of `--once` does with the data
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Once {
+    /// Push it to the remotes
+    Push,
+    /// Print it to stdout as a single JSON object, the remotes are not initialised
+    Json,
+}
 
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
@@ -31,32 +57,286 @@ struct Args {
     #[arg(
         short,
         long,
-        default_value = "config.yaml",
-        help = "Config path",
-        long_help = "Where to find the config file"
+        help = "Config path (can be repeated)",
+        long_help = "Where to find the config file, can be repeated to merge several files (later files override the previous ones), defaults to config.yaml"
+    )]
+    config_file: Vec<String>,
+    #[arg(
+        long,
+        help = "Config directory",
+        long_help = "Directory whose config files (.yaml, .yml, .toml) are merged in name order, after the --config-file ones"
+    )]
+    config_dir: Option<String>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only run these devices",
+        long_help = "Comma separated names of the devices to run, the other configured devices are left out (e.g. to troubleshoot a single machine)"
+    )]
+    devices: Option<Vec<String>>,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Only push to these remotes",
+        long_help = "Comma separated names of the remotes to push to, the other configured remotes are left out"
     )]
-    config_file: String,
+    remotes: Option<Vec<String>>,
+    #[arg(
+        long,
+        help = "Fetch the devices once and exit",
+        long_help = "Run a single fetch cycle, push it to the remotes (or print it with --json) and exit, with code 4 if a push failed"
+    )]
+    once: bool,
+    #[arg(
+        long,
+        requires = "once",
+        help = "Print the data of --once as JSON",
+        long_help = "Print the data of the --once cycle to stdout as a single JSON object keyed by device instead of pushing it to the remotes, the logs stay on stderr"
+    )]
+    json: bool,
+    #[arg(
+        long,
+        value_name = "DEVICE",
+        conflicts_with = "once",
+        help = "Read each register of a device and exit",
+        long_help = "Read the registers of the device one at a time and print which ones can be read, with their value, and which ones fail, exits with code 5 if a register failed"
+    )]
+    check_registers: Option<String>,
+}
+
+/// Keep only the named entries of a map, panics if a name is not in the map
+///
+/// # Arguments
+///
+/// - `map` (`HashMap<String, T>`) - The devices or remotes, keyed by name
+/// - `names` (`Option<Vec<String>>`) - The names to keep, the whole map is kept if `None`
+/// - `kind` (`&str`) - What the map holds (for the error message)
+fn filter_names<T>(
+    mut map: HashMap<String, T>,
+    names: Option<Vec<String>>,
+    kind: &str,
+) -> HashMap<String, T> {
+    let names = match names {
+        Some(names) => names,
+        None => return map,
+    };
+    let mut res = HashMap::new();
+    for name in names {
+        match map.remove(&name) {
+            Some(val) => {
+                res.insert(name, val);
+            }
+            None if res.contains_key(&name) => {}
+            None => panic!("No {kind} named {name} in the configuration"),
+        }
+    }
+    info!(
+        "Only running the {kind}s {:?}",
+        res.keys().collect::<Vec<_>>()
+    );
+    res
+}
+
+/// Measure the time elapsed since the previous fetch cycle against the configured period,
+/// the jitter is recorded and a warning is logged when a cycle starts more than half a period late
+/// (the host is too loaded to keep up)
+///
+/// # Arguments
+///
+/// - `last_cycle` (`&mut Option<Instant>`) - The start of the previous cycle, replaced by the current one
+/// - `period` (`Duration`) - The configured period
+fn record_cycle_jitter(last_cycle: &mut Option<Instant>, period: Duration) {
+    let now = Instant::now();
+    let Some(last) = last_cycle.replace(now) else {
+        return;
+    };
+    let elapsed = now - last;
+    let jitter = elapsed.as_secs_f64() - period.as_secs_f64();
+    telemetry::record_cycle_jitter(jitter);
+    match !period.is_zero() && elapsed > period + period / 2 {
+        true => warn!(
+            "Fetch cycle started {jitter:.3}s late ({elapsed:?} since the previous one for a period of {period:?}), the host may be too loaded to keep up"
+        ),
+        false => debug!("Fetch cycle jitter : {jitter:.3}s"),
+    }
+}
+
+/// Resolves when the bridge is asked to stop (Ctrl-C, or SIGTERM on unix)
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        let mut sigterm =
+            tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()).unwrap();
+        select! {
+            _ = tokio::signal::ctrl_c() => {}
+            _ = sigterm.recv() => {}
+        }
+    }
+    #[cfg(not(unix))]
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+/// Requests of an immediate fetch cycle, out of the schedule (SIGUSR1 on unix, e.g. `kill -USR1 <pid>`
+/// right after changing a setpoint). The requests made while a cycle runs are merged into a single extra
+/// cycle, run once the current one is done.
+fn fetch_triggers() -> mpsc::Receiver<()> {
+    let (tx, rx) = mpsc::channel(1);
+    #[cfg(unix)]
+    tokio::task::spawn(async move {
+        let kind = tokio::signal::unix::SignalKind::user_defined1();
+        let mut sigusr1 = match tokio::signal::unix::signal(kind) {
+            Ok(sigusr1) => sigusr1,
+            Err(err) => {
+                error!("Could not listen to SIGUSR1, the fetch can't be triggered ({err})");
+                return;
+            }
+        };
+        while sigusr1.recv().await.is_some() {
+            // A request is already pending otherwise
+            let _ = tx.try_send(());
+        }
+    });
+    #[cfg(not(unix))]
+    drop(tx);
+    rx
 }
 
-#[tokio::main]
 /// Main function of the bridge
-async fn main() {
+fn main() {
     // Initialize utils
     // configuration du logger
     env_logger::init();
     // recupération des arguments
     let args = Args::parse();
-    let config = config::Config::builder()
-        .add_source(config::File::with_name(&args.config_file))
-        .build()
-        .unwrap(); // récupère la valeur et erreur si rien
-    
-    // récupération des informations du fichier
-    let app: AppConfig = config.try_deserialize().unwrap();
+    let mut config_files = args.config_file;
+    if let Some(dir) = &args.config_dir {
+        config_files.extend(config_dir_files(dir).unwrap());
+    }
+    if config_files.is_empty() {
+        config_files.push("config.yaml".to_string());
+    }
+
+    // récupération des informations des fichiers
+    let app: AppConfig = load_config(&config_files).unwrap();
+    app.log_summary();
+
+    let runtime = app
+        .build_runtime()
+        .unwrap_or_else(|err| panic!("Could not start the runtime ({err})"));
+    if let Some(name) = args.check_registers {
+        let failed = runtime.block_on(check_registers(app, &name));
+        std::process::exit(match failed {
+            true => CHECK_FAILED_EXIT_CODE,
+            false => 0,
+        });
+    }
+    let once = match (args.once, args.json) {
+        (false, _) => None,
+        (true, false) => Some(Once::Push),
+        (true, true) => Some(Once::Json),
+    };
+    runtime.block_on(run(app, args.devices, args.remotes, once));
+}
+
+/// Read the registers of a device one at a time and print the outcome of each read as a table,
+/// to validate a register map against the device (e.g. when commissioning it)
+///
+/// # Arguments
+///
+/// - `app` (`AppConfig`) - The configuration of the bridge
+/// - `name` (`&str`) - The name of the device to check
+///
+/// # Returns
+///
+/// - `bool` - Whether a register could not be read
+async fn check_registers(app: AppConfig, name: &str) -> bool {
+    devices::definitions::set_retry(app.definition_retry);
+    devices::definitions::set_cache_dir(app.definition_cache_dir.as_ref().map(PathBuf::from));
+    let kind = app
+        .devices
+        .kinds()
+        .remove(name)
+        .unwrap_or_else(|| panic!("No device named {name} in the configuration"));
+    let registers = kind
+        .register_names()
+        .unwrap_or_else(|err| panic!("Could not read the registers of {name} ({err})"));
+    let mut device: Box<dyn IndustrialDevice + Send> = kind
+        .try_into()
+        .unwrap_or_else(|err| panic!("Could not initialise {name} ({err})"));
+    let read_timeout = match app.timeout {
+        Some(timeout) => Duration::from_secs(timeout),
+        None => Duration::MAX,
+    };
+    let probes = probe_registers(
+        name,
+        device.as_mut(),
+        registers,
+        Duration::from_secs(app.connect_timeout),
+        read_timeout,
+    )
+    .await
+    .unwrap_or_else(|err| panic!("{err}"));
+    print!("{}", probe_table(&probes));
+    probes
+        .iter()
+        .any(|(_, probe)| matches!(probe, Probe::Failed(_)))
+}
+
+/// Run the bridge until it is asked to stop
+///
+/// # Arguments
+///
+/// - `app` (`AppConfig`) - The configuration of the bridge
+/// - `only_devices` (`Option<Vec<String>>`) - The devices to run (default: all)
+/// - `only_remotes` (`Option<Vec<String>>`) - The remotes to push to (default: all)
+/// - `once` (`Option<Once>`) - Run a single fetch cycle and exit (default: run until asked to stop)
+async fn run(
+    app: AppConfig,
+    only_devices: Option<Vec<String>>,
+    only_remotes: Option<Vec<String>>,
+    once: Option<Once>,
+) {
+    // Export the bridge's own metrics and spans
+    #[cfg(feature = "otel")]
+    let telemetry = app.otel.as_ref().map(|otel| telemetry::init(otel).unwrap());
+    #[cfg(not(feature = "otel"))]
+    if app.otel.is_some() {
+        log::warn!("OpenTelemetry is configured but the bridge was built without the otel feature");
+    }
+
     // Initialize our targets from config
     // panic on error (better catch it here at launch)  
-    let devices_box: HashMap<String, Box<dyn IndustrialDevice + Send>> =
-        app.devices.try_into().unwrap();
+    let mut devices_options = app.devices.options();
+    let mut device_tags = match app.tag_connection_info {
+        true => app.devices.connection_info(),
+        false => HashMap::new(),
+    };
+    for (name, options) in &devices_options {
+        if !options.tags.is_empty() {
+            device_tags.entry(name.clone()).or_default().extend(options.tags.clone());
+        }
+    }
+    let device_units: HashMap<String, HashMap<String, String>> = devices_options
+        .iter()
+        .filter(|(_, options)| !options.units.is_empty())
+        .map(|(name, options)| (name.clone(), options.units.clone()))
+        .collect();
+    // Kept to build the devices whose fetch got stuck again
+    let device_kinds = match app.watchdog_ms {
+        Some(_) => app.devices.kinds(),
+        None => HashMap::new(),
+    };
+    let watchdog = app.watchdog_ms.map(Duration::from_millis);
+    devices::definitions::set_retry(app.definition_retry);
+    devices::definitions::set_cache_dir(app.definition_cache_dir.as_ref().map(PathBuf::from));
+    app.devices
+        .check_types()
+        .unwrap_or_else(|err| panic!("Could not initialise the devices ({err})"));
+    let devices_box: HashMap<String, Box<dyn IndustrialDevice + Send>> = app
+        .devices
+        .try_into()
+        .unwrap_or_else(|err| panic!("Could not initialise the devices ({err})"));
+    let devices_box = filter_names(devices_box, only_devices, "device");
     
     let devices: Rc<RefCell<HashMap<String, Arc<Mutex<Box<dyn IndustrialDevice + Send>>>>>> =
         Rc::new(RefCell::new(
@@ -66,58 +346,406 @@ async fn main() {
                 .collect(),
         ));
     
-        // Initialize the remotes
-    let remotes_box: HashMap<String, Box<dyn Remote + Send>> = app.remotes.try_into().unwrap();
+    // Initialize the remotes
+    let mut remotes_options = app.remotes.options();
+    let remotes_box: HashMap<String, Box<dyn Remote + Send>> = match once {
+        Some(Once::Json) => HashMap::new(),
+        _ => {
+            let remotes_box = app
+                .remotes
+                .try_into()
+                .unwrap_or_else(|err| panic!("Could not initialise the remotes ({err})"));
+            filter_names(remotes_box, only_remotes, "remote")
+        }
+    };
     
 
-    let remotes: Arc<Mutex<HashMap<String, Arc<Mutex<Box<dyn Remote + Send>>>>>> =
+    let remotes: Arc<Mutex<HashMap<String, RemoteEntry<dyn Remote + Send>>>> =
         Arc::new(Mutex::new(
             remotes_box
                 .into_iter()
-                .map(|(name, val)| (name, Arc::new(Mutex::new(val))))
+                .map(|(name, val)| {
+                    let options = remotes_options.remove(&name).unwrap_or_default();
+                    (name, RemoteEntry::new(val, options))
+                })
                 .collect(),
         ));
 
     // connect to all devices
-    connect_devices(devices.clone()).await;
+    let connect_timeout = Duration::from_secs(app.connect_timeout);
+    connect_devices(devices.clone(), connect_timeout).await;
     
     // Data fetch is triggered at the interval entered in configuration
-    let mut interval = tokio::time::interval(Duration::from_secs(app.period));
+    let period = Duration::from_secs(app.period);
+    if app.stagger {
+        let names = devices.borrow().keys().cloned().collect();
+        stagger(&mut devices_options, names, period);
+    }
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(app.missed_tick.into());
     
     let timeout = match app.timeout {
         Some(timeout) => Duration::from_secs(timeout),
         None => Duration::MAX,
     };
-    let (data_received_tx, mut data_received_rx) =
-        watch::channel(HashMap::<String, HashMap<String, RegisterValue>>::new());
+    let (data_received_tx, data_received_rx) =
+        watch::channel(HashMap::<String, Measurement>::new());
     
+    // A single cycle is pushed directly, without write-ahead log
+    let mut wal = app
+        .wal_path
+        .as_ref()
+        .filter(|_| once.is_none())
+        .map(|path| {
+            let max_batches = app.wal_max_batches.unwrap_or(DEFAULT_MAX_BATCHES);
+            Wal::open(path.as_ref(), max_batches)
+                .unwrap_or_else(|err| panic!("Could not open the write-ahead log {path} ({err})"))
+        });
+
+    // Stream the batches to the local clients
+    if let Some(path) = app.unix_socket.clone().filter(|_| once.is_none()) {
+        #[cfg(unix)]
+        {
+            let data = data_received_rx.clone();
+            tokio::task::spawn(async move {
+                if let Err(err) = remotes::unix_socket::serve_unix_socket(&path, data).await {
+                    error!("Could not listen on the Unix socket {path} ({err})");
+                }
+            });
+        }
+        #[cfg(not(unix))]
+        log::warn!("Unix sockets are not supported on this platform, {path} is ignored");
+    }
+
     // Start the task that send data to remotes
-    {
+    if once.is_none() {
+        let remotes = remotes.clone();
+        let max_pushes = app.max_concurrent_pushes;
+        let deadletter = app.deadletter_path.as_ref().map(|path| {
+            DeadLetter::open(path.as_ref())
+                .unwrap_or_else(|err| panic!("Could not open the dead letter file {path} ({err})"))
+        });
         tokio::task::spawn(async move {
-            match data_received_rx.changed().await {
-                Ok(_) => {}
-                Err(err) => error!("There was an error waiting for new data : ({err})"),
-            };
-            send_data_to_remotes(remotes, data_received_rx).await;
+            if let Some(wal) = wal.as_mut() {
+                replay_wal(remotes.clone(), wal).await;
+            }
+            send_data_to_remotes(remotes, data_received_rx, wal, max_pushes, deadletter).await;
         });
     }
+
+    // Push the heartbeats on their own interval, independently of the fetch cycles
+    if let Some(heartbeat) = app.heartbeat.clone().filter(|_| once.is_none()) {
+        tokio::task::spawn(send_heartbeats(remotes.clone(), heartbeat));
+    }
     
-    loop {
-        // Wait for the configured time
-        interval.tick().await;
+    let shutdown = shutdown_signal();
+    tokio::pin!(shutdown);
+    let mut triggers = fetch_triggers();
+    // Devices that were not accessible on their last fetch
+    let mut connections = Connections::default();
+    let mut polls = HashMap::new();
+    // Last known values of the devices with `age_fields` or `value_ttl` set
+    let mut last_values: HashMap<String, LastValues> = HashMap::new();
+    // Start of the previous fetch cycle, to measure the jitter
+    let mut last_cycle: Option<Instant> = None;
+    // Number of cycles in a row where every device failed
+    let mut dead_cycles: u32 = 0;
+    let max_dead_cycles = app.max_dead_cycles.filter(|max| *max > 0);
 
+    // The first tick completes at once, so the first cycle runs (and reaches the remotes) right at startup
+    interval.tick().await;
+    record_cycle_jitter(&mut last_cycle, period);
+    loop {
         // Fetch all data
-        let mut rec_out: HashMap<String, HashMap<String, RegisterValue>> = HashMap::new();
-        rec_out.clear();
+        let mut rec_out: HashMap<String, Measurement> = HashMap::new();
 
-        rec_out = fetch_device(devices.clone(), timeout).await;
-        return;
+        let fetched = telemetry::in_span(
+            "fetch_cycle",
+            &[],
+            fetch_device(
+                devices.clone(),
+                &devices_options,
+                &mut polls,
+                period,
+                timeout,
+                connect_timeout,
+                watchdog,
+            ),
+        )
+        .await;
+        if let Some(max) = max_dead_cycles {
+            match !fetched.is_empty() && fetched.values().all(|res| res.is_err()) {
+                true => dead_cycles += 1,
+                false => dead_cycles = 0,
+            }
+            if dead_cycles >= max {
+                error!("Every device failed for {dead_cycles} cycles in a row, exiting");
+                std::process::exit(DEAD_EXIT_CODE);
+            }
+        }
+        for (name, res) in &fetched {
+            let Err(FetchError::Stuck {}) = res else {
+                continue;
+            };
+            let Some(kind) = device_kinds.get(name) else {
+                continue;
+            };
+            match Box::<dyn IndustrialDevice + Send>::try_from(kind.clone()) {
+                Ok(device) => {
+                    let device = restart_device(name, device, connect_timeout).await;
+                    devices.borrow_mut().insert(name.clone(), device);
+                }
+                Err(err) => error!("[{name}] could not build the device again ({err})"),
+            }
+        }
+        for (name, res) in fetched {
+            let options = devices_options.get(&name);
+            let keep_last = options.is_some_and(|o| o.age_fields || o.value_ttl.is_some());
+            // Only the transitions are reported, a device read again after being inaccessible is pushed with connected=1
+            let status = match &res {
+                Ok(_) => connections.transition(&name, true),
+                Err(FetchError::Disconnected {}) => connections.transition(&name, false),
+                Err(_) => None,
+            }
+            .filter(|_| app.last_will);
+            match res {
+                Ok(measurement) if keep_last => {
+                    let last = last_values.entry(name.clone()).or_insert_with(|| {
+                        let options = options.unwrap();
+                        let ttl = options.value_ttl.map(|ttl| TimeDelta::seconds(ttl as i64));
+                        LastValues::new(options.age_fields, ttl)
+                    });
+                    last.update(&measurement);
+                    if let Some(measurement) = last.current(measurement.timestamp) {
+                        rec_out.insert(name.clone(), measurement);
+                    }
+                }
+                Ok(measurement) => {
+                    rec_out.insert(name.clone(), measurement);
+                }
+                Err(FetchError::Disconnected {}) if status.is_some() => {
+                    rec_out.insert(name.clone(), Measurement::status("connected", false));
+                }
+                Err(_) => {
+                    // Keep forwarding the stale values until their TTL
+                    if let Some(measurement) = last_values
+                        .get_mut(&name)
+                        .and_then(|last| last.current(Utc::now()))
+                    {
+                        rec_out.insert(name.clone(), measurement);
+                    }
+                }
+            }
+            if status == Some(true) {
+                let connected = Measurement::status("connected", true);
+                match rec_out.get_mut(&name) {
+                    Some(measurement) => measurement.values.extend(connected.values),
+                    None => {
+                        rec_out.insert(name, connected);
+                    }
+                }
+            }
+        }
+        // Carry the tags, the connection and the units of each device alongside its values
+        for (name, measurement) in rec_out.iter_mut() {
+            if let Some(tags) = device_tags.get(name) {
+                measurement.tags.extend(tags.clone());
+            }
+            if let Some(units) = device_units.get(name) {
+                measurement.units.extend(units.clone());
+            }
+        }
         debug!("{rec_out:?}");
 
+        if let Some(once) = once {
+            let pushed = match once {
+                Once::Json => {
+                    println!("{}", measurement::batch_to_json(&rec_out));
+                    true
+                }
+                Once::Push => send_once(remotes, rec_out).await,
+            };
+            #[cfg(feature = "otel")]
+            if let Some(telemetry) = telemetry {
+                let _ = tokio::task::spawn_blocking(move || telemetry.shutdown()).await;
+            }
+            if !pushed {
+                error!("Could not push the data to every remote");
+                std::process::exit(ONCE_PUSH_EXIT_CODE);
+            }
+            return;
+        }
+
         // Send the new data
         match data_received_tx.send(rec_out) {
             Ok(_) => {}
             Err(err) => error!("Could not send data to be pushed : ({err})"),
         };
+
+        // Wait for the configured time, or for a triggered fetch
+        select! {
+            _ = interval.tick() => {}
+            // Disabled once the sender is gone (no trigger on this platform)
+            Some(()) = triggers.recv() => info!("Fetch triggered, starting an extra cycle"),
+            _ = &mut shutdown => {
+                info!("Shutting down");
+                if app.last_will {
+                    let mut status =
+                        HashMap::from([("bridge".to_string(), Measurement::status("bridge_up", false))]);
+                    for name in devices.borrow().keys() {
+                        let mut measurement = Measurement::status("connected", false);
+                        if let Some(tags) = device_tags.get(name) {
+                            measurement.tags.extend(tags.clone());
+                        }
+                        status.insert(name.clone(), measurement);
+                    }
+                    send_last_will(remotes, status).await;
+                }
+                #[cfg(feature = "otel")]
+                if let Some(telemetry) = telemetry {
+                    let _ = tokio::task::spawn_blocking(move || telemetry.shutdown()).await;
+                }
+                return;
+            }
+        }
+        record_cycle_jitter(&mut last_cycle, period);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::net::UdpSocket;
+
+    use super::*;
+    use crate::remotes::mock_server::serve;
+
+    /// An Omron PLC answering its first `answers` FINS reads with words holding 215, then unreachable
+    async fn mock_plc(answers: usize) -> std::net::SocketAddr {
+        let socket = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let addr = socket.local_addr().unwrap();
+        tokio::task::spawn(async move {
+            let mut buf = [0u8; 64];
+            for _ in 0..answers {
+                let (len, client) = socket.recv_from(&mut buf).await.unwrap();
+                let count = u16::from_be_bytes([buf[len - 2], buf[len - 1]]);
+                let mut frame = vec![0xC0, 0, 0x02, 0, 0, 0, 0, 0x0A, 0, buf[9], 0x01, 0x01, 0, 0];
+                for _ in 0..count {
+                    frame.extend_from_slice(&215u16.to_be_bytes());
+                }
+                socket.send_to(&frame, client).await.unwrap();
+            }
+        });
+        addr
+    }
+
+    /// A bridge reading a PLC at `plc` every `period` seconds, with `config` merged in,
+    /// and the registers file of the PLC
+    fn bridge(
+        plc: std::net::SocketAddr,
+        period: u64,
+        config: serde_json::Value,
+    ) -> (AppConfig, PathBuf) {
+        let registers = std::env::temp_dir().join(format!("bridge-fins-{}.json", plc.port()));
+        let definition = r#"{"temperature": {"area": "dm", "address": 100, "type": "u16"}}"#;
+        std::fs::write(&registers, definition).unwrap();
+        let mut app = serde_json::json!({
+            "devices": {"fins": {"plc1": {
+                "remote": plc.to_string(),
+                "node": 1,
+                "source_node": 10,
+                "registers": registers,
+            }}},
+            "remotes": {},
+            "period": period,
+            "timeout": 1,
+            "connect_timeout": 1,
+        });
+        app.as_object_mut()
+            .unwrap()
+            .extend(config.as_object().unwrap().clone());
+        (serde_json::from_value(app).unwrap(), registers)
+    }
+
+    #[tokio::test]
+    async fn first_batch_is_pushed_right_at_startup() {
+        let (webhook, mut requests) = serve("200 OK").await;
+        let url = format!("http://{webhook}");
+        let remotes = serde_json::json!({"remotes": {"webhook": {"hook": {"url": url}}}});
+        let (app, registers) = bridge(mock_plc(usize::MAX).await, 3600, remotes);
+        let start = Instant::now();
+        let request = select! {
+            _ = run(app, None, None, None) => panic!("the bridge stopped"),
+            request = requests.recv() => request.unwrap(),
+        };
+        // Well within the first period of an hour
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let batch: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(batch["plc1"]["values"]["temperature"], 215);
+        std::fs::remove_file(registers).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn triggered_fetch_pushes_an_extra_batch() {
+        // SIGUSR1 ends the test process until a handler is installed
+        let kind = tokio::signal::unix::SignalKind::user_defined1();
+        let _sigusr1 = tokio::signal::unix::signal(kind).unwrap();
+        let (webhook, mut requests) = serve("200 OK").await;
+        let url = format!("http://{webhook}");
+        let remotes = serde_json::json!({"remotes": {"webhook": {"hook": {"url": url}}}});
+        let (app, registers) = bridge(mock_plc(usize::MAX).await, 3600, remotes);
+        let bridge = run(app, None, None, None);
+        tokio::pin!(bridge);
+        select! {
+            _ = &mut bridge => panic!("the bridge stopped"),
+            request = requests.recv() => request.unwrap(),
+        };
+
+        // The next scheduled cycle is an hour away, the signal is sent again until the bridge listens to it
+        let start = Instant::now();
+        let request = loop {
+            let pid = std::process::id().to_string();
+            let kill = std::process::Command::new("kill")
+                .args(["-USR1", &pid])
+                .status();
+            assert!(kill.unwrap().success());
+            select! {
+                _ = &mut bridge => panic!("the bridge stopped"),
+                request = requests.recv() => break request.unwrap(),
+                _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+            }
+        };
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let batch: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(batch["plc1"]["values"]["temperature"], 215);
+        std::fs::remove_file(registers).unwrap();
+    }
+
+    /// Set in the environment of the bridge process started by [`dead_devices_exit_with_the_dead_code`]
+    const DEAD_CHILD: &str = "BRIDGE_TEST_DEAD_CHILD";
+
+    #[test]
+    fn dead_devices_exit_with_the_dead_code() {
+        if std::env::var_os(DEAD_CHILD).is_some() {
+            let runtime = tokio::runtime::Runtime::new().unwrap();
+            runtime.block_on(async {
+                // The PLC answers the connection, then never again
+                let config = serde_json::json!({"max_dead_cycles": 2});
+                let (app, _registers) = bridge(mock_plc(1).await, 1, config);
+                run(app, None, None, None).await;
+            });
+            return;
+        }
+        // The bridge exits the process, it runs in a process of its own
+        let status = std::process::Command::new(std::env::current_exe().unwrap())
+            .args([
+                "--exact",
+                "tests::dead_devices_exit_with_the_dead_code",
+                "--nocapture",
+            ])
+            .env(DEAD_CHILD, "1")
+            .status()
+            .unwrap();
+        assert_eq!(status.code(), Some(DEAD_EXIT_CODE));
     }
 }
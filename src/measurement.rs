@@ -0,0 +1,329 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use chrono::{DateTime, TimeDelta, Utc};
+use industrial_device::types::Value;
+use log::warn;
+
+use crate::types_conversion::RegisterValue;
+
+/// Oldest timestamp accepted from a device (2000-01-01T00:00:00Z)
+const MIN_DEVICE_TIMESTAMP: i64 = 946_684_800;
+
+/// A batch of register values read from a single device
+///
+/// # Fields
+/// - `timestamp`: Acquisition time of the values, sent to the remotes.
+/// - `values`: The register values, keyed by register name.
+/// - `tags`: Metadata attached to the values (e.g. the device endpoint), sent as tags or labels.
+/// - `units`: Unit of the fields that have one (e.g. `°C`), keyed by field name.
+#[derive(Debug, Clone)]
+pub struct Measurement {
+    pub timestamp: DateTime<Utc>,
+    pub values: HashMap<String, RegisterValue>,
+    pub tags: HashMap<String, String>,
+    pub units: HashMap<String, String>,
+}
+
+impl Measurement {
+    /// Create a measurement timestamped with the current time
+    pub fn new(values: HashMap<String, RegisterValue>) -> Self {
+        Measurement {
+            timestamp: Utc::now(),
+            values,
+            tags: HashMap::new(),
+            units: HashMap::new(),
+        }
+    }
+
+    /// Create a status record with a single `field` set to 1 (up) or 0 (down)
+    pub fn status(field: &str, up: bool) -> Self {
+        Measurement::new(HashMap::from([(
+            field.to_string(),
+            Value::U16(up as u16).into(),
+        )]))
+    }
+
+    /// The values sorted by field name, for the remotes whose output depends on the order of the fields
+    pub fn sorted_values(&self) -> BTreeMap<&String, &RegisterValue> {
+        self.values.iter().collect()
+    }
+
+    /// The tags sorted by name, for the remotes whose output depends on the order of the tags
+    pub fn sorted_tags(&self) -> BTreeMap<&String, &String> {
+        self.tags.iter().collect()
+    }
+
+    /// The measurement as JSON (fields and tags sorted by name):
+    /// `{"timestamp": "<RFC 3339>", "tags": {...}, "values": {"field": value}}`
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "timestamp": self.timestamp.to_rfc3339(),
+            "tags": self.sorted_tags(),
+            "values": self.sorted_values(),
+        })
+    }
+
+    /// Use the register `field` as the timestamp of the measurement.
+    /// The register is removed from the values and is interpreted as a Unix timestamp (in seconds).
+    /// The current timestamp is kept if the register is missing or does not decode to a sensible time.
+    ///
+    /// # Arguments
+    ///
+    /// - `field` (`&str`) - Name of the register holding the timestamp
+    pub fn take_timestamp(&mut self, field: &str) {
+        let value = match self.values.remove(field) {
+            Some(value) => value,
+            None => {
+                warn!("Timestamp field {field} not found, using the local time");
+                return;
+            }
+        };
+        let secs: f64 = value.into();
+        match parse_timestamp(secs, self.timestamp) {
+            Some(timestamp) => self.timestamp = timestamp,
+            None => warn!("Timestamp field {field} has an invalid value ({secs}), using the local time"),
+        }
+    }
+}
+
+/// A batch as a single JSON object, keyed by source (sources, tags and fields sorted by name):
+/// `{"source": {"timestamp": "<RFC 3339>", "tags": {...}, "values": {"field": value}}}`
+pub fn batch_to_json(data: &HashMap<String, Measurement>) -> serde_json::Value {
+    let sources: BTreeMap<&String, &Measurement> = data.iter().collect();
+    let batch: serde_json::Map<String, serde_json::Value> = sources
+        .into_iter()
+        .map(|(source, measurement)| (source.clone(), measurement.to_json()))
+        .collect();
+    serde_json::Value::Object(batch)
+}
+
+/// Last known value of each register of a device, and when it was read
+///
+/// # Fields
+/// - `values`: The last value of each register and its read time.
+/// - `age_fields`: Add a `{field}_age_seconds` companion field to each value.
+/// - `ttl`: Age after which a value is no longer forwarded (default: forwarded forever).
+#[derive(Debug, Default)]
+pub struct LastValues {
+    values: HashMap<String, (DateTime<Utc>, RegisterValue)>,
+    age_fields: bool,
+    ttl: Option<TimeDelta>,
+}
+
+impl LastValues {
+    /// Create an empty cache
+    pub fn new(age_fields: bool, ttl: Option<TimeDelta>) -> Self {
+        LastValues {
+            values: HashMap::new(),
+            age_fields,
+            ttl,
+        }
+    }
+
+    /// Record the fresh values of a measurement
+    pub fn update(&mut self, measurement: &Measurement) {
+        for (field, value) in &measurement.values {
+            self.values
+                .insert(field.clone(), (measurement.timestamp, value.clone()));
+        }
+    }
+
+    /// Build a measurement holding every last known value still within the TTL at `timestamp`
+    /// (the older ones are forgotten), along with a `{field}_age_seconds` companion field
+    /// telling how old the value is when `age_fields` is set
+    ///
+    /// # Arguments
+    ///
+    /// - `timestamp` (`DateTime<Utc>`) - Time of the fetch the ages are computed from
+    ///
+    /// # Returns
+    ///
+    /// - `Option<Measurement>` - The measurement, `None` if there is no value left to forward
+    pub fn current(&mut self, timestamp: DateTime<Utc>) -> Option<Measurement> {
+        if let Some(ttl) = self.ttl {
+            self.values
+                .retain(|_, (read_at, _)| timestamp - *read_at <= ttl);
+        }
+        if self.values.is_empty() {
+            return None;
+        }
+        let mut values = HashMap::new();
+        for (field, (read_at, value)) in &self.values {
+            if self.age_fields {
+                let age = (timestamp - *read_at).num_milliseconds().max(0) as f64 / 1000.0;
+                values.insert(format!("{field}_age_seconds"), RegisterValue::Float(age));
+            }
+            values.insert(field.clone(), value.clone());
+        }
+        Some(Measurement {
+            timestamp,
+            values,
+            tags: HashMap::new(),
+            units: HashMap::new(),
+        })
+    }
+}
+
+/// The devices that were not accessible on their last fetch, to push their `connected` status
+/// on each transition only
+#[derive(Debug, Default)]
+pub struct Connections {
+    disconnected: HashSet<String>,
+}
+
+impl Connections {
+    /// Record whether a device was accessible on its fetch
+    ///
+    /// # Arguments
+    ///
+    /// - `device` (`&str`) - The name of the device
+    /// - `accessible` (`bool`) - Whether the device was read, `false` if it was not accessible
+    ///
+    /// # Returns
+    ///
+    /// - `Option<bool>` - The status to push if it changed: `false` when the device becomes inaccessible,
+    ///   `true` when it is read again after having been inaccessible
+    pub fn transition(&mut self, device: &str, accessible: bool) -> Option<bool> {
+        match accessible {
+            true => self.disconnected.remove(device).then_some(true),
+            false => self
+                .disconnected
+                .insert(device.to_string())
+                .then_some(false),
+        }
+    }
+}
+
+/// Convert a Unix timestamp in seconds into a date, rejecting values before 2000 or more than a day after `now`
+fn parse_timestamp(secs: f64, now: DateTime<Utc>) -> Option<DateTime<Utc>> {
+    if !secs.is_finite() || secs < MIN_DEVICE_TIMESTAMP as f64 {
+        return None;
+    }
+    let timestamp = DateTime::from_timestamp(secs.trunc() as i64, (secs.fract() * 1e9) as u32)?;
+    match timestamp > now + TimeDelta::days(1) {
+        true => None,
+        false => Some(timestamp),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn connection_status_is_reported_on_both_transitions() {
+        let mut connections = Connections::default();
+        assert_eq!(connections.transition("press1", true), None);
+        assert_eq!(connections.transition("press1", false), Some(false));
+        assert_eq!(connections.transition("press1", false), None);
+        assert_eq!(connections.transition("press2", true), None);
+        assert_eq!(connections.transition("press1", true), Some(true));
+        assert_eq!(connections.transition("press1", true), None);
+    }
+
+    /// The same measurement, its fields and tags inserted in the given order
+    /// (each map has its own random iteration order)
+    fn measurement(names: &[&str], timestamp: DateTime<Utc>) -> Measurement {
+        let values = names
+            .iter()
+            .map(|name| (name.to_string(), RegisterValue::Float(name.len() as f64)));
+        let mut measurement = Measurement::new(values.collect());
+        measurement.timestamp = timestamp;
+        for name in names {
+            measurement
+                .tags
+                .insert(format!("{name}_tag"), name.to_string());
+        }
+        measurement
+    }
+
+    #[test]
+    fn json_is_identical_across_runs() {
+        let timestamp = Utc::now();
+        let names = ["temperature", "pressure", "speed", "alarm", "counter"];
+        let mut reversed = names;
+        reversed.reverse();
+        let first = measurement(&names, timestamp).to_json().to_string();
+        for _ in 0..10 {
+            assert_eq!(
+                measurement(&reversed, timestamp).to_json().to_string(),
+                first
+            );
+        }
+        // Sorted by name
+        let positions: Vec<usize> = ["\"alarm\"", "\"counter\"", "\"pressure\"", "\"speed\""]
+            .iter()
+            .map(|field| first.find(field).unwrap())
+            .collect();
+        assert!(positions.is_sorted());
+    }
+
+    #[test]
+    fn sorted_values_follow_the_field_names() {
+        let measurement = measurement(&["b", "c", "a"], Utc::now());
+        let fields: Vec<&String> = measurement.sorted_values().into_keys().collect();
+        assert_eq!(fields, ["a", "b", "c"]);
+        let tags: Vec<&String> = measurement.sorted_tags().into_keys().collect();
+        assert_eq!(tags, ["a_tag", "b_tag", "c_tag"]);
+    }
+
+    fn with_timestamp_register(value: RegisterValue) -> Measurement {
+        Measurement::new(HashMap::from([
+            ("ts".to_string(), value),
+            ("temperature".to_string(), RegisterValue::Float(21.5)),
+        ]))
+    }
+
+    #[test]
+    fn timestamp_register_is_taken_from_the_values() {
+        let mut measurement = with_timestamp_register(RegisterValue::Int(1_700_000_000));
+        measurement.take_timestamp("ts");
+        assert_eq!(measurement.timestamp.timestamp(), 1_700_000_000);
+        assert!(!measurement.values.contains_key("ts"));
+        assert!(measurement.values.contains_key("temperature"));
+
+        let mut measurement = with_timestamp_register(RegisterValue::Float(1_700_000_000.25));
+        measurement.take_timestamp("ts");
+        assert_eq!(measurement.timestamp.timestamp_millis(), 1_700_000_000_250);
+    }
+
+    #[test]
+    fn invalid_timestamp_register_keeps_the_local_time() {
+        let invalid = [
+            RegisterValue::Int(MIN_DEVICE_TIMESTAMP - 1),
+            RegisterValue::Int(0),
+            RegisterValue::Int((Utc::now() + TimeDelta::days(2)).timestamp()),
+            RegisterValue::Float(f64::NAN),
+            RegisterValue::Float(f64::INFINITY),
+        ];
+        for value in invalid {
+            let mut measurement = with_timestamp_register(value);
+            let now = measurement.timestamp;
+            measurement.take_timestamp("ts");
+            assert_eq!(measurement.timestamp, now);
+            // Removed even when rejected
+            assert!(!measurement.values.contains_key("ts"));
+        }
+
+        let mut measurement = with_timestamp_register(RegisterValue::Int(1_700_000_000));
+        let now = measurement.timestamp;
+        measurement.take_timestamp("missing");
+        assert_eq!(measurement.timestamp, now);
+        assert_eq!(measurement.values.len(), 2);
+    }
+
+    #[test]
+    fn timestamp_bounds() {
+        let now = Utc::now();
+        let min = MIN_DEVICE_TIMESTAMP as f64;
+        assert_eq!(
+            parse_timestamp(min, now).unwrap().timestamp(),
+            MIN_DEVICE_TIMESTAMP
+        );
+        assert!(parse_timestamp(min - 1.0, now).is_none());
+        let tomorrow = (now + TimeDelta::days(1)).timestamp() as f64;
+        assert!(parse_timestamp(tomorrow - 60.0, now).is_some());
+        assert!(parse_timestamp(tomorrow + 60.0, now).is_none());
+        assert!(parse_timestamp(f64::NEG_INFINITY, now).is_none());
+    }
+}
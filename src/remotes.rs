@@ -1,20 +1,88 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Arc,
+    time::Duration,
+};
 
-use log::{error, info, warn};
+use log::{error, info, warn, Level};
 use tokio::{
     select,
-    sync::{watch, Mutex},
+    sync::{watch, Mutex, Semaphore},
     task::JoinSet,
+    time::{timeout, Instant},
 };
 
+use crate::devices::log_throttle;
+use crate::measurement::Measurement;
+use crate::telemetry;
 use crate::types_conversion::RegisterValue;
 
 pub mod remote;
-use remote::{Remote, RemoteError};
+use remote::{Remote, RemoteError, RemoteOptions};
 
+pub mod compression;
+pub mod custom;
+pub mod deadletter;
 pub mod errors;
+pub mod heartbeat;
+pub mod history;
 pub mod influxdb;
+#[cfg(test)]
+pub mod mock_server;
+pub mod modbus_server;
 pub mod prometheus;
+#[cfg(feature = "remote_write")]
+pub mod remote_write;
+#[cfg(unix)]
+pub mod unix_socket;
+pub mod wal;
+pub mod webhook;
+use deadletter::DeadLetter;
+use wal::Wal;
+
+/// A remote and its options, as stored in the shared remotes map
+///
+/// # Fields
+/// - `remote`: The remote.
+/// - `options`: The options of the remote.
+/// - `auth_cooldown`: The end of the cooldown of the remote after it rejected the credentials, shared by the clones.
+pub struct RemoteEntry<R: ?Sized> {
+    pub remote: Arc<Mutex<Box<R>>>,
+    pub options: RemoteOptions,
+    pub auth_cooldown: Arc<Mutex<Option<Instant>>>,
+}
+
+impl<R: ?Sized> RemoteEntry<R> {
+    /// Create the entry of a remote, pushed to right away
+    pub fn new(remote: Box<R>, options: RemoteOptions) -> Self {
+        RemoteEntry {
+            remote: Arc::new(Mutex::new(remote)),
+            options,
+            auth_cooldown: Arc::new(Mutex::new(None)),
+        }
+    }
+}
+
+impl<R: ?Sized> Clone for RemoteEntry<R> {
+    fn clone(&self) -> Self {
+        RemoteEntry {
+            remote: self.remote.clone(),
+            options: self.options.clone(),
+            auth_cooldown: self.auth_cooldown.clone(),
+        }
+    }
+}
+
+/// How a batch was received by the remotes
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Delivery {
+    /// Every remote received the batch
+    Sent,
+    /// A push failed on an error that may not happen again (unreachable remote, timeout, server error)
+    Retryable,
+    /// Every failed push was rejected by its remote (see [`RemoteError::retryable`]), pushing the batch again fails again
+    Rejected,
+}
 
 /// Awaits and processes the completion of all remote sending tasks.
 ///
@@ -24,20 +92,323 @@ pub mod prometheus;
 /// 
 /// Errors are logged but do not interrupt the processing of other tasks.
 ///
+/// The values of the pushed data are recorded for each remote that succeeded.
+///
 /// # Parameters
-/// - `set`: The [`JoinSet`] containing remote sending tasks (`Result<(), RemoteError>`).
-async fn join_remotes_tasks(set: &mut JoinSet<Result<(), RemoteError>>) {
+/// - `set`: The [`JoinSet`] containing remote sending tasks (`(name, values, Result<(), RemoteError>)`).
+/// - `last_sent`: The values of the last data successfully sent, keyed by remote name.
+///
+/// # Returns
+/// - Whether every remote received the data, and if not whether pushing it again may succeed.
+async fn join_remotes_tasks(
+    set: &mut JoinSet<(String, Arc<BatchValues>, Result<(), RemoteError>)>,
+    last_sent: &mut HashMap<String, Arc<BatchValues>>,
+) -> Delivery {
+    let mut delivery = Delivery::Sent;
     while let Some(result) = set.join_next().await {
         match result {
-            Ok(val) => match val {
-                Ok(_) => (),
-                Err(err) => error!("Could not send data to remote : {err}"),
+            Ok((name, values, val)) => match val {
+                Ok(_) => {
+                    last_sent.insert(name, values);
+                }
+                Err(err) => {
+                    error!("Could not send data to remote {name} : {err}");
+                    match err.retryable() {
+                        true => delivery = Delivery::Retryable,
+                        false if delivery == Delivery::Sent => delivery = Delivery::Rejected,
+                        false => {}
+                    }
+                }
             },
             Err(err) => {
-                error!("There was an error joining the tasks responsible for pushing data ({err})")
+                error!("There was an error joining the tasks responsible for pushing data ({err})");
+                delivery = Delivery::Retryable;
+            }
+        }
+    }
+    delivery
+}
+
+/// Append a batch to the write-ahead log before pushing it
+///
+/// # Returns
+/// - The sequence number to acknowledge the batch with, `None` if there is no log or the batch could not be written.
+fn wal_append(wal: &mut Option<Wal>, data: &HashMap<String, Measurement>) -> Option<u64> {
+    match wal.as_mut()?.append(data) {
+        Ok(seq) => Some(seq),
+        Err(err) => {
+            error!("Could not write the batch to the write-ahead log ({err})");
+            None
+        }
+    }
+}
+
+/// Mark a batch of the write-ahead log as pushed to every remote
+fn wal_ack(wal: &mut Wal, seq: u64) {
+    if let Err(err) = wal.ack(seq) {
+        error!("Could not acknowledge the batch in the write-ahead log ({err})");
+    }
+}
+
+/// Push the batches left unacknowledged in the write-ahead log by the previous run to all remotes,
+/// each batch that every remote received is acknowledged, the others are kept for the next run.
+/// A batch only failing on errors that are not retryable (e.g. a remote rejecting the data) is acknowledged
+/// as well, it would otherwise be rejected again on every start.
+///
+/// # Parameters
+/// - `remotes`: The shared map of remote backends.
+/// - `wal`: The write-ahead log.
+pub async fn replay_wal(
+    remotes: Arc<Mutex<HashMap<String, RemoteEntry<impl Remote + 'static + ?Sized>>>>,
+    wal: &mut Wal,
+) {
+    let batches = wal.take_replay();
+    if batches.is_empty() {
+        return;
+    }
+    info!(
+        "Replaying {} batches from the write-ahead log",
+        batches.len()
+    );
+    let snapshot = remotes.lock().await.clone();
+    for (seq, data) in batches {
+        let data = Arc::new(data);
+        let mut set = JoinSet::new();
+        for (name, entry) in snapshot.clone() {
+            let data = data.clone();
+            set.spawn(async move {
+                let res = send_data_to_remote(&name, &entry, &data).await;
+                (name, Arc::default(), res)
+            });
+        }
+        match join_remotes_tasks(&mut set, &mut HashMap::new()).await {
+            Delivery::Sent => wal_ack(wal, seq),
+            Delivery::Rejected => {
+                warn!("Batch {seq} of the write-ahead log was rejected by a remote, dropping it");
+                wal_ack(wal, seq);
+            }
+            Delivery::Retryable => {}
+        }
+    }
+}
+
+/// Whether a field can be pushed to a remote only accepting numbers,
+/// the texts and byte arrays are left out with a warning
+///
+/// # Parameters
+/// - `source`: The source of the measurement (for logging).
+/// - `field`: The name of the field.
+/// - `value`: The value of the field.
+pub fn is_numeric_field(source: &str, field: &str, value: &RegisterValue) -> bool {
+    if value.is_text() {
+        log_throttle::log(
+            &format!("{source}/{field}"),
+            Level::Warn,
+            "The field holds text, it is not pushed to the remotes only accepting numbers"
+                .to_string(),
+        );
+        return false;
+    }
+    true
+}
+
+/// Push a batch to a remote, giving up once `push_timeout` elapsed (e.g. a stalled server)
+///
+/// # Parameters
+/// - `name`: Logical name of the remote (used only for logging).
+/// - `remote`: The remote.
+/// - `data`: The measurements, keyed by source.
+/// - `push_timeout`: The maximum duration of the push (default: no limit).
+///
+/// # Returns
+/// - `Err(RemoteError::ServerError)` if the timeout elapsed.
+async fn send_batch_within(
+    name: &str,
+    remote: &(impl Remote + ?Sized),
+    data: &HashMap<String, Measurement>,
+    push_timeout: Option<Duration>,
+) -> Result<(), RemoteError> {
+    let Some(push_timeout) = push_timeout else {
+        return remote.send_batch(data).await;
+    };
+    match timeout(push_timeout, remote.send_batch(data)).await {
+        Ok(res) => res,
+        Err(_) => {
+            warn!("Push to remote {name} timed out after {push_timeout:?}");
+            Err(RemoteError::ServerError)
+        }
+    }
+}
+
+/// Push a batch to a remote, split in several pushes if it holds more than `max_fields_per_push` fields
+///
+/// # Parameters
+/// - `name`: Logical name of the remote (used only for logging).
+/// - `remote`: The remote.
+/// - `data`: The measurements, keyed by source.
+/// - `options`: The options of the remote (`max_fields_per_push` and `push_timeout_ms`).
+async fn push_batch(
+    name: &str,
+    remote: &(impl Remote + ?Sized),
+    data: &HashMap<String, Measurement>,
+    options: &RemoteOptions,
+) -> Result<(), RemoteError> {
+    let fields: usize = data
+        .values()
+        .map(|measurement| measurement.values.len())
+        .sum();
+    let push_timeout = options.push_timeout();
+    match options.max_fields_per_push {
+        Some(max_fields) if fields > max_fields => {
+            let batches = split_batch(data, max_fields);
+            info!(
+                "Splitting the {fields} fields for remote {name} in {} pushes",
+                batches.len()
+            );
+            for batch in batches {
+                send_batch_within(name, remote, &batch, push_timeout).await?;
+            }
+            Ok(())
+        }
+        _ => send_batch_within(name, remote, data, push_timeout).await,
+    }
+}
+
+/// Split a batch in batches of at most `max_fields` fields, a measurement holding more fields
+/// is split in several measurements sharing its timestamp and tags
+///
+/// # Parameters
+/// - `data`: The batch, keyed by source.
+/// - `max_fields`: The maximum number of fields of a batch (at least 1).
+///
+/// # Returns
+/// - The batches, each source appearing at most once in a batch.
+fn split_batch(
+    data: &HashMap<String, Measurement>,
+    max_fields: usize,
+) -> Vec<HashMap<String, Measurement>> {
+    let max_fields = max_fields.max(1);
+    let sorted: BTreeMap<&String, &Measurement> = data.iter().collect();
+    let mut batches: Vec<HashMap<String, Measurement>> = Vec::new();
+    let mut current: HashMap<String, Measurement> = HashMap::new();
+    let mut fields = 0;
+    for (source, measurement) in sorted {
+        let values: BTreeMap<&String, &RegisterValue> = measurement.values.iter().collect();
+        let values: Vec<(&String, &RegisterValue)> = values.into_iter().collect();
+        for chunk in values.chunks(max_fields) {
+            if fields + chunk.len() > max_fields || current.contains_key(source) {
+                batches.push(std::mem::take(&mut current));
+                fields = 0;
+            }
+            let part = Measurement {
+                timestamp: measurement.timestamp,
+                values: chunk
+                    .iter()
+                    .map(|(field, value)| ((*field).clone(), (*value).clone()))
+                    .collect(),
+                tags: measurement.tags.clone(),
+                units: measurement.units.clone(),
+            };
+            fields += chunk.len();
+            current.insert(source.clone(), part);
+        }
+    }
+    if !current.is_empty() {
+        batches.push(current);
+    }
+    batches
+}
+
+/// Key of a field in a single namespace, `source{separator}field` (e.g. `press1.temperature`)
+///
+/// # Parameters
+/// - `source`: The source of the measurement.
+/// - `field`: The name of the field.
+/// - `separator`: The separator, see [`RemoteOptions::key_separator`].
+pub fn compose_key(source: &str, field: &str, separator: &str) -> String {
+    format!("{source}{separator}{field}")
+}
+
+/// Gather the fields of all the sources of a batch in a single measurement, keyed with [`compose_key`].
+/// The measurement takes the most recent timestamp of the batch and the tags sharing the same value in all sources.
+///
+/// Two sources can compose the same key (e.g. field `b.c` of source `a` and field `c` of source `a.b`),
+/// the sources are then taken in name order and the first one keeps the key, the other values are dropped with a warning.
+///
+/// # Parameters
+/// - `remote`: The name of the remote (for logging).
+/// - `data`: The measurements of the batch, with their source.
+/// - `separator`: The separator between the source and the field.
+///
+/// # Returns
+/// - The flattened measurement, `None` if `data` is empty.
+pub fn flatten_measurements<'a>(
+    remote: &str,
+    data: impl Iterator<Item = (&'a String, &'a Measurement)>,
+    separator: &str,
+) -> Option<Measurement> {
+    let sorted: BTreeMap<&String, &Measurement> = data.collect();
+    let mut flat: Option<Measurement> = None;
+    for (source, part) in sorted {
+        let flat = flat.get_or_insert_with(|| Measurement {
+            timestamp: part.timestamp,
+            values: HashMap::new(),
+            tags: part.tags.clone(),
+            units: HashMap::new(),
+        });
+        flat.timestamp = flat.timestamp.max(part.timestamp);
+        flat.tags
+            .retain(|tag, value| part.tags.get(tag) == Some(value));
+
+        let fields: BTreeMap<&String, &RegisterValue> = part.values.iter().collect();
+        for (field, value) in fields {
+            let key = compose_key(source, field, separator);
+            if flat.values.contains_key(&key) {
+                log_throttle::log(
+                    &format!("{remote}/{key}"),
+                    Level::Warn,
+                    format!("The key {key} of source {source} is already taken in the flattened batch, the value is dropped"),
+                );
+                continue;
+            }
+            if let Some(unit) = part.units.get(field) {
+                flat.units.insert(key.clone(), unit.clone());
             }
+            flat.values.insert(key, value.clone());
         }
     }
+    flat
+}
+
+/// Replace a batch by a single measurement named `measurement` holding the fields of all its sources
+/// (see [`flatten_measurements`])
+fn flatten_batch(
+    remote: &str,
+    data: &HashMap<String, Measurement>,
+    measurement: &str,
+    separator: &str,
+) -> HashMap<String, Measurement> {
+    flatten_measurements(remote, data.iter(), separator)
+        .map(|flat| HashMap::from([(measurement.to_string(), flat)]))
+        .unwrap_or_default()
+}
+
+/// The values of a batch, by source then field, compared by `deduplicate`
+type BatchValues = BTreeMap<String, BTreeMap<String, RegisterValue>>;
+
+/// The values of a batch of measurements, independently of the maps iteration order.
+/// Timestamps are left out so that unchanged values compare equal across cycles.
+fn batch_values(data: &HashMap<String, Measurement>) -> BatchValues {
+    data.iter()
+        .map(|(source, measurement)| {
+            let values = measurement
+                .values
+                .iter()
+                .map(|(field, value)| (field.clone(), value.clone()))
+                .collect();
+            (source.clone(), values)
+        })
+        .collect()
 }
 
 /// Continuously listens for new measurement data and pushes it to all configured remotes.
@@ -48,72 +419,662 @@ async fn join_remotes_tasks(set: &mut JoinSet<Result<(), RemoteError>>) {
 /// It ensures that data is sent concurrently to all remotes, and handles cases where
 /// new data arrives before the previous push finishes.
 ///
+/// Only the batches sent after `data` was created or last seen are pushed, so the initial
+/// (empty) value of the channel is never pushed while the first batch always is,
+/// even when it is sent before this function starts. It returns once the sender is dropped.
+///
+/// Remotes with `deduplicate` set are skipped when the data is identical to
+/// the last batch successfully sent to them.
+///
+/// The remotes map is only locked to take a snapshot at the start of each push,
+/// it can then be swapped between cycles (`*remotes.lock().await = new_map`).
+/// A remote removed while pushing keeps its handle until its push is done.
+///
 /// # Parameters
 /// - `remotes`: A thread-safe shared map of remote backends (keyed by name),
-///   each implementing the [`Remote`] trait.
+///   each implementing the [`Remote`] trait, along with their options.
 /// - `data`: A [`watch::Receiver`] that broadcasts the latest measurement data,
 ///   structured as:
-///   - Outer key = device/source name
-///   - Inner map = field name → `RegisterValue`
+///   - Key = device/source name
+///   - Value = [`Measurement`] (timestamp and field name → `RegisterValue`)
+/// - `wal`: The write-ahead log each batch is written to before the push, and acknowledged in once every
+///   remote received it, a remote rejected it or a newer batch aborted its push. The batches failing
+///   on a retryable error are left for the next run.
+/// - `max_pushes`: The maximum number of remotes pushed to at the same time, the tasks of the other remotes
+///   wait for a permit (without limit when `None`). A new batch still aborts the push at once, waiting tasks included.
+/// - `deadletter`: The file the batches that every remote failed to receive are written to
+///   (a batch skipped by a `deduplicate` remote reached it before, it is not written).
 pub async fn send_data_to_remotes(
-    remotes: Arc<Mutex<HashMap<String, Arc<Mutex<Box<impl Remote + Send + 'static + ?Sized>>>>>>,
-    mut data: watch::Receiver<HashMap<String, HashMap<String, RegisterValue>>>,
+    remotes: Arc<Mutex<HashMap<String, RemoteEntry<impl Remote + 'static + ?Sized>>>>,
+    mut data: watch::Receiver<HashMap<String, Measurement>>,
+    mut wal: Option<Wal>,
+    max_pushes: Option<usize>,
+    mut deadletter: Option<DeadLetter>,
 ) {
+    let mut last_sent: HashMap<String, Arc<BatchValues>> = HashMap::new();
+    let permits = max_pushes.map(|max| Arc::new(Semaphore::new(max.max(1))));
     loop {
+        // Only wait path for new data, returns at once if a batch was sent since the last push
+        // (including one sent before the task started)
+        if let Err(err) = data.changed().await {
+            error!("There was an error waiting for new data : ({err})");
+            return;
+        }
         info!("New data available : starting push");
 
         let mut set = JoinSet::new();
+        let values = Arc::new(batch_values(&data.borrow()));
+        let seq = wal_append(&mut wal, &data.borrow());
 
-        for (name, remote) in remotes.lock().await.iter() {
-            let name = name.clone();
-            let remote = remote.clone();
+        // Only hold the lock while copying the handles
+        let snapshot = remotes.lock().await.clone();
+        last_sent.retain(|name, _| snapshot.contains_key(name));
+
+        let mut skipped = false;
+        for (name, entry) in snapshot {
+            if entry.options.deduplicate && last_sent.get(&name) == Some(&values) {
+                info!("Data unchanged since last push to {name}, skipping");
+                skipped = true;
+                continue;
+            }
             let data_c = data.borrow().clone();
+            let permits = permits.clone();
+            let values = values.clone();
             set.spawn(async move {
-                let name: String = name.to_string();
-                send_data_to_remote(&name, remote, &data_c).await
+                // Held until the push is done, the semaphore is never closed
+                let _permit = match permits {
+                    Some(permits) => Some(permits.acquire_owned().await.unwrap()),
+                    None => None,
+                };
+                let res = send_data_to_remote(&name, &entry, &data_c).await;
+                (name, values, res)
             });
         }
 
+        let mut delivered = HashMap::new();
         select! {
-            _ = join_remotes_tasks(&mut set) => {
-                match data.changed().await {
-                    Ok(_) => {},
-                    Err(err) => error!("There was an error waiting for new data : ({err})"),
-                };
+            delivery = join_remotes_tasks(&mut set, &mut delivered) => {
+                // A batch failing on a retryable error is kept for the next run, pushing it again
+                // would fail again otherwise
+                if let (Some(wal), Some(seq)) = (wal.as_mut(), seq) {
+                    match delivery {
+                        Delivery::Sent => wal_ack(wal, seq),
+                        Delivery::Rejected => {
+                            warn!("The batch was rejected by a remote, dropping it from the write-ahead log");
+                            wal_ack(wal, seq);
+                        }
+                        Delivery::Retryable => {}
+                    }
+                }
+                // The remotes skipped by `deduplicate` received the same data before
+                let lost = delivery != Delivery::Sent && delivered.is_empty() && !skipped;
+                if let Some(deadletter) = deadletter.as_mut().filter(|_| lost) {
+                    warn!("No remote received the batch, writing it to the dead letter file");
+                    if let Err(err) = deadletter.append(&data.borrow()) {
+                        error!("Could not write the batch to the dead letter file ({err})");
+                    }
+                }
+                last_sent.extend(delivered);
             }
             _ = data.changed() => {
+                // The newer batch supersedes it, replaying it on the next run would only push stale data
+                if let (Some(wal), Some(seq)) = (wal.as_mut(), seq) {
+                    wal_ack(wal, seq);
+                }
                 warn!("There was new data available before all previous could be sent, abording push");
+                // Push the new batch right away on the next iteration
+                data.mark_changed();
             }
         }
     }
 }
 
 
+/// Push a single batch to all remotes and wait for every push to complete (e.g. `--once`)
+///
+/// # Parameters
+/// - `remotes`: The shared map of remote backends.
+/// - `data`: The measurements to push, keyed by source name.
+///
+/// # Returns
+/// - `true` if every remote received the batch.
+pub async fn send_once(
+    remotes: Arc<Mutex<HashMap<String, RemoteEntry<impl Remote + 'static + ?Sized>>>>,
+    data: HashMap<String, Measurement>,
+) -> bool {
+    let snapshot = remotes.lock().await.clone();
+    let data = Arc::new(data);
+    let mut set = JoinSet::new();
+    for (name, entry) in snapshot {
+        let data = data.clone();
+        set.spawn(async move {
+            let res = send_data_to_remote(&name, &entry, &data).await;
+            (name, Arc::default(), res)
+        });
+    }
+    join_remotes_tasks(&mut set, &mut HashMap::new()).await == Delivery::Sent
+}
+
+/// Maximum time given to each remote to receive the last will
+const LAST_WILL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Push a final status to all remotes (e.g. on shutdown), waiting for every push to complete
+/// or for `LAST_WILL_TIMEOUT` to elapse.
+///
+/// # Parameters
+/// - `remotes`: The shared map of remote backends.
+/// - `data`: The status measurements to push, keyed by source name.
+pub async fn send_last_will(
+    remotes: Arc<Mutex<HashMap<String, RemoteEntry<impl Remote + 'static + ?Sized>>>>,
+    data: HashMap<String, Measurement>,
+) {
+    let snapshot = remotes.lock().await.clone();
+    let data = Arc::new(data);
+    let mut set = JoinSet::new();
+    for (name, entry) in snapshot {
+        let data = data.clone();
+        set.spawn(async move {
+            let push = send_data_to_remote(&name, &entry, &data);
+            match timeout(LAST_WILL_TIMEOUT, push).await {
+                Ok(Ok(_)) => {}
+                Ok(Err(err)) => error!("Could not send last will to remote {name} : {err}"),
+                Err(_) => error!("Timeout reached while sending last will to remote {name}"),
+            }
+        });
+    }
+    while set.join_next().await.is_some() {}
+}
+
 /// Sends collected register data to a configured remote backend.
 ///
 /// This function iterates over all measurement sources and their
 /// associated field values, and forwards them to the given `Remote`
 /// implementation (e.g. InfluxDB, Prometheus).
 ///
+/// The push runs in a `send_data_to_remote` span and its duration is recorded.
+///
+/// Only the sources selected by the `device_filter` of the remote are sent, nothing is pushed if none is.
+///
+/// When the remote is configured to flatten the batches, the fields of all sources are first gathered
+/// in a single measurement (see [`flatten_batch`]).
+///
+/// A batch holding more than `max_fields_per_push` fields is split and sent in several pushes,
+/// stopping at the first that fails. Each push is given up after `push_timeout_ms`.
+///
+/// When the remote rejects the credentials, it reloads them and the push is retried once if they changed.
+/// If they are still rejected, no push is attempted for the `auth_cooldown_s` of the remote (failing with
+/// `AuthError`), so a wrong token doesn't lock the account, unless the credentials change in the meantime.
+///
 /// # Parameters
 /// - `name`: Logical name of the remote (used only for logging).
-/// - `remote`: A thread-safe, asynchronous reference to a type
-///   implementing the [`Remote`] trait.
-/// - `data`: A nested map of measurements, where:
-///   - Outer key = measurement source (e.g. device name).
-///   - Inner map = field name → `RegisterValue`.
+/// - `entry`: The remote, a thread-safe, asynchronous reference to a type
+///   implementing the [`Remote`] trait, and its options.
+/// - `data`: A map of measurements, where:
+///   - Key = measurement source (e.g. device name).
+///   - Value = [`Measurement`] (timestamp and field name → `RegisterValue`).
 ///
 /// # Returns
 /// - `Ok(())` if all measurements were successfully sent.
 /// - `Err(RemoteError)` if sending failed.
 pub async fn send_data_to_remote(
     name: &str,
-    remote: Arc<Mutex<Box<impl Remote + ?Sized>>>,
-    data: &HashMap<String, HashMap<String, RegisterValue>>,
+    entry: &RemoteEntry<impl Remote + ?Sized>,
+    data: &HashMap<String, Measurement>,
 ) -> Result<(), RemoteError> {
+    let options = &entry.options;
     info!("Sending to remote {name}");
-    for (source, values) in data.iter() {
-        remote.lock().await.send_measurement(source, values).await?;
+    let start = Instant::now();
+    let selected: HashMap<String, Measurement>;
+    let data = match options.device_filter.is_empty() {
+        true => data,
+        false => {
+            selected = data
+                .iter()
+                .filter(|(_, measurement)| options.selects(&measurement.tags))
+                .map(|(source, measurement)| (source.clone(), measurement.clone()))
+                .collect();
+            if selected.is_empty() {
+                info!("No source matches the device filter of remote {name}, skipping");
+                return Ok(());
+            }
+            &selected
+        }
+    };
+    let flat;
+    let data = match &options.flatten {
+        Some(measurement) => {
+            flat = flatten_batch(name, data, measurement, options.key_separator());
+            &flat
+        }
+        None => data,
+    };
+    let push = async {
+        let mut remote = entry.remote.lock().await;
+        let mut cooldown = entry.auth_cooldown.lock().await;
+        if cooldown.is_some_and(|end| Instant::now() < end) {
+            // Only new credentials end the cooldown early
+            if !remote.refresh_auth().await {
+                log_throttle::log(
+                    &format!("remote/{name}"),
+                    Level::Warn,
+                    format!("Remote {name} is disabled until its credentials are fixed, skipping the push"),
+                );
+                return Err(RemoteError::AuthError);
+            }
+            info!("Credentials of remote {name} reloaded, pushing again");
+        }
+        *cooldown = None;
+        let res = match push_batch(name, remote.as_ref(), data, options).await {
+            Err(RemoteError::AuthError) if remote.refresh_auth().await => {
+                info!("Credentials of remote {name} reloaded, pushing again");
+                push_batch(name, remote.as_ref(), data, options).await
+            }
+            res => res,
+        };
+        if let (Err(RemoteError::AuthError), Some(duration)) = (&res, options.auth_cooldown()) {
+            error!("Remote {name} rejected the credentials, it is disabled for {duration:?} (or until its credentials change) to avoid locking the account, fix its credentials");
+            *cooldown = Some(Instant::now() + duration);
+        }
+        res
+    };
+    let res = telemetry::in_span("send_data_to_remote", &[("remote", name.to_string())], push).await;
+    telemetry::record_push(name, start.elapsed(), res.is_ok());
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use async_trait::async_trait;
+    use industrial_device::types::Value;
+    use tokio::sync::mpsc;
+
+    use super::*;
+
+    /// A remote sending its name and the batches pushed to it
+    struct Recorder {
+        name: &'static str,
+        pushes: mpsc::UnboundedSender<(&'static str, HashMap<String, Measurement>)>,
+    }
+
+    #[async_trait]
+    impl Remote for Recorder {
+        async fn send_measurement(
+            &self,
+            name: &str,
+            measurement: &Measurement,
+        ) -> Result<(), RemoteError> {
+            self.send_batch(&HashMap::from([(name.to_string(), measurement.clone())]))
+                .await
+        }
+
+        async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+            self.pushes.send((self.name, data.clone())).unwrap();
+            Ok(())
+        }
+    }
+
+    type Remotes = Arc<Mutex<HashMap<String, RemoteEntry<dyn Remote>>>>;
+
+    fn entry(remote: impl Remote + 'static, options: RemoteOptions) -> RemoteEntry<dyn Remote> {
+        RemoteEntry::new(Box::new(remote), options)
+    }
+
+    fn batch(values: &[(&str, RegisterValue)]) -> HashMap<String, Measurement> {
+        let values = values
+            .iter()
+            .map(|(field, value)| (field.to_string(), value.clone()))
+            .collect();
+        HashMap::from([("press1".to_string(), Measurement::new(values))])
+    }
+
+    #[test]
+    fn unchanged_values_are_equal_whatever_their_timestamp() {
+        let first = batch(&[("temp", RegisterValue::Float(21.5))]);
+        let mut second = first.clone();
+        second.get_mut("press1").unwrap().timestamp += chrono::Duration::seconds(5);
+        assert_eq!(batch_values(&first), batch_values(&second));
+    }
+
+    #[test]
+    fn changed_values_are_not_equal() {
+        let first = batch(&[("temp", RegisterValue::Float(21.5))]);
+        let changed = batch(&[("temp", RegisterValue::Float(21.6))]);
+        let retyped = batch(&[("temp", RegisterValue::Int(21))]);
+        let added = batch(&[
+            ("temp", RegisterValue::Float(21.5)),
+            ("pressure", RegisterValue::Int(3)),
+        ]);
+        assert_ne!(batch_values(&first), batch_values(&changed));
+        assert_ne!(batch_values(&first), batch_values(&retyped));
+        assert_ne!(batch_values(&first), batch_values(&added));
+    }
+
+    #[test]
+    fn not_a_number_is_deduplicated() {
+        let first = batch(&[("temp", RegisterValue::Float(f64::NAN))]);
+        assert_eq!(batch_values(&first), batch_values(&first.clone()));
+    }
+
+    #[tokio::test]
+    async fn remotes_swapped_between_cycles_receive_the_next_batch() {
+        let (pushes, mut pushed) = mpsc::unbounded_channel();
+        let recorder = |name| Recorder {
+            name,
+            pushes: pushes.clone(),
+        };
+        let remotes: Remotes = Arc::new(Mutex::new(HashMap::from([(
+            "first".to_string(),
+            entry(recorder("first"), RemoteOptions::default()),
+        )])));
+        let (data, receiver) = watch::channel(HashMap::new());
+        let task = tokio::spawn(send_data_to_remotes(
+            remotes.clone(),
+            receiver,
+            None,
+            None,
+            None,
+        ));
+
+        data.send(batch(&[("temp", RegisterValue::Float(21.5))]))
+            .unwrap();
+        assert_eq!(pushed.recv().await.unwrap().0, "first");
+
+        *remotes.lock().await = HashMap::from([(
+            "second".to_string(),
+            entry(recorder("second"), RemoteOptions::default()),
+        )]);
+        data.send(batch(&[("temp", RegisterValue::Float(21.6))]))
+            .unwrap();
+        assert_eq!(pushed.recv().await.unwrap().0, "second");
+        assert!(pushed.try_recv().is_err());
+        task.abort();
+    }
+
+    #[test]
+    fn text_fields_are_not_pushed_as_numbers() {
+        let bytes = RegisterValue::Raw(Value::Sized(vec![0x01, 0x2a]));
+        assert!(!is_numeric_field("meter1", "firmware", &bytes));
+        let text = RegisterValue::Text("ASo=".to_string());
+        assert!(!is_numeric_field("meter1", "firmware", &text));
+        assert!(is_numeric_field(
+            "meter1",
+            "energy",
+            &RegisterValue::Float(12.5)
+        ));
+    }
+
+    #[tokio::test]
+    async fn batch_over_the_cap_is_split() {
+        let (pushes, mut pushed) = mpsc::unbounded_channel();
+        let recorder = Recorder {
+            name: "influx",
+            pushes,
+        };
+        let mut data = batch(&[
+            ("a", RegisterValue::Int(1)),
+            ("b", RegisterValue::Int(2)),
+            ("c", RegisterValue::Int(3)),
+            ("d", RegisterValue::Int(4)),
+            ("e", RegisterValue::Int(5)),
+        ]);
+        data.extend(HashMap::from([(
+            "press2".to_string(),
+            Measurement::new(HashMap::from([("f".to_string(), RegisterValue::Int(6))])),
+        )]));
+        let options = RemoteOptions {
+            max_fields_per_push: Some(2),
+            ..Default::default()
+        };
+        push_batch("influx", &recorder, &data, &options)
+            .await
+            .unwrap();
+
+        let mut batches = Vec::new();
+        while let Ok((_, batch)) = pushed.try_recv() {
+            batches.push(batch);
+        }
+        let fields = |batch: &HashMap<String, Measurement>| -> usize {
+            batch
+                .values()
+                .map(|measurement| measurement.values.len())
+                .sum()
+        };
+        assert_eq!(batches.len(), 3);
+        assert!(batches.iter().all(|batch| fields(batch) <= 2));
+        assert_eq!(batches.iter().map(fields).sum::<usize>(), 6);
+        let timestamp = data["press1"].timestamp;
+        assert!(batches
+            .iter()
+            .all(|batch| batch["press1"].timestamp == timestamp));
+    }
+
+    #[tokio::test]
+    async fn batch_under_the_cap_is_pushed_at_once() {
+        let (pushes, mut pushed) = mpsc::unbounded_channel();
+        let recorder = Recorder {
+            name: "influx",
+            pushes,
+        };
+        let data = batch(&[("a", RegisterValue::Int(1)), ("b", RegisterValue::Int(2))]);
+        let options = RemoteOptions {
+            max_fields_per_push: Some(2),
+            ..Default::default()
+        };
+        push_batch("influx", &recorder, &data, &options)
+            .await
+            .unwrap();
+        assert_eq!(
+            batch_values(&pushed.try_recv().unwrap().1),
+            batch_values(&data)
+        );
+        assert!(pushed.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn batch_sent_before_startup_is_pushed_once() {
+        let (pushes, mut pushed) = mpsc::unbounded_channel();
+        let remotes: Remotes = Arc::new(Mutex::new(HashMap::from([(
+            "influx".to_string(),
+            entry(
+                Recorder {
+                    name: "influx",
+                    pushes,
+                },
+                RemoteOptions::default(),
+            ),
+        )])));
+        let (data, receiver) = watch::channel(HashMap::new());
+        // The first batch is fetched before the push task starts
+        data.send(batch(&[("temp", RegisterValue::Float(21.5))]))
+            .unwrap();
+        let task = tokio::spawn(send_data_to_remotes(remotes, receiver, None, None, None));
+
+        let (_, first) = pushed.recv().await.unwrap();
+        assert!(first.contains_key("press1"));
+        // Neither the initial empty batch nor the first one again
+        drop(data);
+        task.await.unwrap();
+        assert!(pushed.recv().await.is_none());
+    }
+
+    /// A remote whose pushes take 50ms, counting the pushes running at the same time
+    struct SlowRemote {
+        active: Arc<AtomicUsize>,
+        max_active: Arc<AtomicUsize>,
+        done: mpsc::UnboundedSender<()>,
+    }
+
+    #[async_trait]
+    impl Remote for SlowRemote {
+        async fn send_measurement(&self, _: &str, _: &Measurement) -> Result<(), RemoteError> {
+            let active = self.active.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_active.fetch_max(active, Ordering::SeqCst);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            self.done.send(()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn concurrent_pushes_are_bounded() {
+        let active = Arc::new(AtomicUsize::new(0));
+        let max_active = Arc::new(AtomicUsize::new(0));
+        let (done, mut pushed) = mpsc::unbounded_channel();
+        let remotes = (0..5).map(|i| {
+            let remote = SlowRemote {
+                active: active.clone(),
+                max_active: max_active.clone(),
+                done: done.clone(),
+            };
+            (
+                format!("remote{i}"),
+                entry(remote, RemoteOptions::default()),
+            )
+        });
+        let remotes: Remotes = Arc::new(Mutex::new(remotes.collect()));
+        let (data, receiver) = watch::channel(HashMap::new());
+        let task = tokio::spawn(send_data_to_remotes(remotes, receiver, None, Some(2), None));
+        data.send(batch(&[("temp", RegisterValue::Float(21.5))]))
+            .unwrap();
+
+        // Every remote is eventually pushed to, two at a time
+        for _ in 0..5 {
+            pushed.recv().await.unwrap();
+        }
+        assert_eq!(max_active.load(Ordering::SeqCst), 2);
+        drop(data);
+        task.await.unwrap();
+    }
+
+    /// A remote rejecting the credentials, counting the pushes attempted,
+    /// its credentials changing on the next reload once `rotated` is set
+    #[derive(Clone, Default)]
+    struct RejectingRemote {
+        pushes: Arc<AtomicUsize>,
+        rotated: Arc<AtomicBool>,
+    }
+
+    #[async_trait]
+    impl Remote for RejectingRemote {
+        async fn send_measurement(&self, _: &str, _: &Measurement) -> Result<(), RemoteError> {
+            self.pushes.fetch_add(1, Ordering::SeqCst);
+            Err(RemoteError::AuthError)
+        }
+
+        async fn refresh_auth(&mut self) -> bool {
+            self.rotated.swap(false, Ordering::SeqCst)
+        }
+    }
+
+    /// The entry of a rejecting remote with a cooldown of `auth_cooldown_s`
+    fn rejecting(remote: &RejectingRemote, auth_cooldown_s: u64) -> RemoteEntry<dyn Remote> {
+        let options = RemoteOptions {
+            auth_cooldown_s: Some(auth_cooldown_s),
+            ..Default::default()
+        };
+        entry(remote.clone(), options)
+    }
+
+    #[tokio::test]
+    async fn rejected_credentials_disable_the_remote_until_the_cooldown_ends() {
+        let remote = RejectingRemote::default();
+        let entry = rejecting(&remote, 1);
+        let data = batch(&[("temp", RegisterValue::Float(21.5))]);
+
+        let res = send_data_to_remote("influx", &entry, &data).await;
+        assert!(matches!(res, Err(RemoteError::AuthError)));
+        assert_eq!(remote.pushes.load(Ordering::SeqCst), 1);
+        // Within the cooldown, the server is not asked again
+        let res = send_data_to_remote("influx", &entry, &data).await;
+        assert!(matches!(res, Err(RemoteError::AuthError)));
+        assert_eq!(remote.pushes.load(Ordering::SeqCst), 1);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let res = send_data_to_remote("influx", &entry, &data).await;
+        assert!(matches!(res, Err(RemoteError::AuthError)));
+        assert_eq!(remote.pushes.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn changed_credentials_end_the_cooldown() {
+        let remote = RejectingRemote::default();
+        let entry = rejecting(&remote, 3600);
+        let data = batch(&[("temp", RegisterValue::Float(21.5))]);
+
+        let _ = send_data_to_remote("influx", &entry, &data).await;
+        assert!(entry.auth_cooldown.lock().await.is_some());
+        remote.rotated.store(true, Ordering::SeqCst);
+        let _ = send_data_to_remote("influx", &entry, &data).await;
+        assert_eq!(remote.pushes.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn no_cooldown_pushes_on_every_cycle() {
+        let remote = RejectingRemote::default();
+        let entry = rejecting(&remote, 0);
+        let data = batch(&[("temp", RegisterValue::Float(21.5))]);
+        for _ in 0..3 {
+            let _ = send_data_to_remote("influx", &entry, &data).await;
+        }
+        assert_eq!(remote.pushes.load(Ordering::SeqCst), 3);
+        assert!(entry.auth_cooldown.lock().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn batch_failing_on_every_remote_lands_in_the_dead_letter_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deadletter.jsonl");
+        let deadletter = DeadLetter::open(&path).unwrap();
+        let influx = || {
+            (
+                "influx".to_string(),
+                rejecting(&RejectingRemote::default(), 0),
+            )
+        };
+        let (pushes, mut pushed) = mpsc::unbounded_channel();
+        let recorder = Recorder {
+            name: "webhook",
+            pushes,
+        };
+        let remotes: Remotes = Arc::new(Mutex::new(HashMap::from([
+            influx(),
+            (
+                "webhook".to_string(),
+                entry(recorder, RemoteOptions::default()),
+            ),
+        ])));
+        let (data, receiver) = watch::channel(HashMap::new());
+        let task = tokio::spawn(send_data_to_remotes(
+            remotes.clone(),
+            receiver,
+            None,
+            None,
+            Some(deadletter),
+        ));
+
+        // Received by one of the remotes
+        data.send(batch(&[("temp", RegisterValue::Float(21.5))]))
+            .unwrap();
+        pushed.recv().await.unwrap();
+        // Received by none
+        *remotes.lock().await = HashMap::from([influx()]);
+        data.send(batch(&[("temp", RegisterValue::Float(22.0))]))
+            .unwrap();
+
+        let content = loop {
+            let content = std::fs::read_to_string(&path).unwrap();
+            if !content.is_empty() {
+                break content;
+            }
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        };
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 1);
+        let batch: serde_json::Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(batch["press1"]["values"]["temp"], 22.0);
+        task.abort();
     }
-    Ok(())
 }
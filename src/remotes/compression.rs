@@ -0,0 +1,68 @@
+use std::io::Write;
+
+use flate2::write::GzEncoder;
+use reqwest::RequestBuilder;
+use serde::Deserialize;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Compression of the bodies sent by the HTTP remotes, for the bandwidth-constrained links (e.g. cellular)
+pub enum Compression {
+    /// Sent as is
+    #[default]
+    None,
+    /// Compressed with gzip and sent with `Content-Encoding: gzip`
+    Gzip,
+}
+
+impl Compression {
+    /// Set the body of a request, compressed with its `Content-Encoding` header when compression is enabled
+    ///
+    /// # Arguments
+    ///
+    /// - `request` (`RequestBuilder`) - The request to send
+    /// - `body` (`Vec<u8>`) - The body, uncompressed
+    ///
+    /// # Returns
+    ///
+    /// - `RequestBuilder` - The request with its body
+    pub fn body(&self, request: RequestBuilder, body: Vec<u8>) -> RequestBuilder {
+        match self {
+            Compression::None => request.body(body),
+            Compression::Gzip => request.header("Content-Encoding", "gzip").body(gzip(&body)),
+        }
+    }
+}
+
+/// Compress data with gzip, at the default level
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+    // Writing to a Vec never fails
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+/// Decompress data compressed with gzip, as the servers accepting it do
+#[cfg(test)]
+pub fn gunzip(data: &[u8]) -> Vec<u8> {
+    use std::io::Read;
+
+    let mut res = Vec::new();
+    flate2::read::GzDecoder::new(data)
+        .read_to_end(&mut res)
+        .unwrap();
+    res
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gzip_decompresses_to_the_data() {
+        let data = "press1 temperature=21.5 1700000000\n".repeat(100);
+        let compressed = gzip(data.as_bytes());
+        assert!(compressed.len() < data.len());
+        assert_eq!(gunzip(&compressed), data.as_bytes());
+    }
+}
@@ -0,0 +1,204 @@
+use std::{
+    collections::HashMap,
+    sync::{LazyLock, Mutex},
+};
+
+use async_trait::async_trait;
+use serde::Deserialize;
+
+use crate::measurement::Measurement;
+use crate::remotes::errors::RemoteInitError;
+use crate::remotes::remote::{RemoteError, RemoteOptions};
+use crate::remotes::Remote;
+
+/// Builds a remote of a type that is not known to the configuration from its config section
+pub trait RemoteFactory: Send + Sync {
+    /// Build the remote
+    ///
+    /// # Arguments
+    ///
+    /// - `config` (`serde_json::Map<String, serde_json::Value>`) - The fields of the remote config,
+    ///   without `type` and the options shared by all remotes
+    ///
+    /// # Returns
+    ///
+    /// - `Result<Box<dyn Remote + Send + Sync>, RemoteInitError>` - The remote, or why it could not be built
+    fn create(
+        &self,
+        config: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Box<dyn Remote + Send + Sync>, RemoteInitError>;
+}
+
+impl<F> RemoteFactory for F
+where
+    F: Fn(serde_json::Map<String, serde_json::Value>) -> Result<Box<dyn Remote + Send + Sync>, RemoteInitError>
+        + Send
+        + Sync,
+{
+    fn create(
+        &self,
+        config: serde_json::Map<String, serde_json::Value>,
+    ) -> Result<Box<dyn Remote + Send + Sync>, RemoteInitError> {
+        self(config)
+    }
+}
+
+/// The registered remote types, keyed by the name used as `type` in the config
+static FACTORIES: LazyLock<Mutex<HashMap<String, Box<dyn RemoteFactory>>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+
+/// Register a remote type, to be used in the `custom` section of the remotes.
+/// Must be called before the configuration is turned into remotes, a type registered twice is replaced.
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - The name of the type, matched against the `type` field of the config
+/// - `factory` (`impl RemoteFactory + 'static`) - Builds the remotes of this type
+#[allow(dead_code)] // Entry point for the remotes defined outside of the bridge
+pub fn register_remote(name: &str, factory: impl RemoteFactory + 'static) {
+    FACTORIES
+        .lock()
+        .unwrap()
+        .insert(name.to_string(), Box::new(factory));
+}
+
+/// A remote of a registered type
+pub struct CustomRemoteClient {
+    pub remote: Box<dyn Remote + Send + Sync>,
+}
+
+#[async_trait]
+impl Remote for CustomRemoteClient {
+    /// Sends a measurement through the registered remote
+    async fn send_measurement(
+        &self,
+        name: &str,
+        measurement: &Measurement,
+    ) -> Result<(), RemoteError> {
+        self.remote.send_measurement(name, measurement).await
+    }
+
+    /// Sends the measurements through the registered remote
+    async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+        self.remote.send_batch(data).await
+    }
+
+    /// Reloads the credentials of the registered remote
+    async fn refresh_auth(&mut self) -> bool {
+        self.remote.refresh_auth().await
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// strucure that represent the config for a remote of a registered type
+///
+/// # Fields
+///
+/// - `kind` (`String`) - the name the type was registered with, given as `type`
+/// - `options` (`RemoteOptions`) - the options shared by all remotes
+/// - `config` (`serde_json::Map<String, serde_json::Value>`) - the other fields, passed to the factory of the type
+pub struct CustomRemote {
+    #[serde(rename = "type")]
+    pub kind: String,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
+    #[serde(flatten)]
+    pub config: serde_json::Map<String, serde_json::Value>,
+}
+
+impl TryFrom<CustomRemote> for CustomRemoteClient {
+    type Error = RemoteInitError;
+
+    fn try_from(value: CustomRemote) -> Result<Self, Self::Error> {
+        let factories = FACTORIES.lock().unwrap();
+        let factory = match factories.get(&value.kind) {
+            Some(factory) => factory,
+            None => return Err(RemoteInitError::UnknownType { kind: value.kind }),
+        };
+        Ok(CustomRemoteClient {
+            remote: factory.create(value.config)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use tokio::sync::{mpsc, Mutex};
+
+    use super::*;
+    use crate::app_config::Remotes;
+    use crate::remotes::{send_once, RemoteEntry};
+    use crate::types_conversion::RegisterValue;
+
+    /// A remote forwarding the batches it receives
+    struct Dummy {
+        batches: mpsc::UnboundedSender<HashMap<String, Measurement>>,
+    }
+
+    #[async_trait]
+    impl Remote for Dummy {
+        async fn send_measurement(
+            &self,
+            name: &str,
+            measurement: &Measurement,
+        ) -> Result<(), RemoteError> {
+            self.send_batch(&HashMap::from([(name.to_string(), measurement.clone())]))
+                .await
+        }
+
+        async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+            self.batches.send(data.clone()).unwrap();
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn registered_remote_receives_the_batches() {
+        let (batches, mut received) = mpsc::unbounded_channel();
+        let (configs, mut config) = mpsc::unbounded_channel();
+        register_remote(
+            "dummy",
+            move |config: serde_json::Map<String, serde_json::Value>| {
+                configs.send(config).unwrap();
+                let remote: Box<dyn Remote + Send + Sync> = Box::new(Dummy {
+                    batches: batches.clone(),
+                });
+                Ok(remote)
+            },
+        );
+
+        let remotes: Remotes = serde_json::from_value(serde_json::json!({
+            "custom": {"sink": {"type": "dummy", "url": "mqtt://broker", "deduplicate": true}},
+        }))
+        .unwrap();
+        let options = remotes.options();
+        assert!(options["sink"].deduplicate);
+        let remotes: HashMap<String, Box<dyn Remote + Send>> = remotes.try_into().unwrap();
+        let config = config.recv().await.unwrap();
+        assert_eq!(config.keys().collect::<Vec<_>>(), ["url"]);
+
+        let remotes = remotes
+            .into_iter()
+            .map(|(name, remote)| {
+                let options = options[&name].clone();
+                (name, RemoteEntry::new(remote, options))
+            })
+            .collect();
+        let values = HashMap::from([("temp".to_string(), RegisterValue::Float(21.5))]);
+        let batch = HashMap::from([("press1".to_string(), Measurement::new(values))]);
+        assert!(send_once(Arc::new(Mutex::new(remotes)), batch).await);
+        assert!(received.recv().await.unwrap().contains_key("press1"));
+    }
+
+    #[test]
+    fn unregistered_type_is_an_error() {
+        let config: CustomRemote =
+            serde_json::from_value(serde_json::json!({"type": "carrier_pigeon"})).unwrap();
+        assert!(matches!(
+            CustomRemoteClient::try_from(config),
+            Err(RemoteInitError::UnknownType { .. })
+        ));
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Write},
+    path::Path,
+};
+
+use crate::measurement::batch_to_json;
+use crate::remotes::wal::Batch;
+
+/// Dead letter file of the batches that no remote accepted, so they are not silently lost
+/// and can be pushed again later by other means.
+///
+/// Each batch is appended as a line of JSON (see [`batch_to_json`]), the file is never truncated by the bridge.
+/// Unlike the write-ahead log, the batches are not pushed again by the bridge.
+///
+/// # Fields
+/// - `file`: The dead letter file, opened in append mode.
+pub struct DeadLetter {
+    file: File,
+}
+
+impl DeadLetter {
+    /// Open (or create) the dead letter file
+    ///
+    /// # Arguments
+    ///
+    /// - `path` (`&Path`) - The dead letter file
+    ///
+    /// # Returns
+    ///
+    /// - `io::Result<DeadLetter>` - The dead letter file
+    pub fn open(path: &Path) -> io::Result<DeadLetter> {
+        let file = OpenOptions::new().append(true).create(true).open(path)?;
+        Ok(DeadLetter { file })
+    }
+
+    /// Append a batch and sync it to disk
+    pub fn append(&mut self, batch: &Batch) -> io::Result<()> {
+        let mut line = batch_to_json(batch).to_string();
+        line.push('\n');
+        self.file.write_all(line.as_bytes())?;
+        self.file.sync_data()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+    use crate::measurement::Measurement;
+    use crate::types_conversion::RegisterValue;
+
+    #[test]
+    fn batches_are_appended_as_json_lines() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("deadletter.jsonl");
+        std::fs::write(&path, "{}\n").unwrap();
+        let mut deadletter = DeadLetter::open(&path).unwrap();
+        for temperature in [21.5, 22.0] {
+            let values = HashMap::from([("temp".to_string(), RegisterValue::Float(temperature))]);
+            let batch = HashMap::from([("press1".to_string(), Measurement::new(values))]);
+            deadletter.append(&batch).unwrap();
+        }
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<serde_json::Value> = content
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .collect();
+        // The batches written before are kept
+        assert_eq!(lines.len(), 3);
+        assert_eq!(lines[1]["press1"]["values"]["temp"], 21.5);
+        assert_eq!(lines[2]["press1"]["values"]["temp"], 22.0);
+    }
+}
@@ -1,34 +1,100 @@
-use std::{convert::Infallible, error::Error};
+use std::{convert::Infallible, net::AddrParseError};
 
-use custom_error::custom_error;
 use prometheus_push::error::PushMetricsError;
+use reqwest::header::{InvalidHeaderName, InvalidHeaderValue};
+use thiserror::Error;
 
-custom_error! {
-    /// List of error related to the config of the remote
-    pub RemoteInitError
-    ParsingFailed{ err: Box<dyn Error>} = "There was an error parsing",
-    InitialisationError{ err: Box<dyn Error> } = "The was an error on initilaisation",
-    NotReachable{} = "This should not happen",
+#[derive(Debug, Error)]
+/// List of error related to the config of the remote
+pub enum RemoteInitError {
+    #[error("There was an error parsing")]
+    ParsingFailed { reason: String },
+    #[error("There was an error parsing")]
+    BadUrl {
+        #[from]
+        err: url::ParseError,
+    },
+    #[error("There was an error parsing")]
+    BadAddress {
+        #[from]
+        err: AddrParseError,
+    },
+    #[error("There was an error parsing")]
+    BadHeaderName {
+        #[from]
+        err: InvalidHeaderName,
+    },
+    #[error("There was an error parsing")]
+    BadHeaderValue {
+        #[from]
+        err: InvalidHeaderValue,
+    },
+    #[error("The was an error on initilaisation")]
+    InitialisationError { reason: String },
+    #[error("The was an error on initilaisation")]
+    IoFailed {
+        #[from]
+        err: std::io::Error,
+    },
+    #[error("The was an error on initilaisation")]
+    PushgatewayFailed {
+        #[from]
+        err: PushMetricsError,
+    },
+    #[error("The was an error on initilaisation")]
+    HttpClientFailed {
+        #[from]
+        err: reqwest::Error,
+    },
+    #[error("This should not happen")]
+    NotReachable,
+    #[error("The token is not allowed to create the bucket {bucket}, create it beforehand or grant the token write access to the buckets")]
+    BucketCreationDenied { bucket: String },
+    #[error("Could not create the bucket {bucket} ({reason})")]
+    BucketCreationFailed { bucket: String, reason: String },
+    #[error("No remote type {kind} was registered")]
+    UnknownType { kind: String },
+    #[error("remote `{name}`: {err}")]
+    Remote {
+        name: String,
+        #[source]
+        err: Box<RemoteInitError>,
+    },
 }
 
-impl From<url::ParseError> for RemoteInitError {
-    fn from(value: url::ParseError) -> Self {
-        RemoteInitError::ParsingFailed {
-            err: Box::new(value),
+impl RemoteInitError {
+    /// Attach the name of the remote whose configuration caused the error
+    pub fn named(self, name: &str) -> Self {
+        RemoteInitError::Remote {
+            name: name.to_string(),
+            err: Box::new(self),
         }
     }
 }
 
-impl From<PushMetricsError> for RemoteInitError {
-    fn from(value: PushMetricsError) -> Self {
-        RemoteInitError::InitialisationError {
-            err: Box::new(value),
-        }
+impl From<Infallible> for RemoteInitError {
+    fn from(_value: Infallible) -> Self {
+        RemoteInitError::NotReachable
     }
 }
 
-impl From<Infallible> for RemoteInitError {
-    fn from(_value: Infallible) -> Self {
-        RemoteInitError::NotReachable {}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_unchanged() {
+        let err = RemoteInitError::ParsingFailed {
+            reason: "bad port".to_string(),
+        };
+        assert_eq!(err.to_string(), "There was an error parsing");
+        let err = RemoteInitError::InitialisationError {
+            reason: "no token".to_string(),
+        };
+        assert_eq!(err.to_string(), "The was an error on initilaisation");
+        assert_eq!(
+            err.named("local").to_string(),
+            "remote `local`: The was an error on initilaisation"
+        );
     }
 }
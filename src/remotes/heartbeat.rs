@@ -0,0 +1,79 @@
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+use log::{error, info};
+use serde::Deserialize;
+use tokio::{
+    sync::Mutex,
+    task::JoinSet,
+    time::{timeout, MissedTickBehavior},
+};
+
+use crate::measurement::Measurement;
+
+use super::{remote::Remote, send_data_to_remote, RemoteEntry};
+
+fn default_interval() -> u64 {
+    60
+}
+
+fn default_name() -> String {
+    "bridge_heartbeat".to_string()
+}
+
+fn default_field() -> String {
+    "value".to_string()
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// Liveness measurement pushed to every remote on its own interval, whatever the devices send
+///
+/// # Fields
+/// - `interval`: Time between two heartbeats, in seconds (default 60).
+/// - `name`: Name of the measurement (default `bridge_heartbeat`).
+/// - `field`: Field of the measurement, always set to 1 (default `value`).
+pub struct Heartbeat {
+    #[serde(default = "default_interval")]
+    pub interval: u64,
+    #[serde(default = "default_name")]
+    pub name: String,
+    #[serde(default = "default_field")]
+    pub field: String,
+}
+
+/// Push the heartbeat to all remotes every `heartbeat.interval`, independently of the fetch cycles.
+/// Each push is given up after the interval, so a slow remote doesn't delay the next heartbeats,
+/// and a failed heartbeat is not pushed again (the next one supersedes it).
+///
+/// # Parameters
+/// - `remotes`: The shared map of remote backends.
+/// - `heartbeat`: The interval and the measurement pushed.
+pub async fn send_heartbeats(
+    remotes: Arc<Mutex<HashMap<String, RemoteEntry<impl Remote + 'static + ?Sized>>>>,
+    heartbeat: Heartbeat,
+) {
+    let period = Duration::from_secs(heartbeat.interval.max(1));
+    let mut interval = tokio::time::interval(period);
+    interval.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    info!("Pushing {} every {period:?}", heartbeat.name);
+    loop {
+        interval.tick().await;
+        let data = Arc::new(HashMap::from([(
+            heartbeat.name.clone(),
+            Measurement::status(&heartbeat.field, true),
+        )]));
+        let snapshot = remotes.lock().await.clone();
+        let mut set = JoinSet::new();
+        for (name, entry) in snapshot {
+            let data = data.clone();
+            set.spawn(async move {
+                let push = send_data_to_remote(&name, &entry, &data);
+                match timeout(period, push).await {
+                    Ok(Ok(_)) => {}
+                    Ok(Err(err)) => error!("Could not send heartbeat to remote {name} : {err}"),
+                    Err(_) => error!("Timeout reached while sending heartbeat to remote {name}"),
+                }
+            });
+        }
+        while set.join_next().await.is_some() {}
+    }
+}
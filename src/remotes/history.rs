@@ -0,0 +1,333 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, SecondsFormat, Utc};
+use log::{error, info};
+use serde::Deserialize;
+use serde_json::json;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::measurement::{batch_to_json, Measurement};
+use crate::remotes::errors::RemoteInitError;
+use crate::remotes::remote::{RemoteError, RemoteOptions};
+use crate::remotes::Remote;
+
+/// Maximum size of the head of a request, the body (if any) is ignored
+const MAX_REQUEST: usize = 8192;
+
+/// The last batches, oldest first, with the time they were pushed at
+type Snapshots = VecDeque<(DateTime<Utc>, serde_json::Value)>;
+
+fn default_size() -> usize {
+    100
+}
+
+fn default_path() -> String {
+    "/history".to_string()
+}
+
+/// Ring buffer of the last batches pushed, served as JSON over HTTP
+///
+/// # Fields
+/// - `snapshots`: The last batches, shared with the server task.
+/// - `size`: The number of batches kept, the oldest one is dropped first.
+pub struct History {
+    pub snapshots: Arc<Mutex<Snapshots>>,
+    pub size: usize,
+}
+
+#[async_trait]
+impl Remote for History {
+    /// Keeps the measurement as a batch of its own
+    async fn send_measurement(
+        &self,
+        name: &str,
+        measurement: &Measurement,
+    ) -> Result<(), RemoteError> {
+        self.send_batch(&HashMap::from([(name.to_string(), measurement.clone())]))
+            .await
+    }
+
+    /// Keeps the batch, dropping the oldest one once `size` batches are kept
+    ///
+    /// Returns
+    /// - `Ok(())` always, the clients read the history on their own schedule.
+    async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+        let mut snapshots = self.snapshots.lock().unwrap();
+        if snapshots.len() >= self.size {
+            snapshots.pop_front();
+        }
+        snapshots.push_back((Utc::now(), batch_to_json(data)));
+        Ok(())
+    }
+}
+
+/// Answer a request of a client
+///
+/// # Arguments
+///
+/// - `snapshots` (`&Snapshots`) - The last batches
+/// - `path` (`&str`) - The path the history is served on
+/// - `request` (`&str`) - The head of the request
+///
+/// # Returns
+///
+/// - `(&str, String)` - The status line and the JSON body
+fn respond(snapshots: &Snapshots, path: &str, request: &str) -> (&'static str, String) {
+    let mut request_line = request.lines().next().unwrap_or_default().split(' ');
+    let (method, target) = (request_line.next(), request_line.next().unwrap_or_default());
+    let (target_path, query) = target.split_once('?').unwrap_or((target, ""));
+    if target_path != path {
+        return ("404 Not Found", json!({"error": "not found"}).to_string());
+    }
+    if method != Some("GET") {
+        return (
+            "405 Method Not Allowed",
+            json!({"error": "only GET is allowed"}).to_string(),
+        );
+    }
+    let mut count = snapshots.len();
+    for (key, value) in query.split('&').filter_map(|param| param.split_once('=')) {
+        if key == "n" {
+            let Ok(n) = value.parse::<usize>() else {
+                return (
+                    "400 Bad Request",
+                    json!({"error": format!("invalid n: {value}")}).to_string(),
+                );
+            };
+            count = count.min(n);
+        }
+    }
+    let history: Vec<serde_json::Value> = snapshots
+        .iter()
+        .skip(snapshots.len() - count)
+        .map(|(timestamp, batch)| {
+            json!({
+                "timestamp": timestamp.to_rfc3339_opts(SecondsFormat::Millis, true),
+                "data": batch,
+            })
+        })
+        .collect();
+    ("200 OK", serde_json::Value::Array(history).to_string())
+}
+
+/// Read the head of a request and answer it, the connection is closed after the response
+async fn serve_client(
+    mut stream: TcpStream,
+    snapshots: Arc<Mutex<Snapshots>>,
+    path: &str,
+) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|end| end == b"\r\n\r\n") && request.len() < MAX_REQUEST {
+        let read = stream.read(&mut buf).await?;
+        if read == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..read]);
+    }
+    let (status, body) = respond(
+        &snapshots.lock().unwrap(),
+        path,
+        &String::from_utf8_lossy(&request),
+    );
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    stream.write_all(response.as_bytes()).await?;
+    stream.shutdown().await
+}
+
+/// Accept the clients of the server, each one served in its own task
+async fn serve(listener: TcpListener, snapshots: Arc<Mutex<Snapshots>>, path: String) {
+    let path: Arc<str> = path.into();
+    loop {
+        let (stream, client) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!("Could not accept a history client ({err})");
+                continue;
+            }
+        };
+        let snapshots = snapshots.clone();
+        let path = path.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = serve_client(stream, snapshots, &path).await {
+                info!("History client {client} disconnected ({err})");
+            }
+        });
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// structure that represent the config for the history remote
+///
+/// # Fields
+///
+/// - `listen` (`String`) - the address the HTTP server listens on (e.g. `0.0.0.0:8080`)
+/// - `size` (`usize`) - the number of batches kept (default 100)
+/// - `path` (`String`) - the path the history is served on (default `/history`)
+/// - `options` (`RemoteOptions`) - the options shared by all remotes
+pub struct HistoryRemote {
+    pub listen: String,
+    #[serde(default = "default_size")]
+    pub size: usize,
+    #[serde(default = "default_path")]
+    pub path: String,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
+}
+
+impl TryFrom<HistoryRemote> for History {
+    type Error = RemoteInitError;
+
+    fn try_from(value: HistoryRemote) -> Result<Self, Self::Error> {
+        if value.size == 0 {
+            return Err(RemoteInitError::ParsingFailed {
+                reason: "the history must keep at least one batch".to_string(),
+            });
+        }
+        let addr: SocketAddr = value.listen.parse()?;
+        let listener = std::net::TcpListener::bind(addr).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })?;
+        info!(
+            "Serving the last {} batches on http://{addr}{}",
+            value.size, value.path
+        );
+
+        let snapshots = Arc::new(Mutex::new(VecDeque::with_capacity(value.size)));
+        tokio::task::spawn(serve(listener, snapshots.clone(), value.path));
+        Ok(History {
+            snapshots,
+            size: value.size,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types_conversion::RegisterValue;
+
+    fn batch(temperature: f64) -> HashMap<String, Measurement> {
+        let values = HashMap::from([("temp".to_string(), RegisterValue::Float(temperature))]);
+        HashMap::from([("press1".to_string(), Measurement::new(values))])
+    }
+
+    /// A history keeping `size` batches, with `pushed` pushed to it
+    async fn history(size: usize, pushed: &[f64]) -> History {
+        let history = History {
+            snapshots: Arc::new(Mutex::new(VecDeque::new())),
+            size,
+        };
+        for temperature in pushed {
+            history.send_batch(&batch(*temperature)).await.unwrap();
+        }
+        history
+    }
+
+    /// The temperatures of the history answered to a request
+    fn temperatures(body: &str) -> Vec<f64> {
+        let history: Vec<serde_json::Value> = serde_json::from_str(body).unwrap();
+        history
+            .iter()
+            .map(|snapshot| {
+                snapshot["data"]["press1"]["values"]["temp"]
+                    .as_f64()
+                    .unwrap()
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn oldest_batch_is_dropped_once_full() {
+        let history = history(3, &[20.0, 21.0, 22.0, 23.0]).await;
+        let (status, body) = respond(
+            &history.snapshots.lock().unwrap(),
+            "/history",
+            "GET /history HTTP/1.1\r\n\r\n",
+        );
+        assert_eq!(status, "200 OK");
+        assert_eq!(temperatures(&body), [21.0, 22.0, 23.0]);
+        let history: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+        assert!(DateTime::parse_from_rfc3339(history[0]["timestamp"].as_str().unwrap()).is_ok());
+    }
+
+    #[tokio::test]
+    async fn last_n_batches_are_answered() {
+        let history = history(10, &[20.0, 21.0, 22.0]).await;
+        let snapshots = history.snapshots.lock().unwrap();
+        let answer = |request: &str| respond(&snapshots, "/history", request);
+        let (_, body) = answer("GET /history?n=2 HTTP/1.1\r\n\r\n");
+        assert_eq!(temperatures(&body), [21.0, 22.0]);
+        let (_, body) = answer("GET /history?pretty=1&n=50 HTTP/1.1\r\n\r\n");
+        assert_eq!(temperatures(&body), [20.0, 21.0, 22.0]);
+        let (_, body) = answer("GET /history?n=0 HTTP/1.1\r\n\r\n");
+        assert_eq!(body, "[]");
+    }
+
+    #[tokio::test]
+    async fn invalid_requests_are_answered_with_an_error() {
+        let history = history(10, &[20.0]).await;
+        let snapshots = history.snapshots.lock().unwrap();
+        let answer = |request: &str| respond(&snapshots, "/history", request).0;
+        assert_eq!(
+            answer("GET /history?n=-1 HTTP/1.1\r\n\r\n"),
+            "400 Bad Request"
+        );
+        assert_eq!(answer("GET /latest HTTP/1.1\r\n\r\n"), "404 Not Found");
+        assert_eq!(
+            answer("POST /history HTTP/1.1\r\n\r\n"),
+            "405 Method Not Allowed"
+        );
+    }
+
+    #[tokio::test]
+    async fn history_is_served_over_http() {
+        // A port no socket is bound to
+        let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = free.local_addr().unwrap();
+        drop(free);
+        let config =
+            serde_json::json!({"listen": addr.to_string(), "size": 5, "path": "/api/history"});
+        let history: History = serde_json::from_value::<HistoryRemote>(config)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        for temperature in [20.0, 21.0] {
+            history.send_batch(&batch(temperature)).await.unwrap();
+        }
+
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        stream
+            .write_all(b"GET /api/history?n=1 HTTP/1.1\r\nHost: bridge\r\n\r\n")
+            .await
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"));
+        assert!(head.contains("Content-Type: application/json"));
+        assert_eq!(temperatures(body), [21.0]);
+    }
+
+    #[test]
+    fn empty_history_is_rejected() {
+        let config = serde_json::json!({"listen": "127.0.0.1:0", "size": 0});
+        let config: HistoryRemote = serde_json::from_value(config).unwrap();
+        assert!(matches!(
+            History::try_from(config),
+            Err(RemoteInitError::ParsingFailed { .. })
+        ));
+    }
+}
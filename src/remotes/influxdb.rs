@@ -1,23 +1,312 @@
-use std::convert::Infallible;
+use std::{
+    collections::{BTreeMap, HashMap},
+    time::Duration,
+};
 
-use crate::remotes::remote::RemoteError;
+use crate::measurement::Measurement;
+use crate::remotes::compression::Compression;
+use crate::remotes::errors::RemoteInitError;
+use crate::remotes::influxdb::bucket::{parse_retention, BucketSpec};
+use crate::remotes::remote::{read_token, RemoteError, RemoteOptions};
 use crate::remotes::Remote;
 
 use async_trait::async_trait;
+use chrono::{DateTime, Utc};
 use influxdb::{Client, InfluxDbWriteable, Type};
+use log::warn;
+use reqwest::StatusCode;
 use serde::Deserialize;
+use tokio::time::timeout;
+use url::Url;
+
+pub mod bucket;
+pub mod line_protocol;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// InfluxDB type all numeric fields are coerced to, avoiding field type conflicts
+/// when a register is not always decoded to the same type
+pub enum FieldType {
+    Float,
+    Int,
+    String,
+}
+
+/// Coerce a numeric InfluxDB value to the `field_type`, booleans and texts are kept as is
+///
+/// # Arguments
+///
+/// - `value` (`Type`) - The value to coerce
+/// - `field_type` (`FieldType`) - The type to coerce to
+///
+/// # Returns
+///
+/// - `Type` - The coerced value
+pub fn coerce_field(value: Type, field_type: FieldType) -> Type {
+    match (value, field_type) {
+        (Type::SignedInteger(val), FieldType::Float) => Type::Float(val as f64),
+        (Type::UnsignedInteger(val), FieldType::Float) => Type::Float(val as f64),
+        // NaN would become 0, it is left out of the writes as a float
+        (Type::Float(val), FieldType::Int) if val.is_finite() => {
+            Type::SignedInteger(val.round() as i64)
+        }
+        (Type::UnsignedInteger(val), FieldType::Int) => {
+            Type::SignedInteger(i64::try_from(val).unwrap_or(i64::MAX))
+        }
+        (Type::SignedInteger(val), FieldType::String) => Type::Text(val.to_string()),
+        (Type::UnsignedInteger(val), FieldType::String) => Type::Text(val.to_string()),
+        (Type::Float(val), FieldType::String) => Type::Text(val.to_string()),
+        (value, _) => value,
+    }
+}
+
+/// Split a measurement by unit, a tag applying to every field of a point: the fields with a unit are written
+/// in a point of their own tagged `unit=<unit>`, the fields without unit stay in a point without this tag
+///
+/// # Arguments
+///
+/// - `measurement` (`&Measurement`) - The measurement to split
+///
+/// # Returns
+///
+/// - `Vec<Measurement>` - The points, the one without unit first then by unit
+pub fn split_units(measurement: &Measurement) -> Vec<Measurement> {
+    if measurement.units.is_empty() {
+        return vec![measurement.clone()];
+    }
+    let mut parts: BTreeMap<Option<&String>, Measurement> = BTreeMap::new();
+    for (field, value) in &measurement.values {
+        let unit = measurement.units.get(field);
+        let part = parts.entry(unit).or_insert_with(|| {
+            let mut tags = measurement.tags.clone();
+            if let Some(unit) = unit {
+                tags.insert("unit".to_string(), unit.clone());
+            }
+            Measurement {
+                timestamp: measurement.timestamp,
+                values: HashMap::new(),
+                tags,
+                units: HashMap::new(),
+            }
+        });
+        part.values.insert(field.clone(), value.clone());
+    }
+    parts.into_values().collect()
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How the fields of a source are laid out in InfluxDB
+pub enum Layout {
+    /// One measurement per source, named after it, holding a field per register
+    #[default]
+    Wide,
+    /// One measurement per register, named after it, holding a `value` field, the source being its `device` tag
+    Narrow,
+}
+
+/// The points to write for the measurement of a source, with their measurement name, laid out as configured
+/// then split by unit (see [`split_units`])
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - The source of the measurement
+/// - `measurement` (`&Measurement`) - The measurement to write
+/// - `layout` (`Layout`) - How the fields are laid out
+///
+/// # Returns
+///
+/// - `Vec<(String, Measurement)>` - The points and their measurement name, sorted by name in `narrow` layout
+pub fn points(name: &str, measurement: &Measurement, layout: Layout) -> Vec<(String, Measurement)> {
+    if layout == Layout::Wide {
+        return split_units(measurement)
+            .into_iter()
+            .map(|part| (name.to_string(), part))
+            .collect();
+    }
+    let mut res = Vec::new();
+    for (field, value) in measurement.sorted_values() {
+        let mut tags = measurement.tags.clone();
+        tags.insert("device".to_string(), name.to_string());
+        let units = match measurement.units.get(field) {
+            Some(unit) => HashMap::from([("value".to_string(), unit.clone())]),
+            None => HashMap::new(),
+        };
+        let point = Measurement {
+            timestamp: measurement.timestamp,
+            values: HashMap::from([("value".to_string(), value.clone())]),
+            tags,
+            units,
+        };
+        res.extend(
+            split_units(&point)
+                .into_iter()
+                .map(|part| (field.clone(), part)),
+        );
+    }
+    res
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How the measurements are written to InfluxDB
+pub enum WriteMode {
+    /// One query per measurement, built by the `influxdb` crate
+    #[default]
+    Builder,
+    /// All the measurements of a push serialized to line protocol and posted at once (`/write`, InfluxDB 1.x compatible)
+    LineProtocol,
+    /// Same as `LineProtocol`, posted to the `/api/v2/write` endpoint of InfluxDB 2.x (requires `org`)
+    LineProtocolV2,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Unit of the timestamps written in line protocol
+pub enum Precision {
+    #[default]
+    Ns,
+    Us,
+    Ms,
+    S,
+}
+
+impl Precision {
+    /// The value of the `precision` parameter of the write endpoints
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Precision::Ns => "ns",
+            Precision::Us => "us",
+            Precision::Ms => "ms",
+            Precision::S => "s",
+        }
+    }
+
+    /// The timestamp in this unit, `None` if it does not fit in 64 bits (nanoseconds after 2262)
+    pub fn timestamp(&self, timestamp: DateTime<Utc>) -> Option<i64> {
+        match self {
+            Precision::Ns => timestamp.timestamp_nanos_opt(),
+            Precision::Us => Some(timestamp.timestamp_micros()),
+            Precision::Ms => Some(timestamp.timestamp_millis()),
+            Precision::S => Some(timestamp.timestamp()),
+        }
+    }
+}
+
+/// Where and how to post line protocol directly
+///
+/// # Fields
+/// - `client`: The HTTP client, shared by all the pushes.
+/// - `url`: The write endpoint, with the bucket and the precision.
+/// - `token`: The token sent in the `Authorization` header.
+/// - `precision`: The unit of the timestamps.
+/// - `compression`: The compression of the bodies.
+pub struct LineProtocolWriter {
+    pub client: reqwest::Client,
+    pub url: Url,
+    pub token: String,
+    pub precision: Precision,
+    pub compression: Compression,
+}
+
+/// InfluxDB remote, the client and the options on how to write the fields
+///
+/// # Fields
+/// - `client`: The client of the query builder.
+/// - `force_field_type`: The type all numeric fields are coerced to.
+/// - `http_timeout`: The maximum time to wait for the server on each push.
+/// - `line_protocol`: Where to post line protocol, in `line_protocol` mode.
+/// - `token`: The token in use.
+/// - `token_file`: The file the token is reloaded from when InfluxDB rejects it.
+/// - `tags`: The tags added to every write, the tags of the measurements take precedence.
+/// - `layout`: How the fields of a source are laid out.
+pub struct InfluxDBClient {
+    pub client: Client,
+    pub force_field_type: Option<FieldType>,
+    pub http_timeout: Option<Duration>,
+    pub line_protocol: Option<LineProtocolWriter>,
+    pub token: String,
+    pub token_file: Option<String>,
+    pub tags: HashMap<String, String>,
+    pub layout: Layout,
+}
+
+impl InfluxDBClient {
+    /// Post the measurements in a single line protocol request
+    ///
+    /// # Arguments
+    ///
+    /// - `writer` (`&LineProtocolWriter`) - Where to post the lines
+    /// - `data` (`&HashMap<String, Measurement>`) - The measurements, keyed by measurement name
+    ///
+    /// # Returns
+    ///
+    /// - `Result<(), RemoteError>` - Whether InfluxDB accepted the lines
+    async fn write_lines(
+        &self,
+        writer: &LineProtocolWriter,
+        data: &HashMap<String, Measurement>,
+    ) -> Result<(), RemoteError> {
+        let sources: BTreeMap<&String, &Measurement> = data.iter().collect();
+        let lines: Vec<String> = sources
+            .into_iter()
+            .flat_map(|(name, measurement)| points(name, measurement, self.layout))
+            .filter_map(|(name, measurement)| {
+                line_protocol::line(
+                    &name,
+                    &measurement,
+                    &self.tags,
+                    self.force_field_type,
+                    writer.precision,
+                )
+            })
+            .collect();
+        if lines.is_empty() {
+            return Ok(());
+        }
+
+        let request = writer
+            .client
+            .post(writer.url.clone())
+            .header("Authorization", format!("Token {}", writer.token))
+            .header("Content-Type", "text/plain; charset=utf-8");
+        let mut request = writer
+            .compression
+            .body(request, lines.join("\n").into_bytes());
+        if let Some(duration) = self.http_timeout {
+            request = request.timeout(duration);
+        }
+        let res = match request.send().await {
+            Ok(res) => res,
+            // Same error as a query builder push running out of time
+            Err(err) if err.is_timeout() => return Err(RemoteError::ServerError),
+            Err(err) => return Err(err.into()),
+        };
+
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(RemoteError::AuthError),
+            status if status.is_server_error() => Err(RemoteError::ServerError),
+            status => Err(RemoteError::PushFailedError {
+                res: format!("{status} {}", res.text().await.unwrap_or_default()),
+            }),
+        }
+    }
+}
 
 #[async_trait]
-impl Remote for Client {
+impl Remote for InfluxDBClient {
     /// Sends a measurement to the remote InfluxDB instance.
     ///
-    /// Builds an InfluxDB query using the current local timestamp and
+    /// Builds an InfluxDB query using the measurement timestamp and tags, and
     /// appends all provided register values as fields of the measurement.
+    /// The fields with a unit are written in points of their own, tagged `unit` (see [`split_units`]).
+    /// In `narrow` layout, each field is written as the `value` of a measurement named after it (see [`points`]).
     ///
     /// Parameters
     /// - `name`: the name of the measurement (InfluxDB series name).
-    /// - `values`: a map of field names to `RegisterValue`s that will be
-    ///   converted and stored as fields in the measurement.
+    /// - `measurement`: the timestamp and a map of field names to `RegisterValue`s
+    ///   that will be converted and stored as fields in the measurement.
     ///
     /// Returns
     /// - `Ok(())` if the measurement was successfully pushed.
@@ -25,19 +314,53 @@ impl Remote for Client {
     ///
     /// Errors
     /// - `RemoteError::PushFailedError` if InfluxDB responded with a non-empty error result.
+    /// - `RemoteError::ServerError` if the server did not answer within `http_timeout`.
     /// - Propagates other errors returned from the underlying query execution.
-    /// 
+    ///
     async fn send_measurement(
         &self,
         name: &str,
-        values: &std::collections::HashMap<String, crate::types_conversion::RegisterValue>,
+        measurement: &Measurement,
     ) -> Result<(), RemoteError> {
-        let mut query = influxdb::Timestamp::from(chrono::offset::Local::now()).into_query(name);
-        for (field, value) in values {
-            query = query.add_field(field, Into::<Type>::into(value.clone()));
+        let mut queries = Vec::new();
+        for (name, part) in points(name, measurement, self.layout) {
+            let mut query = influxdb::Timestamp::from(part.timestamp).into_query(name);
+            // Sorted so the same data always gives the same query
+            let mut tags: BTreeMap<&String, &String> = self.tags.iter().collect();
+            tags.extend(part.sorted_tags());
+            for (tag, value) in tags {
+                query = query.add_tag(tag, value.as_str());
+            }
+            let mut fields = 0;
+            for (field, value) in part.sorted_values() {
+                let mut value = Into::<Type>::into(value.clone());
+                if let Some(field_type) = self.force_field_type {
+                    value = coerce_field(value, field_type);
+                }
+                // InfluxDB can't store NaN (an invalid reading) or the infinities, as in line protocol mode
+                if matches!(value, Type::Float(val) if !val.is_finite()) {
+                    continue;
+                }
+                query = query.add_field(field, value);
+                fields += 1;
+            }
+            if fields > 0 {
+                queries.push(query);
+            }
+        }
+        if queries.is_empty() {
+            return Ok(());
         }
 
-        match self.query(query).await {
+        let res = match self.http_timeout {
+            Some(duration) => match timeout(duration, self.client.query(queries)).await {
+                Ok(res) => res,
+                Err(_) => return Err(RemoteError::ServerError),
+            },
+            None => self.client.query(queries).await,
+        };
+
+        match res {
             Ok(res) => {
                 if !res.is_empty() {
                     return Err(RemoteError::PushFailedError { res });
@@ -47,27 +370,417 @@ impl Remote for Client {
         };
         Ok(())
     }
+
+    /// Sends the measurements of all the sources, in a single request in `line_protocol` mode
+    /// (failing with `RemoteError::ServerError` if the server did not answer within `http_timeout`)
+    async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+        if let Some(writer) = &self.line_protocol {
+            return self.write_lines(writer, data).await;
+        }
+        for (source, measurement) in data.iter() {
+            self.send_measurement(source, measurement).await?;
+        }
+        Ok(())
+    }
+
+    /// Reloads the token from `token_file`, `true` if it was rotated
+    async fn refresh_auth(&mut self) -> bool {
+        let path = match &self.token_file {
+            Some(path) => path,
+            None => return false,
+        };
+        let token = match read_token(path) {
+            Ok(token) => token,
+            Err(err) => {
+                warn!("Could not read the token file {path} ({err})");
+                return false;
+            }
+        };
+        if token == self.token {
+            return false;
+        }
+        self.client = self.client.clone().with_token(token.clone());
+        if let Some(writer) = self.line_protocol.as_mut() {
+            writer.token = token.clone();
+        }
+        self.token = token;
+        true
+    }
 }
 
 #[derive(Deserialize, Debug)]
 /// strucure that represent the config for the influx remote
-/// 
+///
 /// # Fields
-/// 
+///
 /// - `remote` (`String`) - the url address to access to the influxDB
 /// - `bucket` (`String`) - the named location where time series data is stored
-/// - `token` (`String`) - the identifies InfluxDB permissions
+/// - `token` (`Option<String>`) - the identifies InfluxDB permissions
+/// - `token_file` (`Option<String>`) - file holding the token instead of `token`, read again when InfluxDB rejects the token
+/// - `force_field_type` (`Option<FieldType>`) - coerce all numeric fields to `float`, `int` or `string`, also accepted as
+///   `coerce_numeric_to` (default: natural type of each value)
+/// - `http_timeout_ms` (`Option<u64>`) - maximum time to wait for the server on each push (default: no limit)
+/// - `tags` (`HashMap<String, String>`) - tags added to every write (e.g. `interval=1s` for the downsampling tasks)
+/// - `mode` (`WriteMode`) - write through the query builder (`builder`, default) or post line protocol directly
+///   (`line_protocol` to `/write`, `line_protocol_v2` to `/api/v2/write`)
+/// - `org` (`Option<String>`) - the organization of the bucket, required by `line_protocol_v2`
+/// - `precision` (`Precision`) - the unit of the timestamps posted in line protocol (`ns`, default, `us`, `ms` or `s`)
+/// - `compression` (`Compression`) - the compression of the lines posted (`none`, default, or `gzip`), line protocol modes only
+/// - `layout` (`Layout`) - one measurement per source with a field per register (`wide`, default), or one measurement
+///   per register with a `value` field and a `device` tag (`narrow`)
+/// - `create_bucket` (`bool`) - create the bucket (the database with InfluxDB 1.x) on startup if it is missing,
+///   through the 2.x API when `org` is set (default: false)
+/// - `retention` (`Option<String>`) - how long the data of a created bucket is kept, e.g. `30d` (default: forever)
+/// - `options` (`RemoteOptions`) - the options shared by all remotes
 pub struct InfluxDBRemote {
     pub remote: String,
     pub bucket: String,
-    pub token: String,
+    pub token: Option<String>,
+    pub token_file: Option<String>,
+    #[serde(alias = "coerce_numeric_to")]
+    pub force_field_type: Option<FieldType>,
+    pub http_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub tags: HashMap<String, String>,
+    #[serde(default)]
+    pub mode: WriteMode,
+    pub org: Option<String>,
+    #[serde(default)]
+    pub precision: Precision,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub layout: Layout,
+    #[serde(default)]
+    pub create_bucket: bool,
+    pub retention: Option<String>,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
 }
 
-impl TryFrom<InfluxDBRemote> for Client {
-    type Error = Infallible;
+impl TryFrom<InfluxDBRemote> for InfluxDBClient {
+    type Error = RemoteInitError;
 
     fn try_from(value: InfluxDBRemote) -> Result<Self, Self::Error> {
-        let client = Client::new(value.remote, value.bucket).with_token(value.token);
-        Ok(client)
+        let token = match (&value.token_file, value.token) {
+            (Some(path), _) => read_token(path)?,
+            (None, Some(token)) => token,
+            (None, None) => {
+                return Err(RemoteInitError::InitialisationError {
+                    reason: "either token or token_file must be set".to_string(),
+                })
+            }
+        };
+        let http_timeout = value.http_timeout_ms.map(Duration::from_millis);
+
+        if value.create_bucket {
+            let retention = match value.retention.as_deref().map(|r| (r, parse_retention(r))) {
+                None => None,
+                Some((_, Some(seconds))) => Some(seconds),
+                Some((retention, None)) => {
+                    return Err(RemoteInitError::ParsingFailed {
+                        reason: format!("invalid retention {retention}, expected e.g. 30d"),
+                    })
+                }
+            };
+            BucketSpec {
+                remote: value.remote.trim_end_matches('/').to_string(),
+                bucket: value.bucket.clone(),
+                org: value.org.clone(),
+                token: token.clone(),
+                retention,
+            }
+            .ensure()?;
+        } else if value.retention.is_some() {
+            warn!("retention is only applied with create_bucket, ignoring it");
+        }
+
+        // A single client is kept for all the pushes so its connections are reused
+        let mut http_client = reqwest::Client::builder().tcp_keepalive(Duration::from_secs(60));
+        if let Some(duration) = http_timeout {
+            http_client = http_client.connect_timeout(duration);
+        }
+
+        let http_client = http_client.build()?;
+
+        let remote = value.remote.trim_end_matches('/');
+        let url = match value.mode {
+            // The query builder sends its own requests
+            WriteMode::Builder if value.compression != Compression::None => {
+                return Err(RemoteInitError::InitialisationError {
+                    reason: "compression requires a line_protocol mode".to_string(),
+                })
+            }
+            WriteMode::Builder => None,
+            WriteMode::LineProtocol => {
+                // Same endpoint as the query builder
+                let mut url = Url::parse(&format!("{remote}/write"))?;
+                url.query_pairs_mut()
+                    .append_pair("db", &value.bucket)
+                    .append_pair("precision", value.precision.as_str());
+                Some(url)
+            }
+            WriteMode::LineProtocolV2 => {
+                let Some(org) = &value.org else {
+                    return Err(RemoteInitError::InitialisationError {
+                        reason: "org must be set in line_protocol_v2 mode".to_string(),
+                    });
+                };
+                let mut url = Url::parse(&format!("{remote}/api/v2/write"))?;
+                url.query_pairs_mut()
+                    .append_pair("org", org)
+                    .append_pair("bucket", &value.bucket)
+                    .append_pair("precision", value.precision.as_str());
+                Some(url)
+            }
+        };
+        let line_protocol = url.map(|url| LineProtocolWriter {
+            client: http_client.clone(),
+            url,
+            token: token.clone(),
+            precision: value.precision,
+            compression: value.compression,
+        });
+
+        let client = Client::new(value.remote, value.bucket)
+            .with_token(token.clone())
+            .with_http_client(http_client);
+        Ok(InfluxDBClient {
+            client,
+            force_field_type: value.force_field_type,
+            http_timeout,
+            line_protocol,
+            token,
+            token_file: value.token_file,
+            tags: value.tags,
+            layout: value.layout,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::{io::AsyncWriteExt, net::TcpListener, time::Instant};
+
+    use super::*;
+    use crate::remotes::compression::gunzip;
+    use crate::remotes::mock_server::{read_request, serve_with};
+    use crate::remotes::{send_data_to_remote, RemoteEntry};
+    use crate::types_conversion::RegisterValue;
+
+    /// A line protocol client of the server at `addr`
+    fn line_protocol_client(addr: std::net::SocketAddr, http_timeout_ms: u64) -> InfluxDBClient {
+        let config = serde_json::json!({
+            "remote": format!("http://{addr}"),
+            "bucket": "plant",
+            "token": "secret",
+            "mode": "line_protocol",
+            "http_timeout_ms": http_timeout_ms,
+        });
+        serde_json::from_value::<InfluxDBRemote>(config)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    fn batch() -> HashMap<String, Measurement> {
+        let values = HashMap::from([("temp".to_string(), RegisterValue::Float(21.5))]);
+        HashMap::from([("press1".to_string(), Measurement::new(values))])
+    }
+
+    #[tokio::test]
+    async fn stalled_server_fails_the_push_within_the_timeout() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = line_protocol_client(listener.local_addr().unwrap(), 200);
+        // Accepts the connections and never answers
+        tokio::spawn(async move {
+            let mut streams = Vec::new();
+            while let Ok((stream, _)) = listener.accept().await {
+                streams.push(stream);
+            }
+        });
+
+        let start = Instant::now();
+        let res = client.send_batch(&batch()).await;
+        assert!(matches!(res, Err(RemoteError::ServerError)));
+        assert!(start.elapsed() < Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn connection_is_reused_across_pushes() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = line_protocol_client(listener.local_addr().unwrap(), 2000);
+        let connections = Arc::new(AtomicUsize::new(0));
+        let accepted = connections.clone();
+        // Answers every request of a connection with 204, keeping it open
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                accepted.fetch_add(1, Ordering::SeqCst);
+                tokio::spawn(async move {
+                    while read_request(&mut stream).await.is_some() {
+                        let response = "HTTP/1.1 204 No Content\r\nContent-Length: 0\r\n\r\n";
+                        stream.write_all(response.as_bytes()).await.unwrap();
+                    }
+                });
+            }
+        });
+
+        for _ in 0..3 {
+            client.send_batch(&batch()).await.unwrap();
+        }
+        assert_eq!(connections.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn rotated_token_is_reloaded_and_the_push_retried() {
+        let (addr, mut requests) = serve_with(|request| match request.header("authorization") {
+            Some("Token rotated") => "204 No Content",
+            _ => "401 Unauthorized",
+        })
+        .await;
+        let token_file = std::env::temp_dir().join(format!("bridge-token-{}", addr.port()));
+        std::fs::write(&token_file, "expired\n").unwrap();
+        let config = serde_json::json!({
+            "remote": format!("http://{addr}"),
+            "bucket": "plant",
+            "token_file": token_file,
+            "mode": "line_protocol",
+        });
+        let client: InfluxDBClient = serde_json::from_value::<InfluxDBRemote>(config)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let entry = RemoteEntry::new(Box::new(client), RemoteOptions::default());
+
+        // The token is rotated while the bridge runs
+        std::fs::write(&token_file, "rotated\n").unwrap();
+        let res = send_data_to_remote("influx", &entry, &batch()).await;
+        std::fs::remove_file(&token_file).unwrap();
+        assert!(res.is_ok());
+        let tokens: Vec<String> = [requests.recv().await, requests.recv().await]
+            .into_iter()
+            .map(|request| {
+                request
+                    .unwrap()
+                    .header("authorization")
+                    .unwrap()
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(tokens, ["Token expired", "Token rotated"]);
+    }
+
+    #[tokio::test]
+    async fn rejected_token_without_file_is_an_auth_error() {
+        let (addr, _requests) = serve_with(|_| "401 Unauthorized").await;
+        let client = line_protocol_client(addr, 2000);
+        let entry = RemoteEntry::new(Box::new(client), RemoteOptions::default());
+        let res = send_data_to_remote("influx", &entry, &batch()).await;
+        assert!(matches!(res, Err(RemoteError::AuthError)));
+    }
+
+    #[test]
+    fn numeric_fields_are_coerced_to_one_type() {
+        let coerce = |field_type| {
+            [Type::SignedInteger(3), Type::Float(21.5)].map(|value| coerce_field(value, field_type))
+        };
+        assert!(matches!(
+            coerce(FieldType::Float),
+            [Type::Float(3.0), Type::Float(21.5)]
+        ));
+        assert!(matches!(
+            coerce(FieldType::Int),
+            [Type::SignedInteger(3), Type::SignedInteger(22)]
+        ));
+        assert!(matches!(
+            coerce(FieldType::String),
+            [Type::Text(int), Type::Text(float)] if int == "3" && float == "21.5"
+        ));
+    }
+
+    #[tokio::test]
+    async fn int_and_float_fields_are_written_as_the_coerced_type() {
+        let (addr, mut requests) = serve_with(|_| "204 No Content").await;
+        let config = serde_json::json!({
+            "remote": format!("http://{addr}"),
+            "bucket": "plant",
+            "token": "secret",
+            "mode": "line_protocol",
+            "coerce_numeric_to": "float",
+        });
+        let client: InfluxDBClient = serde_json::from_value::<InfluxDBRemote>(config)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        let values = HashMap::from([
+            ("pressure".to_string(), RegisterValue::Int(3)),
+            ("temperature".to_string(), RegisterValue::Float(21.5)),
+        ]);
+        let data = HashMap::from([("press1".to_string(), Measurement::new(values))]);
+        client.send_batch(&data).await.unwrap();
+
+        let body = String::from_utf8(requests.recv().await.unwrap().body).unwrap();
+        assert!(
+            body.starts_with("press1 pressure=3,temperature=21.5 "),
+            "{body}"
+        );
+    }
+
+    #[tokio::test]
+    async fn gzip_lines_decompress_to_the_line_protocol() {
+        let (addr, mut requests) = serve_with(|_| "204 No Content").await;
+        let config = serde_json::json!({
+            "remote": format!("http://{addr}"),
+            "bucket": "plant",
+            "token": "secret",
+            "mode": "line_protocol",
+            "compression": "gzip",
+        });
+        let client: InfluxDBClient = serde_json::from_value::<InfluxDBRemote>(config)
+            .unwrap()
+            .try_into()
+            .unwrap();
+        client.send_batch(&batch()).await.unwrap();
+
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request.header("content-encoding"), Some("gzip"));
+        let body = String::from_utf8(gunzip(&request.body)).unwrap();
+        assert!(body.starts_with("press1 temp=21.5 "), "{body}");
+    }
+
+    #[test]
+    fn compression_is_rejected_with_the_query_builder() {
+        let config = serde_json::json!({
+            "remote": "http://localhost:8086",
+            "bucket": "plant",
+            "token": "secret",
+            "compression": "gzip",
+        });
+        let config: InfluxDBRemote = serde_json::from_value(config).unwrap();
+        assert!(matches!(
+            InfluxDBClient::try_from(config),
+            Err(RemoteInitError::InitialisationError { .. })
+        ));
+    }
+
+    #[test]
+    fn invalid_retention_is_rejected_before_provisioning() {
+        let config = serde_json::json!({
+            "remote": "http://localhost:8086",
+            "bucket": "plant",
+            "token": "secret",
+            "create_bucket": true,
+            "retention": "30 days",
+        });
+        let config: InfluxDBRemote = serde_json::from_value(config).unwrap();
+        assert!(matches!(
+            InfluxDBClient::try_from(config),
+            Err(RemoteInitError::ParsingFailed { .. })
+        ));
     }
 }
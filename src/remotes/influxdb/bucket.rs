@@ -0,0 +1,375 @@
+use std::thread;
+
+use log::info;
+use reqwest::{
+    blocking::{Client, RequestBuilder},
+    StatusCode,
+};
+use serde_json::{json, Value};
+use url::Url;
+
+use crate::remotes::errors::RemoteInitError;
+
+/// Where the bucket is provisioned, and how
+///
+/// # Fields
+/// - `remote`: The url of InfluxDB, without the trailing slash.
+/// - `bucket`: The bucket (InfluxDB 2.x) or database (InfluxDB 1.x) to create.
+/// - `org`: The organization of the bucket, the 2.x API is used when set, the 1.x one otherwise.
+/// - `token`: The token sent in the `Authorization` header.
+/// - `retention`: How long the data is kept, in seconds (forever when `None`).
+pub struct BucketSpec {
+    pub remote: String,
+    pub bucket: String,
+    pub org: Option<String>,
+    pub token: String,
+    pub retention: Option<u64>,
+}
+
+/// Parse a retention duration, a number followed by its unit (`s`, `m`, `h`, `d` or `w`, e.g. `30d`)
+///
+/// # Arguments
+///
+/// - `retention` (`&str`) - The duration
+///
+/// # Returns
+///
+/// - `Option<u64>` - The duration in seconds, `None` if it is not a valid duration
+pub fn parse_retention(retention: &str) -> Option<u64> {
+    let retention = retention.trim();
+    let split = retention.find(|c: char| !c.is_ascii_digit())?;
+    let (count, unit) = retention.split_at(split);
+    let unit = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        "d" => 86400,
+        "w" => 604800,
+        _ => return None,
+    };
+    count.parse::<u64>().ok()?.checked_mul(unit)
+}
+
+/// Why the bucket could not be provisioned, sent back from the thread of the blocking client
+/// (`RemoteInitError` can't cross threads)
+enum BucketError {
+    /// InfluxDB rejected the token
+    Denied,
+    /// InfluxDB refused a request, or could not be reached
+    Failed(String),
+}
+
+impl From<reqwest::Error> for BucketError {
+    fn from(value: reqwest::Error) -> Self {
+        BucketError::Failed(value.to_string())
+    }
+}
+
+impl From<url::ParseError> for BucketError {
+    fn from(value: url::ParseError) -> Self {
+        BucketError::Failed(value.to_string())
+    }
+}
+
+impl BucketSpec {
+    /// Create the bucket if it doesn't exist yet.
+    /// The blocking client runs on its own thread to stay out of the async runtime
+    ///
+    /// # Returns
+    ///
+    /// - `Result<(), RemoteInitError>` - `BucketCreationDenied` if the token isn't allowed to list or create the buckets
+    pub fn ensure(self) -> Result<(), RemoteInitError> {
+        let bucket = self.bucket.clone();
+        let res = thread::spawn(move || {
+            let client = Client::new();
+            match &self.org {
+                Some(org) => self.ensure_v2(&client, org),
+                None => self.ensure_v1(&client),
+            }
+        })
+        .join()
+        .map_err(|_| BucketError::Failed("the bucket creation thread panicked".to_string()));
+        match res.and_then(|res| res) {
+            Ok(()) => Ok(()),
+            Err(BucketError::Denied) => Err(RemoteInitError::BucketCreationDenied { bucket }),
+            Err(BucketError::Failed(reason)) => {
+                Err(RemoteInitError::BucketCreationFailed { bucket, reason })
+            }
+        }
+    }
+
+    /// Send a request with the token, the response body is read as JSON
+    fn send(&self, request: RequestBuilder) -> Result<(StatusCode, Value), BucketError> {
+        let res = request
+            .header("Authorization", format!("Token {}", self.token))
+            .send()?;
+        let status = res.status();
+        if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            return Err(BucketError::Denied);
+        }
+        let body = res.text()?;
+        Ok((
+            status,
+            serde_json::from_str(&body).unwrap_or(Value::String(body)),
+        ))
+    }
+
+    /// The error of a request InfluxDB refused
+    fn failed(status: StatusCode, body: &Value) -> BucketError {
+        BucketError::Failed(format!("{status} {body}"))
+    }
+
+    /// Look the bucket up with the 2.x API, create it in the organization if missing
+    fn ensure_v2(&self, client: &Client, org: &str) -> Result<(), BucketError> {
+        let mut url = Url::parse(&format!("{}/api/v2/buckets", self.remote))?;
+        url.query_pairs_mut()
+            .append_pair("name", &self.bucket)
+            .append_pair("org", org);
+        let (status, body) = self.send(client.get(url))?;
+        let exists = match status {
+            StatusCode::NOT_FOUND => false,
+            status if status.is_success() => body["buckets"]
+                .as_array()
+                .is_some_and(|buckets| !buckets.is_empty()),
+            status => return Err(Self::failed(status, &body)),
+        };
+        if exists {
+            return Ok(());
+        }
+
+        let mut url = Url::parse(&format!("{}/api/v2/orgs", self.remote))?;
+        url.query_pairs_mut().append_pair("org", org);
+        let (status, body) = self.send(client.get(url))?;
+        let Some(org_id) = body["orgs"][0]["id"]
+            .as_str()
+            .filter(|_| status.is_success())
+        else {
+            return Err(BucketError::Failed(format!(
+                "organization {org} not found ({status})"
+            )));
+        };
+
+        // No retention rule keeps the data forever
+        let rules: Vec<Value> = self
+            .retention
+            .map(|seconds| json!({"type": "expire", "everySeconds": seconds}))
+            .into_iter()
+            .collect();
+        let bucket = json!({"orgID": org_id, "name": self.bucket, "retentionRules": rules});
+        let request = client
+            .post(format!("{}/api/v2/buckets", self.remote))
+            .header("Content-Type", "application/json")
+            .body(bucket.to_string());
+        let (status, body) = self.send(request)?;
+        if !status.is_success() {
+            return Err(Self::failed(status, &body));
+        }
+        info!("Created the bucket {} in {org}", self.bucket);
+        Ok(())
+    }
+
+    /// Run an InfluxQL query, the error of its statement (e.g. not authorized) is returned as a failure
+    fn query(&self, client: &Client, query: &str) -> Result<Value, BucketError> {
+        let request = client
+            .post(format!("{}/query", self.remote))
+            .form(&[("q", query)]);
+        let (status, body) = self.send(request)?;
+        let error = body["results"][0]["error"]
+            .as_str()
+            .or(body["error"].as_str());
+        match error {
+            Some(err) if err.contains("authoriz") => Err(BucketError::Denied),
+            Some(_) => Err(Self::failed(status, &body)),
+            None if !status.is_success() => Err(Self::failed(status, &body)),
+            None => Ok(body),
+        }
+    }
+
+    /// Look the database up with InfluxQL (1.x), create it with its retention if missing
+    fn ensure_v1(&self, client: &Client) -> Result<(), BucketError> {
+        let body = self.query(client, "SHOW DATABASES")?;
+        let exists = body["results"][0]["series"][0]["values"]
+            .as_array()
+            .is_some_and(|names| names.iter().any(|name| name[0] == self.bucket.as_str()));
+        if exists {
+            return Ok(());
+        }
+
+        let name = self.bucket.replace('\\', "\\\\").replace('"', "\\\"");
+        let query = match self.retention {
+            Some(seconds) => format!("CREATE DATABASE \"{name}\" WITH DURATION {seconds}s"),
+            None => format!("CREATE DATABASE \"{name}\""),
+        };
+        self.query(client, &query)?;
+        info!("Created the database {}", self.bucket);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::mpsc;
+
+    use super::*;
+    use crate::remotes::mock_server::{serve_with_body, Request};
+
+    fn spec(addr: std::net::SocketAddr, org: Option<&str>) -> BucketSpec {
+        BucketSpec {
+            remote: format!("http://{addr}"),
+            bucket: "plant".to_string(),
+            org: org.map(String::from),
+            token: "admin".to_string(),
+            retention: Some(30 * 86400),
+        }
+    }
+
+    /// Provision the bucket, the blocking client kept off the runtime serving the mock API
+    async fn ensure(spec: BucketSpec) -> Result<(), RemoteInitError> {
+        tokio::task::spawn_blocking(move || spec.ensure())
+            .await
+            .unwrap()
+    }
+
+    /// The request lines received by the mock API, with their body
+    fn received(requests: &mut mpsc::UnboundedReceiver<Request>) -> Vec<(String, String)> {
+        std::iter::from_fn(|| requests.try_recv().ok())
+            .map(|request| {
+                let line = request.head.lines().next().unwrap().to_string();
+                (line, String::from_utf8(request.body).unwrap())
+            })
+            .collect()
+    }
+
+    /// A mock of the 2.x API, where the bucket exists if `exists` is set
+    async fn api_v2(exists: bool) -> (std::net::SocketAddr, mpsc::UnboundedReceiver<Request>) {
+        serve_with_body(move |request| {
+            let line = request.head.lines().next().unwrap_or_default();
+            let body = if line.starts_with("GET /api/v2/buckets?") {
+                let buckets = if exists {
+                    vec![json!({"name": "plant"})]
+                } else {
+                    vec![]
+                };
+                json!({"buckets": buckets})
+            } else if line.starts_with("GET /api/v2/orgs?") {
+                json!({"orgs": [{"id": "o1", "name": "site"}]})
+            } else {
+                return ("201 Created", json!({"id": "b1"}).to_string());
+            };
+            ("200 OK", body.to_string())
+        })
+        .await
+    }
+
+    /// A mock of the InfluxQL endpoint, where the database exists if `exists` is set
+    async fn api_v1(exists: bool) -> (std::net::SocketAddr, mpsc::UnboundedReceiver<Request>) {
+        serve_with_body(move |request| {
+            let query = String::from_utf8_lossy(&request.body);
+            let body = if query.contains("SHOW") {
+                let mut names = vec![json!(["_internal"])];
+                if exists {
+                    names.push(json!(["plant"]));
+                }
+                json!({"results": [{"series": [{"name": "databases", "values": names}]}]})
+            } else {
+                json!({"results": [{"statement_id": 0}]})
+            };
+            ("200 OK", body.to_string())
+        })
+        .await
+    }
+
+    #[test]
+    fn retention_is_parsed_in_seconds() {
+        assert_eq!(parse_retention("90s"), Some(90));
+        assert_eq!(parse_retention("30d"), Some(30 * 86400));
+        assert_eq!(parse_retention(" 2w "), Some(2 * 604800));
+        for invalid in ["30", "d", "30y", "-1d", "1.5h"] {
+            assert_eq!(parse_retention(invalid), None, "{invalid}");
+        }
+    }
+
+    #[tokio::test]
+    async fn missing_bucket_is_created_with_its_retention() {
+        let (addr, mut requests) = api_v2(false).await;
+        ensure(spec(addr, Some("site"))).await.unwrap();
+
+        let requests = received(&mut requests);
+        let lines: Vec<&str> = requests.iter().map(|(line, _)| line.as_str()).collect();
+        assert_eq!(
+            lines,
+            [
+                "GET /api/v2/buckets?name=plant&org=site HTTP/1.1",
+                "GET /api/v2/orgs?org=site HTTP/1.1",
+                "POST /api/v2/buckets HTTP/1.1",
+            ]
+        );
+        let bucket: Value = serde_json::from_str(&requests[2].1).unwrap();
+        let rule = json!({"type": "expire", "everySeconds": 2592000});
+        assert_eq!(
+            bucket,
+            json!({"orgID": "o1", "name": "plant", "retentionRules": [rule]})
+        );
+    }
+
+    #[tokio::test]
+    async fn existing_bucket_is_not_created() {
+        let (addr, mut requests) = api_v2(true).await;
+        ensure(spec(addr, Some("site"))).await.unwrap();
+        let requests = received(&mut requests);
+        assert_eq!(requests.len(), 1);
+        assert!(requests[0].0.starts_with("GET /api/v2/buckets?"));
+    }
+
+    #[tokio::test]
+    async fn missing_database_is_created_with_its_duration() {
+        let (addr, mut requests) = api_v1(false).await;
+        ensure(spec(addr, None)).await.unwrap();
+
+        let queries: Vec<String> = received(&mut requests)
+            .into_iter()
+            .map(|(line, body)| {
+                assert_eq!(line, "POST /query HTTP/1.1");
+                url::form_urlencoded::parse(body.as_bytes())
+                    .find(|(key, _)| key == "q")
+                    .unwrap()
+                    .1
+                    .to_string()
+            })
+            .collect();
+        assert_eq!(
+            queries,
+            [
+                "SHOW DATABASES",
+                "CREATE DATABASE \"plant\" WITH DURATION 2592000s"
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn existing_database_is_not_created() {
+        let (addr, mut requests) = api_v1(true).await;
+        ensure(spec(addr, None)).await.unwrap();
+        assert_eq!(received(&mut requests).len(), 1);
+    }
+
+    #[tokio::test]
+    async fn denied_token_is_reported() {
+        let (addr, _requests) = serve_with_body(|_| ("401 Unauthorized", String::new())).await;
+        assert!(matches!(
+            ensure(spec(addr, Some("site"))).await,
+            Err(RemoteInitError::BucketCreationDenied { bucket }) if bucket == "plant"
+        ));
+
+        // InfluxDB 1.x answers the statement with an error
+        let (addr, _requests) = serve_with_body(|_| {
+            let body = json!({"results": [{"error": "user not authorized to execute statement"}]});
+            ("200 OK", body.to_string())
+        })
+        .await;
+        assert!(matches!(
+            ensure(spec(addr, None)).await,
+            Err(RemoteInitError::BucketCreationDenied { .. })
+        ));
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::{BTreeMap, HashMap};
+
+use influxdb::Type;
+
+use super::{coerce_field, FieldType, Precision};
+use crate::measurement::Measurement;
+
+/// Escape the characters of `text` found in `special` with a backslash
+fn escape(text: &str, special: &[char]) -> String {
+    let mut res = String::with_capacity(text.len());
+    for c in text.chars() {
+        if special.contains(&c) {
+            res.push('\\');
+        }
+        res.push(c);
+    }
+    res
+}
+
+/// Escape a measurement name (commas and spaces)
+pub fn escape_measurement(name: &str) -> String {
+    escape(name, &[',', ' '])
+}
+
+/// Escape a tag key, tag value or field key (commas, equal signs and spaces)
+pub fn escape_key(key: &str) -> String {
+    escape(key, &[',', '=', ' '])
+}
+
+/// Format a field value, `None` for the values the line protocol can't represent (NaN, infinities)
+///
+/// # Arguments
+///
+/// - `value` (`Type`) - The value of the field
+///
+/// # Returns
+///
+/// - `Option<String>` - The value as written in the line protocol
+pub fn field_value(value: Type) -> Option<String> {
+    Some(match value {
+        Type::Boolean(val) => val.to_string(),
+        Type::Float(val) if !val.is_finite() => return None,
+        Type::Float(val) => val.to_string(),
+        Type::SignedInteger(val) => format!("{val}i"),
+        Type::UnsignedInteger(val) => format!("{val}i"),
+        Type::Text(val) => format!("\"{}\"", escape(&val, &['"', '\\'])),
+    })
+}
+
+/// Serialize a measurement to a line of the InfluxDB line protocol, tags and fields sorted by key
+///
+/// # Arguments
+///
+/// - `name` (`&str`) - The name of the measurement
+/// - `measurement` (`&Measurement`) - The values, their timestamp and their tags
+/// - `remote_tags` (`&HashMap<String, String>`) - The tags of the remote, the tags of the measurement take precedence
+/// - `force_field_type` (`Option<FieldType>`) - The type all numeric fields are coerced to
+/// - `precision` (`Precision`) - The unit of the timestamp
+///
+/// # Returns
+///
+/// - `Option<String>` - The line (without newline), `None` if there is no field to write
+pub fn line(
+    name: &str,
+    measurement: &Measurement,
+    remote_tags: &HashMap<String, String>,
+    force_field_type: Option<FieldType>,
+    precision: Precision,
+) -> Option<String> {
+    let mut line = escape_measurement(name);
+
+    let mut tags: BTreeMap<&String, &String> = remote_tags.iter().collect();
+    tags.extend(measurement.sorted_tags());
+    for (tag, value) in tags {
+        // Empty tag values are not allowed
+        if value.is_empty() {
+            continue;
+        }
+        line.push_str(&format!(",{}={}", escape_key(tag), escape_key(value)));
+    }
+
+    let fields: Vec<String> = measurement
+        .sorted_values()
+        .into_iter()
+        .filter_map(|(field, value)| {
+            let mut value = Into::<Type>::into(value.clone());
+            if let Some(field_type) = force_field_type {
+                value = coerce_field(value, field_type);
+            }
+            Some(format!("{}={}", escape_key(field), field_value(value)?))
+        })
+        .collect();
+    if fields.is_empty() {
+        return None;
+    }
+    line.push(' ');
+    line.push_str(&fields.join(","));
+
+    line.push_str(&format!(" {}", precision.timestamp(measurement.timestamp)?));
+    Some(line)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types_conversion::RegisterValue;
+
+    #[test]
+    fn line_is_identical_across_runs() {
+        let timestamp = chrono::Utc::now();
+        let lines: Vec<String> = (0..10)
+            .map(|_| {
+                let mut measurement = Measurement::new(HashMap::from([
+                    ("temperature".to_string(), RegisterValue::Float(21.5)),
+                    ("pressure".to_string(), RegisterValue::Int(3)),
+                    ("alarm".to_string(), RegisterValue::Text("none".to_string())),
+                ]));
+                measurement.timestamp = timestamp;
+                measurement.tags = HashMap::from([
+                    ("line".to_string(), "a".to_string()),
+                    ("building".to_string(), "b2".to_string()),
+                ]);
+                let remote_tags = HashMap::from([("site".to_string(), "plant1".to_string())]);
+                line("press1", &measurement, &remote_tags, None, Precision::S).unwrap()
+            })
+            .collect();
+        assert!(lines.iter().all(|line| *line == lines[0]));
+        assert_eq!(
+            lines[0],
+            format!(
+                "press1,building=b2,line=a,site=plant1 alarm=\"none\",pressure=3i,temperature=21.5 {}",
+                timestamp.timestamp()
+            )
+        );
+    }
+
+    #[test]
+    fn line_is_timestamped_with_the_timestamp_register() {
+        let mut measurement = Measurement::new(HashMap::from([
+            ("ts".to_string(), RegisterValue::Int(1_700_000_000)),
+            ("temperature".to_string(), RegisterValue::Float(21.5)),
+        ]));
+        measurement.take_timestamp("ts");
+
+        let written = line("press1", &measurement, &HashMap::new(), None, Precision::S);
+        assert_eq!(written.unwrap(), "press1 temperature=21.5 1700000000");
+        let written = line("press1", &measurement, &HashMap::new(), None, Precision::Ms);
+        assert_eq!(written.unwrap(), "press1 temperature=21.5 1700000000000");
+    }
+}
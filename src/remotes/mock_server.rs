@@ -0,0 +1,105 @@
+//! HTTP server standing in for the remotes in the tests
+
+use std::{net::SocketAddr, sync::Arc};
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+};
+
+/// A request received by the server
+pub struct Request {
+    pub head: String,
+    pub body: Vec<u8>,
+}
+
+/// The value of a header in the head of a request
+fn header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.lines().find_map(|line| {
+        let (key, value) = line.split_once(':')?;
+        key.eq_ignore_ascii_case(name).then(|| value.trim())
+    })
+}
+
+impl Request {
+    /// The value of a header
+    pub fn header(&self, name: &str) -> Option<&str> {
+        header(&self.head, name)
+    }
+}
+
+/// Read a request and its body, `None` once the client closed the connection
+pub async fn read_request(stream: &mut TcpStream) -> Option<Request> {
+    let mut request = Vec::new();
+    let mut buf = [0; 4096];
+    loop {
+        if let Some(end) = request.windows(4).position(|end| end == b"\r\n\r\n") {
+            let head = String::from_utf8_lossy(&request[..end]).to_string();
+            let length: usize = header(&head, "content-length").map_or(0, |l| l.parse().unwrap());
+            if request.len() >= end + 4 + length {
+                let body = request[end + 4..end + 4 + length].to_vec();
+                return Some(Request { head, body });
+            }
+        }
+        match stream.read(&mut buf).await {
+            Ok(0) | Err(_) => return None,
+            Ok(read) => request.extend_from_slice(&buf[..read]),
+        }
+    }
+}
+
+/// Answer every request with `status` (e.g. `204 No Content`), the connections are kept open
+///
+/// # Returns
+///
+/// - `(SocketAddr, mpsc::UnboundedReceiver<Request>)` - The address of the server and the requests it received
+pub async fn serve(status: &'static str) -> (SocketAddr, mpsc::UnboundedReceiver<Request>) {
+    serve_with(move |_| status).await
+}
+
+/// Answer each request with the status `respond` gives for it, the connections are kept open
+///
+/// # Returns
+///
+/// - `(SocketAddr, mpsc::UnboundedReceiver<Request>)` - The address of the server and the requests it received
+pub async fn serve_with(
+    respond: impl Fn(&Request) -> &'static str + Send + Sync + 'static,
+) -> (SocketAddr, mpsc::UnboundedReceiver<Request>) {
+    serve_with_body(move |request| (respond(request), String::new())).await
+}
+
+/// Answer each request with the status and the body `respond` gives for it, the connections are kept open
+///
+/// # Returns
+///
+/// - `(SocketAddr, mpsc::UnboundedReceiver<Request>)` - The address of the server and the requests it received
+pub async fn serve_with_body(
+    respond: impl Fn(&Request) -> (&'static str, String) + Send + Sync + 'static,
+) -> (SocketAddr, mpsc::UnboundedReceiver<Request>) {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (requests, received) = mpsc::unbounded_channel();
+    let respond = Arc::new(respond);
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let requests = requests.clone();
+            let respond = respond.clone();
+            tokio::spawn(async move {
+                while let Some(request) = read_request(&mut stream).await {
+                    let (status, body) = respond(&request);
+                    let response = format!(
+                        "HTTP/1.1 {status}\r\nContent-Length: {}\r\n\r\n{body}",
+                        body.len()
+                    );
+                    if requests.send(request).is_err()
+                        || stream.write_all(response.as_bytes()).await.is_err()
+                    {
+                        return;
+                    }
+                }
+            });
+        }
+    });
+    (addr, received)
+}
@@ -0,0 +1,413 @@
+use std::{
+    collections::HashMap,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use log::{error, info};
+use serde::Deserialize;
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+
+use crate::measurement::Measurement;
+use crate::remotes::errors::RemoteInitError;
+use crate::remotes::remote::{RemoteError, RemoteOptions};
+use crate::remotes::{compose_key, Remote};
+
+/// Function codes served
+const READ_HOLDING_REGISTERS: u8 = 0x03;
+const READ_INPUT_REGISTERS: u8 = 0x04;
+/// Exception codes answered
+const ILLEGAL_FUNCTION: u8 = 0x01;
+const ILLEGAL_DATA_ADDRESS: u8 = 0x02;
+const ILLEGAL_DATA_VALUE: u8 = 0x03;
+/// Maximum number of registers of a read (Modbus application protocol, 6.3)
+const MAX_READ: u16 = 125;
+/// Length of the MBAP header (transaction, protocol, length, unit)
+const MBAP_LEN: usize = 7;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+/// Table of registers a value is exposed in
+pub enum RegisterTable {
+    /// Read with function 0x03
+    #[default]
+    Holding,
+    /// Read with function 0x04
+    Input,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How a value is encoded in the registers, 32 bits values taking two registers, most significant word first
+pub enum ServerType {
+    U16,
+    I16,
+    U32,
+    I32,
+    #[default]
+    F32,
+}
+
+impl ServerType {
+    /// The number of registers taken by a value
+    fn len(&self) -> u16 {
+        match self {
+            ServerType::U16 | ServerType::I16 => 1,
+            ServerType::U32 | ServerType::I32 | ServerType::F32 => 2,
+        }
+    }
+
+    /// Encode a value in registers, the integers being rounded and saturated to the bounds of the type
+    fn encode(&self, value: f64) -> Vec<u16> {
+        let bits = match self {
+            ServerType::U16 => return vec![value.round() as u16],
+            ServerType::I16 => return vec![value.round() as i16 as u16],
+            ServerType::U32 => value.round() as u32,
+            ServerType::I32 => value.round() as i32 as u32,
+            ServerType::F32 => (value as f32).to_bits(),
+        };
+        vec![(bits >> 16) as u16, bits as u16]
+    }
+}
+
+#[derive(Deserialize, Debug, Clone)]
+/// Registers a field is exposed in
+///
+/// # Fields
+/// - `table`: Table of the registers (`holding`, default, or `input`).
+/// - `address`: Address of the first register.
+/// - `kind`: Encoding of the value (`u16`, `i16`, `u32`, `i32` or `f32`, default `f32`), set as `type`.
+/// - `scale`: Factor the value is multiplied by before being encoded (e.g. 10 to keep a decimal in an integer).
+pub struct ServerRegister {
+    #[serde(default)]
+    pub table: RegisterTable,
+    pub address: u16,
+    #[serde(default, rename = "type")]
+    pub kind: ServerType,
+    pub scale: Option<f64>,
+}
+
+/// Content of the registers exposed, keyed by table and address
+type Tables = HashMap<(RegisterTable, u16), u16>;
+
+/// Modbus TCP server remote, exposing the last value of the mapped fields in registers,
+/// for the clients that can only pull data over Modbus (e.g. a SCADA)
+///
+/// # Fields
+/// - `registers`: The registers of each field, keyed by `source{key_separator}field`.
+/// - `tables`: The content of the registers, shared with the server task.
+/// - `key_separator`: The separator between the source and the field of the keys.
+pub struct ModbusServer {
+    pub registers: HashMap<String, ServerRegister>,
+    pub tables: Arc<Mutex<Tables>>,
+    pub key_separator: String,
+}
+
+#[async_trait]
+impl Remote for ModbusServer {
+    /// Updates the registers of the mapped fields of the measurement, the other fields are ignored
+    ///
+    /// Returns
+    /// - `Ok(())` always, the clients read the registers on their own schedule.
+    async fn send_measurement(
+        &self,
+        name: &str,
+        measurement: &Measurement,
+    ) -> Result<(), RemoteError> {
+        let mut tables = self.tables.lock().unwrap();
+        for (field, value) in &measurement.values {
+            let Some(register) = self
+                .registers
+                .get(&compose_key(name, field, &self.key_separator))
+            else {
+                continue;
+            };
+            if value.is_text() {
+                continue;
+            }
+            let value = value.as_f64() * register.scale.unwrap_or(1.0);
+            for (offset, word) in register.kind.encode(value).into_iter().enumerate() {
+                tables.insert((register.table, register.address + offset as u16), word);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Answer a request of a client
+///
+/// # Arguments
+///
+/// - `tables` (`&Tables`) - The content of the registers
+/// - `pdu` (`&[u8]`) - The request, function code first
+///
+/// # Returns
+///
+/// - `Result<Vec<u8>, u8>` - The response, function code first, or the exception code
+fn respond(tables: &Tables, pdu: &[u8]) -> Result<Vec<u8>, u8> {
+    let function = pdu[0];
+    let table = match function {
+        READ_HOLDING_REGISTERS => RegisterTable::Holding,
+        READ_INPUT_REGISTERS => RegisterTable::Input,
+        _ => return Err(ILLEGAL_FUNCTION),
+    };
+    if pdu.len() != 5 {
+        return Err(ILLEGAL_DATA_VALUE);
+    }
+    let start = u16::from_be_bytes([pdu[1], pdu[2]]);
+    let count = u16::from_be_bytes([pdu[3], pdu[4]]);
+    if count == 0 || count > MAX_READ {
+        return Err(ILLEGAL_DATA_VALUE);
+    }
+    let mut res = vec![function, (count * 2) as u8];
+    for offset in 0..count {
+        let address = start.checked_add(offset).ok_or(ILLEGAL_DATA_ADDRESS)?;
+        let word = tables.get(&(table, address)).ok_or(ILLEGAL_DATA_ADDRESS)?;
+        res.extend_from_slice(&word.to_be_bytes());
+    }
+    Ok(res)
+}
+
+/// Serve the requests of a client until it disconnects
+async fn serve_client(mut stream: TcpStream, tables: Arc<Mutex<Tables>>) -> std::io::Result<()> {
+    loop {
+        let mut header = [0u8; MBAP_LEN];
+        stream.read_exact(&mut header).await?;
+        // The length counts the unit identifier, which is in the header
+        let len = u16::from_be_bytes([header[4], header[5]]) as usize;
+        if len < 2 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "Modbus frame without function code",
+            ));
+        }
+        let mut pdu = vec![0u8; len - 1];
+        stream.read_exact(&mut pdu).await?;
+
+        let answer = {
+            let tables = tables.lock().unwrap();
+            respond(&tables, &pdu)
+        };
+        let answer = match answer {
+            Ok(answer) => answer,
+            Err(exception) => vec![pdu[0] | 0x80, exception],
+        };
+        let mut frame = header[..4].to_vec();
+        frame.extend_from_slice(&(answer.len() as u16 + 1).to_be_bytes());
+        frame.push(header[6]);
+        frame.extend_from_slice(&answer);
+        stream.write_all(&frame).await?;
+    }
+}
+
+/// Accept the clients of the server, each one served in its own task
+async fn serve(listener: TcpListener, tables: Arc<Mutex<Tables>>) {
+    loop {
+        let (stream, client) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                error!("Could not accept a Modbus client ({err})");
+                continue;
+            }
+        };
+        info!("Modbus client {client} connected");
+        let tables = tables.clone();
+        tokio::task::spawn(async move {
+            if let Err(err) = serve_client(stream, tables).await {
+                info!("Modbus client {client} disconnected ({err})");
+            }
+        });
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// strucure that represent the config for the Modbus TCP server remote
+///
+/// # Fields
+///
+/// - `listen` (`String`) - the address the server listens on (e.g. `0.0.0.0:502`)
+/// - `registers` (`HashMap<String, ServerRegister>`) - the registers of each field, keyed by `source.field`
+///   (with the `key_separator` of the options)
+/// - `options` (`RemoteOptions`) - the options shared by all remotes
+pub struct ModbusServerRemote {
+    pub listen: String,
+    pub registers: HashMap<String, ServerRegister>,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
+}
+
+impl TryFrom<ModbusServerRemote> for ModbusServer {
+    type Error = RemoteInitError;
+
+    fn try_from(value: ModbusServerRemote) -> Result<Self, Self::Error> {
+        let mut tables = Tables::new();
+        for (key, register) in &value.registers {
+            for offset in 0..register.kind.len() {
+                let address = register.address.checked_add(offset).ok_or_else(|| {
+                    RemoteInitError::ParsingFailed {
+                        reason: format!("{key} goes past the last register"),
+                    }
+                })?;
+                if tables.insert((register.table, address), 0).is_some() {
+                    return Err(RemoteInitError::ParsingFailed {
+                        reason: format!("{key} overlaps another field at address {address}"),
+                    });
+                }
+            }
+        }
+
+        let addr: SocketAddr = value.listen.parse()?;
+        let listener = std::net::TcpListener::bind(addr).and_then(|listener| {
+            listener.set_nonblocking(true)?;
+            TcpListener::from_std(listener)
+        })?;
+        info!("Modbus server listening on {addr}");
+
+        let tables = Arc::new(Mutex::new(tables));
+        tokio::task::spawn(serve(listener, tables.clone()));
+        Ok(ModbusServer {
+            registers: value.registers,
+            tables,
+            key_separator: value.options.key_separator().to_string(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::types_conversion::RegisterValue;
+
+    /// A server exposing `press1.pressure` as a `u16` scaled by 10 and `press1.temperature` as a `f32`
+    fn server() -> (ModbusServer, SocketAddr) {
+        // A port no socket is bound to
+        let free = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = free.local_addr().unwrap();
+        drop(free);
+        let config = serde_json::json!({
+            "listen": addr.to_string(),
+            "registers": {
+                "press1.pressure": {"address": 0, "type": "u16", "scale": 10.0},
+                "press1.temperature": {"table": "input", "address": 10},
+            },
+        });
+        let remote: ModbusServerRemote = serde_json::from_value(config).unwrap();
+        (remote.try_into().unwrap(), addr)
+    }
+
+    /// Read registers with a client, returning the words or the exception code
+    async fn read(
+        stream: &mut TcpStream,
+        function: u8,
+        start: u16,
+        count: u16,
+    ) -> Result<Vec<u16>, u8> {
+        let mut frame = vec![0, 1, 0, 0, 0, 6, 1, function];
+        frame.extend_from_slice(&start.to_be_bytes());
+        frame.extend_from_slice(&count.to_be_bytes());
+        stream.write_all(&frame).await.unwrap();
+        let mut header = [0u8; MBAP_LEN];
+        stream.read_exact(&mut header).await.unwrap();
+        let mut pdu = vec![0u8; u16::from_be_bytes([header[4], header[5]]) as usize - 1];
+        stream.read_exact(&mut pdu).await.unwrap();
+        if pdu[0] & 0x80 != 0 {
+            return Err(pdu[1]);
+        }
+        Ok(pdu[2..]
+            .chunks(2)
+            .map(|word| u16::from_be_bytes([word[0], word[1]]))
+            .collect())
+    }
+
+    fn measurement(pressure: f64, temperature: f64) -> Measurement {
+        Measurement::new(HashMap::from([
+            ("pressure".to_string(), RegisterValue::Float(pressure)),
+            ("temperature".to_string(), RegisterValue::Float(temperature)),
+            (
+                "state".to_string(),
+                RegisterValue::Text("running".to_string()),
+            ),
+        ]))
+    }
+
+    #[test]
+    fn values_are_encoded_in_registers() {
+        assert_eq!(ServerType::U16.encode(12.6), [13]);
+        assert_eq!(ServerType::I16.encode(-5.0), [0xFFFB]);
+        assert_eq!(ServerType::U32.encode(70000.0), [0x0001, 0x1170]);
+        assert_eq!(ServerType::I32.encode(-2.0), [0xFFFF, 0xFFFE]);
+        assert_eq!(ServerType::F32.encode(21.5), [0x41AC, 0x0000]);
+        // Saturated to the bounds of the type
+        assert_eq!(ServerType::U16.encode(-3.0), [0]);
+        assert_eq!(ServerType::U16.encode(1e6), [u16::MAX]);
+    }
+
+    #[tokio::test]
+    async fn client_reads_the_latest_collected_value() {
+        let (server, addr) = server();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // Mapped registers read 0 until a value is collected
+        assert_eq!(
+            read(&mut stream, READ_HOLDING_REGISTERS, 0, 1).await,
+            Ok(vec![0])
+        );
+
+        server
+            .send_measurement("press1", &measurement(3.21, 21.5))
+            .await
+            .unwrap();
+        assert_eq!(
+            read(&mut stream, READ_HOLDING_REGISTERS, 0, 1).await,
+            Ok(vec![32])
+        );
+        assert_eq!(
+            read(&mut stream, READ_INPUT_REGISTERS, 10, 2).await,
+            Ok(vec![0x41AC, 0x0000])
+        );
+
+        server
+            .send_measurement("press1", &measurement(4.0, 21.5))
+            .await
+            .unwrap();
+        assert_eq!(
+            read(&mut stream, READ_HOLDING_REGISTERS, 0, 1).await,
+            Ok(vec![40])
+        );
+    }
+
+    #[tokio::test]
+    async fn unmapped_reads_are_answered_with_an_exception() {
+        let (_server, addr) = server();
+        let mut stream = TcpStream::connect(addr).await.unwrap();
+        // The temperature is in the input registers only
+        assert_eq!(
+            read(&mut stream, READ_HOLDING_REGISTERS, 10, 2).await,
+            Err(ILLEGAL_DATA_ADDRESS)
+        );
+        assert_eq!(
+            read(&mut stream, READ_INPUT_REGISTERS, 10, 0).await,
+            Err(ILLEGAL_DATA_VALUE)
+        );
+        assert_eq!(read(&mut stream, 0x06, 0, 1).await, Err(ILLEGAL_FUNCTION));
+    }
+
+    #[test]
+    fn overlapping_fields_are_rejected() {
+        let config = serde_json::json!({
+            "listen": "127.0.0.1:0",
+            "registers": {
+                "press1.temperature": {"address": 0},
+                "press1.pressure": {"address": 1, "type": "u16"},
+            },
+        });
+        let remote: ModbusServerRemote = serde_json::from_value(config).unwrap();
+        assert!(matches!(
+            ModbusServer::try_from(remote),
+            Err(RemoteInitError::ParsingFailed { .. })
+        ));
+    }
+}
@@ -1,27 +1,256 @@
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::sync::Mutex;
 
-use prometheus::Gauge;
+use log::{info, warn};
+use prometheus::{Counter, Gauge, IntGauge, Opts};
 use prometheus_push::prometheus_crate::PrometheusMetricsPusher;
 use serde::Deserialize;
 use url::Url;
 
-use crate::remotes::remote::RemoteError;
-use crate::remotes::Remote;
+use crate::measurement::Measurement;
+use crate::remotes::remote::{RemoteError, RemoteOptions};
+use crate::remotes::{is_numeric_field, Remote};
+use crate::types_conversion::RegisterValue;
 
 use async_trait::async_trait;
 
 use super::errors::RemoteInitError;
 
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Prometheus type of the metric a field is pushed as
+pub enum MetricType {
+    /// A value that can go up and down
+    #[default]
+    Gauge,
+    /// A monotonic total (energy, cycle count...), so `rate()` and `increase()` can be used
+    Counter,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How the 64 and 128 bits unsigned integers are pushed, Prometheus values being floats
+/// that only hold the integers up to 2^53 exactly
+pub enum WideIntegers {
+    /// As their value, rounded above 2^53 (a warning is logged once per field)
+    #[default]
+    Float,
+    /// As a `{field}_info` gauge set to 1, the exact value being its `value` label
+    Label,
+}
+
+/// Names of the usual units in metric names, following the Prometheus conventions (`_celsius`, `_watts`...)
+const UNIT_NAMES: [(&str, &str); 16] = [
+    ("°C", "celsius"),
+    ("°F", "fahrenheit"),
+    ("K", "kelvin"),
+    ("%", "percent"),
+    ("W", "watts"),
+    ("kW", "kilowatts"),
+    ("Wh", "watt_hours"),
+    ("kWh", "kilowatt_hours"),
+    ("V", "volts"),
+    ("A", "amperes"),
+    ("Hz", "hertz"),
+    ("s", "seconds"),
+    ("ms", "milliseconds"),
+    ("Pa", "pascals"),
+    ("m", "meters"),
+    ("rpm", "rpm"),
+];
+
+/// The suffix of the metrics of a field with this unit: the name of a usual unit (`°C` gives `celsius`),
+/// any other unit lowercased with the characters not allowed in a metric name replaced (`m3/h` gives `m3_h`)
+///
+/// # Arguments
+///
+/// - `unit` (`&str`) - The unit of the field
+///
+/// # Returns
+///
+/// - `String` - The suffix, without the leading underscore, empty if nothing is left of the unit
+pub fn unit_suffix(unit: &str) -> String {
+    if let Some((_, name)) = UNIT_NAMES.iter().find(|(symbol, _)| *symbol == unit) {
+        return name.to_string();
+    }
+    let suffix: String = unit
+        .to_lowercase()
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() {
+            true => c,
+            false => '_',
+        })
+        .collect();
+    suffix.trim_matches('_').to_string()
+}
+
+/// The name of the metric of a field, suffixed with its unit if it has one and does not already end with it
+///
+/// # Arguments
+///
+/// - `metric_name` (`&str`) - The name of the metric built from the field
+/// - `unit` (`Option<&String>`) - The unit of the field
+///
+/// # Returns
+///
+/// - `String` - The name of the metric
+pub fn with_unit(metric_name: &str, unit: Option<&String>) -> String {
+    let suffix = unit.map(|unit| unit_suffix(unit)).unwrap_or_default();
+    match suffix.is_empty() || metric_name.ends_with(&format!("_{suffix}")) {
+        true => metric_name.to_string(),
+        false => format!("{metric_name}_{suffix}"),
+    }
+}
+
+/// The help of the metric of a field, the field name followed by its unit if it has one (`temperature (°C)`)
+fn help(field: &str, unit: Option<&String>) -> String {
+    match unit {
+        Some(unit) => format!("{field} ({unit})"),
+        None => field.to_string(),
+    }
+}
+
+/// A metric pushed to the pushgateway
+///
+/// # Fields
+/// - `help`: The name of the field the metric was built from, with its unit.
+/// - `value`: The value of the metric.
+/// - `labels`: The tags of the measurement the value was part of.
+/// - `kind`: The type of the metric.
+#[derive(Clone)]
+struct Metric {
+    help: String,
+    value: f64,
+    labels: HashMap<String, String>,
+    kind: MetricType,
+    /// Pushed as an `IntGauge` (a gauge of an integer value, see `as_integer`)
+    integer: bool,
+}
+
+/// The last value read for a counter and what was added to keep it monotonic across device resets
+struct CounterState {
+    last: f64,
+    offset: f64,
+}
+
+/// Prometheus pushgateway remote
+///
+/// # Fields
+/// - `pusher`: The client of the pushgateway.
+/// - `retained`: The last metrics pushed for each job, when they are retained between pushes.
+/// - `metric_types`: The type of the fields that are not gauges, keyed by field name.
+/// - `counters`: The state of the counters, keyed by job and field name.
+/// - `wide_integers`: How the 64 and 128 bits unsigned integers are pushed.
+/// - `rounded`: The fields already reported as rounded, keyed by job and field name.
+pub struct PrometheusPusher {
+    pub pusher: PrometheusMetricsPusher,
+    retained: Option<Mutex<HashMap<String, HashMap<String, Metric>>>>,
+    metric_types: HashMap<String, MetricType>,
+    counters: Mutex<HashMap<(String, String), CounterState>>,
+    wide_integers: WideIntegers,
+    rounded: Mutex<HashSet<(String, String)>>,
+}
+
+impl PrometheusPusher {
+    /// The value to push for a counter, kept monotonic: when the device value decreases (the device counter
+    /// was reset), the last value is added to the following ones
+    ///
+    /// # Arguments
+    ///
+    /// - `job` (`&str`) - The job the counter is pushed to
+    /// - `field` (`&str`) - The field of the counter
+    /// - `value` (`f64`) - The value read on the device
+    ///
+    /// # Returns
+    ///
+    /// - `Option<f64>` - The value to push, `None` if the value can't be a counter (negative or NaN)
+    fn counter_value(&self, job: &str, field: &str, value: f64) -> Option<f64> {
+        if value.is_nan() || value < 0.0 {
+            warn!("{field} of {job} can't be pushed as a counter ({value})");
+            return None;
+        }
+        let mut counters = self.counters.lock().unwrap();
+        let state = counters
+            .entry((job.to_string(), field.to_string()))
+            .or_insert(CounterState {
+                last: value,
+                offset: 0.0,
+            });
+        if value < state.last {
+            info!("{field} of {job} was reset ({} -> {value})", state.last);
+            state.offset += state.last;
+        }
+        state.last = value;
+        Some(value + state.offset)
+    }
+
+    /// Warn the first time a field of a job is pushed rounded (an integer above 2^53)
+    fn warn_rounded(&self, job: &str, field: &str, value: &RegisterValue) {
+        if value.is_exact_f64() {
+            return;
+        }
+        let mut rounded = self.rounded.lock().unwrap();
+        if rounded.insert((job.to_string(), field.to_string())) {
+            warn!(
+                "{field} of {job} is above 2^53 and pushed rounded ({}), set `wide_integers: label` to push the exact value",
+                Into::<String>::into(value.clone())
+            );
+        }
+    }
+
+    /// Merge the metrics of a push into the ones retained for the job
+    ///
+    /// # Arguments
+    ///
+    /// - `job` (`&str`) - The job the metrics are pushed to
+    /// - `metrics` (`HashMap<String, Metric>`) - The metrics of the measurement, keyed by metric name
+    ///
+    /// # Returns
+    ///
+    /// - `HashMap<String, Metric>` - The metrics to push, the retained ones absent from the measurement
+    ///   come with a `{metric}_stale` metric set to 1 (0 for the fresh ones)
+    fn retain(&self, job: &str, metrics: HashMap<String, Metric>) -> HashMap<String, Metric> {
+        let retained = match &self.retained {
+            Some(retained) => retained,
+            None => return metrics,
+        };
+        let mut retained = retained.lock().unwrap();
+        let last = retained.entry(job.to_string()).or_default();
+        let fresh: Vec<String> = metrics.keys().cloned().collect();
+        last.extend(metrics);
+        let mut res = last.clone();
+        for (name, metric) in last.iter() {
+            let stale = !fresh.contains(name);
+            res.insert(
+                format!("{name}_stale"),
+                Metric {
+                    help: format!("Whether {} is the last known value", metric.help),
+                    value: match stale {
+                        true => 1.0,
+                        false => 0.0,
+                    },
+                    labels: metric.labels.clone(),
+                    kind: MetricType::Gauge,
+                    integer: false,
+                },
+            );
+        }
+        res
+    }
+}
+
 #[async_trait]
-impl Remote for PrometheusMetricsPusher {
+impl Remote for PrometheusPusher {
     /// Sends a measurement to the remote prometheus instance.
     ///
-    /// Builds an prometheus query using the current local timestamp and
-    /// appends all provided register values as fields of the measurement.
+    /// Builds an prometheus query (the pushgateway does not keep the timestamp) and
+    /// appends all provided register values as fields of the measurement, labelled with its tags.
+    /// The metrics of the fields with a unit are suffixed with it (see [`with_unit`]), the unit is also in their help.
+    /// With `retain`, the fields absent from the measurement are pushed again at their last value.
     ///
     /// Parameters
     /// - `name`: the name of the measurement (prometheus series name).
-    /// - `values`: a map of field names to `RegisterValue`s that will be
+    /// - `measurement`: a map of field names to `RegisterValue`s that will be
     ///   converted and stored as fields in the measurement.
     ///
     /// Returns
@@ -34,17 +263,73 @@ impl Remote for PrometheusMetricsPusher {
     async fn send_measurement(
         &self,
         name: &str,
-        values: &std::collections::HashMap<String, crate::types_conversion::RegisterValue>,
+        measurement: &Measurement,
     ) -> Result<(), RemoteError> {
+        let metrics = measurement
+            .values
+            .iter()
+            .filter(|(field, value)| is_numeric_field(name, field, value))
+            .filter_map(|(field, value)| {
+                let unit = measurement.units.get(field);
+                let metric_name =
+                    with_unit(&field.replace(&['-', '/', '[', ']', '%'][..], "_"), unit);
+                if self.wide_integers == WideIntegers::Label && value.is_wide_integer() {
+                    let mut labels = measurement.tags.clone();
+                    labels.insert("value".to_string(), value.clone().into());
+                    let metric = Metric {
+                        help: help(field, unit),
+                        value: 1.0,
+                        labels,
+                        kind: MetricType::Gauge,
+                        integer: false,
+                    };
+                    return Some((format!("{metric_name}_info"), metric));
+                }
+                self.warn_rounded(name, field, value);
+                let kind = self.metric_types.get(field).copied().unwrap_or_default();
+                let integer = kind == MetricType::Gauge && matches!(value, RegisterValue::Int(_));
+                let value = match kind {
+                    MetricType::Gauge => value.clone().into(),
+                    MetricType::Counter => self.counter_value(name, field, value.as_f64())?,
+                };
+                Some((
+                    metric_name,
+                    Metric {
+                        help: help(field, unit),
+                        value,
+                        labels: measurement.tags.clone(),
+                        kind,
+                        integer,
+                    },
+                ))
+            })
+            .collect();
+        let metrics = self.retain(name, metrics);
+
         let registry = prometheus::Registry::new();
-        for (field, value) in values {
-            let gauge =
-                Gauge::new(field.replace(&['-', '/', '[', ']', '%'][..], "_"), field).unwrap();
-            gauge.set(value.clone().into());
-            registry.register(Box::new(gauge)).unwrap();
+        for (metric_name, metric) in metrics {
+            let opts = Opts::new(metric_name, metric.help).const_labels(metric.labels);
+            match metric.kind {
+                MetricType::Gauge if metric.integer => {
+                    let gauge = IntGauge::with_opts(opts).unwrap();
+                    gauge.set(metric.value as i64);
+                    registry.register(Box::new(gauge)).unwrap();
+                }
+                MetricType::Gauge => {
+                    let gauge = Gauge::with_opts(opts).unwrap();
+                    gauge.set(metric.value);
+                    registry.register(Box::new(gauge)).unwrap();
+                }
+                MetricType::Counter => {
+                    let counter = Counter::with_opts(opts).unwrap();
+                    counter.inc_by(metric.value);
+                    registry.register(Box::new(counter)).unwrap();
+                }
+            }
         }
 
-        self.push_all(name, &HashMap::new(), registry.gather())
+        self.pusher
+            .push_all(name, &HashMap::new(), registry.gather())
             .await?;
 
         Ok(())
@@ -52,16 +337,145 @@ impl Remote for PrometheusMetricsPusher {
 }
 
 #[derive(Deserialize, Debug)]
+/// strucure that represent the config for the Prometheus pushgateway remote
+///
+/// # Fields
+///
+/// - `remote` (`String`) - the url of the pushgateway
+/// - `retain` (`bool`) - keep pushing the fields absent from a measurement at their last value,
+///   with a `{field}_stale` metric set to 1 (default false, absent fields disappear from the pushgateway)
+/// - `metric_types` (`HashMap<String, MetricType>`) - the type of the fields pushed as counters (`counter`)
+///   instead of gauges (`gauge`, default), keyed by field name
+/// - `wide_integers` (`WideIntegers`) - how the 64 and 128 bits unsigned integers are pushed (`float`, default,
+///   rounded above 2^53, or `label`, the exact value as the `value` label of a `{field}_info` gauge)
+/// - `options` (`RemoteOptions`) - the options shared by all remotes
 pub struct PrometheusRemote {
     pub remote: String,
+    #[serde(default)]
+    pub retain: bool,
+    #[serde(default)]
+    pub metric_types: HashMap<String, MetricType>,
+    #[serde(default)]
+    pub wide_integers: WideIntegers,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
 }
 
-impl TryFrom<PrometheusRemote> for PrometheusMetricsPusher {
+impl TryFrom<PrometheusRemote> for PrometheusPusher {
     type Error = RemoteInitError;
 
     fn try_from(value: PrometheusRemote) -> Result<Self, Self::Error> {
         let client = reqwest::Client::new();
         let pusher = PrometheusMetricsPusher::from(client, &Url::parse(&value.remote)?)?;
-        Ok(pusher)
+        Ok(PrometheusPusher {
+            pusher,
+            retained: value.retain.then(|| Mutex::new(HashMap::new())),
+            metric_types: value.metric_types,
+            counters: Mutex::new(HashMap::new()),
+            wide_integers: value.wide_integers,
+            rounded: Mutex::new(HashSet::new()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use industrial_device::types::Value;
+
+    fn pusher(config: serde_json::Value) -> PrometheusPusher {
+        let config: PrometheusRemote = serde_json::from_value(config).unwrap();
+        config.try_into().unwrap()
+    }
+
+    #[test]
+    fn rising_counter_is_pushed_as_read() {
+        let pusher = pusher(serde_json::json!({
+            "remote": "http://localhost:9091",
+            "metric_types": {"energy": "counter"},
+        }));
+        assert_eq!(pusher.metric_types["energy"], MetricType::Counter);
+        let pushed: Vec<_> = [10.0, 12.5, 20.0]
+            .into_iter()
+            .map(|value| pusher.counter_value("meter1", "energy", value))
+            .collect();
+        assert_eq!(pushed, [Some(10.0), Some(12.5), Some(20.0)]);
+    }
+
+    #[test]
+    fn counter_stays_monotonic_across_a_reset() {
+        let pusher = pusher(serde_json::json!({"remote": "http://localhost:9091"}));
+        let pushed: Vec<_> = [100.0, 120.0, 5.0, 8.0]
+            .into_iter()
+            .map(|value| pusher.counter_value("meter1", "energy", value))
+            .collect();
+        assert_eq!(pushed, [Some(100.0), Some(120.0), Some(125.0), Some(128.0)]);
+        // The counters of the other jobs are not offset
+        assert_eq!(pusher.counter_value("meter2", "energy", 3.0), Some(3.0));
+    }
+
+    #[test]
+    fn negative_value_is_not_a_counter() {
+        let pusher = pusher(serde_json::json!({"remote": "http://localhost:9091"}));
+        assert_eq!(pusher.counter_value("meter1", "energy", -1.0), None);
+        assert_eq!(pusher.counter_value("meter1", "energy", f64::NAN), None);
+    }
+
+    /// A measurement of a 128 bits energy counter above 2^53 and of a temperature
+    fn wide_measurement() -> Measurement {
+        let mut measurement = Measurement::new(HashMap::from([
+            (
+                "energy".to_string(),
+                RegisterValue::Raw(Value::U128((1 << 60) + 1)),
+            ),
+            ("temperature".to_string(), RegisterValue::Float(21.5)),
+        ]));
+        measurement.tags.insert("line".to_string(), "a".to_string());
+        measurement
+    }
+
+    /// The metrics pushed for the job, as retained by the pusher
+    fn pushed(pusher: &PrometheusPusher, job: &str) -> HashMap<String, Metric> {
+        pusher.retained.as_ref().unwrap().lock().unwrap()[job].clone()
+    }
+
+    #[tokio::test]
+    async fn wide_integer_is_pushed_rounded_by_default() {
+        let pusher = pusher(serde_json::json!({"remote": "http://localhost:9091", "retain": true}));
+        pusher
+            .send_measurement("meter1", &wide_measurement())
+            .await
+            .unwrap();
+        let metrics = pushed(&pusher, "meter1");
+        assert_eq!(metrics["energy"].value, (1u64 << 60) as f64);
+        assert_eq!(metrics["temperature"].value, 21.5);
+        // Reported once per field
+        assert!(pusher
+            .rounded
+            .lock()
+            .unwrap()
+            .contains(&("meter1".to_string(), "energy".to_string())));
+        assert_eq!(pusher.rounded.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn wide_integer_is_pushed_exact_as_a_label() {
+        let pusher = pusher(serde_json::json!({
+            "remote": "http://localhost:9091",
+            "retain": true,
+            "wide_integers": "label",
+        }));
+        pusher
+            .send_measurement("meter1", &wide_measurement())
+            .await
+            .unwrap();
+        let metrics = pushed(&pusher, "meter1");
+        assert!(!metrics.contains_key("energy"));
+        let info = &metrics["energy_info"];
+        assert_eq!(info.value, 1.0);
+        assert_eq!(info.labels["value"], ((1u128 << 60) + 1).to_string());
+        assert_eq!(info.labels["line"], "a");
+        assert_eq!(metrics["temperature"].value, 21.5);
+        assert!(pusher.rounded.lock().unwrap().is_empty());
     }
 }
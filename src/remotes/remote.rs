@@ -1,20 +1,43 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
-use custom_error::custom_error;
 use prometheus_push::error::PushMetricsError;
+use serde::Deserialize;
+use thiserror::Error;
 
-use crate::types_conversion::RegisterValue;
+use crate::measurement::Measurement;
 
 use async_trait::async_trait;
 
-custom_error! {
-    /// List of error related to the push of the data to the remote
-    pub RemoteError
-    DisconnectedRemoteError = "The remote is not connected",
-    PushFailedError{ res: String } = "There was an error during push : {res}",
-    AuthError = "Authentification error",
-    ServerError = "Server error",
-    QueryError = "Query error",
+/// Default time a remote rejecting the credentials is disabled, in seconds
+const DEFAULT_AUTH_COOLDOWN_S: u64 = 3600;
+
+#[derive(Debug, Error)]
+/// List of error related to the push of the data to the remote
+pub enum RemoteError {
+    #[error("The remote is not connected")]
+    DisconnectedRemoteError,
+    #[error("There was an error during push : {res}")]
+    PushFailedError { res: String },
+    #[error("Authentification error")]
+    AuthError,
+    #[error("Server error")]
+    ServerError,
+    #[error("Query error")]
+    QueryError,
+}
+
+impl RemoteError {
+    /// Whether pushing the same data again may succeed: the remote was unreachable, timed out or
+    /// failed on its side. A rejected query or push, or rejected credentials, fail again until
+    /// the data or the configuration changes.
+    pub fn retryable(&self) -> bool {
+        match self {
+            RemoteError::DisconnectedRemoteError | RemoteError::ServerError => true,
+            RemoteError::PushFailedError { .. }
+            | RemoteError::AuthError
+            | RemoteError::QueryError => false,
+        }
+    }
 }
 
 impl From<PushMetricsError> for RemoteError {
@@ -47,12 +70,128 @@ impl From<influxdb::Error> for RemoteError {
     }
 }
 
+impl From<reqwest::Error> for RemoteError {
+    fn from(value: reqwest::Error) -> Self {
+        match value.is_connect() || value.is_timeout() {
+            true => RemoteError::DisconnectedRemoteError,
+            false => RemoteError::QueryError,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+/// Options shared by every remote, flattened into each remote configuration.
+///
+/// # Fields
+/// - `deduplicate`: Skip the push when the data is identical to the last batch
+///   successfully sent to this remote (default `false`).
+/// - `max_fields_per_push`: Split the batches holding more fields in several pushes,
+///   for the servers rejecting large payloads (default: no limit).
+/// - `flatten`: Gather the fields of all the sources in a single measurement of this name,
+///   keyed `source.field`, for the consumers expecting a single namespace (default: one measurement per source).
+/// - `key_separator`: Separator between the source and the field when they are composed into a single key
+///   (default `.`), to pick one that appears in none of the names.
+/// - `push_timeout_ms`: Maximum duration of a push, a stalled server then fails the push with `ServerError`
+///   instead of holding the remote forever (default: no limit).
+/// - `device_filter`: Only push the sources whose tags match all these tags, a value of `*` only requiring
+///   the tag to be set (default: every source).
+/// - `auth_cooldown_s`: Time during which no push is attempted after the remote rejected the credentials,
+///   unless they change, so the server doesn't lock the account (default 3600, pushed on every cycle when 0).
+pub struct RemoteOptions {
+    #[serde(default)]
+    pub deduplicate: bool,
+    pub max_fields_per_push: Option<usize>,
+    pub flatten: Option<String>,
+    pub key_separator: Option<String>,
+    pub push_timeout_ms: Option<u64>,
+    #[serde(default)]
+    pub device_filter: HashMap<String, String>,
+    pub auth_cooldown_s: Option<u64>,
+}
+
+impl RemoteOptions {
+    /// The separator between the source and the field of the composed keys
+    pub fn key_separator(&self) -> &str {
+        self.key_separator.as_deref().unwrap_or(".")
+    }
+
+    /// Whether the tags of a source match the `device_filter`
+    pub fn selects(&self, tags: &HashMap<String, String>) -> bool {
+        self.device_filter
+            .iter()
+            .all(|(tag, expected)| match tags.get(tag) {
+                Some(value) => expected == "*" || value == expected,
+                None => false,
+            })
+    }
+
+    /// The maximum duration of a push
+    pub fn push_timeout(&self) -> Option<Duration> {
+        self.push_timeout_ms.map(Duration::from_millis)
+    }
+
+    /// The time the remote is disabled after rejecting the credentials, `None` if it is never disabled
+    pub fn auth_cooldown(&self) -> Option<Duration> {
+        match self.auth_cooldown_s.unwrap_or(DEFAULT_AUTH_COOLDOWN_S) {
+            0 => None,
+            secs => Some(Duration::from_secs(secs)),
+        }
+    }
+}
+
 #[async_trait]
 /// Interface to describe the remote where we send all the collected data
-pub trait Remote {
+pub trait Remote: Send + Sync {
     async fn send_measurement(
         &self,
         name: &str,
-        values: &HashMap<String, RegisterValue>,
+        measurement: &Measurement,
     ) -> Result<(), RemoteError>;
+
+    /// Send the measurements of all the sources, one after the other unless the remote can write them at once
+    async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+        for (source, measurement) in data.iter() {
+            self.send_measurement(source, measurement).await?;
+        }
+        Ok(())
+    }
+
+    /// Reload the credentials after a push failed with `AuthError` (e.g. a token rotated while the bridge runs)
+    ///
+    /// # Returns
+    ///
+    /// - `bool` - Whether the credentials changed, the push is then retried once
+    async fn refresh_auth(&mut self) -> bool {
+        false
+    }
+}
+
+/// Read a token from a file, without the surrounding whitespace
+///
+/// # Arguments
+///
+/// - `path` (`&str`) - The file holding the token (e.g. a mounted secret)
+///
+/// # Returns
+///
+/// - `std::io::Result<String>` - The token
+pub fn read_token(path: &str) -> std::io::Result<String> {
+    Ok(std::fs::read_to_string(path)?.trim().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_unreachable_or_failing_remotes_are_retried() {
+        assert!(RemoteError::DisconnectedRemoteError.retryable());
+        assert!(RemoteError::ServerError.retryable());
+        assert!(!RemoteError::PushFailedError {
+            res: "field type conflict".to_string()
+        }
+        .retryable());
+        assert!(!RemoteError::AuthError.retryable());
+        assert!(!RemoteError::QueryError.retryable());
+    }
 }
@@ -0,0 +1,295 @@
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use prost::Message;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use url::Url;
+
+use crate::measurement::Measurement;
+use crate::remotes::errors::RemoteInitError;
+use crate::remotes::prometheus::with_unit;
+use crate::remotes::remote::{RemoteError, RemoteOptions};
+use crate::remotes::{is_numeric_field, Remote};
+
+/// Messages of the Prometheus remote-write (v1) protocol
+pub mod proto {
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct WriteRequest {
+        #[prost(message, repeated, tag = "1")]
+        pub timeseries: Vec<TimeSeries>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct TimeSeries {
+        #[prost(message, repeated, tag = "1")]
+        pub labels: Vec<Label>,
+        #[prost(message, repeated, tag = "2")]
+        pub samples: Vec<Sample>,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Label {
+        #[prost(string, tag = "1")]
+        pub name: String,
+        #[prost(string, tag = "2")]
+        pub value: String,
+    }
+
+    #[derive(Clone, PartialEq, prost::Message)]
+    pub struct Sample {
+        #[prost(double, tag = "1")]
+        pub value: f64,
+        #[prost(int64, tag = "2")]
+        pub timestamp: i64,
+    }
+}
+
+/// Prometheus remote-write remote, pushing to a TSDB endpoint (Prometheus, Mimir, Thanos...)
+pub struct PrometheusRemoteWriter {
+    pub client: reqwest::Client,
+    pub url: Url,
+    pub labels: HashMap<String, String>,
+}
+
+/// Replace the characters that are not allowed in a metric name
+fn metric_name(field: &str) -> String {
+    field
+        .chars()
+        .map(|c| match c.is_ascii_alphanumeric() || c == '_' || c == ':' {
+            true => c,
+            false => '_',
+        })
+        .collect()
+}
+
+impl PrometheusRemoteWriter {
+    /// Build the remote-write request of a measurement, one time series per field
+    ///
+    /// # Arguments
+    ///
+    /// - `name` (`&str`) - The source of the measurement, set as the `source` label
+    /// - `measurement` (`&Measurement`) - The values, their timestamp and their tags (added as labels)
+    ///
+    /// # Returns
+    ///
+    /// - `proto::WriteRequest` - The request, labels sorted by name as required by the protocol
+    pub fn write_request(&self, name: &str, measurement: &Measurement) -> proto::WriteRequest {
+        let timestamp = measurement.timestamp.timestamp_millis();
+        let timeseries = measurement
+            .values
+            .iter()
+            .filter(|(field, value)| is_numeric_field(name, field, value))
+            .map(|(field, value)| {
+                let mut labels: BTreeMap<String, String> = self.labels.clone().into_iter().collect();
+                labels.extend(measurement.tags.clone());
+                labels.insert("source".to_string(), name.to_string());
+                labels.insert(
+                    "__name__".to_string(),
+                    with_unit(&metric_name(field), measurement.units.get(field)),
+                );
+                proto::TimeSeries {
+                    labels: labels
+                        .into_iter()
+                        .map(|(name, value)| proto::Label { name, value })
+                        .collect(),
+                    samples: vec![proto::Sample {
+                        value: value.clone().into(),
+                        timestamp,
+                    }],
+                }
+            })
+            .collect();
+        proto::WriteRequest { timeseries }
+    }
+}
+
+#[async_trait]
+impl Remote for PrometheusRemoteWriter {
+    /// Sends a measurement to the remote-write endpoint.
+    ///
+    /// Each field becomes a time series named after the field (suffixed with its unit), labelled with the
+    /// source and the static labels, holding a single sample at the measurement timestamp.
+    /// The request is encoded as protobuf and compressed with snappy.
+    ///
+    /// Parameters
+    /// - `name`: the source of the measurement (`source` label).
+    /// - `measurement`: the timestamp and a map of field names to `RegisterValue`s.
+    ///
+    /// Returns
+    /// - `Ok(())` if the measurement was successfully pushed.
+    /// - `Err(RemoteError)` if the push failed or the server returned an error.
+    ///
+    /// Errors
+    /// - `RemoteError::AuthError` if the endpoint answered 401 or 403.
+    /// - `RemoteError::ServerError` if the endpoint answered with a 5xx status.
+    /// - `RemoteError::PushFailedError` if the endpoint rejected the request.
+    /// - `RemoteError::DisconnectedRemoteError` if the endpoint could not be reached.
+    async fn send_measurement(
+        &self,
+        name: &str,
+        measurement: &Measurement,
+    ) -> Result<(), RemoteError> {
+        let body = self.write_request(name, measurement).encode_to_vec();
+        let body = match snap::raw::Encoder::new().compress_vec(&body) {
+            Ok(body) => body,
+            Err(err) => {
+                return Err(RemoteError::PushFailedError {
+                    res: err.to_string(),
+                })
+            }
+        };
+
+        let res = self
+            .client
+            .post(self.url.clone())
+            .header("Content-Encoding", "snappy")
+            .header("Content-Type", "application/x-protobuf")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0")
+            .body(body)
+            .send()
+            .await?;
+
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(RemoteError::AuthError),
+            status if status.is_server_error() => Err(RemoteError::ServerError),
+            status => Err(RemoteError::PushFailedError {
+                res: format!("{status} {}", res.text().await.unwrap_or_default()),
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// strucure that represent the config for the Prometheus remote-write remote
+///
+/// # Fields
+///
+/// - `remote` (`String`) - the url of the remote-write endpoint (e.g. `http://mimir:9009/api/v1/push`)
+/// - `labels` (`HashMap<String, String>`) - static labels added to every time series
+/// - `options` (`RemoteOptions`) - the options shared by all remotes
+pub struct PrometheusRemoteWriteRemote {
+    pub remote: String,
+    #[serde(default)]
+    pub labels: HashMap<String, String>,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
+}
+
+impl TryFrom<PrometheusRemoteWriteRemote> for PrometheusRemoteWriter {
+    type Error = RemoteInitError;
+
+    fn try_from(value: PrometheusRemoteWriteRemote) -> Result<Self, Self::Error> {
+        Ok(PrometheusRemoteWriter {
+            client: reqwest::Client::new(),
+            url: Url::parse(&value.remote)?,
+            labels: value.labels,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remotes::mock_server;
+    use crate::types_conversion::RegisterValue;
+
+    #[tokio::test]
+    async fn posted_body_holds_the_samples_and_labels() {
+        let (addr, mut requests) = mock_server::serve("204 No Content").await;
+        let config = serde_json::json!({
+            "remote": format!("http://{addr}/api/v1/push"),
+            "labels": {"site": "plant1"},
+        });
+        let writer: PrometheusRemoteWriter =
+            serde_json::from_value::<PrometheusRemoteWriteRemote>(config)
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let mut measurement = Measurement::new(HashMap::from([(
+            "motor speed".to_string(),
+            RegisterValue::Float(1450.0),
+        )]));
+        measurement.tags.insert("line".to_string(), "a".to_string());
+
+        writer
+            .send_measurement("press1", &measurement)
+            .await
+            .unwrap();
+
+        let request = requests.recv().await.unwrap();
+        assert!(request.head.starts_with("POST /api/v1/push "));
+        assert_eq!(request.header("content-encoding"), Some("snappy"));
+        let body = snap::raw::Decoder::new()
+            .decompress_vec(&request.body)
+            .unwrap();
+        let write_request = proto::WriteRequest::decode(body.as_slice()).unwrap();
+        assert_eq!(write_request.timeseries.len(), 1);
+        let series = &write_request.timeseries[0];
+        let labels: Vec<(&str, &str)> = series
+            .labels
+            .iter()
+            .map(|label| (label.name.as_str(), label.value.as_str()))
+            .collect();
+        assert_eq!(
+            labels,
+            [
+                ("__name__", "motor_speed"),
+                ("line", "a"),
+                ("site", "plant1"),
+                ("source", "press1"),
+            ]
+        );
+        assert_eq!(series.samples[0].value, 1450.0);
+    }
+
+    #[tokio::test]
+    async fn rejected_push_is_mapped_to_a_remote_error() {
+        for (status, retryable) in [
+            ("500 Internal Server Error", true),
+            ("400 Bad Request", false),
+        ] {
+            let (addr, _requests) = mock_server::serve(status).await;
+            let writer = PrometheusRemoteWriter {
+                client: reqwest::Client::new(),
+                url: Url::parse(&format!("http://{addr}/api/v1/push")).unwrap(),
+                labels: HashMap::new(),
+            };
+            let measurement = Measurement::new(HashMap::from([(
+                "speed".to_string(),
+                RegisterValue::Int(3),
+            )]));
+            let err = writer
+                .send_measurement("press1", &measurement)
+                .await
+                .unwrap_err();
+            assert_eq!(err.retryable(), retryable);
+        }
+    }
+
+    #[test]
+    fn samples_carry_the_acquisition_time() {
+        let config = serde_json::json!({"remote": "http://localhost:9090/api/v1/write"});
+        let writer: PrometheusRemoteWriter =
+            serde_json::from_value::<PrometheusRemoteWriteRemote>(config)
+                .unwrap()
+                .try_into()
+                .unwrap();
+        let mut measurement = Measurement::new(HashMap::from([
+            ("temperature".to_string(), RegisterValue::Float(21.5)),
+            ("pressure".to_string(), RegisterValue::Int(3)),
+        ]));
+        // Read a minute before the push, e.g. a batch replayed from the write-ahead log
+        measurement.timestamp = chrono::Utc::now() - chrono::Duration::seconds(60);
+
+        let write_request = writer.write_request("press1", &measurement);
+        assert_eq!(write_request.timeseries.len(), 2);
+        for series in &write_request.timeseries {
+            assert_eq!(
+                series.samples[0].timestamp,
+                measurement.timestamp.timestamp_millis()
+            );
+        }
+    }
+}
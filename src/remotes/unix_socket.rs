@@ -0,0 +1,152 @@
+use std::{collections::HashMap, io, path::Path};
+
+use log::{error, info};
+use tokio::{
+    io::AsyncWriteExt,
+    net::{UnixListener, UnixStream},
+    sync::watch,
+};
+
+use crate::measurement::{batch_to_json, Measurement};
+
+/// Serialize a batch to a line of JSON, keyed by source (sources, tags and fields sorted by name)
+fn batch_line(data: &HashMap<String, Measurement>) -> String {
+    let mut line = batch_to_json(data).to_string();
+    line.push('\n');
+    line
+}
+
+/// Stream the batches to a client until it disconnects
+async fn stream_batches(
+    mut stream: UnixStream,
+    mut data: watch::Receiver<HashMap<String, Measurement>>,
+) {
+    // Only the batches fetched after the client connected are sent
+    data.borrow_and_update();
+    while data.changed().await.is_ok() {
+        let line = batch_line(&data.borrow_and_update());
+        if let Err(err) = stream.write_all(line.as_bytes()).await {
+            info!("Unix socket client disconnected ({err})");
+            return;
+        }
+    }
+}
+
+/// Listen on a Unix domain socket and stream every fetched batch to the connected clients,
+/// as newline-delimited JSON (`{"source": {"timestamp": "<RFC 3339>", "tags": {...}, "values": {...}}}`).
+/// A file left at `path` by a previous run is replaced.
+///
+/// # Parameters
+/// - `path`: Where to create the socket.
+/// - `data`: The [`watch::Receiver`] the batches are sent to the remotes through.
+///
+/// # Returns
+/// - `Err(io::Error)` if the socket could not be created, runs forever otherwise.
+pub async fn serve_unix_socket(
+    path: &str,
+    data: watch::Receiver<HashMap<String, Measurement>>,
+) -> io::Result<()> {
+    if Path::new(path).exists() {
+        std::fs::remove_file(path)?;
+    }
+    let listener = UnixListener::bind(path)?;
+    info!("Streaming the batches on the Unix socket {path}");
+    loop {
+        match listener.accept().await {
+            Ok((stream, _addr)) => {
+                info!("Unix socket client connected");
+                tokio::spawn(stream_batches(stream, data.clone()));
+            }
+            Err(err) => error!("Could not accept a Unix socket client ({err})"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::io::{AsyncBufReadExt, BufReader};
+
+    use super::*;
+    use crate::types_conversion::RegisterValue;
+
+    fn batch(temperature: f64) -> HashMap<String, Measurement> {
+        let values = HashMap::from([("temp".to_string(), RegisterValue::Float(temperature))]);
+        HashMap::from([("press1".to_string(), Measurement::new(values))])
+    }
+
+    /// The next batch streamed to a client, as JSON
+    async fn next_batch(lines: &mut tokio::io::Lines<BufReader<UnixStream>>) -> serde_json::Value {
+        let line = lines.next_line().await.unwrap().unwrap();
+        serde_json::from_str(&line).unwrap()
+    }
+
+    #[tokio::test]
+    async fn connected_clients_read_the_streamed_batches() {
+        let path = std::env::temp_dir().join(format!("bridge-{}.sock", std::process::id()));
+        let path = path.to_str().unwrap().to_string();
+        // A file left by a previous run is replaced
+        std::fs::write(&path, "").unwrap();
+        let (data, receiver) = watch::channel(HashMap::new());
+        let server = tokio::spawn({
+            let path = path.clone();
+            async move { serve_unix_socket(&path, receiver).await }
+        });
+        let connect = || async {
+            loop {
+                match UnixStream::connect(&path).await {
+                    Ok(stream) => return BufReader::new(stream).lines(),
+                    Err(_) => tokio::time::sleep(Duration::from_millis(10)).await,
+                }
+            }
+        };
+        let mut first = connect().await;
+        let mut second = connect().await;
+        // The batches fetched before a client is streamed to are not sent, keep fetching until it is
+        let cycles = tokio::spawn(async move {
+            for cycle in 0.. {
+                data.send(batch(20.0 + cycle as f64)).unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        });
+
+        for client in [&mut first, &mut second] {
+            let batch = next_batch(client).await;
+            assert!(batch["press1"]["values"]["temp"].is_number());
+        }
+        // A client leaving doesn't stop the stream of the others
+        drop(first);
+        let last = next_batch(&mut second).await["press1"]["values"]["temp"].clone();
+        assert_ne!(
+            next_batch(&mut second).await["press1"]["values"]["temp"],
+            last
+        );
+
+        cycles.abort();
+        server.abort();
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn batch_line_is_identical_across_runs() {
+        let timestamp = chrono::Utc::now();
+        let batch = || {
+            let sources = ["press3", "press1", "press2"].map(|source| {
+                let mut measurement = batch(21.5).remove("press1").unwrap();
+                measurement.timestamp = timestamp;
+                (source.to_string(), measurement)
+            });
+            HashMap::from(sources)
+        };
+        let first = batch_line(&batch());
+        for _ in 0..10 {
+            assert_eq!(batch_line(&batch()), first);
+        }
+        let positions: Vec<usize> = ["press1", "press2", "press3"]
+            .iter()
+            .map(|source| first.find(source).unwrap())
+            .collect();
+        assert!(positions.is_sorted());
+    }
+}
@@ -0,0 +1,605 @@
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use chrono::DateTime;
+use industrial_device::types::Value;
+use log::warn;
+
+use crate::measurement::Measurement;
+use crate::types_conversion::RegisterValue;
+
+/// A batch of measurements, keyed by source name
+pub type Batch = HashMap<String, Measurement>;
+
+/// Record holding a batch to push, without the units (written by the previous versions)
+const RECORD_BATCH_NO_UNITS: u8 = 1;
+/// Record marking a batch as pushed to every remote
+const RECORD_ACK: u8 = 2;
+/// Record holding a batch to push, with the units of its measurements
+const RECORD_BATCH: u8 = 3;
+/// Size of a record without its payload (kind, sequence number, length and checksum)
+const RECORD_OVERHEAD: u64 = 21;
+/// Number of batches kept unacknowledged when `wal_max_batches` is not set
+pub const DEFAULT_MAX_BATCHES: usize = 1000;
+
+/// Write-ahead log of the batches pushed to the remotes.
+///
+/// Every batch is appended (and synced to disk) before being pushed, and acknowledged once every remote
+/// accepted it, or once it is dropped (rejected, superseded by a newer batch). The batches that were never
+/// acknowledged (e.g. the bridge crashed while pushing, a remote was down) are replayed on startup.
+/// At most `max_batches` batches are kept unacknowledged, the oldest one is dropped first.
+///
+/// The file is emptied whenever no batch is left unacknowledged, and rewritten with only the unacknowledged
+/// batches once the acknowledged ones take most of it, so it doesn't grow while a remote is down.
+///
+/// Records are `[kind: u8][seq: u64][len: u32][payload: len bytes][checksum: u64]`, little endian,
+/// the checksum (FNV-1a) covering everything before it. A torn record at the end of the file is dropped.
+/// The batches left by a previous version without the units are replayed, and rewritten with the units
+/// (none) on compaction.
+///
+/// # Fields
+/// - `path`: The log file, to rewrite it.
+/// - `file`: The log file.
+/// - `len`: The size of the file.
+/// - `next_seq`: The sequence number of the next batch.
+/// - `pending`: The encoded batches appended but not acknowledged yet, by sequence number.
+/// - `max_batches`: The maximum number of batches left unacknowledged.
+/// - `replay`: The batches left unacknowledged by the previous run, until taken.
+pub struct Wal {
+    path: PathBuf,
+    file: File,
+    len: u64,
+    next_seq: u64,
+    pending: BTreeMap<u64, Vec<u8>>,
+    max_batches: usize,
+    replay: Vec<(u64, Batch)>,
+}
+
+impl Wal {
+    /// Open (or create) the log, and read the batches left unacknowledged by the previous run
+    ///
+    /// # Arguments
+    ///
+    /// - `path` (`&Path`) - The log file
+    /// - `max_batches` (`usize`) - The maximum number of batches left unacknowledged (at least 1)
+    ///
+    /// # Returns
+    ///
+    /// - `io::Result<Wal>` - The log
+    pub fn open(path: &Path, max_batches: usize) -> io::Result<Wal> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        let mut batches: BTreeMap<u64, Batch> = BTreeMap::new();
+        let mut acked: HashSet<u64> = HashSet::new();
+        let mut next_seq = 0;
+        let mut reader = Reader::new(&content);
+        let mut valid = 0;
+        while let Some((kind, seq, payload)) = reader.record() {
+            match kind {
+                RECORD_BATCH | RECORD_BATCH_NO_UNITS => {
+                    match decode_batch(payload, kind == RECORD_BATCH) {
+                        Some(batch) => batches.insert(seq, batch),
+                        None => break,
+                    };
+                }
+                RECORD_ACK => {
+                    acked.insert(seq);
+                }
+                _ => break,
+            }
+            next_seq = next_seq.max(seq + 1);
+            valid = reader.pos;
+        }
+        // Drop a record torn by a crash, so the next ones are not appended after it
+        if valid < content.len() {
+            warn!(
+                "Dropping a torn record at the end of the write-ahead log {}",
+                path.display()
+            );
+            file.set_len(valid as u64)?;
+        }
+
+        batches.retain(|seq, _| !acked.contains(seq));
+        let pending = batches
+            .iter()
+            .map(|(seq, batch)| (*seq, encode_batch(batch)))
+            .collect();
+        let mut wal = Wal {
+            path: path.to_path_buf(),
+            file,
+            len: valid as u64,
+            next_seq,
+            pending,
+            max_batches: max_batches.max(1),
+            replay: batches.into_iter().collect(),
+        };
+        wal.trim()?;
+        wal.compact()?;
+        Ok(wal)
+    }
+
+    /// Take the batches left unacknowledged by the previous run, oldest first
+    pub fn take_replay(&mut self) -> Vec<(u64, Batch)> {
+        std::mem::take(&mut self.replay)
+    }
+
+    /// Append a batch and sync it to disk, dropping the oldest batch if `max_batches` are left unacknowledged
+    ///
+    /// # Returns
+    ///
+    /// - `io::Result<u64>` - The sequence number of the batch, to acknowledge it
+    pub fn append(&mut self, batch: &Batch) -> io::Result<u64> {
+        let seq = self.next_seq;
+        let payload = encode_batch(batch);
+        self.write(RECORD_BATCH, seq, &payload)?;
+        self.next_seq += 1;
+        self.pending.insert(seq, payload);
+        self.trim()?;
+        Ok(seq)
+    }
+
+    /// Mark a batch as pushed to every remote, or as dropped
+    pub fn ack(&mut self, seq: u64) -> io::Result<()> {
+        if self.pending.remove(&seq).is_none() {
+            return Ok(());
+        }
+        self.write(RECORD_ACK, seq, &[])?;
+        self.compact()
+    }
+
+    /// Drop the oldest batches while more than `max_batches` are left unacknowledged
+    fn trim(&mut self) -> io::Result<()> {
+        while self.pending.len() > self.max_batches {
+            let Some((seq, _)) = self.pending.pop_first() else {
+                break;
+            };
+            warn!(
+                "The write-ahead log holds more than {} batches, dropping batch {seq}",
+                self.max_batches
+            );
+            self.write(RECORD_ACK, seq, &[])?;
+        }
+        self.compact()
+    }
+
+    /// Empty the file once no batch is pending, rewrite it with only the pending batches once the
+    /// acknowledged records take more than half of it
+    fn compact(&mut self) -> io::Result<()> {
+        if self.pending.is_empty() {
+            if self.len > 0 {
+                self.file.set_len(0)?;
+                self.len = 0;
+            }
+            return Ok(());
+        }
+        let live: u64 = self
+            .pending
+            .values()
+            .map(|payload| payload.len() as u64 + RECORD_OVERHEAD)
+            .sum();
+        if self.len <= live * 2 {
+            return Ok(());
+        }
+
+        // Written aside then renamed, so a crash leaves either the old or the new log
+        let tmp = self.path.with_extension("compact");
+        let mut content = Vec::with_capacity(live as usize);
+        for (seq, payload) in &self.pending {
+            content.extend(record(RECORD_BATCH, *seq, payload));
+        }
+        let mut file = File::create(&tmp)?;
+        file.write_all(&content)?;
+        file.sync_all()?;
+        fs::rename(&tmp, &self.path)?;
+        self.file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .open(&self.path)?;
+        self.len = content.len() as u64;
+        Ok(())
+    }
+
+    fn write(&mut self, kind: u8, seq: u64, payload: &[u8]) -> io::Result<()> {
+        let record = record(kind, seq, payload);
+        self.file.write_all(&record)?;
+        self.file.sync_data()?;
+        self.len += record.len() as u64;
+        Ok(())
+    }
+}
+
+/// Serialize a record, its checksum last
+fn record(kind: u8, seq: u64, payload: &[u8]) -> Vec<u8> {
+    let mut record = Vec::with_capacity(payload.len() + RECORD_OVERHEAD as usize);
+    record.push(kind);
+    record.extend(seq.to_le_bytes());
+    record.extend((payload.len() as u32).to_le_bytes());
+    record.extend(payload);
+    record.extend(checksum(&record).to_le_bytes());
+    record
+}
+
+/// FNV-1a hash of a record
+fn checksum(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ *byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn put_str(buf: &mut Vec<u8>, text: &str) {
+    buf.extend((text.len() as u32).to_le_bytes());
+    buf.extend(text.as_bytes());
+}
+
+fn put_value(buf: &mut Vec<u8>, value: &RegisterValue) {
+    let value = match value {
+        RegisterValue::Raw(value) => value,
+        RegisterValue::Float(val) => {
+            buf.push(10);
+            buf.extend(val.to_le_bytes());
+            return;
+        }
+        RegisterValue::Int(val) => {
+            buf.push(11);
+            buf.extend(val.to_le_bytes());
+            return;
+        }
+        RegisterValue::Text(val) => {
+            buf.push(12);
+            put_str(buf, val);
+            return;
+        }
+    };
+    match value {
+        Value::U16(val) => {
+            buf.push(0);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::U32(val) => {
+            buf.push(1);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::U64(val) => {
+            buf.push(2);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::U128(val) => {
+            buf.push(3);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::S16(val) => {
+            buf.push(4);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::S32(val) => {
+            buf.push(5);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::Enum16(val) => {
+            buf.push(6);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::Sized(val) => {
+            buf.push(7);
+            buf.extend((val.len() as u32).to_le_bytes());
+            buf.extend(val);
+        }
+        Value::Float32(val) => {
+            buf.push(8);
+            buf.extend(val.to_le_bytes());
+        }
+        Value::Boolean(val) => {
+            buf.push(9);
+            buf.push(*val as u8);
+        }
+    }
+}
+
+/// Encode a batch: the number of sources, then for each its name, timestamp (ns), tags, units and values
+fn encode_batch(batch: &Batch) -> Vec<u8> {
+    let mut buf = Vec::new();
+    buf.extend((batch.len() as u32).to_le_bytes());
+    for (source, measurement) in batch {
+        put_str(&mut buf, source);
+        let timestamp = measurement
+            .timestamp
+            .timestamp_nanos_opt()
+            .unwrap_or_default();
+        buf.extend(timestamp.to_le_bytes());
+        buf.extend((measurement.tags.len() as u32).to_le_bytes());
+        for (tag, value) in &measurement.tags {
+            put_str(&mut buf, tag);
+            put_str(&mut buf, value);
+        }
+        buf.extend((measurement.units.len() as u32).to_le_bytes());
+        for (field, unit) in &measurement.units {
+            put_str(&mut buf, field);
+            put_str(&mut buf, unit);
+        }
+        buf.extend((measurement.values.len() as u32).to_le_bytes());
+        for (field, value) in &measurement.values {
+            put_str(&mut buf, field);
+            put_value(&mut buf, value);
+        }
+    }
+    buf
+}
+
+/// Reads the records and their fields, `None` when the data is truncated
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        let bytes = self.data.get(self.pos..self.pos.checked_add(len)?)?;
+        self.pos += len;
+        Some(bytes)
+    }
+
+    fn array<const N: usize>(&mut self) -> Option<[u8; N]> {
+        self.bytes(N)?.try_into().ok()
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.array()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        String::from_utf8(self.bytes(len)?.to_vec()).ok()
+    }
+
+    /// The next record (kind, sequence number, payload), `None` at the end or on a torn record
+    fn record(&mut self) -> Option<(u8, u64, &'a [u8])> {
+        let start = self.pos;
+        let kind = self.array::<1>()?[0];
+        let seq = u64::from_le_bytes(self.array()?);
+        let len = self.u32()? as usize;
+        let payload = self.bytes(len)?;
+        let end = self.pos;
+        let sum = u64::from_le_bytes(self.array()?);
+        match sum == checksum(&self.data[start..end]) {
+            true => Some((kind, seq, payload)),
+            false => None,
+        }
+    }
+
+    fn value(&mut self) -> Option<RegisterValue> {
+        let kind = self.array::<1>()?[0];
+        Some(match kind {
+            0 => Value::U16(u16::from_le_bytes(self.array()?)).into(),
+            1 => Value::U32(u32::from_le_bytes(self.array()?)).into(),
+            2 => Value::U64(u64::from_le_bytes(self.array()?)).into(),
+            3 => Value::U128(u128::from_le_bytes(self.array()?)).into(),
+            4 => Value::S16(i16::from_le_bytes(self.array()?)).into(),
+            5 => Value::S32(i32::from_le_bytes(self.array()?)).into(),
+            6 => Value::Enum16(u16::from_le_bytes(self.array()?)).into(),
+            7 => {
+                let len = self.u32()? as usize;
+                Value::Sized(self.bytes(len)?.to_vec()).into()
+            }
+            8 => Value::Float32(f32::from_le_bytes(self.array()?)).into(),
+            9 => Value::Boolean(self.array::<1>()?[0] != 0).into(),
+            10 => RegisterValue::Float(f64::from_le_bytes(self.array()?)),
+            11 => RegisterValue::Int(i64::from_le_bytes(self.array()?)),
+            12 => RegisterValue::Text(self.string()?),
+            _ => return None,
+        })
+    }
+}
+
+/// Decode a batch encoded by `encode_batch`, or without the units by the previous versions
+fn decode_batch(payload: &[u8], with_units: bool) -> Option<Batch> {
+    let mut reader = Reader::new(payload);
+    let mut batch = HashMap::new();
+    for _ in 0..reader.u32()? {
+        let source = reader.string()?;
+        let timestamp = DateTime::from_timestamp_nanos(i64::from_le_bytes(reader.array()?));
+        let mut measurement = Measurement::new(HashMap::new());
+        measurement.timestamp = timestamp;
+        for _ in 0..reader.u32()? {
+            measurement.tags.insert(reader.string()?, reader.string()?);
+        }
+        if with_units {
+            for _ in 0..reader.u32()? {
+                measurement.units.insert(reader.string()?, reader.string()?);
+            }
+        }
+        for _ in 0..reader.u32()? {
+            measurement.values.insert(reader.string()?, reader.value()?);
+        }
+        batch.insert(source, measurement);
+    }
+    Some(batch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn batch(value: i64) -> Batch {
+        HashMap::from([(
+            "press1".to_string(),
+            Measurement::new(HashMap::from([(
+                "temperature".to_string(),
+                RegisterValue::Int(value),
+            )])),
+        )])
+    }
+
+    fn value(batch: &Batch) -> &RegisterValue {
+        &batch["press1"].values["temperature"]
+    }
+
+    fn replayed(path: &Path) -> Vec<i64> {
+        let mut wal = Wal::open(path, DEFAULT_MAX_BATCHES).unwrap();
+        wal.take_replay()
+            .iter()
+            .map(|(_, batch)| match value(batch) {
+                RegisterValue::Int(value) => *value,
+                other => panic!("unexpected value {other:?}"),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn unacknowledged_batch_is_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        let first = wal.append(&batch(1)).unwrap();
+        wal.append(&batch(2)).unwrap();
+        wal.ack(first).unwrap();
+        // Crash: the log is dropped without acknowledging the second batch
+        drop(wal);
+
+        assert_eq!(replayed(&path), vec![2]);
+    }
+
+    #[test]
+    fn acknowledged_log_is_emptied() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        let seq = wal.append(&batch(1)).unwrap();
+        wal.ack(seq).unwrap();
+
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+        assert!(replayed(&path).is_empty());
+    }
+
+    #[test]
+    fn torn_trailing_record_is_dropped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        wal.append(&batch(1)).unwrap();
+        let intact = fs::metadata(&path).unwrap().len();
+        wal.append(&batch(2)).unwrap();
+        drop(wal);
+        // Crash while writing the second record
+        let file = OpenOptions::new().write(true).open(&path).unwrap();
+        file.set_len(intact + 10).unwrap();
+
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        assert_eq!(fs::metadata(&path).unwrap().len(), intact);
+        let replay = wal.take_replay();
+        assert_eq!(replay.len(), 1);
+        assert!(matches!(value(&replay[0].1), RegisterValue::Int(1)));
+
+        // The records appended after the torn one are read back
+        wal.append(&batch(3)).unwrap();
+        drop(wal);
+        assert_eq!(replayed(&path), vec![1, 3]);
+    }
+
+    #[test]
+    fn corrupted_record_stops_the_replay() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        wal.append(&batch(1)).unwrap();
+        drop(wal);
+        let mut content = fs::read(&path).unwrap();
+        let last = content.len() - 1;
+        content[last] ^= 0xff;
+        fs::write(&path, content).unwrap();
+
+        assert!(replayed(&path).is_empty());
+        assert_eq!(fs::metadata(&path).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn oldest_batches_are_dropped_past_the_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let mut wal = Wal::open(&path, 2).unwrap();
+        for value in 1..=3 {
+            wal.append(&batch(value)).unwrap();
+        }
+        drop(wal);
+
+        assert_eq!(replayed(&path), vec![2, 3]);
+    }
+
+    #[test]
+    fn log_is_compacted_while_a_batch_is_pending() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        wal.append(&batch(0)).unwrap();
+        let single = fs::metadata(&path).unwrap().len();
+        for value in 1..100 {
+            let seq = wal.append(&batch(value)).unwrap();
+            wal.ack(seq).unwrap();
+        }
+
+        // The pending batch and at most as many acknowledged bytes
+        assert!(fs::metadata(&path).unwrap().len() <= single * 3);
+        drop(wal);
+        assert_eq!(replayed(&path), vec![0]);
+    }
+
+    #[test]
+    fn batch_round_trips() {
+        let mut original = batch(7);
+        let measurement = original.get_mut("press1").unwrap();
+        measurement.tags.insert("line".to_string(), "2".to_string());
+        measurement
+            .values
+            .insert("state".to_string(), RegisterValue::Text("run".to_string()));
+        measurement
+            .values
+            .insert("speed".to_string(), Value::Float32(1.5).into());
+
+        measurement
+            .units
+            .insert("temperature".to_string(), "°C".to_string());
+
+        let decoded = decode_batch(&encode_batch(&original), true).unwrap();
+        assert_eq!(decoded["press1"].to_json(), original["press1"].to_json());
+        assert_eq!(decoded["press1"].units, original["press1"].units);
+    }
+
+    #[test]
+    fn batch_without_units_is_replayed() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("wal");
+        // Batch record written by a version not persisting the units: the tags and values only
+        let mut payload = Vec::new();
+        payload.extend(1u32.to_le_bytes());
+        put_str(&mut payload, "press1");
+        payload.extend(0i64.to_le_bytes());
+        payload.extend(0u32.to_le_bytes());
+        payload.extend(1u32.to_le_bytes());
+        put_str(&mut payload, "temperature");
+        put_value(&mut payload, &RegisterValue::Int(4));
+        fs::write(&path, record(RECORD_BATCH_NO_UNITS, 0, &payload)).unwrap();
+
+        let mut wal = Wal::open(&path, DEFAULT_MAX_BATCHES).unwrap();
+        let replay = wal.take_replay();
+        assert_eq!(replay.len(), 1);
+        assert!(matches!(value(&replay[0].1), RegisterValue::Int(4)));
+        assert!(replay[0].1["press1"].units.is_empty());
+        // Appended after it, not on top of it
+        assert_eq!(wal.append(&batch(5)).unwrap(), 1);
+        drop(wal);
+        assert_eq!(replayed(&path), vec![4, 5]);
+    }
+}
@@ -0,0 +1,331 @@
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+
+use async_trait::async_trait;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
+use reqwest::{Method, StatusCode};
+use serde::Deserialize;
+use serde_json::Map;
+use url::Url;
+
+use crate::measurement::Measurement;
+use crate::remotes::compression::Compression;
+use crate::remotes::errors::RemoteInitError;
+use crate::remotes::remote::{RemoteError, RemoteOptions};
+use crate::remotes::{flatten_measurements, Remote};
+use crate::types_conversion::{RegisterValue, ValueFormat};
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "UPPERCASE")]
+/// HTTP method the batches are sent with
+pub enum WebhookMethod {
+    #[default]
+    Post,
+    Put,
+    Patch,
+}
+
+impl From<WebhookMethod> for Method {
+    fn from(value: WebhookMethod) -> Self {
+        match value {
+            WebhookMethod::Post => Method::POST,
+            WebhookMethod::Put => Method::PUT,
+            WebhookMethod::Patch => Method::PATCH,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// Shape of the JSON body
+pub enum WebhookLayout {
+    /// One object per source: `{"source": {"timestamp": ..., "tags": {...}, "values": {"field": value}}}`
+    #[default]
+    Nested,
+    /// A single object holding the fields of all sources:
+    /// `{"timestamp": ..., "tags": {...}, "values": {"source.field": value}}`
+    Flat,
+}
+
+/// Webhook remote, sending the measurements as JSON to an HTTP endpoint
+///
+/// # Fields
+/// - `client`: The HTTP client, sending the configured headers with every request.
+/// - `url`: The endpoint.
+/// - `method`: The HTTP method.
+/// - `layout`: The shape of the body.
+/// - `key_separator`: The separator between the source and the field with the flat layout.
+/// - `compression`: The compression of the bodies.
+/// - `format`: How the values are written as text, `None` to send them as JSON numbers.
+pub struct WebhookClient {
+    pub client: reqwest::Client,
+    pub url: Url,
+    pub method: Method,
+    pub layout: WebhookLayout,
+    pub key_separator: String,
+    pub compression: Compression,
+    pub format: Option<ValueFormat>,
+}
+
+impl WebhookClient {
+    /// The measurement with its values written as text (see [`RegisterValue::as_format`]) if a `format` is set
+    fn formatted<'a>(&self, measurement: &'a Measurement) -> Cow<'a, Measurement> {
+        let Some(format) = &self.format else {
+            return Cow::Borrowed(measurement);
+        };
+        let mut formatted = measurement.clone();
+        for (field, value) in formatted.values.iter_mut() {
+            *value = RegisterValue::Text(value.as_format(format, measurement.units.get(field)));
+        }
+        Cow::Owned(formatted)
+    }
+
+    /// Send measurements in a single request, as a JSON object keyed by source (sorted by name):
+    /// `{"source": {"timestamp": "<RFC 3339>", "tags": {...}, "values": {"field": value}}}`,
+    /// or as a single measurement with the flat layout (see [`flatten_measurements`])
+    ///
+    /// # Arguments
+    ///
+    /// - `data` (`impl Iterator<Item = (&String, &Measurement)>`) - The measurements, with their source
+    ///
+    /// # Returns
+    ///
+    /// - `Result<(), RemoteError>` - Whether the endpoint accepted the measurements
+    async fn post<'a>(
+        &self,
+        data: impl Iterator<Item = (&'a String, &'a Measurement)>,
+    ) -> Result<(), RemoteError> {
+        let body = match self.layout {
+            WebhookLayout::Nested => {
+                let sources: BTreeMap<&String, &Measurement> = data.collect();
+                let body: Map<String, serde_json::Value> = sources
+                    .into_iter()
+                    .map(|(source, measurement)| {
+                        (source.clone(), self.formatted(measurement).to_json())
+                    })
+                    .collect();
+                serde_json::Value::Object(body)
+            }
+            WebhookLayout::Flat => {
+                match flatten_measurements(self.url.as_str(), data, &self.key_separator) {
+                    Some(flat) => self.formatted(&flat).to_json(),
+                    None => return Ok(()),
+                }
+            }
+        };
+
+        let request = self
+            .client
+            .request(self.method.clone(), self.url.clone())
+            .header("Content-Type", "application/json");
+        let res = self
+            .compression
+            .body(request, body.to_string().into_bytes())
+            .send()
+            .await?;
+
+        match res.status() {
+            status if status.is_success() => Ok(()),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Err(RemoteError::AuthError),
+            _ => Err(RemoteError::ServerError),
+        }
+    }
+}
+
+#[async_trait]
+impl Remote for WebhookClient {
+    /// Sends a measurement to the endpoint, as a batch of a single source
+    ///
+    /// Errors
+    /// - `RemoteError::AuthError` if the endpoint answered 401 or 403.
+    /// - `RemoteError::ServerError` for any other non-2xx answer.
+    async fn send_measurement(
+        &self,
+        name: &str,
+        measurement: &Measurement,
+    ) -> Result<(), RemoteError> {
+        let name = name.to_string();
+        self.post(std::iter::once((&name, measurement))).await
+    }
+
+    /// Sends the measurements of all the sources in a single request
+    async fn send_batch(&self, data: &HashMap<String, Measurement>) -> Result<(), RemoteError> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        self.post(data.iter()).await
+    }
+}
+
+#[derive(Deserialize, Debug)]
+/// strucure that represent the config for the webhook remote
+///
+/// # Fields
+///
+/// - `url` (`String`) - the endpoint the measurements are sent to
+/// - `headers` (`HashMap<String, String>`) - headers sent with every request (e.g. `Authorization`)
+/// - `method` (`WebhookMethod`) - the HTTP method (`POST`, default, `PUT` or `PATCH`)
+/// - `layout` (`WebhookLayout`) - the shape of the body (`nested`, default, or `flat`)
+/// - `compression` (`Compression`) - the compression of the body (`none`, default, or `gzip`)
+/// - `format` (`Option<ValueFormat>`) - write the values as text (`decimals`, `unit_suffix`, `thousands_separator`)
+///   instead of JSON numbers (default: numbers)
+/// - `options` (`RemoteOptions`) - the options shared by all remotes, `key_separator` is used by the flat layout
+pub struct WebhookRemote {
+    pub url: String,
+    #[serde(default)]
+    pub headers: HashMap<String, String>,
+    #[serde(default)]
+    pub method: WebhookMethod,
+    #[serde(default)]
+    pub layout: WebhookLayout,
+    #[serde(default)]
+    pub compression: Compression,
+    pub format: Option<ValueFormat>,
+    #[serde(flatten)]
+    pub options: RemoteOptions,
+}
+
+impl TryFrom<WebhookRemote> for WebhookClient {
+    type Error = RemoteInitError;
+
+    fn try_from(value: WebhookRemote) -> Result<Self, Self::Error> {
+        let mut headers = HeaderMap::new();
+        for (name, header) in &value.headers {
+            let name = HeaderName::from_bytes(name.as_bytes())?;
+            let mut header = HeaderValue::from_str(header)?;
+            // Keep the credentials out of the logs
+            header.set_sensitive(true);
+            headers.insert(name, header);
+        }
+        Ok(WebhookClient {
+            client: reqwest::Client::builder()
+                .default_headers(headers)
+                .build()?,
+            url: Url::parse(&value.url)?,
+            method: value.method.into(),
+            layout: value.layout,
+            key_separator: value.options.key_separator().to_string(),
+            compression: value.compression,
+            format: value.format,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::remotes::compression::gunzip;
+    use crate::remotes::mock_server::serve;
+
+    fn webhook(config: serde_json::Value) -> WebhookClient {
+        serde_json::from_value::<WebhookRemote>(config)
+            .unwrap()
+            .try_into()
+            .unwrap()
+    }
+
+    fn batch(sources: &[(&str, &str, f64)]) -> HashMap<String, Measurement> {
+        let mut batch: HashMap<String, Measurement> = HashMap::new();
+        for (source, field, value) in sources {
+            batch
+                .entry(source.to_string())
+                .or_insert_with(|| Measurement::new(HashMap::new()))
+                .values
+                .insert(field.to_string(), RegisterValue::Float(*value));
+        }
+        batch
+    }
+
+    /// The body posted for a batch
+    async fn posted(
+        mut config: serde_json::Value,
+        data: &HashMap<String, Measurement>,
+    ) -> serde_json::Value {
+        let (addr, mut requests) = serve("200 OK").await;
+        config["url"] = format!("http://{addr}/ingest").into();
+        webhook(config).send_batch(data).await.unwrap();
+        serde_json::from_slice(&requests.recv().await.unwrap().body).unwrap()
+    }
+
+    #[tokio::test]
+    async fn nested_layout_keeps_one_object_per_source() {
+        let data = batch(&[("press1", "temp", 21.5), ("press2", "temp", 19.0)]);
+        let body = posted(serde_json::json!({}), &data).await;
+        assert_eq!(body["press1"]["values"]["temp"], 21.5);
+        assert_eq!(body["press2"]["values"]["temp"], 19.0);
+    }
+
+    #[tokio::test]
+    async fn flat_layout_composes_the_keys() {
+        let data = batch(&[("press1", "temp", 21.5), ("press2", "temp", 19.0)]);
+        let body = posted(
+            serde_json::json!({"layout": "flat", "key_separator": "/"}),
+            &data,
+        )
+        .await;
+        let values = body["values"].as_object().unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values["press1/temp"], 21.5);
+        assert_eq!(values["press2/temp"], 19.0);
+    }
+
+    #[tokio::test]
+    async fn colliding_keys_are_kept_apart_by_another_separator() {
+        // Field `b.c` of source `a` and field `c` of source `a.b` both compose `a.b.c`
+        let data = batch(&[("a", "b.c", 1.0), ("a.b", "c", 2.0)]);
+        let body = posted(serde_json::json!({"layout": "flat"}), &data).await;
+        assert_eq!(body["values"], serde_json::json!({"a.b.c": 1.0}));
+
+        let body = posted(
+            serde_json::json!({"layout": "flat", "key_separator": "::"}),
+            &data,
+        )
+        .await;
+        assert_eq!(
+            body["values"],
+            serde_json::json!({"a::b.c": 1.0, "a.b::c": 2.0})
+        );
+    }
+
+    #[tokio::test]
+    async fn gzip_body_decompresses_to_the_json() {
+        let (addr, mut requests) = serve("200 OK").await;
+        let config = serde_json::json!({
+            "url": format!("http://{addr}/ingest"),
+            "compression": "gzip",
+        });
+        let data = batch(&[("press1", "temp", 21.5)]);
+        webhook(config).send_batch(&data).await.unwrap();
+
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request.header("content-encoding"), Some("gzip"));
+        let body: serde_json::Value = serde_json::from_slice(&gunzip(&request.body)).unwrap();
+        assert_eq!(body["press1"]["values"]["temp"], 21.5);
+    }
+
+    #[tokio::test]
+    async fn body_is_sent_as_is_by_default() {
+        let (addr, mut requests) = serve("200 OK").await;
+        let config = serde_json::json!({"url": format!("http://{addr}/ingest")});
+        let data = batch(&[("press1", "temp", 21.5)]);
+        webhook(config).send_batch(&data).await.unwrap();
+
+        let request = requests.recv().await.unwrap();
+        assert_eq!(request.header("content-encoding"), None);
+        let body: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(body["press1"]["values"]["temp"], 21.5);
+    }
+
+    #[tokio::test]
+    async fn formatted_values_are_sent_as_text() {
+        let config = serde_json::json!({
+            "format": {"decimals": 1, "unit_suffix": true, "thousands_separator": " "},
+        });
+        let mut data = batch(&[("meter1", "energy", 12345.67), ("meter1", "power", 3.0)]);
+        let meter = data.get_mut("meter1").unwrap();
+        meter.units.insert("energy".to_string(), "kWh".to_string());
+        let body = posted(config, &data).await;
+        assert_eq!(body["meter1"]["values"]["energy"], "12 345.7 kWh");
+        assert_eq!(body["meter1"]["values"]["power"], "3.0");
+    }
+}
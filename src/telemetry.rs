@@ -0,0 +1,81 @@
+use std::{future::Future, time::Duration};
+
+use serde::Deserialize;
+
+#[cfg(feature = "otel")]
+mod otel;
+#[cfg(feature = "otel")]
+pub use otel::init;
+
+#[derive(Deserialize, Debug, Clone)]
+#[cfg_attr(not(feature = "otel"), allow(dead_code))]
+/// OpenTelemetry export of the bridge's own metrics and spans (requires the `otel` feature)
+///
+/// # Fields
+/// - `endpoint`: Base url of the OTLP/HTTP collector (e.g. `http://otel-collector:4318`).
+/// - `service_name`: The `service.name` of the bridge (default `industrial_bridge`).
+pub struct OtelConfig {
+    pub endpoint: String,
+    pub service_name: Option<String>,
+}
+
+/// Run a future inside a span, a no-op without the `otel` feature
+///
+/// # Arguments
+///
+/// - `name` (`&'static str`) - The name of the span
+/// - `attributes` (`&[(&'static str, String)]`) - The attributes of the span
+/// - `fut` (`F`) - The future to run
+pub async fn in_span<F: Future>(
+    name: &'static str,
+    attributes: &[(&'static str, String)],
+    fut: F,
+) -> F::Output {
+    #[cfg(feature = "otel")]
+    return otel::in_span(name, attributes, fut).await;
+    #[cfg(not(feature = "otel"))]
+    {
+        let _ = (name, attributes);
+        fut.await
+    }
+}
+
+/// Keep the current span as the parent of the spans of a future run in another task
+pub fn propagate<F: Future>(fut: F) -> impl Future<Output = F::Output> {
+    #[cfg(feature = "otel")]
+    return opentelemetry::context::FutureExt::with_current_context(fut);
+    #[cfg(not(feature = "otel"))]
+    fut
+}
+
+/// Count a fetch of a device
+pub fn record_fetch(device: &str, success: bool) {
+    #[cfg(feature = "otel")]
+    otel::record_fetch(device, success);
+    #[cfg(not(feature = "otel"))]
+    let _ = (device, success);
+}
+
+/// Record the number of fetch cycles in a row a device failed
+pub fn record_failures(device: &str, failures: u32) {
+    #[cfg(feature = "otel")]
+    otel::record_failures(device, failures);
+    #[cfg(not(feature = "otel"))]
+    let _ = (device, failures);
+}
+
+/// Record the duration of a push to a remote
+pub fn record_push(remote: &str, duration: Duration, success: bool) {
+    #[cfg(feature = "otel")]
+    otel::record_push(remote, duration, success);
+    #[cfg(not(feature = "otel"))]
+    let _ = (remote, duration, success);
+}
+
+/// Record the jitter of a fetch cycle (time since the previous cycle minus the period, in seconds)
+pub fn record_cycle_jitter(jitter: f64) {
+    #[cfg(feature = "otel")]
+    otel::record_cycle_jitter(jitter);
+    #[cfg(not(feature = "otel"))]
+    let _ = jitter;
+}
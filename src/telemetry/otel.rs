@@ -0,0 +1,176 @@
+use std::{future::Future, sync::LazyLock, time::Duration};
+
+use opentelemetry::{
+    context::FutureExt,
+    global,
+    metrics::{Counter, Gauge, Histogram},
+    trace::{TraceContextExt, Tracer},
+    Context, KeyValue,
+};
+use opentelemetry_otlp::{MetricExporter, SpanExporter, WithExportConfig};
+use opentelemetry_sdk::{
+    metrics::{PeriodicReader, SdkMeterProvider},
+    trace::SdkTracerProvider,
+    Resource,
+};
+use thiserror::Error;
+
+use super::OtelConfig;
+
+/// Name of the instrumentation scope of the bridge
+const SCOPE: &str = "industrial_bridge";
+
+#[derive(Debug, Error)]
+/// Errors while setting up the OpenTelemetry export
+pub enum TelemetryError {
+    #[error("Could not create the OTLP exporter ({err})")]
+    ExporterError {
+        #[from]
+        err: opentelemetry_otlp::ExporterBuildError,
+    },
+}
+
+static FETCHES: LazyLock<Counter<u64>> = LazyLock::new(|| {
+    global::meter(SCOPE)
+        .u64_counter("bridge.fetches")
+        .with_description("Number of device fetches")
+        .build()
+});
+
+static CONSECUTIVE_FAILURES: LazyLock<Gauge<u64>> = LazyLock::new(|| {
+    global::meter(SCOPE)
+        .u64_gauge("bridge.device.consecutive_failures")
+        .with_description("Number of fetch cycles in a row a device failed")
+        .build()
+});
+
+static PUSH_DURATION: LazyLock<Histogram<f64>> = LazyLock::new(|| {
+    global::meter(SCOPE)
+        .f64_histogram("bridge.push.duration")
+        .with_description("Duration of the pushes to the remotes")
+        .with_unit("s")
+        .build()
+});
+
+static CYCLE_JITTER: LazyLock<Histogram<f64>> = LazyLock::new(|| {
+    global::meter(SCOPE)
+        .f64_histogram("bridge.cycle.jitter")
+        .with_description("Time between two fetch cycles minus the configured period")
+        .with_unit("s")
+        .build()
+});
+
+/// The installed providers, to be shut down on exit so the last data is exported
+pub struct Telemetry {
+    tracer_provider: SdkTracerProvider,
+    meter_provider: SdkMeterProvider,
+}
+
+impl Telemetry {
+    /// Flush and stop the export (blocking)
+    pub fn shutdown(self) {
+        if let Err(err) = self.tracer_provider.shutdown() {
+            log::error!("Could not shut down the OpenTelemetry traces export ({err})");
+        }
+        if let Err(err) = self.meter_provider.shutdown() {
+            log::error!("Could not shut down the OpenTelemetry metrics export ({err})");
+        }
+    }
+}
+
+/// Install the OTLP/HTTP exporters of the traces and metrics as the global providers
+///
+/// # Arguments
+///
+/// - `config` (`&OtelConfig`) - The collector to export to
+///
+/// # Returns
+///
+/// - `Result<Telemetry, TelemetryError>` - The providers, to shut down on exit
+pub fn init(config: &OtelConfig) -> Result<Telemetry, TelemetryError> {
+    let endpoint = config.endpoint.trim_end_matches('/').to_string();
+    let resource = Resource::builder()
+        .with_service_name(config.service_name.clone().unwrap_or(SCOPE.to_string()))
+        .build();
+
+    // The exporters use a blocking http client, which can't be created from the async runtime
+    let (span_exporter, metric_exporter) = std::thread::spawn(move || {
+        let span_exporter = SpanExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{endpoint}/v1/traces"))
+            .build()?;
+        let metric_exporter = MetricExporter::builder()
+            .with_http()
+            .with_endpoint(format!("{endpoint}/v1/metrics"))
+            .build()?;
+        Ok::<_, TelemetryError>((span_exporter, metric_exporter))
+    })
+    .join()
+    .expect("OpenTelemetry exporter thread panicked")?;
+
+    let tracer_provider = SdkTracerProvider::builder()
+        .with_batch_exporter(span_exporter)
+        .with_resource(resource.clone())
+        .build();
+    let meter_provider = SdkMeterProvider::builder()
+        .with_reader(PeriodicReader::builder(metric_exporter).build())
+        .with_resource(resource)
+        .build();
+
+    global::set_tracer_provider(tracer_provider.clone());
+    global::set_meter_provider(meter_provider.clone());
+    Ok(Telemetry {
+        tracer_provider,
+        meter_provider,
+    })
+}
+
+/// Run a future inside a span, child of the current one
+pub async fn in_span<F: Future>(
+    name: &'static str,
+    attributes: &[(&'static str, String)],
+    fut: F,
+) -> F::Output {
+    let tracer = global::tracer(SCOPE);
+    let span = tracer
+        .span_builder(name)
+        .with_attributes(
+            attributes
+                .iter()
+                .map(|(key, value)| KeyValue::new(*key, value.clone())),
+        )
+        .start(&tracer);
+    fut.with_context(Context::current_with_span(span)).await
+}
+
+/// Count a fetch of a device
+pub fn record_fetch(device: &str, success: bool) {
+    FETCHES.add(
+        1,
+        &[
+            KeyValue::new("device", device.to_string()),
+            KeyValue::new("success", success),
+        ],
+    );
+}
+
+/// Record the number of fetch cycles in a row a device failed
+pub fn record_failures(device: &str, failures: u32) {
+    CONSECUTIVE_FAILURES.record(failures as u64, &[KeyValue::new("device", device.to_string())]);
+}
+
+/// Record the duration of a push to a remote
+pub fn record_push(remote: &str, duration: Duration, success: bool) {
+    PUSH_DURATION.record(
+        duration.as_secs_f64(),
+        &[
+            KeyValue::new("remote", remote.to_string()),
+            KeyValue::new("success", success),
+        ],
+    );
+}
+
+/// Record the jitter of a fetch cycle
+pub fn record_cycle_jitter(jitter: f64) {
+    CYCLE_JITTER.record(jitter, &[]);
+}
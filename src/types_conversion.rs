@@ -1,43 +1,393 @@
-use std::{collections::HashMap, hash::Hash};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+    mem::discriminant,
+};
 
 use industrial_device::types::Value;
 use influxdb::Type;
+use serde::{Deserialize, Serialize, Serializer};
+
+const BASE64_ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+/// How byte arrays (`Value::Sized`) are turned into the strings pushed to the remotes
+pub enum BytesEncoding {
+    /// Lowercase hexadecimal digits, two per byte (`0a1b`)
+    #[default]
+    Hex,
+    /// Standard base64, padded
+    Base64,
+    /// ASCII text, the other bytes replaced by `?`
+    Ascii,
+    /// UTF-8 text, the invalid sequences replaced by `�`
+    Utf8,
+}
+
+impl BytesEncoding {
+    /// Encode a byte array, the NUL bytes padding a text (`ascii` and `utf8`) are left out
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            BytesEncoding::Hex => bytes.iter().map(|byte| format!("{byte:02x}")).collect(),
+            BytesEncoding::Base64 => bytes
+                .chunks(3)
+                .flat_map(|chunk| {
+                    let block = chunk
+                        .iter()
+                        .enumerate()
+                        .fold(0u32, |block, (i, byte)| block | (*byte as u32) << (16 - 8 * i));
+                    (0..4).map(move |i| match i <= chunk.len() {
+                        true => BASE64_ALPHABET[(block >> (18 - 6 * i) & 0x3f) as usize] as char,
+                        false => '=',
+                    })
+                })
+                .collect(),
+            BytesEncoding::Ascii => trim_nul(bytes)
+                .iter()
+                .map(|byte| match byte.is_ascii() {
+                    true => *byte as char,
+                    false => '?',
+                })
+                .collect(),
+            BytesEncoding::Utf8 => String::from_utf8_lossy(trim_nul(bytes)).into_owned(),
+        }
+    }
+}
+
+/// The bytes before the NUL bytes padding a text
+fn trim_nul(bytes: &[u8]) -> &[u8] {
+    let len = bytes.iter().rposition(|byte| *byte != 0).map_or(0, |pos| pos + 1);
+    &bytes[..len]
+}
+
+/// Turn the byte arrays read from a device into text
+///
+/// # Arguments
+///
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read
+/// - `encoding` (`BytesEncoding`) - How the byte arrays are encoded
+pub fn encode_bytes(values: &mut HashMap<String, RegisterValue>, encoding: BytesEncoding) {
+    for value in values.values_mut() {
+        if let RegisterValue::Raw(Value::Sized(bytes)) = value {
+            *value = RegisterValue::Text(encoding.encode(bytes));
+        }
+    }
+}
+
+/// Round the floating point values of the registers to a number of decimals, matching the precision of the sensors
+/// (a `Float32` widened to 64 bits reads `0.10000000149011612` instead of `0.1`), the other values are left untouched
+///
+/// # Arguments
+///
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read
+/// - `decimals` (`&HashMap<String, u8>`) - The number of decimals kept, keyed by register name
+pub fn round_values(values: &mut HashMap<String, RegisterValue>, decimals: &HashMap<String, u8>) {
+    for (register, decimals) in decimals {
+        let value = match values.get(register) {
+            Some(RegisterValue::Raw(Value::Float32(val))) => *val as f64,
+            Some(RegisterValue::Float(val)) => *val,
+            _ => continue,
+        };
+        if !value.is_finite() {
+            continue;
+        }
+        let factor = 10f64.powi(*decimals as i32);
+        let rounded = (value * factor).round() / factor;
+        // Very large values would overflow once scaled, they have no decimals to round anyway
+        if rounded.is_finite() {
+            values.insert(register.clone(), RegisterValue::Float(rounded));
+        }
+    }
+}
+
+/// Push the numeric values of registers as integers, rounded to the nearest one (e.g. counts turned into floats
+/// by a scaling), so they are not pushed with a `.0` and stay exact, the values that don't fit an `i64`
+/// (or are not numbers) are left untouched
+///
+/// # Arguments
+///
+/// - `values` (`&mut HashMap<String, RegisterValue>`) - The values read
+/// - `as_integer` (`&[String]`) - The registers pushed as integers
+pub fn integer_values(values: &mut HashMap<String, RegisterValue>, as_integer: &[String]) {
+    for register in as_integer {
+        let value = match values.get(register) {
+            Some(RegisterValue::Raw(Value::U64(val))) => i64::try_from(*val).ok(),
+            Some(RegisterValue::Raw(Value::U128(val))) => i64::try_from(*val).ok(),
+            Some(value) if value.is_numeric() => {
+                let rounded = value.as_f64().round();
+                // The bounds of an i64 are not floats, the saturating cast would change the value
+                (rounded.is_finite() && rounded.abs() < i64::MAX as f64).then_some(rounded as i64)
+            }
+            _ => None,
+        };
+        if let Some(value) = value {
+            values.insert(register.clone(), RegisterValue::Int(value));
+        }
+    }
+}
 
 #[derive(Debug, Clone)]
-pub struct RegisterValue {
-    value: Value,
+/// Value of a register as pushed to the remotes
+pub enum RegisterValue {
+    /// Value as read from the device
+    Raw(Value),
+    /// Value computed by the bridge (e.g. an aggregation of several samples)
+    Float(f64),
+    /// Signed integer assembled by the bridge from several registers
+    Int(i64),
+    /// Text assembled by the bridge from several registers
+    Text(String),
 }
 
 impl From<Value> for RegisterValue {
     fn from(value: Value) -> Self {
-        RegisterValue { value }
+        RegisterValue::Raw(value)
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default)]
+/// How the values are written by the remotes pushing them as human-readable text
+///
+/// # Fields
+/// - `decimals`: Number of decimals the floating point values are written with (default: as read).
+/// - `unit_suffix`: Append the unit of the register to the value (`23.4 °C`), see the `units` of the devices.
+/// - `thousands_separator`: Separator inserted between the groups of three digits of the integer part
+///   (e.g. `,` for `1,234.5`, default: none).
+pub struct ValueFormat {
+    pub decimals: Option<u8>,
+    #[serde(default)]
+    pub unit_suffix: bool,
+    pub thousands_separator: Option<String>,
+}
+
+/// Insert `separator` between the groups of three digits of the integer part of a number written in decimal
+fn group_thousands(number: &str, separator: &str) -> String {
+    let (sign, digits) = match number.strip_prefix('-') {
+        Some(digits) => ("-", digits),
+        None => ("", number),
+    };
+    let (integer, fraction) = match digits.find('.') {
+        Some(dot) => digits.split_at(dot),
+        None => (digits, ""),
+    };
+    if !integer.chars().all(|c| c.is_ascii_digit()) {
+        return number.to_string();
+    }
+    let mut grouped = String::new();
+    for (i, digit) in integer.chars().enumerate() {
+        if i > 0 && (integer.len() - i) % 3 == 0 {
+            grouped.push_str(separator);
+        }
+        grouped.push(digit);
+    }
+    format!("{sign}{grouped}{fraction}")
+}
+
+/// Largest integer a float holds exactly (2^53), larger integers are rounded when converted
+pub const MAX_EXACT_INTEGER: u128 = 1 << 53;
+
+impl RegisterValue {
+    /// Whether the value is a quantity that can be aggregated (booleans, enums and byte arrays are not)
+    pub fn is_numeric(&self) -> bool {
+        match self {
+            RegisterValue::Raw(Value::Enum16(_))
+            | RegisterValue::Raw(Value::Sized(_))
+            | RegisterValue::Raw(Value::Boolean(_))
+            | RegisterValue::Text(_) => false,
+            RegisterValue::Raw(_) | RegisterValue::Float(_) | RegisterValue::Int(_) => true,
+        }
+    }
+
+    /// Whether the value can only be pushed as a string (texts and byte arrays)
+    pub fn is_text(&self) -> bool {
+        matches!(self, RegisterValue::Text(_) | RegisterValue::Raw(Value::Sized(_)))
+    }
+
+    /// Whether the value is a 64 or 128 bits unsigned integer, which a float can't always hold exactly
+    pub fn is_wide_integer(&self) -> bool {
+        matches!(self, RegisterValue::Raw(Value::U64(_)) | RegisterValue::Raw(Value::U128(_)))
+    }
+
+    /// Whether the value is held exactly by a float (the integers up to 2^53)
+    pub fn is_exact_f64(&self) -> bool {
+        match self {
+            RegisterValue::Raw(Value::U64(val)) => *val as u128 <= MAX_EXACT_INTEGER,
+            RegisterValue::Raw(Value::U128(val)) => *val <= MAX_EXACT_INTEGER,
+            RegisterValue::Int(val) => val.unsigned_abs() as u128 <= MAX_EXACT_INTEGER,
+            _ => true,
+        }
+    }
+
+    /// The value as text, written as set by the `format` of a remote (see [`ValueFormat`]),
+    /// the values that are not numbers (texts, byte arrays, booleans, enums) are written as with `Into<String>`
+    ///
+    /// # Arguments
+    ///
+    /// - `format` (`&ValueFormat`) - How the value is written
+    /// - `unit` (`Option<&String>`) - The unit of the register, appended with `unit_suffix`
+    ///
+    /// # Returns
+    ///
+    /// - `String` - The value as text (e.g. `1,234.5 kWh`)
+    pub fn as_format(&self, format: &ValueFormat, unit: Option<&String>) -> String {
+        if !self.is_numeric() {
+            return self.clone().into();
+        }
+        let is_float = matches!(self, RegisterValue::Float(_) | RegisterValue::Raw(Value::Float32(_)));
+        let mut text = match (is_float, format.decimals) {
+            (true, Some(decimals)) => format!("{:.*}", decimals as usize, self.as_f64()),
+            _ => self.clone().into(),
+        };
+        if let Some(separator) = &format.thousands_separator {
+            text = group_thousands(&text, separator);
+        }
+        match unit {
+            Some(unit) if format.unit_suffix => format!("{text} {unit}"),
+            _ => text,
+        }
+    }
+
+    /// The value as a float, keeping its sign and NaN (an invalid reading), the texts and byte arrays being 0
+    pub fn as_f64(&self) -> f64 {
+        let value = match self {
+            RegisterValue::Raw(value) => value,
+            RegisterValue::Float(val) => return *val,
+            RegisterValue::Int(val) => return *val as f64,
+            RegisterValue::Text(_val) => return 0 as f64,
+        };
+        match value {
+            Value::U16(val) => (*val).into(),
+            Value::U32(val) => (*val).into(),
+            Value::U64(val) => *val as f64,
+            Value::U128(val) => *val as f64,
+            Value::S16(val) => (*val).into(),
+            Value::S32(val) => (*val).into(),
+            Value::Enum16(val) => (*val).into(),
+            Value::Sized(_val) => 0 as f64,
+            Value::Float32(val) => (*val).into(),
+            Value::Boolean(val) => (*val).into(),
+        }
+    }
+}
+
+/// Serialize the underlying value as a number, a boolean or a string (byte arrays in hexadecimal),
+/// NaN being serialized as `null` by JSON
+impl Serialize for RegisterValue {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let value = match self {
+            RegisterValue::Raw(value) => value,
+            RegisterValue::Float(val) => return serializer.serialize_f64(*val),
+            RegisterValue::Int(val) => return serializer.serialize_i64(*val),
+            RegisterValue::Text(val) => return serializer.serialize_str(val),
+        };
+        match value {
+            Value::U16(val) => serializer.serialize_u16(*val),
+            Value::U32(val) => serializer.serialize_u32(*val),
+            Value::U64(val) => serializer.serialize_u64(*val),
+            Value::U128(val) => serializer.serialize_u128(*val),
+            Value::S16(val) => serializer.serialize_i16(*val),
+            Value::S32(val) => serializer.serialize_i32(*val),
+            Value::Enum16(val) => serializer.serialize_u16(*val),
+            Value::Sized(val) => serializer.serialize_str(&BytesEncoding::default().encode(val)),
+            Value::Float32(val) => serializer.serialize_f32(*val),
+            Value::Boolean(val) => serializer.serialize_bool(*val),
+        }
     }
 }
 
+/// Hash the underlying value, floats are hashed by their bit pattern
+impl Hash for RegisterValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        let value = match self {
+            RegisterValue::Raw(value) => value,
+            RegisterValue::Float(val) => {
+                val.to_bits().hash(state);
+                return;
+            }
+            RegisterValue::Int(val) => {
+                val.hash(state);
+                return;
+            }
+            RegisterValue::Text(val) => {
+                val.hash(state);
+                return;
+            }
+        };
+        discriminant(value).hash(state);
+        match value {
+            Value::U16(val) => val.hash(state),
+            Value::U32(val) => val.hash(state),
+            Value::U64(val) => val.hash(state),
+            Value::U128(val) => val.hash(state),
+            Value::S16(val) => val.hash(state),
+            Value::S32(val) => val.hash(state),
+            Value::Enum16(val) => val.hash(state),
+            Value::Sized(val) => val.hash(state),
+            Value::Float32(val) => val.to_bits().hash(state),
+            Value::Boolean(val) => val.hash(state),
+        }
+    }
+}
+
+/// Compare the underlying values, floats by their bit pattern as in `Hash` (a NaN equals itself)
+impl PartialEq for RegisterValue {
+    fn eq(&self, other: &Self) -> bool {
+        match (self, other) {
+            (RegisterValue::Raw(a), RegisterValue::Raw(b)) => match (a, b) {
+                (Value::U16(a), Value::U16(b)) => a == b,
+                (Value::U32(a), Value::U32(b)) => a == b,
+                (Value::U64(a), Value::U64(b)) => a == b,
+                (Value::U128(a), Value::U128(b)) => a == b,
+                (Value::S16(a), Value::S16(b)) => a == b,
+                (Value::S32(a), Value::S32(b)) => a == b,
+                (Value::Enum16(a), Value::Enum16(b)) => a == b,
+                (Value::Sized(a), Value::Sized(b)) => a == b,
+                (Value::Float32(a), Value::Float32(b)) => a.to_bits() == b.to_bits(),
+                (Value::Boolean(a), Value::Boolean(b)) => a == b,
+                _ => false,
+            },
+            (RegisterValue::Float(a), RegisterValue::Float(b)) => a.to_bits() == b.to_bits(),
+            (RegisterValue::Int(a), RegisterValue::Int(b)) => a == b,
+            (RegisterValue::Text(a), RegisterValue::Text(b)) => a == b,
+            _ => false,
+        }
+    }
+}
+
+impl Eq for RegisterValue {}
+
 /// Ugly conversion because of https://github.com/rust-lang/rust/issues/31844
 /// Converts a `HashMap<K, V1>` into a `HashMap<K, V2>`
 /// by transforming each value using the `Into` trait.
+/// The keys and values are moved out of the input, nothing is cloned.
 /// # Type Parameters
-/// - `K`: the key type, must implement `Hash + Eq + Clone`.
-/// - `V1`: the source value type, must implement `Clone` and `Into<V2>`.
+/// - `K`: the key type, must implement `Hash + Eq`.
+/// - `V1`: the source value type, must implement `Into<V2>`.
 /// - `V2`: the target value type.
 ///
 /// # Parameters
-/// - `val`: input `HashMap<K, V1>`
+/// - `val`: input `HashMap<K, V1>`, consumed
 ///
 /// # Returns
 /// A new `HashMap<K, V2>` with identical keys and converted values.
-pub fn convert_hashmap<K: Hash + Eq + Clone, V1: Into<V2> + Clone, V2>(
-    val: HashMap<K, V1>,
-) -> HashMap<K, V2> {
-    val.iter()
-        .map(|(name, field)| ((*name).clone(), (*field).clone().into()))
+pub fn convert_hashmap<K: Hash + Eq, V1: Into<V2>, V2>(val: HashMap<K, V1>) -> HashMap<K, V2> {
+    val.into_iter()
+        .map(|(name, field)| (name, field.into()))
         .collect()
 }
 
+/// NaN (an invalid reading) is kept as is, the remotes that can't write it leave the field out
+/// (see [`crate::remotes::influxdb::line_protocol::field_value`])
 impl Into<Type> for RegisterValue {
     fn into(self) -> Type {
-        match self.value {
+        let value = match self {
+            RegisterValue::Raw(value) => value,
+            RegisterValue::Float(val) => return val.into(),
+            RegisterValue::Int(val) => return val.into(),
+            RegisterValue::Text(val) => return val.into(),
+        };
+        match value {
             Value::U16(val) => val.into(),
             Value::U32(val) => val.into(),
             Value::U64(val) => val.into(),
@@ -45,11 +395,8 @@ impl Into<Type> for RegisterValue {
             Value::S16(val) => val.into(),
             Value::S32(val) => val.into(),
             Value::Enum16(val) => val.into(),
-            Value::Sized(val) => format!("{0:x?}", &val).into(),
-            Value::Float32(val) => match val.is_nan() {
-                true => (-1.0).into(),
-                _ => val.into(),
-            },
+            Value::Sized(val) => BytesEncoding::default().encode(&val).into(),
+            Value::Float32(val) => val.into(),
             Value::Boolean(val) => val.into(),
         }
     }
@@ -57,7 +404,13 @@ impl Into<Type> for RegisterValue {
 
 impl Into<String> for RegisterValue {
     fn into(self) -> String {
-        match self.value {
+        let value = match self {
+            RegisterValue::Raw(value) => value,
+            RegisterValue::Float(val) => return val.to_string(),
+            RegisterValue::Int(val) => return val.to_string(),
+            RegisterValue::Text(val) => return val,
+        };
+        match value {
             Value::U16(val) => val.to_string(),
             Value::U32(val) => val.to_string(),
             Value::U64(val) => val.to_string(),
@@ -65,7 +418,7 @@ impl Into<String> for RegisterValue {
             Value::S16(val) => val.to_string(),
             Value::S32(val) => val.to_string(),
             Value::Enum16(val) => val.to_string(),
-            Value::Sized(val) => format!("{0:x?}", &val),
+            Value::Sized(val) => BytesEncoding::default().encode(&val),
             Value::Float32(val) => val.to_string(),
             Value::Boolean(val) => match val {
                 true => "1".to_string(),
@@ -75,22 +428,257 @@ impl Into<String> for RegisterValue {
     }
 }
 
+/// See [`RegisterValue::as_f64`], NaN is pushed as is (Prometheus stores it)
 impl Into<f64> for RegisterValue {
     fn into(self) -> f64 {
-        match self.value {
-            Value::U16(val) => val.into(),
-            Value::U32(val) => val.into(),
-            Value::U64(val) => val as f64,
-            Value::U128(val) => val as f64,
-            Value::S16(val) => val.into(),
-            Value::S32(val) => val.into(),
-            Value::Enum16(val) => val.into(),
-            Value::Sized(_val) => 0 as f64,
-            Value::Float32(val) => match val.is_nan() {
-                true => (-1.0).into(),
-                _ => val.into(),
-            },
-            Value::Boolean(val) => val.into(),
+        self.as_f64()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn negative_reading_keeps_its_sign() {
+        for value in [
+            RegisterValue::Raw(Value::S16(-40)),
+            RegisterValue::Raw(Value::S32(-40)),
+            RegisterValue::Int(-40),
+        ] {
+            assert_eq!(value.as_f64(), -40.0);
+            assert_eq!(Into::<f64>::into(value.clone()), -40.0);
+            assert!(matches!(
+                Into::<Type>::into(value.clone()),
+                Type::SignedInteger(-40)
+            ));
+            assert_eq!(Into::<String>::into(value), "-40");
+        }
+    }
+
+    #[test]
+    fn negative_float_reading_keeps_its_sign() {
+        for value in [
+            RegisterValue::Raw(Value::Float32(-40.5)),
+            RegisterValue::Float(-40.5),
+        ] {
+            assert_eq!(Into::<f64>::into(value.clone()), -40.5);
+            assert!(matches!(Into::<Type>::into(value.clone()), Type::Float(val) if val == -40.5));
+            assert_eq!(Into::<String>::into(value), "-40.5");
+        }
+    }
+
+    #[test]
+    fn signed_extremes_are_not_truncated() {
+        let value = RegisterValue::Raw(Value::S16(i16::MIN));
+        assert_eq!(Into::<f64>::into(value.clone()), -32768.0);
+        assert!(matches!(
+            Into::<Type>::into(value),
+            Type::SignedInteger(-32768)
+        ));
+        let value = RegisterValue::Raw(Value::S32(i32::MIN));
+        assert_eq!(Into::<f64>::into(value.clone()), i32::MIN as f64);
+        assert!(
+            matches!(Into::<Type>::into(value), Type::SignedInteger(val) if val == i32::MIN as i64)
+        );
+    }
+
+    #[test]
+    fn nan_reading_is_not_negative() {
+        for value in [
+            RegisterValue::Raw(Value::Float32(f32::NAN)),
+            RegisterValue::Float(f64::NAN),
+        ] {
+            assert!(value.as_f64().is_nan());
+            assert!(Into::<f64>::into(value.clone()).is_nan());
+            assert!(matches!(Into::<Type>::into(value), Type::Float(val) if val.is_nan()));
         }
     }
+
+    #[test]
+    fn negative_values_round_and_convert() {
+        let mut values = HashMap::from([
+            ("t".to_string(), RegisterValue::Raw(Value::Float32(-40.25))),
+            ("n".to_string(), RegisterValue::Float(-39.6)),
+        ]);
+        round_values(&mut values, &HashMap::from([("t".to_string(), 1)]));
+        integer_values(&mut values, &["n".to_string()]);
+        assert!(matches!(values["t"], RegisterValue::Float(val) if val == -40.3));
+        assert!(matches!(values["n"], RegisterValue::Int(-40)));
+    }
+
+    #[test]
+    fn values_are_equal_by_variant_and_value() {
+        assert_eq!(RegisterValue::Float(1.0), RegisterValue::Float(1.0));
+        assert_ne!(RegisterValue::Float(1.0), RegisterValue::Int(1));
+        assert_eq!(
+            RegisterValue::Raw(Value::Float32(f32::NAN)),
+            RegisterValue::Raw(Value::Float32(f32::NAN))
+        );
+        assert_ne!(
+            RegisterValue::Raw(Value::U16(1)),
+            RegisterValue::Raw(Value::S16(1))
+        );
+        assert_ne!(
+            RegisterValue::Text("on".to_string()),
+            RegisterValue::Text("off".to_string())
+        );
+    }
+
+    #[test]
+    fn text_register_is_rendered_as_text() {
+        for encoding in [BytesEncoding::Ascii, BytesEncoding::Utf8] {
+            let mut values = HashMap::from([(
+                "serial".to_string(),
+                RegisterValue::Raw(Value::Sized(b"ABCD\0\0".to_vec())),
+            )]);
+            encode_bytes(&mut values, encoding);
+            let serial = values.remove("serial").unwrap();
+            assert!(matches!(
+                Into::<Type>::into(serial.clone()),
+                Type::Text(text) if text == "ABCD"
+            ));
+            assert_eq!(Into::<String>::into(serial), "ABCD");
+        }
+    }
+
+    #[test]
+    fn text_register_is_hex_by_default() {
+        let serial = RegisterValue::Raw(Value::Sized(b"ABCD".to_vec()));
+        assert_eq!(Into::<String>::into(serial), "41424344");
+    }
+
+    #[test]
+    fn byte_array_is_encoded() {
+        let bytes = [0x4d, 0x61, 0xe9, 0x00];
+        assert_eq!(BytesEncoding::Hex.encode(&bytes), "4d61e900");
+        assert_eq!(BytesEncoding::Base64.encode(&bytes), "TWHpAA==");
+        assert_eq!(BytesEncoding::Base64.encode(b"Man"), "TWFu");
+        assert_eq!(BytesEncoding::Base64.encode(b"Ma"), "TWE=");
+        assert_eq!(BytesEncoding::Ascii.encode(&bytes), "Ma?");
+        assert_eq!(BytesEncoding::Utf8.encode(&bytes), "Ma\u{fffd}");
+    }
+
+    #[test]
+    fn byte_array_is_a_text_field() {
+        let mut values = HashMap::from([
+            (
+                "firmware".to_string(),
+                RegisterValue::Raw(Value::Sized(vec![0x01, 0x2a])),
+            ),
+            ("temperature".to_string(), RegisterValue::Float(21.5)),
+        ]);
+        encode_bytes(&mut values, BytesEncoding::Base64);
+        assert_eq!(values["firmware"], RegisterValue::Text("ASo=".to_string()));
+        assert_eq!(values["temperature"], RegisterValue::Float(21.5));
+        assert!(values["firmware"].is_text());
+        assert!(!values["firmware"].is_numeric());
+    }
+
+    #[test]
+    fn floats_are_rounded_to_their_decimals() {
+        let mut values = HashMap::from([
+            (
+                "setpoint".to_string(),
+                RegisterValue::Raw(Value::Float32(0.1)),
+            ),
+            ("temperature".to_string(), RegisterValue::Float(21.456)),
+            ("speed".to_string(), RegisterValue::Raw(Value::U16(1500))),
+            ("flow".to_string(), RegisterValue::Float(f64::NAN)),
+        ]);
+        assert_ne!(Into::<f64>::into(values["setpoint"].clone()), 0.1);
+        let decimals = HashMap::from([
+            ("setpoint".to_string(), 3),
+            ("temperature".to_string(), 1),
+            ("speed".to_string(), 1),
+            ("flow".to_string(), 1),
+        ]);
+        round_values(&mut values, &decimals);
+        assert_eq!(values["setpoint"], RegisterValue::Float(0.1));
+        assert_eq!(Into::<String>::into(values["setpoint"].clone()), "0.1");
+        assert_eq!(values["temperature"], RegisterValue::Float(21.5));
+        assert_eq!(values["speed"], RegisterValue::Raw(Value::U16(1500)));
+        assert_eq!(values["flow"], RegisterValue::Float(f64::NAN));
+    }
+
+    #[test]
+    fn u128_conversions_are_pinned() {
+        let big = (1u128 << 64) + 1;
+        let value = RegisterValue::Raw(Value::U128(big));
+        // Exact as a string (InfluxDB), rounded as a float (Prometheus)
+        assert_eq!(Into::<String>::into(value.clone()), big.to_string());
+        assert!(
+            matches!(Into::<Type>::into(value.clone()), Type::Text(text) if text == big.to_string())
+        );
+        assert_eq!(Into::<f64>::into(value.clone()), 18446744073709551616.0);
+        assert!(value.is_wide_integer());
+        assert!(!value.is_exact_f64());
+    }
+
+    #[test]
+    fn integers_up_to_2_pow_53_are_exact_floats() {
+        let exact = MAX_EXACT_INTEGER as u64;
+        assert!(RegisterValue::Raw(Value::U64(exact)).is_exact_f64());
+        assert!(!RegisterValue::Raw(Value::U64(exact + 1)).is_exact_f64());
+        assert!(RegisterValue::Raw(Value::U128(MAX_EXACT_INTEGER)).is_exact_f64());
+        assert!(!RegisterValue::Int(-(exact as i64) - 1).is_exact_f64());
+        assert!(RegisterValue::Float(1e300).is_exact_f64());
+        assert!(!RegisterValue::Raw(Value::U32(u32::MAX)).is_wide_integer());
+    }
+
+    fn format(decimals: Option<u8>, unit_suffix: bool, separator: Option<&str>) -> ValueFormat {
+        ValueFormat {
+            decimals,
+            unit_suffix,
+            thousands_separator: separator.map(String::from),
+        }
+    }
+
+    #[test]
+    fn floats_are_formatted_with_their_decimals() {
+        let value = RegisterValue::Float(23.456);
+        assert_eq!(value.as_format(&format(Some(1), false, None), None), "23.5");
+        assert_eq!(value.as_format(&format(Some(0), false, None), None), "23");
+        assert_eq!(value.as_format(&format(None, false, None), None), "23.456");
+        // The integers have no decimals to round
+        let value = RegisterValue::Raw(Value::U16(215));
+        assert_eq!(value.as_format(&format(Some(2), false, None), None), "215");
+    }
+
+    #[test]
+    fn unit_is_appended_when_suffixed() {
+        let value = RegisterValue::Raw(Value::Float32(23.4));
+        let unit = "°C".to_string();
+        let suffixed = format(Some(1), true, None);
+        assert_eq!(value.as_format(&suffixed, Some(&unit)), "23.4 °C");
+        assert_eq!(value.as_format(&suffixed, None), "23.4");
+        assert_eq!(
+            value.as_format(&format(Some(1), false, None), Some(&unit)),
+            "23.4"
+        );
+    }
+
+    #[test]
+    fn thousands_are_separated() {
+        let separated = format(Some(1), true, Some(","));
+        let unit = "kWh".to_string();
+        let value = RegisterValue::Float(1234567.89);
+        assert_eq!(value.as_format(&separated, Some(&unit)), "1,234,567.9 kWh");
+        assert_eq!(
+            RegisterValue::Int(-1234).as_format(&separated, None),
+            "-1,234"
+        );
+        assert_eq!(RegisterValue::Int(123).as_format(&separated, None), "123");
+        assert_eq!(group_thousands("NaN", "."), "NaN");
+    }
+
+    #[test]
+    fn non_numeric_values_are_written_as_is() {
+        let value = RegisterValue::Text("running".to_string());
+        let unit = "rpm".to_string();
+        assert_eq!(
+            value.as_format(&format(Some(1), true, Some(",")), Some(&unit)),
+            "running"
+        );
+    }
 }